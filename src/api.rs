@@ -0,0 +1,529 @@
+use std::ffi::OsString;
+use std::fs;
+use std::future::Future;
+use std::io::{self, BufRead, BufReader, PipeReader};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use serde_json::{self, Value};
+use syn;
+use syn::spanned::Spanned;
+
+use cache;
+use capture;
+use config::Config;
+use features;
+use interrupt;
+use json;
+use opts::Opts;
+use platform;
+use rewrite;
+use sandbox;
+use syntax;
+use tmp::TmpDir;
+use tty;
+use version;
+use {
+    cargo_command, cargo_err_rule, definitely_not_nightly, pick_packages, run_cargo_with,
+    sum_lines, wrap_args,
+};
+
+/// What to expand, for [`expand`]. The defaults are those of running
+/// `cargo expand` with no arguments in the current directory.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// The `Cargo.toml` of the package, if not the one cargo would find from
+    /// the current directory.
+    pub manifest_path: Option<PathBuf>,
+    /// The package of the workspace to expand, as with `-p`.
+    pub package: Option<String>,
+    /// The target of the package to expand, if not its lib or only bin.
+    pub target: Option<Target>,
+    /// Features of the package to enable, as with `--features`.
+    pub features: Vec<String>,
+    /// Enable every feature of the package, as with `--all-features`.
+    pub all_features: bool,
+    /// Leave out the package's default features, as with
+    /// `--no-default-features`.
+    pub no_default_features: bool,
+    /// The build profile, like `release` or a custom one, if not `dev`.
+    /// `cfg!(debug_assertions)` and the like follow it.
+    pub profile: Option<String>,
+    /// The platform to expand for, like `wasm32-unknown-unknown`, if not the
+    /// host, as with `--target`.
+    pub platform: Option<String>,
+    /// The path of one item to keep, like `http::Handlers`, rather than the
+    /// whole crate.
+    pub item: Option<String>,
+    /// The rustup toolchain to build with, like `nightly`.
+    pub toolchain: Option<String>,
+    /// Any other arguments of `cargo expand`, such as `--derives` or
+    /// `--release`, given the same way as on the command line.
+    pub args: Vec<OsString>,
+}
+
+/// A target of a package, as selected with `--lib`, `--bin` and the like.
+#[derive(Clone, Debug)]
+pub enum Target {
+    Lib,
+    Bin(String),
+    Test(String),
+    Example(String),
+    Bench(String),
+}
+
+/// The result of [`expand`].
+#[derive(Clone, Debug)]
+pub struct Expansion {
+    /// The expanded code, formatted.
+    pub code: String,
+    /// The target that was expanded, as `lib`, `bin:name` and so on.
+    pub target: String,
+    /// The items of the expanded code, empty if it does not parse.
+    pub items: Vec<Item>,
+    /// What the compiler said while building the crate, such as warnings.
+    pub diagnostics: Vec<Diagnostic>,
+    pub stats: Stats,
+    /// The SHA-256 of the expanded code before it was formatted, as
+    /// `--checksum` prints it, which is the same whatever the formatting.
+    pub checksum: String,
+    /// The compiler the crate was built with, as `rustc --version` puts it.
+    pub toolchain: String,
+}
+
+/// An item of the expanded code.
+#[derive(Clone, Debug)]
+pub struct Item {
+    /// The kind of item, like `fn`, `struct` or `impl`.
+    pub kind: String,
+    /// The path of a named item from the crate root, like `http::Handlers`.
+    /// Impls, `use` items and the like have none.
+    pub path: Option<String>,
+    /// A short description of the item, like `impl Debug for Point`.
+    pub label: String,
+    /// The line of the expanded code that the item starts on.
+    pub line: usize,
+    /// The items inside a module.
+    pub items: Vec<Item>,
+}
+
+/// A message from the compiler, as cargo reported it.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    /// `error`, `warning`, `note` and so on.
+    pub level: String,
+    pub message: String,
+    /// The lint or error code, like `dead_code` or `E0308`.
+    pub code: Option<String>,
+    /// Where the message points to in the source, as the file relative to
+    /// the package, the line and the column.
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// The message as the compiler would have printed it.
+    pub rendered: String,
+}
+
+/// Facts about the expansion.
+#[derive(Clone, Debug)]
+pub struct Stats {
+    /// How long the build took.
+    pub duration: Duration,
+    /// How many crates were compiled rather than found up to date.
+    pub compiled: usize,
+    /// The size of the code, in lines and bytes.
+    pub lines: usize,
+    pub bytes: usize,
+}
+
+/// A step of an expansion, for [`expand_with_progress`].
+#[derive(Clone, Debug)]
+pub enum Progress {
+    /// Cargo started compiling a crate: a dependency, or last of all the one
+    /// being expanded.
+    Compiling { name: String, version: String },
+    /// Cargo is done with a crate, having compiled it or, if `fresh`, found it
+    /// up to date.
+    Compiled { name: String, fresh: bool },
+    /// The crate is expanded, and its code is about to be formatted.
+    Expanded,
+    /// The code is formatted, and the expansion about to be returned.
+    Formatted,
+}
+
+/// Expand a crate the way `cargo expand` does and return the code instead of
+/// printing it. Cargo's diagnostics are returned rather than printed, while
+/// its progress still goes to stderr. A build that fails is an error, with
+/// the errors in its message. The crate is always built, even if it did not
+/// change since it was last expanded.
+pub fn expand(options: Options) -> io::Result<Expansion> {
+    expand_with_progress(options, |_| {})
+}
+
+/// [`expand`], calling `on_progress` at each step of the way, for tools that
+/// show their own progress. It may be called from another thread than this
+/// one, though never from two at once.
+pub fn expand_with_progress<F>(options: Options, on_progress: F) -> io::Result<Expansion>
+where
+    F: FnMut(Progress) + Send,
+{
+    let on_progress = Mutex::new(on_progress);
+    let report = |progress: Progress| (on_progress.lock().unwrap())(progress);
+
+    let config = Config::load()?;
+    let mut opts = Opts::parse(options.args(), &config)?;
+    opts.toolchain = options.toolchain.clone().or(opts.toolchain);
+    // The command would run itself again on nightly; this builds with it.
+    if opts.toolchain.is_none() && definitely_not_nightly() {
+        opts.toolchain = Some("nightly".to_owned());
+    }
+    if pick_packages(&mut opts)?.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "more than one package to expand; pick one with `package`",
+        ));
+    }
+    features::check(&opts)?;
+    platform::check(&opts)?;
+    features::enable_required(&mut opts);
+    cache::configure(&mut opts, &config)?;
+    sandbox::prepare(&mut opts)?;
+
+    let outdir = TmpDir::create(opts.keep_tmp)?;
+    let outfile = outdir.path().join("expanded");
+    opts.cargo.push("--message-format=json".into());
+    let mut cmd = cargo_command(&opts);
+    cmd.args(wrap_args(&opts, Some(&outfile)));
+    let (messages, stdout) = io::pipe()?;
+    cmd.stdout(stdout);
+
+    let start = Instant::now();
+    // Cargo's JSON messages are read as they come, while it runs.
+    let (status, messages) = thread::scope(|scope| {
+        let manifest_path = opts.manifest_path.as_ref();
+        let reader = scope.spawn(move || read_messages(messages, manifest_path, &report));
+        let status = run_cargo_with(&opts, &mut cmd, &mut |line| {
+            let line = tty::strip_ansi(line);
+            let mut words = line.split_whitespace();
+            if let (Some("Compiling"), Some(name), Some(version)) =
+                (words.next(), words.next(), words.next())
+            {
+                report(Progress::Compiling {
+                    name: name.to_owned(),
+                    version: version.trim_start_matches('v').to_owned(),
+                });
+            }
+        });
+        // Closing our end of the pipe lets the reader see the end of it.
+        drop(cmd);
+        (status, reader.join().unwrap())
+    });
+    let duration = start.elapsed();
+    let status = status?;
+    let (diagnostics, compiled) = messages?;
+    if status != 0 {
+        let mut msg = "cargo failed to build the crate".to_owned();
+        for diagnostic in diagnostics.iter().filter(|d| d.level == "error") {
+            msg.push_str("\n\n");
+            msg.push_str(diagnostic.rendered.trim_end());
+        }
+        return Err(io::Error::other(msg));
+    }
+    report(Progress::Expanded);
+
+    rewrite::apply(&opts, &outfile)?;
+    let checksum = sum_lines(&fs::read_to_string(&outfile)?);
+    let code = capture::format(&opts, &config, &outfile)?;
+    report(Progress::Formatted);
+    let items = match syn::parse_file(&code) {
+        Ok(file) => list(&json::entries(&file.items, "")),
+        Err(_) => Vec::new(),
+    };
+    let toolchain = version::rustc_command(opts.toolchain.as_deref())
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim_end().to_owned())
+        .unwrap_or_default();
+    Ok(Expansion {
+        stats: Stats {
+            duration,
+            compiled,
+            lines: code.lines().count(),
+            bytes: code.len(),
+        },
+        code,
+        checksum,
+        target: opts.target_label(),
+        items,
+        diagnostics,
+        toolchain,
+    })
+}
+
+/// Start expanding a crate on a thread of its own, as [`expand`] does, and
+/// return a handle to wait for the result with or to cancel the expansion.
+/// The handle is also a future, for hosts with an async runtime. Dropping it
+/// before the expansion is done cancels it.
+pub fn spawn(options: Options) -> Handle {
+    spawn_with_progress(options, |_| {})
+}
+
+/// [`spawn`], calling `on_progress` at each step of the way as
+/// [`expand_with_progress`] does.
+pub fn spawn_with_progress<F>(options: Options, on_progress: F) -> Handle
+where
+    F: FnMut(Progress) + Send + 'static,
+{
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            result: None,
+            waker: None,
+        }),
+        done: Condvar::new(),
+    });
+    let thread = {
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            let result = expand_with_progress(options, on_progress);
+            let result = if interrupt::take_cancelled() {
+                Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    "the expansion was cancelled",
+                ))
+            } else {
+                result
+            };
+            let mut state = shared.state.lock().unwrap();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+            shared.done.notify_all();
+        })
+    };
+    Handle {
+        thread: thread.thread().id(),
+        shared,
+        taken: false,
+    }
+}
+
+/// An expansion started by [`spawn`].
+pub struct Handle {
+    thread: ThreadId,
+    shared: Arc<Shared>,
+    taken: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    done: Condvar,
+}
+
+struct State {
+    result: Option<io::Result<Expansion>>,
+    waker: Option<Waker>,
+}
+
+impl Handle {
+    /// Stop the build. The expansion then ends with an error of kind
+    /// [`io::ErrorKind::Interrupted`], unless it was already done.
+    pub fn cancel(&self) {
+        if !self.is_finished() {
+            interrupt::cancel(self.thread);
+        }
+    }
+
+    /// Whether the result is ready, so that [`wait`](Handle::wait) would not
+    /// block.
+    pub fn is_finished(&self) -> bool {
+        self.taken || self.shared.state.lock().unwrap().result.is_some()
+    }
+
+    /// Block until the expansion is done and return its result.
+    pub fn wait(mut self) -> io::Result<Expansion> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                drop(state);
+                self.taken = true;
+                return result;
+            }
+            state = self.shared.done.wait(state).unwrap();
+        }
+    }
+}
+
+impl Future for Handle {
+    type Output = io::Result<Expansion>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => {
+                drop(state);
+                self.taken = true;
+                Poll::Ready(result)
+            }
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if !self.taken {
+            self.cancel();
+        }
+    }
+}
+
+// The compiler's messages among cargo's JSON ones, other than those kept off
+// the terminal by the command, and how many crates were compiled.
+fn read_messages(
+    messages: PipeReader,
+    manifest_path: Option<&OsString>,
+    report: &(dyn Fn(Progress) + Sync),
+) -> io::Result<(Vec<Diagnostic>, usize)> {
+    let package_dir = manifest_path.and_then(|p| Path::new(p).parent());
+    let mut diagnostics = Vec::new();
+    let mut compiled = 0;
+    for line in BufReader::new(messages).lines() {
+        let message: Value = match serde_json::from_str(&line?) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        match message["reason"].as_str() {
+            Some("compiler-artifact") => {
+                let fresh = message["fresh"] == true;
+                if !fresh {
+                    compiled += 1;
+                }
+                // A build script is a step of compiling its crate.
+                let build_script = message["target"]["kind"]
+                    .as_array()
+                    .is_some_and(|kinds| kinds.iter().any(|kind| kind == "custom-build"));
+                if !build_script {
+                    let package_id = message["package_id"].as_str().unwrap_or_default();
+                    report(Progress::Compiled {
+                        name: package_name(package_id).to_owned(),
+                        fresh,
+                    });
+                }
+                continue;
+            }
+            Some("compiler-message") => {}
+            _ => continue,
+        }
+        let message = &message["message"];
+        let text = message["message"].as_str().unwrap_or_default();
+        if cargo_err_rule(text).is_some() {
+            continue;
+        }
+        let span = message["spans"].as_array().and_then(|spans| {
+            spans
+                .iter()
+                .find(|span| span["is_primary"] == true)
+                .or_else(|| spans.first())
+        });
+        let number = |key: &str| span.and_then(|span| span[key].as_u64()).map(|n| n as usize);
+        let file = span
+            .and_then(|span| span["file_name"].as_str())
+            .map(|file| {
+                package_dir
+                    .and_then(|dir| Path::new(file).strip_prefix(dir).ok())
+                    .unwrap_or(Path::new(file))
+                    .display()
+                    .to_string()
+            });
+        diagnostics.push(Diagnostic {
+            level: message["level"].as_str().unwrap_or_default().to_owned(),
+            message: text.to_owned(),
+            code: message["code"]["code"].as_str().map(str::to_owned),
+            file,
+            line: number("line_start"),
+            column: number("column_start"),
+            rendered: message["rendered"].as_str().unwrap_or(text).to_owned(),
+        });
+    }
+    Ok((diagnostics, compiled))
+}
+
+// The name in a package id like `registry+https://...#serde@1.0.0`, or
+// `path+file:///work/demo#0.1.0` for a package named after its directory.
+fn package_name(package_id: &str) -> &str {
+    let (source, spec) = package_id.rsplit_once('#').unwrap_or((package_id, ""));
+    match spec.split_once('@') {
+        Some((name, _)) => name,
+        None => source.rsplit('/').next().unwrap_or(source),
+    }
+}
+
+fn list(entries: &[json::Entry]) -> Vec<Item> {
+    entries
+        .iter()
+        .map(|entry| Item {
+            kind: syntax::item_kind(entry.item).to_owned(),
+            path: entry.path.clone(),
+            label: syntax::item_label(entry.item),
+            line: entry.item.span().start().line,
+            items: list(&entry.items),
+        })
+        .collect()
+}
+
+impl Options {
+    // The command line these options stand for, as it would reach us from
+    // cargo, starting with `cargo-expand expand`.
+    fn args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["cargo-expand".into(), "expand".into()];
+        if let Some(ref manifest_path) = self.manifest_path {
+            args.push("--manifest-path".into());
+            args.push(manifest_path.clone().into());
+        }
+        if let Some(ref package) = self.package {
+            args.push("--package".into());
+            args.push(package.into());
+        }
+        match self.target {
+            Some(Target::Lib) => args.push("--lib".into()),
+            Some(Target::Bin(ref name)) => args.extend(["--bin".into(), name.into()]),
+            Some(Target::Test(ref name)) => args.extend(["--test".into(), name.into()]),
+            Some(Target::Example(ref name)) => args.extend(["--example".into(), name.into()]),
+            Some(Target::Bench(ref name)) => args.extend(["--bench".into(), name.into()]),
+            None => {}
+        }
+        if !self.features.is_empty() {
+            args.push("--features".into());
+            args.push(self.features.join(",").into());
+        }
+        if self.all_features {
+            args.push("--all-features".into());
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".into());
+        }
+        if let Some(ref profile) = self.profile {
+            args.push("--profile".into());
+            args.push(profile.into());
+        }
+        if let Some(ref platform) = self.platform {
+            args.push("--target".into());
+            args.push(platform.into());
+        }
+        if let Some(ref item) = self.item {
+            args.push(item.into());
+        }
+        args.extend(self.args.iter().cloned());
+        args
+    }
+}
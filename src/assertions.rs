@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::{
+    Block, Expr, Fields, File, GenericArgument, GenericParam, Item, Pat, PathArguments, Stmt, Type,
+    TypeParamBound,
+};
+
+use syntax;
+
+// Stands in for folded code until the expansion is printed, when `render`
+// replaces it with a comment summing that code up.
+const MARKER: &str = "__cargo_expand_folded";
+
+// Names like these are given to the types with which derives assert that the
+// fields of a type implement a trait, as in `let _: AssertParamIsEq<u8>;`.
+const ASSERT_PARAM: &str = "AssertParamIs";
+
+// Fold the trait assertions derives and assertion macros generate into one
+// line each: the runs of `let _: AssertParamIsClone<T>;` in derived impls, and
+// the `const _` blocks of empty generic functions called only to check that a
+// type satisfies their bounds, like those of `assert_impl_all!`. Returns where
+// each is in the text the file was parsed from with the marker to put there,
+// and the summaries, one for each marker.
+pub fn fold(file: &File) -> (Vec<(Range<usize>, String)>, Vec<String>) {
+    let mut fold = Fold {
+        edits: Vec::new(),
+        summaries: Vec::new(),
+    };
+    fold.visit_file(file);
+    (fold.edits, fold.summaries)
+}
+
+// The expansion with the markers made into comments, each on a line of its
+// own.
+pub fn render(code: &str, summaries: &[String]) -> String {
+    let mut code = code.to_owned();
+    for (i, summary) in summaries.iter().enumerate() {
+        let marker = format!("{} ! ({}) ;", MARKER, i);
+        let start = match code.find(&marker) {
+            Some(start) => start,
+            None => continue,
+        };
+        let end = start + marker.len();
+        let line_start = code[..start].rfind('\n').map_or(0, |i| i + 1);
+        let before = if code[line_start..start].trim().is_empty() {
+            ""
+        } else {
+            "\n"
+        };
+        let after = if code[end..].starts_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        code.replace_range(start..end, &format!("{}// {}{}", before, summary, after));
+    }
+    code
+}
+
+struct Fold {
+    edits: Vec<(Range<usize>, String)>,
+    summaries: Vec<String>,
+}
+
+impl Fold {
+    fn marker(&mut self, range: Range<usize>, summary: String) {
+        let index = self.summaries.len();
+        self.summaries.push(summary);
+        self.edits
+            .push((range, format!("{} ! ({}) ;", MARKER, index)));
+    }
+}
+
+impl<'ast> Visit<'ast> for Fold {
+    fn visit_item(&mut self, item: &'ast Item) {
+        match assertion_const(item) {
+            Some(summary) => self.marker(item.span().byte_range(), summary),
+            None => visit::visit_item(self, item),
+        }
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        visit::visit_block(self, block);
+
+        // Consecutive param assertions, as their trait and the type, and
+        // where the run of them starts and ends.
+        let mut run: Vec<(String, String)> = Vec::new();
+        let mut range = 0..0;
+        for stmt in &block.stmts {
+            if let Some(assertion) = param_assertion(stmt) {
+                let span = stmt.span().byte_range();
+                if run.is_empty() {
+                    range.start = span.start;
+                }
+                range.end = span.end;
+                run.push(assertion);
+                continue;
+            }
+            if !run.is_empty() {
+                self.marker(range.clone(), param_summary(&run));
+                run.clear();
+            }
+        }
+        if !run.is_empty() {
+            self.marker(range, param_summary(&run));
+        }
+    }
+}
+
+// `let _: ::core::cmp::AssertParamIsEq<T>;` as the trait and the type.
+fn param_assertion(stmt: &Stmt) -> Option<(String, String)> {
+    let local = match *stmt {
+        Stmt::Local(ref local) if local.init.is_none() => local,
+        _ => return None,
+    };
+    let pat = match local.pat {
+        Pat::Type(ref pat) => pat,
+        _ => return None,
+    };
+    if !matches!(*pat.pat, Pat::Wild(_)) {
+        return None;
+    }
+    let segment = match *pat.ty {
+        Type::Path(ref ty) => ty.path.segments.last()?,
+        _ => return None,
+    };
+    let name = segment.ident.to_string();
+    let trait_name = name.strip_prefix(ASSERT_PARAM)?;
+    let ty = match segment.arguments {
+        PathArguments::AngleBracketed(ref args) => match args.args.first()? {
+            GenericArgument::Type(ref ty) => syntax::compact(ty),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some((trait_name.to_owned(), ty))
+}
+
+fn param_summary(run: &[(String, String)]) -> String {
+    let mut by_trait: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (trait_name, ty) in run {
+        by_trait.entry(trait_name).or_default().push(ty);
+    }
+    let parts: Vec<String> = by_trait
+        .into_iter()
+        .map(|(trait_name, types)| {
+            if types.len() > 4 {
+                format!("{} field types are {}", types.len(), trait_name)
+            } else {
+                format!(
+                    "{} {} {}",
+                    types.join(", "),
+                    if types.len() == 1 { "is" } else { "are" },
+                    trait_name
+                )
+            }
+        })
+        .collect();
+    format!("asserts that {}", parts.join("; "))
+}
+
+// A `const _` holding nothing but generic functions with empty bodies, structs
+// of PhantomData, and calls of those functions with the types to check, as
+// what it asserts, like `Foo: Send + Sync`.
+fn assertion_const(item: &Item) -> Option<String> {
+    let item = match *item {
+        Item::Const(ref item) if item.ident == "_" => item,
+        _ => return None,
+    };
+    let block = match *item.expr {
+        Expr::Block(ref expr) => &expr.block,
+        Expr::Closure(ref closure) => match *closure.body {
+            Expr::Block(ref expr) => &expr.block,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    // The bounds of each function's type parameters.
+    let mut bounds: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut calls = Vec::new();
+    for stmt in &block.stmts {
+        match *stmt {
+            Stmt::Item(Item::Fn(ref f)) if f.block.stmts.is_empty() => {
+                let params = f
+                    .sig
+                    .generics
+                    .params
+                    .iter()
+                    .filter_map(|param| match *param {
+                        GenericParam::Type(ref param) => Some(param),
+                        _ => None,
+                    })
+                    .map(|param| {
+                        let bounds: Vec<String> = param
+                            .bounds
+                            .iter()
+                            .filter(|bound| match **bound {
+                                TypeParamBound::Trait(ref bound) => {
+                                    matches!(bound.modifier, syn::TraitBoundModifier::None)
+                                }
+                                _ => true,
+                            })
+                            .map(syntax::compact)
+                            .collect();
+                        bounds.join(" + ")
+                    })
+                    .collect();
+                bounds.insert(f.sig.ident.to_string(), params);
+            }
+            Stmt::Item(Item::Struct(ref s)) if is_phantom(&s.fields) => {}
+            Stmt::Expr(Expr::Call(ref call), _) if call.args.is_empty() => match *call.func {
+                Expr::Path(ref path) => calls.push(path.path.segments.last()?),
+                _ => return None,
+            },
+            _ => return None,
+        }
+    }
+    if calls.is_empty() {
+        return None;
+    }
+
+    let mut asserted = Vec::new();
+    for call in calls {
+        let params = bounds.get(&call.ident.to_string())?;
+        let args = match call.arguments {
+            PathArguments::AngleBracketed(ref args) => &args.args,
+            _ => return None,
+        };
+        let types = args.iter().filter_map(|arg| match *arg {
+            GenericArgument::Type(ref ty) => Some(syntax::compact(ty)),
+            _ => None,
+        });
+        for (ty, bounds) in types.zip(params) {
+            if !bounds.is_empty() {
+                asserted.push(format!("{}: {}", ty, bounds));
+            }
+        }
+    }
+    if asserted.is_empty() {
+        return None;
+    }
+    Some(format!("asserts {}", asserted.join(", ")))
+}
+
+fn is_phantom(fields: &Fields) -> bool {
+    fields.iter().all(|field| match field.ty {
+        Type::Path(ref ty) => ty
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "PhantomData"),
+        _ => false,
+    })
+}
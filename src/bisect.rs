@@ -0,0 +1,555 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use regex::Regex;
+use serde_json::{self, Value};
+
+use capture;
+use config::Config;
+use metadata::{self, Metadata};
+use opts::Opts;
+use tmp::TmpDir;
+use trust;
+use tty::confirm;
+use {cargo_binary, project};
+
+// `--bisect-dep NAME LOW..HIGH`
+#[derive(Clone)]
+pub struct DepRange {
+    pub name: String,
+    pub low: String,
+    pub high: String,
+}
+
+impl DepRange {
+    pub fn new(name: String, range: &str) -> Result<Self, String> {
+        let mut bounds = range.splitn(2, "..");
+        match (bounds.next(), bounds.next()) {
+            (Some(low), Some(high)) if !low.is_empty() && !high.is_empty() => Ok(DepRange {
+                name,
+                low: low.to_owned(),
+                high: high.to_owned(),
+            }),
+            _ => Err(format!("expected a range LOW..HIGH, found `{}`", range)),
+        }
+    }
+}
+
+// Find the release of a dependency in which the expanded output changed with
+// respect to `pattern`, by pinning successive versions in the lockfile. That
+// is done in a copy of the workspace, so the real lockfile is never touched.
+pub fn dep(opts: &Opts, config: &Config, range: &DepRange) -> io::Result<i32> {
+    let pattern = grep_pattern(opts)?;
+
+    let mut versions: Vec<String> = published_versions(&range.name)?
+        .into_iter()
+        .filter(|v| {
+            compare(v, &range.low) != Ordering::Less && compare(v, &range.high) != Ordering::Greater
+        })
+        .collect();
+    versions.sort_by(|a, b| compare(a, b));
+    if versions.len() < 2 {
+        return Err(io::Error::other(format!(
+            "found {} published version{} of {} in {}..{}, need at least two",
+            versions.len(),
+            if versions.len() == 1 { "" } else { "s" },
+            range.name,
+            range.low,
+            range.high,
+        )));
+    }
+
+    let outdir = TmpDir::create(opts.keep_tmp)?;
+    let opts = copy_workspace(opts, config, &outdir.path().join("workspace"))?;
+    let opts = &opts;
+    let workspace = project::locate_workspace(opts.manifest_path.as_ref())
+        .ok_or_else(|| io::Error::other("could not find the workspace to bisect in"))?;
+
+    let test = |version: &str| -> io::Result<bool> {
+        let mut update = Command::new(cargo_binary());
+        update.arg("update").arg("--quiet");
+        update.arg("--manifest-path").arg(&workspace);
+        update.arg("--package").arg(&range.name);
+        update.arg("--precise").arg(version);
+        if !update.status()?.success() {
+            return Err(io::Error::other(format!(
+                "failed to pin {} {} in Cargo.lock",
+                range.name, version,
+            )));
+        }
+        let matched = match capture::expand(opts, config)? {
+            Some(code) => pattern.is_match(&code),
+            None => {
+                return Err(io::Error::other(format!(
+                    "expansion failed with {} {}",
+                    range.name, version
+                )))
+            }
+        };
+        let _ = writeln!(
+            &mut io::stderr(),
+            "bisect: {} {} {}",
+            range.name,
+            version,
+            if matched { "matches" } else { "does not match" },
+        );
+        Ok(matched)
+    };
+
+    let at_low = test(&versions[0])?;
+    let at_high = test(&versions[versions.len() - 1])?;
+    let verdict = if at_low { "stops" } else { "starts" };
+    let stdout = io::stdout();
+    if at_low == at_high {
+        writeln!(
+            stdout.lock(),
+            "the output {} `{}` at both {} {} and {}",
+            if at_low { "matches" } else { "does not match" },
+            pattern,
+            range.name,
+            versions[0],
+            versions[versions.len() - 1],
+        )?;
+        return Ok(1);
+    }
+
+    let mut good = 0;
+    let mut bad = versions.len() - 1;
+    while bad - good > 1 {
+        let mid = good + (bad - good) / 2;
+        if test(&versions[mid])? == at_low {
+            good = mid;
+        } else {
+            bad = mid;
+        }
+    }
+
+    writeln!(
+        stdout.lock(),
+        "{} {} is the first version in which the output {} matching `{}` (last {}: {})",
+        range.name,
+        versions[bad],
+        verdict,
+        pattern,
+        if at_low { "match" } else { "non-match" },
+        versions[good],
+    )?;
+    Ok(0)
+}
+
+// `--bisect-nightly YYYY-MM-DD..YYYY-MM-DD`, as days since 1970-01-01.
+#[derive(Clone)]
+pub struct DateRange {
+    low: i64,
+    high: i64,
+}
+
+impl FromStr for DateRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = || format!("expected a range YYYY-MM-DD..YYYY-MM-DD, found `{}`", s);
+        let mut bounds = s.splitn(2, "..");
+        let low = bounds.next().and_then(parse_date).ok_or_else(expected)?;
+        let high = bounds.next().and_then(parse_date).ok_or_else(expected)?;
+        if low >= high {
+            return Err(format!("the start of `{}` must be before its end", s));
+        }
+        Ok(DateRange { low, high })
+    }
+}
+
+// Find the nightly in which the expanded output changed with respect to
+// `pattern`, by building with nightlies from successive dates. Toolchains not
+// yet installed are installed through rustup once the user agrees to it.
+pub fn nightly(opts: &Opts, config: &Config, range: &DateRange) -> io::Result<i32> {
+    let pattern = grep_pattern(opts)?;
+    let mut consented = opts.yes;
+
+    let mut test = |day: i64| -> io::Result<Option<bool>> {
+        let toolchain = format!("nightly-{}", format_date(day));
+        if !installed(&toolchain) {
+            if !consented {
+                consented = confirm(&format!(
+                    "bisecting needs toolchains which are not installed, starting with {}. Install them with rustup?",
+                    toolchain,
+                ))?;
+                if !consented {
+                    return Err(io::Error::other("not installing toolchains; stopping"));
+                }
+            }
+            let installed = Command::new("rustup")
+                .args(["toolchain", "install", "--profile", "minimal"])
+                .arg(&toolchain)
+                .status()?
+                .success();
+            if !installed {
+                let _ = writeln!(
+                    &mut io::stderr(),
+                    "bisect: {} is not available, skipping",
+                    toolchain
+                );
+                return Ok(None);
+            }
+        }
+
+        let mut opts = opts.clone();
+        opts.toolchain = Some(toolchain.clone());
+        let matched = match capture::expand(&opts, config)? {
+            Some(code) => pattern.is_match(&code),
+            None => {
+                let _ = writeln!(
+                    &mut io::stderr(),
+                    "bisect: expansion failed with {}, skipping",
+                    toolchain
+                );
+                return Ok(None);
+            }
+        };
+        let _ = writeln!(
+            &mut io::stderr(),
+            "bisect: {} {}",
+            toolchain,
+            if matched { "matches" } else { "does not match" },
+        );
+        Ok(Some(matched))
+    };
+
+    let (mut good, mut bad) = (range.low, range.high);
+    let at_low = test(good)?.ok_or_else(|| unusable(good))?;
+    let at_high = test(bad)?.ok_or_else(|| unusable(bad))?;
+    let stdout = io::stdout();
+    if at_low == at_high {
+        writeln!(
+            stdout.lock(),
+            "the output {} `{}` with both nightly-{} and nightly-{}",
+            if at_low { "matches" } else { "does not match" },
+            pattern,
+            format_date(good),
+            format_date(bad),
+        )?;
+        return Ok(1);
+    }
+
+    'search: while bad - good > 1 {
+        // Days without a usable nightly are skipped by probing the next one.
+        let mid = good + (bad - good) / 2;
+        for day in (mid..bad).chain((good + 1..mid).rev()) {
+            if let Some(matched) = test(day)? {
+                if matched == at_low {
+                    good = day;
+                } else {
+                    bad = day;
+                }
+                continue 'search;
+            }
+        }
+        break;
+    }
+
+    if bad - good > 1 {
+        // None of the nightlies in between could be used, so the change is in
+        // one of them or in `bad`, but not known which.
+        writeln!(
+            stdout.lock(),
+            "inconclusive: the output {} matching `{}` somewhere after nightly-{} and by nightly-{}, \
+             as no nightly in between was usable",
+            if at_low { "stops" } else { "starts" },
+            pattern,
+            format_date(good),
+            format_date(bad),
+        )?;
+        return Ok(1);
+    }
+
+    writeln!(
+        stdout.lock(),
+        "nightly-{} is the first nightly in which the output {} matching `{}` (last {}: nightly-{})",
+        format_date(bad),
+        if at_low { "stops" } else { "starts" },
+        pattern,
+        if at_low { "match" } else { "non-match" },
+        format_date(good),
+    )?;
+    Ok(0)
+}
+
+fn unusable(day: i64) -> io::Error {
+    io::Error::other(format!(
+        "cannot bisect from nightly-{}, which is unusable",
+        format_date(day)
+    ))
+}
+
+fn installed(toolchain: &str) -> bool {
+    Command::new("rustup")
+        .args(["run", toolchain, "rustc", "--version"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+// Days since 1970-01-01 of a YYYY-MM-DD date, using Howard Hinnant's
+// days_from_civil.
+fn parse_date(s: &str) -> Option<i64> {
+    let mut parts = s.splitn(3, '-');
+    let y: i64 = parts.next()?.parse().ok()?;
+    let m: i64 = parts.next()?.parse().ok()?;
+    let d: i64 = parts.next()?.parse().ok()?;
+    let leap = y % 4 == 0 && (y % 100 != 0 || y % 400 == 0);
+    let days_in_month = match m {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if !(1..=days_in_month).contains(&d) {
+        return None;
+    }
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Some(era * 146097 + doe - 719468)
+}
+
+fn format_date(days: i64) -> String {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+pub fn grep_pattern(opts: &Opts) -> io::Result<Regex> {
+    let grep = match opts.grep {
+        Some(ref grep) => grep,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "bisecting requires --grep PATTERN to tell versions apart",
+            ))
+        }
+    };
+    Regex::new(grep).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))
+}
+
+// Non-yanked versions of a crate, from the crates.io sparse index.
+fn published_versions(name: &str) -> io::Result<Vec<String>> {
+    let name = name.to_lowercase();
+    let prefix = match name.len() {
+        1 => "1".to_owned(),
+        2 => "2".to_owned(),
+        3 => format!("3/{}", &name[..1]),
+        _ => format!("{}/{}", &name[..2], &name[2..4]),
+    };
+    let url = format!("https://index.crates.io/{}/{}", prefix, name);
+    let output = Command::new("curl")
+        .arg("--silent")
+        .arg("--show-error")
+        .arg("--fail")
+        .arg(&url)
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("failed to run curl to fetch {}: {}", url, err),
+            )
+        })?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("failed to fetch {}", url)));
+    }
+
+    let mut versions = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let entry: Value = match serde_json::from_str(line) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        if entry["yanked"] == true {
+            continue;
+        }
+        if let Some(vers) = entry["vers"].as_str() {
+            versions.push(vers.to_owned());
+        }
+    }
+    Ok(versions)
+}
+
+// Semver precedence, close enough for ordering crates.io releases: numeric
+// major.minor.patch, then a prerelease before its release.
+fn compare(a: &str, b: &str) -> Ordering {
+    fn parse(v: &str) -> (Vec<u64>, Option<&str>) {
+        let v = v.split('+').next().unwrap_or(v);
+        let (core, pre) = match v.find('-') {
+            Some(dash) => (&v[..dash], Some(&v[dash + 1..])),
+            None => (v, None),
+        };
+        let nums = core.split('.').map(|n| n.parse().unwrap_or(0)).collect();
+        (nums, pre)
+    }
+    let (a_nums, a_pre) = parse(a);
+    let (b_nums, b_pre) = parse(b);
+    a_nums.cmp(&b_nums).then_with(|| match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => a.cmp(b),
+    })
+}
+
+// Copy the workspace of the package, less its target directories, to `dir`,
+// along with the path dependencies outside of it, laid out as they are
+// relative to it so that the paths to them still lead there. Return the
+// options with which to expand the package in the copy, into the same target
+// directory as before so that the dependencies built there are used again. The
+// workspace is checked to be trusted first, as the copy is not recorded as
+// such.
+fn copy_workspace(opts: &Opts, config: &Config, dir: &Path) -> io::Result<Opts> {
+    trust::check(opts, config)?;
+    let manifest = project::locate_workspace(opts.manifest_path.as_ref())
+        .ok_or_else(|| io::Error::other("could not find the workspace to bisect in"))?;
+    let root = manifest.parent().unwrap_or_else(|| Path::new("/"));
+    let metadata = Metadata::load(opts)?;
+    let mut outside: Vec<PathBuf> = metadata::local_package_dirs(opts)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|package_dir| !package_dir.starts_with(root))
+        .collect();
+    // Those inside another come along with it.
+    outside.sort();
+    outside.dedup_by(|inner, outer| inner.starts_with(outer));
+    let mut base = root.to_path_buf();
+    for package_dir in &outside {
+        while !package_dir.starts_with(&base) {
+            if !base.pop() {
+                break;
+            }
+        }
+    }
+    let target_dir = match opts.target_dir {
+        Some(ref target_dir) => Some(target_dir.clone()),
+        None => metadata.target_directory().map(PathBuf::from),
+    };
+    let copied = |from: &Path| dir.join(from.strip_prefix(&base).unwrap_or(from));
+    for package_dir in &outside {
+        copy_dir(package_dir, &copied(package_dir), target_dir.as_deref())?;
+    }
+    let copied_root = copied(root);
+    // Unless it came along with a package it is in.
+    if !outside.iter().any(|package_dir| root.starts_with(package_dir)) {
+        copy_dir(root, &copied_root, target_dir.as_deref())?;
+    }
+
+    let mut opts = opts.clone();
+    opts.manifest_path = match opts.manifest_path {
+        Some(ref manifest_path) => {
+            let relative = Path::new(manifest_path)
+                .strip_prefix(root)
+                .map_err(|_| io::Error::other("the package is not inside its workspace"))?;
+            Some(copied_root.join(relative).into_os_string())
+        }
+        None => Some(copied_root.join("Cargo.toml").into_os_string()),
+    };
+    opts.target_dir = target_dir;
+    // Each version makes another expansion, none of them worth keeping.
+    opts.reuse = false;
+    Ok(opts)
+}
+
+// Copy a directory, less the target directories in it. Symlinks are copied as
+// symlinks, so that those within it lead to the copy and others to where they
+// did.
+fn copy_dir(from: &Path, to: &Path, target_dir: Option<&Path>) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            // Cargo marks its target directories with a CACHEDIR.TAG.
+            if path.join("CACHEDIR.TAG").exists() || Some(&*path) == target_dir {
+                continue;
+            }
+            copy_dir(&path, &to.join(&name), target_dir)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&path, &to.join(&name))?;
+        } else if file_type.is_file() {
+            fs::copy(&path, to.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+// Windows has separate symlinks for files and directories.
+#[cfg(windows)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    let link = fs::read_link(from)?;
+    if fs::metadata(from).is_ok_and(|metadata| metadata.is_dir()) {
+        std::os::windows::fs::symlink_dir(link, to)
+    } else {
+        std::os::windows::fs::symlink_file(link, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("1969-12-31"), Some(-1));
+        assert_eq!(parse_date("2000-03-01"), Some(11017));
+        assert_eq!(parse_date("2024-02-29"), Some(19782));
+        assert_eq!(parse_date("2000-02-29"), Some(11016));
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(-1), "1969-12-31");
+        assert_eq!(format_date(19782), "2024-02-29");
+    }
+
+    #[test]
+    fn round_trip() {
+        for days in (-700_000..800_000).step_by(97) {
+            assert_eq!(parse_date(&format_date(days)), Some(days));
+        }
+    }
+
+    #[test]
+    fn invalid_dates() {
+        for date in [
+            "2024-13-01",
+            "2024-00-10",
+            "2024-01-00",
+            "2024-01-32",
+            "2024-02-30",
+            "2024-02-31",
+            "2023-02-29",
+            "1900-02-29",
+            "2024-04-31",
+            "2024-01",
+            "yesterday",
+        ] {
+            assert_eq!(parse_date(date), None, "{}", date);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use config::{self, Config};
+use dry_run;
+use opts::Opts;
+use rewrite;
+use tmp::TmpDir;
+use trust;
+use tty;
+use version;
+use {cargo_command, ignore_cargo_err, is_progress, which, wrap_args};
+
+// Run the expansion once with everything captured, and print what a bug
+// report needs as Markdown: versions, config, the tools found, the command and
+// how it went, and with --minimize-around the reproducer.
+pub fn print(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let mut report = String::new();
+
+    report.push_str("### Environment\n\n");
+    report.push_str(&format!("- cargo-expand: {}\n", env!("CARGO_PKG_VERSION")));
+    report.push_str(&format!(
+        "- os: {} ({})\n",
+        env::consts::OS,
+        env::consts::ARCH
+    ));
+    let mut cargo = cargo_command(opts);
+    cargo.arg("--version");
+    report.push_str(&format!("- cargo: {}\n", first_line(cargo)));
+    let mut rustc = version::rustc_command(opts.toolchain.as_deref());
+    rustc.arg("--version");
+    report.push_str(&format!("- rustc: {}\n", first_line(rustc)));
+
+    let tools = [("rustfmt", &["rustfmt"][..], "--version")];
+    for &(name, probe, version) in &tools {
+        let found = match which(config, probe) {
+            Ok(Some(path)) => {
+                let mut cmd = Command::new(&path);
+                cmd.arg(version);
+                format!("`{}`, {}", path.to_string_lossy(), first_line(cmd))
+            }
+            Ok(None) => "not found".to_owned(),
+            Err(err) => err.to_string(),
+        };
+        report.push_str(&format!("- {}: {}\n", name, found));
+    }
+
+    report.push_str("\n### Config\n\n");
+    match config::path() {
+        Some(path) => match fs::read_to_string(&path) {
+            Ok(content) => {
+                report.push_str(&format!("`{}`:\n\n", path.display()));
+                report.push_str(&fence("toml", &content));
+            }
+            Err(_) => report.push_str(&format!("none at `{}`\n", path.display())),
+        },
+        None => report.push_str("none\n"),
+    }
+
+    let outdir = TmpDir::create(opts.keep_tmp)?;
+    let outfile = outdir.path().join("expanded");
+    let mut cmd = cargo_command(opts);
+    cmd.args(wrap_args(opts, Some(&outfile)));
+
+    let invocation: Vec<_> = env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--bug-report")
+        .collect();
+    let invocation: Vec<_> = invocation.iter().map(|arg| arg.to_string_lossy()).collect();
+    report.push_str("\n### Command\n\n");
+    report.push_str(&fence(
+        "console",
+        &format!(
+            "$ cargo {}\n{}",
+            invocation.join(" "),
+            dry_run::render(&cmd)
+        ),
+    ));
+
+    trust::check(opts, config)?;
+    let output = cmd.stdin(Stdio::null()).output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr: String = stderr
+        .lines()
+        .filter(|line| !is_progress(line) && !ignore_cargo_err(line))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    report.push_str("\n### Result\n\n");
+    let status = match output.status.code() {
+        Some(code) => format!("exit code {}", code),
+        None => "killed by a signal".to_owned(),
+    };
+    report.push_str(&format!("cargo finished with {}.\n", status));
+    if !stderr.is_empty() {
+        report.push('\n');
+        report.push_str(&fence("text", &tty::strip_ansi(&stderr)));
+    }
+
+    if output.status.success() && opts.minimize_around.is_some() {
+        report.push_str("\n### Reproducer\n\n");
+        match rewrite::apply(opts, &outfile).and_then(|()| fs::read_to_string(&outfile)) {
+            Ok(reproducer) => report.push_str(&fence("rust", &reproducer)),
+            Err(err) => report.push_str(&format!("could not minimize: {}\n", err)),
+        }
+    }
+
+    let stdout = io::stdout();
+    stdout.lock().write_all(report.as_bytes())?;
+    Ok(0)
+}
+
+// The first line a tool prints about itself, or why there was none.
+fn first_line(mut cmd: Command) -> String {
+    let output = match cmd.stdin(Stdio::null()).stderr(Stdio::null()).output() {
+        Ok(output) => output,
+        Err(err) => return format!("failed to run `{}`: {}", display(cmd.get_program()), err),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.lines().next() {
+        Some(line) if output.status.success() => line.trim().to_owned(),
+        _ => format!("`{}` exited unsuccessfully", display(cmd.get_program())),
+    }
+}
+
+fn display(program: &OsStr) -> String {
+    program.to_string_lossy().into_owned()
+}
+
+// A fenced code block, with a fence longer than any run of backticks inside.
+fn fence(lang: &str, content: &str) -> String {
+    let mut longest = 0;
+    let mut run = 0;
+    for ch in content.chars() {
+        run = if ch == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    let fence = "`".repeat(longest.max(2) + 1);
+    let newline = if content.ends_with('\n') { "" } else { "\n" };
+    format!("{}{}\n{}{}{}\n", fence, lang, content, newline, fence)
+}
@@ -0,0 +1,357 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime};
+
+use config::Config;
+use opts::Opts;
+use project;
+use reuse;
+use tmp;
+
+// Most that the workspace directories may take up together, unless `max-size`
+// under `[cache]` says otherwise. Those used least recently go first.
+const DEFAULT_MAX_SIZE: u64 = 10 << 30;
+
+// Adding up the size of the cache means reading every file in it, so it is
+// done at most this often.
+const TRIM_EVERY: Duration = Duration::from_secs(60 * 60);
+
+// What cargo expand keeps between runs for a workspace lives in a directory of
+// its own under the cache directory, named for the workspace and a hash of its
+// path, so that two worktrees of the same crate never see each other's state.
+// With `share-worktrees = true` under `[cache]`, worktrees of one git
+// repository hash the same and share it instead.
+pub fn workspace_dir(opts: &Opts, config: &Config) -> Option<PathBuf> {
+    let manifest = project::locate_workspace(opts.manifest_path.as_ref())?;
+    let root = manifest.parent()?.canonicalize().ok()?;
+    let shared = if flag(config, "share-worktrees") {
+        repository_key(&root)
+    } else {
+        None
+    };
+    let (name, key) = shared.unwrap_or_else(|| {
+        let name = root
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        (name, root.to_string_lossy().into_owned())
+    });
+    Some(tmp::cache_dir().join("workspaces").join(format!(
+        "{}-{:016x}",
+        name,
+        fnv1a(key.as_bytes())
+    )))
+}
+
+// With `target-dir = true` under `[cache]`, build expansions in a target
+// directory of the workspace's own there, rather than churning the one that
+// `cargo build` uses.
+pub fn configure(opts: &mut Opts, config: &Config) -> io::Result<()> {
+    let max_size = max_size(config)?;
+    let dir = if flag(config, "target-dir") {
+        workspace_dir(opts, config)
+    } else {
+        None
+    };
+    if let Some(ref dir) = dir {
+        if fs::create_dir_all(dir).is_ok() {
+            // The stamp's modification time is when the directory was last used.
+            let _ = fs::write(dir.join(".used"), "");
+        }
+    }
+    trim(max_size, dir.as_deref());
+    opts.target_dir = dir.map(|dir| dir.join("target"));
+    Ok(())
+}
+
+// Show the expansion of an unchanged crate kept from before rather than build
+// it again, unless `reuse = false` under `[cache]` says not to.
+pub fn reuse(config: &Config) -> bool {
+    config
+        .section("cache")
+        .and_then(|cache| cache.get("reuse"))
+        .and_then(|value| value.as_bool())
+        .unwrap_or(true)
+}
+
+// `cargo expand cache stats` lists what is in the cache and how big it is;
+// `cargo expand cache clean` empties it.
+pub fn command<I>(mut args: I) -> io::Result<i32>
+where
+    I: Iterator<Item = OsString>,
+{
+    let config = Config::load()?;
+    let max_size = max_size(&config)?;
+    let subcommand = args.next();
+    match subcommand.as_ref().and_then(|arg| arg.to_str()) {
+        Some("stats") => stats(max_size),
+        Some("clean") => clean(),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: cargo expand cache stats|clean",
+        )),
+    }
+}
+
+fn stats(max_size: u64) -> io::Result<i32> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "cache: {}", tmp::cache_dir().display())?;
+    let mut total = 0;
+    for workspace in workspaces() {
+        total += workspace.size;
+        let age = workspace.used.elapsed().unwrap_or_default();
+        let expansions = reuse::count(&workspace.path);
+        writeln!(
+            out,
+            "{:>10}  {}  used {}{}",
+            human_size(workspace.size),
+            workspace.name,
+            human_age(age),
+            match expansions {
+                0 => String::new(),
+                1 => ", 1 expansion kept".to_owned(),
+                n => format!(", {} expansions kept", n),
+            },
+        )?;
+    }
+    writeln!(
+        out,
+        "total {} of {} allowed",
+        human_size(total),
+        human_size(max_size),
+    )?;
+    let scratch = dir_size(&tmp::cache_dir().join("tmp"));
+    if scratch > 0 {
+        writeln!(out, "temporary files: {}", human_size(scratch))?;
+    }
+    Ok(0)
+}
+
+fn clean() -> io::Result<i32> {
+    let mut freed = 0;
+    for workspace in workspaces() {
+        fs::remove_dir_all(&workspace.path)?;
+        freed += workspace.size;
+    }
+    let scratch = tmp::cache_dir().join("tmp");
+    let before = dir_size(&scratch);
+    tmp::clean_stale(&scratch);
+    freed += before.saturating_sub(dir_size(&scratch));
+    let _ = writeln!(&mut io::stderr(), "removed {}", human_size(freed));
+    Ok(0)
+}
+
+// Remove the least recently used workspace directories, other than the one in
+// use, until the rest fit in `max_size`.
+fn trim(max_size: u64, current: Option<&Path>) {
+    let root = tmp::cache_dir().join("workspaces");
+    let stamp = root.join(".trimmed");
+    let recent = fs::metadata(&stamp)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age < TRIM_EVERY);
+    if recent || fs::write(&stamp, "").is_err() {
+        return;
+    }
+
+    let mut workspaces = workspaces();
+    let mut total: u64 = workspaces.iter().map(|workspace| workspace.size).sum();
+    workspaces.sort_by_key(|workspace| workspace.used);
+    for workspace in workspaces {
+        if total <= max_size {
+            break;
+        }
+        if Some(&*workspace.path) == current {
+            continue;
+        }
+        if fs::remove_dir_all(&workspace.path).is_ok() {
+            total -= workspace.size;
+            let _ = writeln!(
+                &mut io::stderr(),
+                "note: removed {} of cached build for {} to keep the cache under {}",
+                human_size(workspace.size),
+                workspace.name,
+                human_size(max_size),
+            );
+        }
+    }
+}
+
+struct Workspace {
+    path: PathBuf,
+    name: String,
+    size: u64,
+    used: SystemTime,
+}
+
+fn workspaces() -> Vec<Workspace> {
+    let entries = match fs::read_dir(tmp::cache_dir().join("workspaces")) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut workspaces = Vec::new();
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let used = fs::metadata(path.join(".used"))
+            .or_else(|_| entry.metadata())
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        workspaces.push(Workspace {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            size: dir_size(&path),
+            path,
+            used,
+        });
+    }
+    workspaces.sort_by(|a, b| a.name.cmp(&b.name));
+    workspaces
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let mut size = 0;
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(Result::ok) {
+            match entry.metadata() {
+                Ok(ref metadata) if metadata.is_dir() => dirs.push(entry.path()),
+                Ok(metadata) => size += metadata.len(),
+                Err(_) => {}
+            }
+        }
+    }
+    size
+}
+
+// `max-size` under `[cache]`: a number of bytes, or a string like `"500M"` or
+// `"10G"`.
+fn max_size(config: &Config) -> io::Result<u64> {
+    let value = match config
+        .section("cache")
+        .and_then(|cache| cache.get("max-size"))
+    {
+        Some(value) => value,
+        None => return Ok(DEFAULT_MAX_SIZE),
+    };
+    let size = match value.as_integer() {
+        Some(bytes) if bytes >= 0 => Some(bytes as u64),
+        Some(_) => None,
+        None => value.as_str().and_then(parse_size),
+    };
+    size.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "invalid max-size `{}` under [cache]: expected bytes or a size like \"10G\"",
+                value,
+            ),
+        )
+    })
+}
+
+fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let s = s
+        .strip_suffix("iB")
+        .or_else(|| s.strip_suffix('B'))
+        .unwrap_or(s);
+    let (number, shift) = match s.chars().last()?.to_ascii_uppercase() {
+        'K' => (&s[..s.len() - 1], 10),
+        'M' => (&s[..s.len() - 1], 20),
+        'G' => (&s[..s.len() - 1], 30),
+        'T' => (&s[..s.len() - 1], 40),
+        _ => (s, 0),
+    };
+    let number: f64 = number.trim().parse().ok()?;
+    if number < 0.0 {
+        return None;
+    }
+    Some((number * (1u64 << shift) as f64) as u64)
+}
+
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+fn human_age(age: Duration) -> String {
+    let minutes = age.as_secs() / 60;
+    if minutes < 1 {
+        "just now".to_owned()
+    } else if minutes < 60 {
+        format!("{} min ago", minutes)
+    } else if minutes < 48 * 60 {
+        format!("{} h ago", minutes / 60)
+    } else {
+        format!("{} days ago", minutes / 60 / 24)
+    }
+}
+
+fn flag(config: &Config, key: &str) -> bool {
+    config
+        .section("cache")
+        .and_then(|cache| cache.get(key))
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false)
+}
+
+// A name and key that are the same for every worktree of a git repository: the
+// name of the main worktree, and the common git directory together with where
+// the workspace is within the worktree.
+fn repository_key(root: &Path) -> Option<(String, String)> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args([
+            "rev-parse",
+            "--path-format=absolute",
+            "--git-common-dir",
+            "--show-toplevel",
+        ])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let mut lines = stdout.lines();
+    let common_dir = lines.next()?;
+    let toplevel = Path::new(lines.next()?).canonicalize().ok()?;
+    let within = root.strip_prefix(&toplevel).ok()?;
+    let name = Path::new(common_dir)
+        .parent()?
+        .file_name()?
+        .to_string_lossy()
+        .into_owned();
+    Some((name, format!("{}\n{}", common_dir, within.display())))
+}
+
+// A hash that stays the same from one build of cargo expand to the next, unlike
+// std's.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
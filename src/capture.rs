@@ -0,0 +1,68 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use config::Config;
+use opts::{Formatter, Opts};
+use pretty;
+use reuse;
+use rewrite;
+use tmp::TmpDir;
+use {cargo_command, wrap_args};
+use {rustfmt_args, which_rustfmt};
+
+// Run the expansion and hand back the formatted code instead of printing it,
+// for modes that look at the result rather than show it. None if the build
+// failed, which cargo will have explained on stderr.
+pub fn expand(opts: &Opts, config: &Config) -> io::Result<Option<String>> {
+    build(opts, config, true)
+}
+
+// The same, without cargo expand's own edits, for modes that need the code as
+// rustc printed it.
+pub fn unedited(opts: &Opts, config: &Config) -> io::Result<Option<String>> {
+    build(opts, config, false)
+}
+
+fn build(opts: &Opts, config: &Config, edit: bool) -> io::Result<Option<String>> {
+    let outdir = TmpDir::create(opts.keep_tmp)?;
+    let outfile = outdir.path().join("expanded");
+
+    let mut cmd = cargo_command(opts);
+    cmd.args(wrap_args(opts, Some(&outfile)));
+    if reuse::build(opts, config, &mut cmd, &outfile)? != 0 {
+        return Ok(None);
+    }
+
+    if edit {
+        rewrite::apply(opts, &outfile)?;
+    }
+    format(opts, config, &outfile).map(Some)
+}
+
+// The code in the file at `path`, formatted with rustfmt if it is available
+// and succeeds, or else with the built-in formatter unless --formatter none.
+pub fn format(opts: &Opts, config: &Config, path: &Path) -> io::Result<String> {
+    let fmt = match which_rustfmt(opts, config)? {
+        Some(fmt) => fmt,
+        None => {
+            if opts.formatter != Some(Formatter::None) {
+                pretty::format_file(opts, path)?;
+            }
+            return fs::read_to_string(path);
+        }
+    };
+    let code = fs::read_to_string(path)?;
+    let output = Command::new(fmt)
+        .args(rustfmt_args(opts))
+        .stdin(fs::File::open(path)?)
+        .stderr(Stdio::null())
+        .output()?;
+    if output.status.success() {
+        if let Ok(formatted) = String::from_utf8(output.stdout) {
+            return Ok(formatted);
+        }
+    }
+    Ok(code)
+}
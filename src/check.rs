@@ -0,0 +1,251 @@
+use std::fs;
+use std::io::{self, Write};
+
+use serde_json::Value;
+use toml::{Table, Value as Toml};
+
+use cache;
+use capture;
+use config::Config;
+use metadata::Metadata;
+use opts::Opts;
+use project;
+use tmp::TmpDir;
+use trust;
+use cargo_command;
+
+// --check: whether the expansion as printed is code that compiles. Printing
+// loses hygiene, and the standard library's macros expand to its unstable
+// internals, so it often is not. The expansion is written out, as rustc
+// printed it without any of cargo expand's own edits, as the source of a crate
+// with the package's name, edition, features and dependencies, which is then
+// run through `cargo check`. A crate that fails is kept to look into.
+pub fn run(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let metadata = Metadata::load(opts)?;
+    let package = opts
+        .manifest_path
+        .as_ref()
+        .and_then(|manifest_path| metadata.package(manifest_path))
+        .ok_or_else(|| io::Error::other("cannot find the package to check the expansion of"))?;
+
+    let expanded = match capture::unedited(opts, config)? {
+        Some(expanded) => expanded,
+        None => return Ok(1),
+    };
+
+    let mut outdir = TmpDir::create(opts.keep_tmp)?;
+    let dir = outdir.path().join("crate");
+    let kind = target_kind(opts, package);
+    fs::create_dir_all(dir.join("src"))?;
+    fs::write(dir.join("Cargo.toml"), manifest(package, &kind).to_string())?;
+    let source = if kind == "lib" || kind == "proc-macro" {
+        "lib.rs"
+    } else {
+        "main.rs"
+    };
+    fs::write(
+        dir.join("src").join(source),
+        format!("#![allow(warnings)]\n{}", expanded),
+    )?;
+    // The same versions of the dependencies, as far as they still fit.
+    if let Some(root) = project::locate_workspace(opts.manifest_path.as_ref()) {
+        if let Some(lockfile) = root.parent().map(|root| root.join("Cargo.lock")) {
+            let _ = fs::copy(lockfile, dir.join("Cargo.lock"));
+        }
+    }
+
+    // The dependencies stay built from one check to the next, in a target
+    // directory of the check's own.
+    let mut check_opts = opts.clone();
+    if let Some(dir) = cache::workspace_dir(opts, config) {
+        check_opts.target_dir = Some(dir.join("check"));
+    }
+    let mut cmd = cargo_command(&check_opts);
+    cmd.arg("check");
+    cmd.arg("--manifest-path").arg(dir.join("Cargo.toml"));
+    let mut it = opts.cargo.iter();
+    while let Some(arg) = it.next() {
+        let name = arg.to_string_lossy();
+        let with_value = name == "--features" || name == "-F" || name == "--target";
+        let flag = [
+            "--offline",
+            "--frozen",
+            "--all-features",
+            "--no-default-features",
+        ]
+        .contains(&&*name)
+            || name.starts_with("--features=")
+            || name.starts_with("--target=")
+            || (name.starts_with("-F") && !with_value);
+        if with_value {
+            cmd.arg(arg).args(it.next());
+        } else if flag {
+            cmd.arg(arg);
+        }
+    }
+
+    trust::check(opts, config)?;
+    let status = cmd.status()?;
+    let mut stderr = io::stderr();
+    if status.success() {
+        let _ = writeln!(stderr, "note: the expanded code compiles");
+        return Ok(0);
+    }
+    outdir.keep();
+    let _ = writeln!(
+        stderr,
+        "error: the expanded code does not compile; the crate it was checked as is in {}",
+        dir.display(),
+    );
+    Ok(1)
+}
+
+// The kind of target that is expanded: `lib`, `proc-macro`, `bin`, `example`,
+// `test` or `bench`.
+fn target_kind(opts: &Opts, package: &Value) -> String {
+    if let Some((kind, _)) = opts.selected_target() {
+        if kind != "lib" {
+            return kind;
+        }
+    }
+    let targets = package["targets"].as_array().map_or(&[][..], Vec::as_slice);
+    let kinds = targets
+        .iter()
+        .filter_map(|target| target["kind"].as_array())
+        .flatten()
+        .filter_map(Value::as_str);
+    let mut kind = "bin";
+    for target_kind in kinds {
+        match target_kind {
+            "proc-macro" => return target_kind.to_owned(),
+            "lib" | "rlib" | "dylib" | "cdylib" | "staticlib" => kind = "lib",
+            _ => {}
+        }
+    }
+    kind.to_owned()
+}
+
+// A Cargo.toml for the package, from what `cargo metadata` says of it, with
+// its build script left out since what it generated is in the expansion.
+fn manifest(package: &Value, kind: &str) -> Table {
+    let mut manifest = Table::new();
+
+    let mut section = Table::new();
+    for key in ["name", "version", "edition"] {
+        if let Some(value) = package[key].as_str() {
+            section.insert(key.to_owned(), value.into());
+        }
+    }
+    section.insert("publish".to_owned(), false.into());
+    section.insert("build".to_owned(), false.into());
+    manifest.insert("package".to_owned(), section.into());
+    if kind == "proc-macro" {
+        let mut lib = Table::new();
+        lib.insert("proc-macro".to_owned(), true.into());
+        manifest.insert("lib".to_owned(), lib.into());
+    }
+    // Not part of whatever workspace the temporary directory is in.
+    manifest.insert("workspace".to_owned(), Table::new().into());
+
+    if let Some(features) = package["features"].as_object() {
+        let mut section = Table::new();
+        for (name, enables) in features {
+            let enables: Vec<Toml> = enables
+                .as_array()
+                .map_or(&[][..], Vec::as_slice)
+                .iter()
+                .filter_map(Value::as_str)
+                .map(Toml::from)
+                .collect();
+            section.insert(name.clone(), enables.into());
+        }
+        manifest.insert("features".to_owned(), section.into());
+    }
+
+    // Tests, benchmarks and examples get the dev-dependencies as well. The
+    // build dependencies were only for the build script.
+    let wanted = |dep_kind: Option<&str>| match dep_kind {
+        None => true,
+        Some("dev") => kind == "test" || kind == "bench" || kind == "example",
+        _ => false,
+    };
+    let mut dependencies = Table::new();
+    let mut platforms = Table::new();
+    for dependency in package["dependencies"]
+        .as_array()
+        .map_or(&[][..], Vec::as_slice)
+    {
+        if !wanted(dependency["kind"].as_str()) {
+            continue;
+        }
+        let name = dependency["name"].as_str().unwrap_or_default().to_owned();
+        let (key, spec) = match dependency["rename"].as_str() {
+            Some(rename) => {
+                let mut spec = dependency_spec(dependency);
+                spec.insert("package".to_owned(), name.into());
+                (rename.to_owned(), spec)
+            }
+            None => (name, dependency_spec(dependency)),
+        };
+        let section = match dependency["target"].as_str() {
+            Some(platform) => platforms
+                .entry(platform.to_owned())
+                .or_insert_with(|| {
+                    let mut table = Table::new();
+                    table.insert("dependencies".to_owned(), Table::new().into());
+                    table.into()
+                })
+                .get_mut("dependencies")
+                .and_then(Toml::as_table_mut),
+            None => Some(&mut dependencies),
+        };
+        if let Some(section) = section {
+            section.insert(key, spec.into());
+        }
+    }
+    manifest.insert("dependencies".to_owned(), dependencies.into());
+    if !platforms.is_empty() {
+        manifest.insert("target".to_owned(), platforms.into());
+    }
+    manifest
+}
+
+fn dependency_spec(dependency: &Value) -> Table {
+    let mut spec = Table::new();
+    if let Some(req) = dependency["req"].as_str() {
+        spec.insert("version".to_owned(), req.into());
+    }
+    if let Some(path) = dependency["path"].as_str() {
+        spec.insert("path".to_owned(), path.into());
+    } else if let Some(source) = dependency["source"].as_str() {
+        if let Some(git) = source.strip_prefix("git+") {
+            let git = git.split('#').next().unwrap_or(git);
+            let mut parts = git.splitn(2, '?');
+            spec.insert("git".to_owned(), parts.next().unwrap_or(git).into());
+            // `?branch=main`, `?tag=v1` or `?rev=abc`.
+            if let Some((key, value)) = parts.next().and_then(|query| query.split_once('=')) {
+                spec.insert(key.to_owned(), value.into());
+            }
+        }
+    }
+    if let Some(registry) = dependency["registry"].as_str() {
+        spec.insert("registry-index".to_owned(), registry.into());
+    }
+    if dependency["optional"].as_bool() == Some(true) {
+        spec.insert("optional".to_owned(), true.into());
+    }
+    if dependency["uses_default_features"].as_bool() == Some(false) {
+        spec.insert("default-features".to_owned(), false.into());
+    }
+    let features: Vec<Toml> = dependency["features"]
+        .as_array()
+        .map_or(&[][..], Vec::as_slice)
+        .iter()
+        .filter_map(Value::as_str)
+        .map(Toml::from)
+        .collect();
+    if !features.is_empty() {
+        spec.insert("features".to_owned(), features.into());
+    }
+    spec
+}
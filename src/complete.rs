@@ -0,0 +1,124 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+
+#[cfg(windows)]
+use std::os::windows::io::IntoRawHandle;
+
+#[cfg(unix)]
+use libc;
+#[cfg(windows)]
+use windows_sys::Win32::System::Console::{SetStdHandle, STD_ERROR_HANDLE};
+use syn;
+
+use cache;
+use capture;
+use config::Config;
+use opts::Opts;
+use syntax;
+use watch;
+
+// --complete-items PREFIX, for shell completion scripts rather than people:
+// print the item paths in the expansion that start with PREFIX, one per line,
+// each only up to the end of the next path segment. The paths are kept in the
+// workspace's cache directory until a file in the package changes, so that
+// only the first completion has to wait for a build.
+pub fn run(opts: &Opts, config: &Config, prefix: &str) -> io::Result<i32> {
+    // The shell is in the middle of redrawing the command line.
+    quiet_stderr();
+
+    let paths = match paths(opts, config)? {
+        Some(paths) => paths,
+        None => return Ok(1),
+    };
+    let mut completions = BTreeSet::new();
+    for path in &paths {
+        let rest = match path.strip_prefix(prefix) {
+            Some(rest) => rest,
+            None => continue,
+        };
+        // Complete `http::` to `http::Handlers` and not every method in it,
+        // though a prefix ending partway into a segment completes it.
+        let skip = if rest.starts_with("::") { 2 } else { 0 };
+        let end = rest[skip..].find("::").map_or(rest.len(), |i| i + skip);
+        completions.insert(format!("{}{}", prefix, &rest[..end]));
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for completion in completions {
+        writeln!(out, "{}", completion)?;
+    }
+    Ok(0)
+}
+
+// The item paths of the expansion, from the cache if it is newer than every
+// file in the package.
+fn paths(opts: &Opts, config: &Config) -> io::Result<Option<Vec<String>>> {
+    let key = opts
+        .cargo
+        .iter()
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let cached = cache::workspace_dir(opts, config)
+        .map(|dir| dir.join(format!("items-{:016x}.txt", cache::fnv1a(key.as_bytes()))));
+
+    if let Some(ref cached) = cached {
+        let modified = fs::metadata(cached).and_then(|metadata| metadata.modified());
+        let newest = watch::stamp(&watch::package_dir(opts)).into_values().max();
+        if let (Ok(modified), Some(newest)) = (modified, newest) {
+            if modified >= newest {
+                if let Ok(content) = fs::read_to_string(cached) {
+                    return Ok(Some(content.lines().map(str::to_owned).collect()));
+                }
+            }
+        }
+    }
+
+    let code = match capture::expand(opts, config)? {
+        Some(code) => code,
+        None => return Ok(None),
+    };
+    let file = match syn::parse_file(&code) {
+        Ok(file) => file,
+        Err(_) => return Ok(None),
+    };
+    let paths: Vec<String> = syntax::item_paths(&file.items, "").into_iter().collect();
+
+    if let Some(ref cached) = cached {
+        let mut content = String::new();
+        for path in &paths {
+            content.push_str(path);
+            content.push('\n');
+        }
+        let _ = cached
+            .parent()
+            .map(fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|()| fs::write(cached, content));
+    }
+    Ok(Some(paths))
+}
+
+#[cfg(unix)]
+fn quiet_stderr() {
+    unsafe {
+        let null = libc::open(
+            b"/dev/null\0".as_ptr() as *const libc::c_char,
+            libc::O_WRONLY,
+        );
+        if null >= 0 {
+            libc::dup2(null, libc::STDERR_FILENO);
+            libc::close(null);
+        }
+    }
+}
+
+// What is spawned from here on inherits the handle too.
+#[cfg(windows)]
+fn quiet_stderr() {
+    if let Ok(null) = fs::OpenOptions::new().write(true).open("NUL") {
+        unsafe { SetStdHandle(STD_ERROR_HANDLE, null.into_raw_handle()) };
+    }
+}
@@ -0,0 +1,135 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use toml::{Table, Value};
+
+// User configuration, read from `config.toml` in the cargo-expand directory
+// under the platform's config dir. A missing file is the same as an empty one.
+pub struct Config {
+    table: Table,
+}
+
+impl Config {
+    pub fn load() -> io::Result<Config> {
+        let path = match path() {
+            Some(path) => path,
+            None => return Ok(Config { table: Table::new() }),
+        };
+
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+
+        match content.parse() {
+            Ok(table) => Ok(Config { table }),
+            Err(err) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("failed to parse {}: {}", path.display(), err),
+            )),
+        }
+    }
+
+    // A config as written in `content`, for tests.
+    #[cfg(test)]
+    pub fn from_toml(content: &str) -> Config {
+        Config {
+            table: content.parse().unwrap(),
+        }
+    }
+
+    // The executable pinned for the given tool, if any. A `[tools.<os>]` entry
+    // for the current target_os wins over `[tools.unix]` or `[tools.windows]`,
+    // which win over plain `[tools]`.
+    pub fn tool(&self, name: &str) -> Option<&str> {
+        let tools = self.table.get("tools")?;
+        let family = if cfg!(windows) { "windows" } else { "unix" };
+        [env::consts::OS, family]
+            .iter()
+            .filter_map(|os| tools.get(os))
+            .chain(Some(tools))
+            .filter_map(|table| table.get(name))
+            .filter_map(Value::as_str)
+            .next()
+    }
+
+    // A table like `[sccache]`, if the config has one.
+    pub fn section(&self, name: &str) -> Option<&Table> {
+        self.table.get(name).and_then(Value::as_table)
+    }
+
+    // The command line options making up a named `[pipeline.NAME]`, which has
+    // an entry per option, minus its leading `--`. `true` turns a flag on, and
+    // an array gives an option more than once.
+    pub fn pipeline(&self, name: &str) -> io::Result<Vec<OsString>> {
+        let table = self
+            .table
+            .get("pipeline")
+            .and_then(|pipelines| pipelines.get(name))
+            .and_then(Value::as_table)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("no pipeline named `{}` in the config", name),
+                )
+            })?;
+        options(table, &format!("pipeline `{}`", name))
+    }
+
+    // The options under `[defaults]`, written like a pipeline's, that every
+    // run starts out with.
+    pub fn defaults(&self) -> io::Result<Vec<OsString>> {
+        match self.section("defaults") {
+            Some(table) => options(table, "[defaults]"),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+// Command line options from a table with an entry per option, minus its
+// leading `--`, as in a pipeline. `what` names the table in errors.
+pub fn options(table: &Table, what: &str) -> io::Result<Vec<OsString>> {
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let values = match *value {
+            Value::Array(ref values) => values.iter().collect(),
+            ref value => vec![value],
+        };
+        for value in values {
+            let arg = match *value {
+                Value::Boolean(true) => format!("--{}", key),
+                Value::Boolean(false) => continue,
+                Value::String(ref value) => format!("--{}={}", key, value),
+                Value::Integer(value) => format!("--{}={}", key, value),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unsupported value for `{}` in {}", key, what),
+                    ))
+                }
+            };
+            args.push(arg.into());
+        }
+    }
+    Ok(args)
+}
+
+pub fn path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("CARGO_EXPAND_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    base.map(|dir| dir.join("cargo-expand").join("config.toml"))
+}
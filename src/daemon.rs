@@ -0,0 +1,590 @@
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{self, Value};
+
+use api::{self, Options};
+use cache;
+use config::Config;
+use opts::Opts;
+use print_checksum;
+use schema;
+use trust;
+use write_code;
+
+const DEFAULT_JOBS: u64 = 2;
+const DEFAULT_QUEUE: u64 = 16;
+
+// `cargo expand daemon` keeps one process serving the expansions of a
+// workspace, so that editors share its warm build rather than each starting
+// cargo from cold. It listens on a unix socket in the workspace's cache
+// directory, or on Windows a named pipe named after it, taking requests and giving replies as one JSON object per line:
+//
+//   {"command": "expand", "args": ["--lib", "http::Handlers"], "manifest_path": "..."}
+//   {"command": "query"}
+//   {"command": "stats"}
+//   {"command": "shutdown"}
+//
+// Every reply has `"schema"` and `"ok"`, and an `"error"` if not ok. At most
+// `jobs` expansions under `[daemon]` in the config run at once, and beyond
+// `queue` more waiting a request is turned away as busy. Options given to
+// `cargo expand daemon`, like --offline, go with every expansion.
+// `cargo expand daemon query|stats|stop` send the other commands.
+pub fn command(args: &[OsString]) -> io::Result<i32> {
+    // `cargo-expand expand daemon [SUBCOMMAND] [OPTIONS]`.
+    let (subcommand, rest) = match args.get(3).and_then(|arg| arg.to_str()) {
+        Some(subcommand) if !subcommand.starts_with('-') => (Some(subcommand), &args[4..]),
+        _ => (None, &args[3..]),
+    };
+    let config = Config::load()?;
+    let opts = Opts::parse(args[..2].iter().chain(rest).cloned(), &config)?;
+    let socket = socket_path(&opts, &config)?;
+
+    let command = match subcommand {
+        None => {
+            trust::check(&opts, &config)?;
+            return serve(&opts, &config, &socket, rest);
+        }
+        Some("query") => "query",
+        Some("stats") => "stats",
+        Some("stop") => "shutdown",
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "usage: cargo expand daemon [query|stats|stop]",
+            ));
+        }
+    };
+    let mut reply = request(&socket, &json!({ "command": command }))?;
+    if let Value::Object(ref mut fields) = reply {
+        fields.remove("ok");
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if command == "shutdown" {
+        writeln!(out, "stopped the daemon at {}", socket.display())?;
+    } else {
+        writeln!(out, "{}", serde_json::to_string_pretty(&reply).unwrap())?;
+    }
+    Ok(0)
+}
+
+// --connect: have the workspace's daemon expand the crate and print the code
+// it sends back, as if expanded here.
+pub fn connect(opts: &Opts, args: &[OsString]) -> io::Result<i32> {
+    let config = Config::load()?;
+    // Asked here, where there is someone to ask, rather than by the daemon.
+    trust::check(opts, &config)?;
+    let socket = socket_path(opts, &config)?;
+    let forwarded: Vec<String> = args[2..]
+        .iter()
+        .filter(|arg| *arg != "--connect")
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    // The daemon runs elsewhere, so the package is given by its manifest
+    // rather than left to the daemon's working directory.
+    let manifest_path = opts
+        .manifest_path
+        .as_ref()
+        .map(|path| path.to_string_lossy().into_owned());
+    let reply = request(
+        &socket,
+        &json!({
+            "command": "expand",
+            "args": forwarded,
+            "manifest_path": manifest_path,
+        }),
+    )?;
+
+    for diagnostic in reply["diagnostics"].as_array().into_iter().flatten() {
+        if let Some(rendered) = diagnostic["rendered"].as_str() {
+            let _ = write!(&mut io::stderr(), "{}", rendered);
+        }
+    }
+    let code = reply["code"].as_str().unwrap_or_default();
+    let stdout = io::stdout();
+    match write_code(opts, code.as_bytes(), &mut stdout.lock()) {
+        Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(0),
+        Err(err) => return Err(err),
+        Ok(()) => {}
+    }
+    if opts.checksum {
+        print_checksum(opts, reply["checksum"].as_str());
+    }
+    Ok(0)
+}
+
+// Where the daemon of the workspace listens.
+fn socket_path(opts: &Opts, config: &Config) -> io::Result<PathBuf> {
+    match cache::workspace_dir(opts, config) {
+        Some(dir) => Ok(transport::address(&dir.join("daemon.sock"))),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "cannot find the workspace for the daemon",
+        )),
+    }
+}
+
+// Send one request to the daemon and return its reply, or its error as ours.
+fn request(socket: &Path, request: &Value) -> io::Result<Value> {
+    let mut stream = transport::connect(socket).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!(
+                "no daemon is serving this workspace at {}; start one with `cargo expand daemon`",
+                socket.display(),
+            ),
+        )
+    })?;
+    writeln!(stream, "{}", request)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let reply: Value = serde_json::from_str(&line).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid reply from the daemon: {}", err),
+        )
+    })?;
+    if reply["ok"] == true {
+        Ok(reply)
+    } else {
+        let error = reply["error"].as_str().unwrap_or("the daemon failed");
+        Err(io::Error::other(error.to_owned()))
+    }
+}
+
+struct Daemon {
+    manifest_path: Option<PathBuf>,
+    // What the daemon was started with, like --offline, ahead of what each
+    // request asks for.
+    args: Vec<OsString>,
+    socket: PathBuf,
+    jobs: u64,
+    queue: u64,
+    started: Instant,
+    counts: Mutex<Counts>,
+    // Signaled as an expansion finishes, for those waiting for a turn and
+    // for shutting down once none is left.
+    finished: Condvar,
+}
+
+#[derive(Default)]
+struct Counts {
+    running: u64,
+    waiting: u64,
+    expansions: u64,
+    failed: u64,
+    busy: u64,
+    time: Duration,
+    stopping: bool,
+}
+
+fn serve(opts: &Opts, config: &Config, socket: &Path, args: &[OsString]) -> io::Result<i32> {
+    if transport::connect(socket).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!(
+                "a daemon is already serving this workspace at {}",
+                socket.display(),
+            ),
+        ));
+    }
+    let mut listener = transport::Listener::bind(socket).map_err(|err| {
+        let msg = format!("failed to listen at {}: {}", socket.display(), err);
+        io::Error::new(err.kind(), msg)
+    })?;
+
+    let daemon = Arc::new(Daemon {
+        manifest_path: opts.manifest_path.as_ref().map(PathBuf::from),
+        args: args.to_vec(),
+        socket: socket.to_path_buf(),
+        jobs: limit(config, "jobs", DEFAULT_JOBS)?.max(1),
+        queue: limit(config, "queue", DEFAULT_QUEUE)?,
+        started: Instant::now(),
+        counts: Mutex::new(Counts::default()),
+        finished: Condvar::new(),
+    });
+    let _ = writeln!(
+        &mut io::stderr(),
+        "note: serving expansions at {}; stop with `cargo expand daemon stop`",
+        socket.display(),
+    );
+
+    loop {
+        let stream = listener.accept();
+        if daemon.counts.lock().unwrap().stopping {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let daemon = Arc::clone(&daemon);
+        thread::spawn(move || {
+            let _ = handle(&daemon, stream);
+        });
+    }
+
+    // Let the expansions under way finish and reply.
+    let mut counts = daemon.counts.lock().unwrap();
+    while counts.running > 0 || counts.waiting > 0 {
+        counts = daemon.finished.wait(counts).unwrap();
+    }
+    drop(listener);
+    Ok(0)
+}
+
+// Answer the requests of one connection, one line each, until it closes.
+fn handle(daemon: &Daemon, stream: transport::Stream) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => respond(daemon, &request),
+            Err(err) => failure(format!("invalid request: {}", err)),
+        };
+        writeln!(writer, "{}", reply)?;
+        if daemon.counts.lock().unwrap().stopping {
+            // Wake the listener, which is waiting for a connection.
+            let _ = transport::connect(&daemon.socket);
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn respond(daemon: &Daemon, request: &Value) -> Value {
+    match request["command"].as_str() {
+        Some("expand") => expand(daemon, request),
+        Some("query") => {
+            let counts = daemon.counts.lock().unwrap();
+            success(json!({
+                "pid": process::id(),
+                "version": env!("CARGO_PKG_VERSION"),
+                "manifest_path": daemon.manifest_path,
+                "jobs": daemon.jobs,
+                "queue": daemon.queue,
+                "running": counts.running,
+                "waiting": counts.waiting,
+                "uptime_secs": daemon.started.elapsed().as_secs(),
+            }))
+        }
+        Some("stats") => {
+            let counts = daemon.counts.lock().unwrap();
+            success(json!({
+                "expansions": counts.expansions,
+                "failed": counts.failed,
+                "busy": counts.busy,
+                "expansion_ms": counts.time.as_millis() as u64,
+            }))
+        }
+        Some("shutdown") => {
+            daemon.counts.lock().unwrap().stopping = true;
+            success(json!({}))
+        }
+        _ => failure(format!(
+            "unknown command {}; expected `expand`, `query`, `stats` or `shutdown`",
+            request["command"],
+        )),
+    }
+}
+
+fn expand(daemon: &Daemon, request: &Value) -> Value {
+    let manifest_path = request["manifest_path"]
+        .as_str()
+        .map(PathBuf::from)
+        .or_else(|| daemon.manifest_path.clone());
+    // Whatever is at the other end of the socket picks the package, which is
+    // built only if already trusted, with no one here to ask. A client cannot
+    // trust it with --trust; `--connect` asks on its own terminal instead.
+    let args: Vec<OsString> = request["args"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .filter(|arg| *arg != "--trust")
+        .map(OsString::from)
+        .collect();
+    let trusted = Config::load().and_then(|config| {
+        let manifest = manifest_path.clone().map(PathBuf::into_os_string);
+        trust::check_workspace(manifest.as_ref(), false, &config, false)
+    });
+    if let Err(err) = trusted {
+        return failure(err.to_string());
+    }
+
+    {
+        let mut counts = daemon.counts.lock().unwrap();
+        if counts.stopping {
+            return failure("the daemon is stopping".to_owned());
+        }
+        if counts.running >= daemon.jobs && counts.waiting >= daemon.queue {
+            counts.busy += 1;
+            return failure(format!(
+                "the daemon is busy, with {} expansions running and {} waiting",
+                counts.running, counts.waiting,
+            ));
+        }
+        counts.waiting += 1;
+        while counts.running >= daemon.jobs {
+            counts = daemon.finished.wait(counts).unwrap();
+        }
+        counts.waiting -= 1;
+        counts.running += 1;
+    }
+
+    let options = Options {
+        manifest_path,
+        args: daemon.args.iter().cloned().chain(args).collect(),
+        ..Options::default()
+    };
+    let start = Instant::now();
+    let result = api::expand(options);
+
+    let mut counts = daemon.counts.lock().unwrap();
+    counts.running -= 1;
+    counts.time += start.elapsed();
+    daemon.finished.notify_all();
+    match result {
+        Ok(expansion) => {
+            counts.expansions += 1;
+            let diagnostics: Vec<Value> = expansion
+                .diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    json!({
+                        "level": diagnostic.level,
+                        "message": diagnostic.message,
+                        "rendered": diagnostic.rendered,
+                    })
+                })
+                .collect();
+            success(json!({
+                "code": expansion.code,
+                "checksum": expansion.checksum,
+                "target": expansion.target,
+                "diagnostics": diagnostics,
+            }))
+        }
+        Err(err) => {
+            counts.failed += 1;
+            failure(err.to_string())
+        }
+    }
+}
+
+fn success(mut fields: Value) -> Value {
+    fields["schema"] = json!(schema::VERSION);
+    fields["ok"] = json!(true);
+    fields
+}
+
+fn failure(error: String) -> Value {
+    json!({ "schema": schema::VERSION, "ok": false, "error": error })
+}
+
+fn limit(config: &Config, key: &str, default: u64) -> io::Result<u64> {
+    let value = match config.section("daemon").and_then(|daemon| daemon.get(key)) {
+        Some(value) => value,
+        None => return Ok(default),
+    };
+    match value.as_integer() {
+        Some(n) if n >= 0 => Ok(n as u64),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "invalid {} `{}` under [daemon]: expected a number",
+                key, value
+            ),
+        )),
+    }
+}
+
+#[cfg(unix)]
+mod transport {
+    use std::fs;
+    use std::io;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+
+    use interrupt;
+
+    pub type Stream = UnixStream;
+
+    pub fn address(socket: &Path) -> PathBuf {
+        socket.to_path_buf()
+    }
+
+    pub fn connect(socket: &Path) -> io::Result<Stream> {
+        UnixStream::connect(socket)
+    }
+
+    pub struct Listener {
+        listener: UnixListener,
+        socket: PathBuf,
+    }
+
+    impl Listener {
+        pub fn bind(socket: &Path) -> io::Result<Listener> {
+            // Left behind by a daemon that was killed.
+            let _ = fs::remove_file(socket);
+            if let Some(dir) = socket.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let listener = UnixListener::bind(socket)?;
+            // Gone again however the daemon stops.
+            interrupt::track_restore(socket.to_path_buf(), None);
+            Ok(Listener {
+                listener,
+                socket: socket.to_path_buf(),
+            })
+        }
+
+        pub fn accept(&mut self) -> io::Result<Stream> {
+            self.listener.accept().map(|(stream, _)| stream)
+        }
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.socket);
+            interrupt::untrack_restore(&self.socket);
+        }
+    }
+}
+
+// A named pipe, one instance per connection, each made as the one before it
+// is connected to. Only processes on this machine can connect, and only
+// those of the same user can write to it.
+#[cfg(windows)]
+mod transport {
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::iter;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+    use std::path::{Path, PathBuf};
+    use std::ptr;
+
+    use windows_sys::Win32::Foundation::{
+        ERROR_PIPE_BUSY, ERROR_PIPE_CONNECTED, INVALID_HANDLE_VALUE,
+    };
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX,
+    };
+    use windows_sys::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, WaitNamedPipeW, NMPWAIT_USE_DEFAULT_WAIT,
+        PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES,
+        PIPE_WAIT,
+    };
+
+    use sha256::Sha256;
+
+    const BUFFER: u32 = 64 * 1024;
+
+    pub type Stream = File;
+
+    // The pipe for a socket path, named after it since pipes live in a
+    // namespace of their own.
+    pub fn address(socket: &Path) -> PathBuf {
+        let mut hash = Sha256::new();
+        hash.update(socket.to_string_lossy().as_bytes());
+        let hash = hash.finish();
+        PathBuf::from(format!(r"\\.\pipe\cargo-expand-{}", &hash[..32]))
+    }
+
+    pub fn connect(pipe: &Path) -> io::Result<Stream> {
+        loop {
+            match OpenOptions::new().read(true).write(true).open(pipe) {
+                Err(ref err) if err.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => {
+                    // Every instance is taken until the daemon makes the next.
+                    if unsafe { WaitNamedPipeW(wide(pipe).as_ptr(), NMPWAIT_USE_DEFAULT_WAIT) } == 0
+                    {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub struct Listener {
+        pipe: Vec<u16>,
+        first: bool,
+        // The instance that the next client will connect to.
+        next: Option<OwnedHandle>,
+    }
+
+    impl Listener {
+        pub fn bind(pipe: &Path) -> io::Result<Listener> {
+            let mut listener = Listener {
+                pipe: wide(pipe),
+                first: true,
+                next: None,
+            };
+            // Made up front, so that a daemon already serving the pipe is
+            // an error here rather than at the first connection.
+            listener.next = Some(listener.instance()?);
+            Ok(listener)
+        }
+
+        // Wait for the next client.
+        pub fn accept(&mut self) -> io::Result<Stream> {
+            let handle = match self.next.take() {
+                Some(handle) => handle,
+                None => self.instance()?,
+            };
+            let connected =
+                unsafe { ConnectNamedPipe(handle.as_raw_handle(), ptr::null_mut()) } != 0;
+            if !connected {
+                let err = io::Error::last_os_error();
+                // The client got in between making the instance and waiting.
+                if err.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+                    return Err(err);
+                }
+            }
+            Ok(File::from(handle))
+        }
+
+        fn instance(&mut self) -> io::Result<OwnedHandle> {
+            let mut open_mode = PIPE_ACCESS_DUPLEX;
+            if self.first {
+                // Fail rather than share the name with a pipe someone else
+                // made.
+                open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+            }
+            let handle = unsafe {
+                CreateNamedPipeW(
+                    self.pipe.as_ptr(),
+                    open_mode,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                    PIPE_UNLIMITED_INSTANCES,
+                    BUFFER,
+                    BUFFER,
+                    0,
+                    ptr::null(),
+                )
+            };
+            if handle == INVALID_HANDLE_VALUE {
+                return Err(io::Error::last_os_error());
+            }
+            self.first = false;
+            Ok(unsafe { OwnedHandle::from_raw_handle(handle) })
+        }
+    }
+
+    fn wide(path: &Path) -> Vec<u16> {
+        path.as_os_str()
+            .encode_wide()
+            .chain(iter::once(0))
+            .collect()
+    }
+}
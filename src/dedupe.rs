@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+
+use proc_macro2::{Group, Ident, Literal, TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::{Item, ItemImpl};
+
+use capture;
+use config::Config;
+use opts::Opts;
+use provenance;
+use syntax;
+use tmp::TmpDir;
+
+// What a type's name is replaced with to compare impls for different types.
+const PLACEHOLDER: &str = "__Type";
+
+// How many of the types sharing a shape to name.
+const LISTED: usize = 8;
+
+// --dedupe-report: the impls that macros generated which are the same but for
+// the type they are for, grouped by that shape with the types that share it,
+// most shared first. A type's name counts wherever it appears in its impl, as
+// an identifier or as the string a `Debug` impl prints, so that only what
+// differs in substance keeps impls apart. Impls written in the source are
+// left out.
+pub fn report(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let code = match capture::expand(opts, config)? {
+        Some(code) => code,
+        None => return Ok(1),
+    };
+    let file = syn::parse_file(&code).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "cannot compare impls, expanded code failed to parse: {}",
+                err
+            ),
+        )
+    })?;
+    let source = provenance::load_crate(opts)?;
+    let mut in_source = BTreeSet::new();
+    labels(&source.items, &mut in_source);
+
+    let mut impls = Vec::new();
+    collect(&file.items, &mut impls);
+    // The impls of each shape, keyed by what they implement and the shape.
+    let mut shapes: BTreeMap<(String, String), Vec<(String, &ItemImpl)>> = BTreeMap::new();
+    for imp in impls {
+        let ty = match syntax::impl_self_ident(imp) {
+            Some(ty) => ty.to_string(),
+            None => continue,
+        };
+        let item = Item::Impl(imp.clone());
+        if in_source.contains(&syntax::item_label(&item)) {
+            continue;
+        }
+        let what = match imp.trait_ {
+            Some((_, ref path, _)) => provenance::last_segment(path),
+            None => "inherent".to_owned(),
+        };
+        let shape = normalize(imp.to_token_stream(), &ty).to_string();
+        shapes.entry((what, shape)).or_default().push((ty, imp));
+    }
+    let mut shared: Vec<_> = shapes
+        .into_iter()
+        .filter(|(_, impls)| {
+            let types: BTreeSet<&String> = impls.iter().map(|(ty, _)| ty).collect();
+            types.len() > 1
+        })
+        .collect();
+    shared.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if shared.is_empty() {
+        writeln!(
+            out,
+            "no two generated impls are the same but for their type"
+        )?;
+        return Ok(0);
+    }
+
+    // Each shape is shown as the impl for the first of its types, under a
+    // comment listing the others, all formatted together.
+    let mut shown = String::new();
+    for ((what, _), impls) in &shared {
+        let mut types: Vec<&str> = impls.iter().map(|(ty, _)| &**ty).collect();
+        types.dedup();
+        let mut names = types[..types.len().min(LISTED)].join(", ");
+        if types.len() > LISTED {
+            names.push_str(&format!(" and {} more", types.len() - LISTED));
+        }
+        let what = if what == "inherent" {
+            "inherent impl".to_owned()
+        } else {
+            format!("{} impl", what)
+        };
+        shown.push_str(&format!(
+            "\n// {} types share this {} shape: {}\n",
+            types.len(),
+            what,
+            names,
+        ));
+        shown.push_str(&impls[0].1.to_token_stream().to_string());
+        shown.push('\n');
+    }
+    let outdir = TmpDir::create(opts.keep_tmp)?;
+    let path = outdir.path().join("shapes.rs");
+    fs::write(&path, shown)?;
+    out.write_all(capture::format(opts, config, &path)?.as_bytes())?;
+
+    let covered: usize = shared.iter().map(|(_, impls)| impls.len()).sum();
+    writeln!(
+        out,
+        "\n{} impl shape{} shared by {} generated impls",
+        shared.len(),
+        if shared.len() == 1 { "" } else { "s" },
+        covered,
+    )?;
+    Ok(0)
+}
+
+// The labels of the items written in the source, in any module.
+fn labels(items: &[Item], found: &mut BTreeSet<String>) {
+    for item in items {
+        found.insert(syntax::item_label(item));
+        if let Item::Mod(ref m) = *item {
+            if let Some((_, ref content)) = m.content {
+                labels(content, found);
+            }
+        }
+    }
+}
+
+// The impls of the expansion, including those in anonymous const blocks, in
+// any module.
+fn collect<'a>(items: &'a [Item], impls: &mut Vec<&'a ItemImpl>) {
+    for item in items {
+        match *item {
+            Item::Impl(ref imp) => impls.push(imp),
+            Item::Mod(ref m) => {
+                if let Some((_, ref content)) = m.content {
+                    collect(content, impls);
+                }
+            }
+            _ => {
+                for item in syntax::const_block_items(item) {
+                    if let Item::Impl(ref imp) = *item {
+                        impls.push(imp);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// The tokens with the type's name, and strings of just that name, replaced.
+fn normalize(tokens: TokenStream, ty: &str) -> TokenStream {
+    let name = format!("{:?}", ty);
+    tokens
+        .into_iter()
+        .map(|tt| match tt {
+            TokenTree::Ident(ref ident) if ident == ty => {
+                TokenTree::Ident(Ident::new(PLACEHOLDER, ident.span()))
+            }
+            TokenTree::Literal(ref lit) if lit.to_string() == name => {
+                TokenTree::Literal(Literal::string(PLACEHOLDER))
+            }
+            TokenTree::Group(group) => {
+                TokenTree::Group(Group::new(group.delimiter(), normalize(group.stream(), ty)))
+            }
+            tt => tt,
+        })
+        .collect()
+}
@@ -0,0 +1,158 @@
+// Line diffs between two versions of expanded code, using Myers' algorithm so
+// that the edits are minimal and the hunks read the way `diff -u` would print
+// them.
+
+pub enum Edit<'a> {
+    Same(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+impl<'a> Edit<'a> {
+    fn is_change(&self) -> bool {
+        match *self {
+            Edit::Same(_) => false,
+            Edit::Delete(_) | Edit::Insert(_) => true,
+        }
+    }
+}
+
+// The shortest list of line edits that turns `old` into `new`.
+pub fn lines<'a>(old: &'a str, new: &'a str) -> Vec<Edit<'a>> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    let index = |k: isize| (k + offset) as usize;
+
+    // For each number of edits d, the furthest x reached on each diagonal k,
+    // kept so the path can be walked back.
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace = Vec::new();
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let down = k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]);
+            let mut x = if down {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+            k += 2;
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let down = k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]);
+        let prev_k = if down { k + 1 } else { k - 1 };
+        let prev_x = v[index(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Same(a[x as usize - 1]));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(b[y as usize - 1]));
+            } else {
+                edits.push(Edit::Delete(a[x as usize - 1]));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+// A unified diff with `context` unchanged lines around each change, or None if
+// the lines are the same.
+pub fn unified(
+    old_name: &str,
+    new_name: &str,
+    old: &str,
+    new: &str,
+    context: usize,
+) -> Option<String> {
+    let edits = lines(old, new);
+    let changes: Vec<usize> = (0..edits.len()).filter(|&i| edits[i].is_change()).collect();
+    if changes.is_empty() {
+        return None;
+    }
+
+    // Group changes whose context would touch or overlap into one hunk.
+    let mut hunks = Vec::new();
+    let mut start = changes[0];
+    let mut end = changes[0];
+    for &i in &changes[1..] {
+        if i - end > 2 * context {
+            hunks.push((start, end));
+            start = i;
+        }
+        end = i;
+    }
+    hunks.push((start, end));
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for (first, last) in hunks {
+        let from = first.saturating_sub(context);
+        let to = (last + context + 1).min(edits.len());
+
+        let (mut old_line, mut new_line) = (0, 0);
+        for edit in &edits[..from] {
+            match *edit {
+                Edit::Same(_) => {
+                    old_line += 1;
+                    new_line += 1;
+                }
+                Edit::Delete(_) => old_line += 1,
+                Edit::Insert(_) => new_line += 1,
+            }
+        }
+        let old_len = edits[from..to]
+            .iter()
+            .filter(|edit| !matches!(**edit, Edit::Insert(_)))
+            .count();
+        let new_len = edits[from..to]
+            .iter()
+            .filter(|edit| !matches!(**edit, Edit::Delete(_)))
+            .count();
+
+        // Like diff, an empty side is numbered by the line before it.
+        let start = |line: usize, len: usize| if len == 0 { line } else { line + 1 };
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            start(old_line, old_len),
+            old_len,
+            start(new_line, new_len),
+            new_len,
+        ));
+        for edit in &edits[from..to] {
+            let (sign, line) = match *edit {
+                Edit::Same(line) => (' ', line),
+                Edit::Delete(line) => ('-', line),
+                Edit::Insert(line) => ('+', line),
+            };
+            out.push(sign);
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,217 @@
+use std::fs;
+use std::io::{self, Write};
+
+use proc_macro2::Ident;
+use quote::ToTokens;
+use syn::visit_mut::{self, VisitMut};
+use syn::{self, Attribute, Block, ImplItem, Item, ItemImpl, Path};
+
+use capture;
+use config::Config;
+use opts::Opts;
+use provenance::{self, BUILTIN_DERIVES};
+use suggest;
+use syntax;
+use tmp::TmpDir;
+
+// Statements of a function body to keep before the rest is cut.
+const MAX_STMTS: usize = 3;
+
+// Stands in for the statements cut from a body until the code is formatted,
+// then becomes a `// ...` comment.
+const ELIDED: &str = "__cargo_expand_elided";
+
+// --emit-doc-example TYPE: print what the derives on a sample type generate,
+// cleaned up and shortened, as a doc comment to paste into the documentation
+// of the macro crate. The derives built into the compiler are left out unless
+// there is nothing else.
+pub fn print(opts: &Opts, config: &Config, ty: &str) -> io::Result<i32> {
+    let ty = ty.rsplit("::").next().unwrap_or(ty);
+    let code = match capture::expand(opts, config)? {
+        Some(code) => code,
+        None => return Ok(1),
+    };
+    let file = syn::parse_file(&code).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "cannot find the derived impls, expanded code failed to parse: {}",
+                err
+            ),
+        )
+    })?;
+
+    let mut derived = provenance::derived_for(opts, &file, ty)?;
+    if derived.is_empty() {
+        let names = syntax::item_names(&file.items);
+        let suggestions = suggest::similar(ty, names.iter().map(String::as_str));
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "found no derived impls for `{}`{}",
+                ty,
+                suggest::did_you_mean(&suggestions),
+            ),
+        ));
+    }
+    if derived
+        .iter()
+        .any(|(derive, _)| !BUILTIN_DERIVES.contains(&derive.as_str()))
+    {
+        derived.retain(|(derive, _)| !BUILTIN_DERIVES.contains(&derive.as_str()));
+    }
+    let mut names: Vec<&str> = Vec::new();
+    for (derive, _) in &derived {
+        if !names.contains(&derive.as_str()) {
+            names.push(derive);
+        }
+    }
+
+    let mut items = Vec::new();
+    for (_, item) in &derived {
+        items.extend(clean(item.clone()));
+    }
+    let outdir = TmpDir::create(opts.keep_tmp)?;
+    let path = outdir.path().join("example.rs");
+    let tokens: Vec<String> = items
+        .iter()
+        .map(|item| item.to_token_stream().to_string())
+        .collect();
+    fs::write(&path, tokens.join("\n"))?;
+    let formatted = capture::format(opts, config, &path)?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(
+        out,
+        "/// `#[derive({})]` on `{}` generates code along these lines:",
+        names.join(", "),
+        ty,
+    )?;
+    writeln!(out, "///")?;
+    writeln!(out, "/// ```ignore")?;
+    for line in formatted.trim_end().lines() {
+        let line = match line.trim_start().strip_prefix(ELIDED) {
+            Some(_) => format!("{}// ...", &line[..line.len() - line.trim_start().len()]),
+            None => line.to_owned(),
+        };
+        if line.is_empty() {
+            writeln!(out, "///")?;
+        } else {
+            writeln!(out, "/// {}", line)?;
+        }
+    }
+    writeln!(out, "/// ```")?;
+    writeln!(out, "///")?;
+    writeln!(
+        out,
+        "/// The exact code is an implementation detail, shown to illustrate what the"
+    )?;
+    writeln!(
+        out,
+        "/// derive does. It may change in any release without notice."
+    )?;
+    Ok(0)
+}
+
+// Take impls out of the anonymous const block they may be in, along with the
+// `extern crate serde as _serde` that the block is there to scope, and strip
+// what matters only to the compiler.
+fn clean(item: Item) -> Vec<Item> {
+    let consts = syntax::const_block_items(&item);
+    let mut cleaner = Cleaner {
+        aliases: Vec::new(),
+    };
+    let unwrap = !consts.is_empty()
+        && consts.iter().all(|item| match **item {
+            Item::Impl(_) | Item::Use(_) => true,
+            Item::ExternCrate(ref item) => {
+                if let Some((_, ref alias)) = item.rename {
+                    cleaner.aliases.push((alias.clone(), item.ident.clone()));
+                }
+                true
+            }
+            _ => false,
+        });
+    let mut items = if unwrap {
+        consts
+            .into_iter()
+            .filter(|item| matches!(**item, Item::Impl(_)))
+            .cloned()
+            .collect()
+    } else {
+        cleaner.aliases.clear();
+        vec![item]
+    };
+    for item in &mut items {
+        cleaner.visit_item_mut(item);
+    }
+    items
+}
+
+struct Cleaner {
+    // Crates renamed by an `extern crate` that was taken away, as the alias
+    // and the crate.
+    aliases: Vec<(Ident, Ident)>,
+}
+
+impl VisitMut for Cleaner {
+    fn visit_item_impl_mut(&mut self, imp: &mut ItemImpl) {
+        imp.attrs.retain(|attr| !is_noise(attr));
+        for item in &mut imp.items {
+            let attrs = match *item {
+                ImplItem::Const(ref mut item) => &mut item.attrs,
+                ImplItem::Fn(ref mut item) => &mut item.attrs,
+                ImplItem::Type(ref mut item) => &mut item.attrs,
+                _ => continue,
+            };
+            attrs.retain(|attr| !is_noise(attr));
+        }
+        visit_mut::visit_item_impl_mut(self, imp);
+    }
+
+    fn visit_path_mut(&mut self, path: &mut Path) {
+        if path.leading_colon.is_none() {
+            if let Some(first) = path.segments.first_mut() {
+                let krate = self
+                    .aliases
+                    .iter()
+                    .find(|(alias, _)| first.ident == *alias)
+                    .map(|(_, krate)| krate.clone());
+                if let Some(krate) = krate {
+                    first.ident = krate;
+                    path.leading_colon = Some(Default::default());
+                }
+            }
+        }
+        visit_mut::visit_path_mut(self, path);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        visit_mut::visit_block_mut(self, block);
+        if block.stmts.len() > MAX_STMTS + 1 {
+            block.stmts.truncate(MAX_STMTS);
+            if let Ok(marker) = syn::parse_str(&format!("{}!();", ELIDED)) {
+                block.stmts.push(marker);
+            }
+        }
+    }
+}
+
+// Attributes that derives add for the compiler or for lints rather than for
+// whoever reads the code.
+fn is_noise(attr: &Attribute) -> bool {
+    let path = attr.path();
+    [
+        "automatically_derived",
+        "allow",
+        "inline",
+        "coverage",
+        "doc",
+    ]
+    .iter()
+    .any(|name| path.is_ident(name))
+        || path
+            .get_ident()
+            .is_some_and(|ident| ident.to_string().starts_with("rustc_"))
+}
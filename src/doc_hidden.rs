@@ -0,0 +1,55 @@
+use std::ops::Range;
+
+use syn::spanned::Spanned;
+use syn::{Attribute, Expr, File, Item, Token};
+
+use syntax;
+
+// Find the `#[doc(hidden)]` items, the support modules and helper functions
+// that macro crates generate for their own use, anywhere in the module tree.
+// Returns where each is in the text the file was parsed from, to be cut out
+// there.
+pub fn find(file: &File) -> Vec<Range<usize>> {
+    let mut hidden = Vec::new();
+    find_in(&file.items, &mut hidden);
+    hidden
+}
+
+fn find_in(items: &[Item], hidden: &mut Vec<Range<usize>>) {
+    for item in items {
+        if is_hidden(item) {
+            hidden.push(item.span().byte_range());
+        } else if let Item::Mod(ref m) = *item {
+            if let Some((_, ref items)) = m.content {
+                find_in(items, hidden);
+            }
+        }
+    }
+}
+
+fn is_hidden(item: &Item) -> bool {
+    // Derives like serde's wrap the impls they generate in a hidden `const _`.
+    // Those impls are the reason to look at the expansion, so keep them.
+    if let Item::Const(ref item) = *item {
+        if item.ident == "_" {
+            return false;
+        }
+    }
+    syntax::item_attrs(item).iter().any(is_doc_hidden)
+}
+
+fn is_doc_hidden(attr: &Attribute) -> bool {
+    if !attr.path().is_ident("doc") {
+        return false;
+    }
+    let mut hidden = false;
+    let _ = attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("hidden") {
+            hidden = true;
+        } else if meta.input.peek(Token![=]) {
+            meta.value()?.parse::<Expr>()?;
+        }
+        Ok(())
+    });
+    hidden
+}
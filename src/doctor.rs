@@ -0,0 +1,186 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use config::{self, Config};
+use term;
+use tmp::{self, TmpDir};
+use tty::{stdout_colors, stdout_isatty};
+use {cargo_binary, definitely_not_nightly, which};
+
+enum Status {
+    Pass,
+    // Something optional is missing; expansions still work.
+    Warn,
+    Fail,
+}
+
+struct Check {
+    status: Status,
+    what: &'static str,
+    detail: String,
+    fix: Option<&'static str>,
+}
+
+// `cargo expand doctor`: check everything an expansion depends on and say how
+// to fix what is missing. Exits with 1 if any required check failed.
+pub fn run() -> io::Result<i32> {
+    let mut checks = Vec::new();
+
+    let config = match Config::load() {
+        Ok(config) => {
+            let detail = match config::path() {
+                Some(path) if path.exists() => format!("loaded {}", path.display()),
+                _ => "no config file".to_owned(),
+            };
+            checks.push(Check::pass("config", detail));
+            config
+        }
+        Err(err) => {
+            checks.push(Check {
+                status: Status::Fail,
+                what: "config",
+                detail: err.to_string(),
+                fix: Some("fix the syntax error, or set CARGO_EXPAND_CONFIG to another file"),
+            });
+            return report(&checks);
+        }
+    };
+
+    checks.push(nightly());
+    checks.push(match which(&config, &["rustfmt"]) {
+        Ok(Some(path)) => tool("rustfmt", &path.to_string_lossy(), "--version"),
+        Ok(None) => Check {
+            status: Status::Fail,
+            what: "rustfmt",
+            detail: "not found; expansions will be printed unformatted".to_owned(),
+            fix: Some("rustup component add rustfmt --toolchain nightly"),
+        },
+        Err(err) => Check {
+            status: Status::Fail,
+            what: "rustfmt",
+            detail: err.to_string(),
+            fix: Some("correct the path under [tools] in the config"),
+        },
+    });
+    checks.push(terminal());
+    checks.push(match TmpDir::create(false) {
+        Ok(_dir) => Check::pass(
+            "cache",
+            format!("{} is writable", tmp::cache_dir().display()),
+        ),
+        Err(err) => Check {
+            status: Status::Fail,
+            what: "cache",
+            detail: format!("cannot write to {}: {}", tmp::cache_dir().display(), err),
+            fix: Some("make the directory writable, or point XDG_CACHE_HOME elsewhere"),
+        },
+    });
+
+    report(&checks)
+}
+
+impl Check {
+    fn pass(what: &'static str, detail: String) -> Self {
+        Check {
+            status: Status::Pass,
+            what,
+            detail,
+            fix: None,
+        }
+    }
+}
+
+fn nightly() -> Check {
+    if !definitely_not_nightly() {
+        let cargo = cargo_binary();
+        return tool("nightly", &cargo.to_string_lossy(), "--version");
+    }
+    let installed = Command::new("cargo")
+        .args(["+nightly", "--version"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if installed {
+        Check::pass(
+            "nightly",
+            "installed; cargo expand switches to it".to_owned(),
+        )
+    } else {
+        Check {
+            status: Status::Fail,
+            what: "nightly",
+            detail: "no nightly toolchain; expanding needs one".to_owned(),
+            fix: Some("rustup toolchain install nightly"),
+        }
+    }
+}
+
+fn terminal() -> Check {
+    if !stdout_isatty() {
+        return Check {
+            status: Status::Warn,
+            what: "terminal",
+            detail: "stdout is not a terminal; output will not be colored".to_owned(),
+            fix: None,
+        };
+    }
+    if !stdout_colors() {
+        return Check {
+            status: Status::Warn,
+            what: "terminal",
+            detail: "TERM=dumb; output will not be colored".to_owned(),
+            fix: None,
+        };
+    }
+    let detail = match term::width() {
+        Some(width) => format!("{} columns", width),
+        None => "width unknown; rustfmt's default of 100 columns is used".to_owned(),
+    };
+    Check::pass("terminal", detail)
+}
+
+// Passes with the first line of the tool's version output. A tool named by an
+// environment variable is not probed when it is looked up, so this is where a
+// bad path shows up.
+fn tool(what: &'static str, program: &str, flag: &str) -> Check {
+    let version = Command::new(program)
+        .arg(flag)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .and_then(|stdout| stdout.lines().next().map(str::to_owned));
+    match version {
+        Some(version) => Check::pass(what, version),
+        None => Check {
+            status: Status::Fail,
+            what,
+            detail: format!("`{}` does not run", program),
+            fix: Some("check the path given in the environment or the config"),
+        },
+    }
+}
+
+fn report(checks: &[Check]) -> io::Result<i32> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut failed = false;
+    for check in checks {
+        let label = match check.status {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => {
+                failed = true;
+                "fail"
+            }
+        };
+        writeln!(out, "{} {}: {}", label, check.what, check.detail)?;
+        if let Some(fix) = check.fix {
+            writeln!(out, "     fix: {}", fix)?;
+        }
+    }
+    Ok(if failed { 1 } else { 0 })
+}
@@ -0,0 +1,128 @@
+use std::io::{self, Write};
+
+use quote::ToTokens;
+use syn::{Attribute, File, ImplItem, Item};
+
+use opts::Opts;
+use provenance;
+use syntax;
+
+// --show-dropped: list on stderr the items of the source that a `#[cfg]` left
+// out of the expansion, so that code that was expected and is missing can be
+// told apart from code that a macro failed to generate. An item counts as
+// dropped if it has a `#[cfg]` and nothing in the expansion at the same place
+// in the module tree has its label; methods are looked for in the impls that
+// are in both.
+pub fn report(opts: &Opts, expanded: &File) -> io::Result<()> {
+    let source = provenance::load_crate(opts)?;
+    let mut dropped = Vec::new();
+    items(&source.items, &expanded.items, "", &mut dropped);
+
+    let stderr = io::stderr();
+    let mut err = stderr.lock();
+    if dropped.is_empty() {
+        let _ = writeln!(err, "note: no items were dropped by #[cfg]");
+        return Ok(());
+    }
+    let _ = writeln!(
+        err,
+        "note: {} item{} dropped by #[cfg]:",
+        dropped.len(),
+        if dropped.len() == 1 { " was" } else { "s were" },
+    );
+    for (place, cfgs) in dropped {
+        let _ = writeln!(err, "  {}: {}", place, cfgs);
+    }
+    Ok(())
+}
+
+fn items(source: &[Item], expanded: &[Item], module: &str, dropped: &mut Vec<(String, String)>) {
+    let place = |label: String| {
+        if module.is_empty() {
+            label
+        } else {
+            format!("{} in {}", label, module)
+        }
+    };
+    for item in source {
+        let label = syntax::item_label(item);
+        let found = expanded
+            .iter()
+            .find(|expanded| syntax::item_label(expanded) == label);
+        let found = match found {
+            Some(found) => found,
+            None => {
+                if let Some(cfgs) = cfgs(syntax::item_attrs(item)) {
+                    dropped.push((place(label), cfgs));
+                }
+                continue;
+            }
+        };
+        match (item, found) {
+            (Item::Mod(m), Item::Mod(found)) => {
+                if let (Some((_, inner)), Some((_, found))) = (&m.content, &found.content) {
+                    let module = if module.is_empty() {
+                        m.ident.to_string()
+                    } else {
+                        format!("{}::{}", module, m.ident)
+                    };
+                    items(inner, found, &module, dropped);
+                }
+            }
+            // A type can have more than one impl with the same label.
+            (Item::Impl(imp), Item::Impl(_)) => {
+                let found: Vec<&ImplItem> = expanded
+                    .iter()
+                    .filter(|expanded| syntax::item_label(expanded) == label)
+                    .flat_map(|expanded| match *expanded {
+                        Item::Impl(ref found) => found.items.iter().collect(),
+                        _ => Vec::new(),
+                    })
+                    .collect();
+                for item in &imp.items {
+                    let (kind, ident, attrs) = match *item {
+                        ImplItem::Fn(ref item) => ("fn", &item.sig.ident, &item.attrs),
+                        ImplItem::Const(ref item) => ("const", &item.ident, &item.attrs),
+                        ImplItem::Type(ref item) => ("type", &item.ident, &item.attrs),
+                        _ => continue,
+                    };
+                    let present = found.iter().any(|found| match **found {
+                        ImplItem::Fn(ref found) => found.sig.ident == *ident,
+                        ImplItem::Const(ref found) => found.ident == *ident,
+                        ImplItem::Type(ref found) => found.ident == *ident,
+                        _ => false,
+                    });
+                    if !present {
+                        if let Some(cfgs) = cfgs(attrs) {
+                            dropped.push((place(format!("{} {} in {}", kind, ident, label)), cfgs));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// The item's `#[cfg]` attributes as written, if it has any.
+fn cfgs(attrs: &[Attribute]) -> Option<String> {
+    let cfgs: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("cfg"))
+        .map(|attr| {
+            let tokens = attr.meta.to_token_stream().to_string();
+            format!(
+                "#[{}]",
+                tokens
+                    .replace(" (", "(")
+                    .replace("( ", "(")
+                    .replace(" )", ")")
+            )
+        })
+        .collect();
+    if cfgs.is_empty() {
+        None
+    } else {
+        Some(cfgs.join(" "))
+    }
+}
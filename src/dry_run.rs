@@ -0,0 +1,39 @@
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::process::Command;
+
+// Print the commands that make up the expansion pipeline, one line per step,
+// quoted so they can be pasted into a POSIX shell. The first command runs on
+// its own; the rest are joined with pipes.
+pub fn print(cargo: &Command, pipeline: &[Command]) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "{}", render(cargo))?;
+    if !pipeline.is_empty() {
+        let steps: Vec<String> = pipeline.iter().map(render).collect();
+        writeln!(out, "{}", steps.join(" | "))?;
+    }
+    Ok(())
+}
+
+pub fn render(cmd: &Command) -> String {
+    let mut words = Vec::new();
+    for (key, value) in cmd.get_envs() {
+        if let Some(value) = value {
+            words.push(format!("{}={}", key.to_string_lossy(), quote(value)));
+        }
+    }
+    words.push(quote(cmd.get_program()));
+    words.extend(cmd.get_args().map(quote));
+    words.join(" ")
+}
+
+fn quote(word: &OsStr) -> String {
+    let word = word.to_string_lossy();
+    let plain = |ch: char| ch.is_ascii_alphanumeric() || "-_=+/.,:@%".contains(ch);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.into_owned()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
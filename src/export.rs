@@ -0,0 +1,104 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde_json::{self, Value};
+use syn;
+
+use capture;
+use config::Config;
+use guard;
+use metadata::{self, Metadata};
+use opts::Opts;
+use output;
+use schema;
+
+// --output-dir DIR: write the expansion of the selected target, or with
+// --workspace of every lib and bin in the workspace, to a file each under DIR,
+// along with an `index.json` manifest and an `index.html` page linking them,
+// so the set can be browsed when published from CI.
+pub fn run(opts: &Opts, config: &Config, dir: &Path) -> io::Result<i32> {
+    let targets = if opts.cargo.iter().any(|arg| arg == "--workspace") {
+        let metadata = Metadata::load(opts)?;
+        metadata::workspace_targets(&metadata, opts)
+    } else {
+        vec![(opts.target_label(), opts.clone())]
+    };
+    guard::confirm_targets(opts, config, targets.len())?;
+    fs::create_dir_all(dir)?;
+
+    let mut entries = Vec::new();
+    let mut failed = false;
+    for (label, opts) in targets {
+        let file = format!("{}.rs", output::file_stem(&label));
+        let entry = match capture::expand(&opts, config)? {
+            Some(code) => {
+                fs::write(dir.join(&file), opts.output_encoding.encode(&code))?;
+                let items = syn::parse_file(&code).ok().map(|file| file.items.len());
+                json!({
+                    "target": label,
+                    "file": file,
+                    "lines": code.lines().count(),
+                    "bytes": code.len(),
+                    "items": items,
+                })
+            }
+            None => {
+                failed = true;
+                json!({ "target": label, "file": null, "error": "build failed" })
+            }
+        };
+        entries.push(entry);
+    }
+
+    let manifest = json!({ "schema": schema::VERSION, "targets": entries });
+    let json = serde_json::to_string_pretty(&manifest).map_err(io::Error::other)?;
+    fs::write(dir.join("index.json"), json + "\n")?;
+    fs::write(dir.join("index.html"), html(&entries))?;
+    let _ = writeln!(
+        &mut io::stderr(),
+        "wrote {} expansion{} to {}",
+        entries.len(),
+        if entries.len() == 1 { "" } else { "s" },
+        dir.display(),
+    );
+    Ok(if failed { 1 } else { 0 })
+}
+
+fn html(entries: &[Value]) -> String {
+    let mut rows = String::new();
+    for entry in entries {
+        let target = escape(entry["target"].as_str().unwrap_or(""));
+        let cell = match entry["file"].as_str() {
+            Some(file) => format!("<a href=\"{}\">{}</a>", escape(file), target),
+            None => target,
+        };
+        let number = |key: &str| match entry[key].as_u64() {
+            Some(n) => n.to_string(),
+            None => String::new(),
+        };
+        let status = entry["error"].as_str().unwrap_or("");
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            cell,
+            number("lines"),
+            number("items"),
+            number("bytes"),
+            escape(status),
+        ));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>cargo expand</title>\n</head>\n<body>\n<h1>Expansions</h1>\n\
+         <table>\n<tr><th>target</th><th>lines</th><th>items</th><th>bytes</th>\
+         <th></th></tr>\n{}</table>\n</body>\n</html>\n",
+        rows,
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,195 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use capture;
+use config::Config;
+use metadata::{self, Metadata};
+use opts::Opts;
+use syn;
+use syntax;
+
+// Cargo refuses to build a target whose `required-features` are not all on.
+// Rather than fail partway through the pipeline, turn on whichever are missing
+// for the selected target and say so.
+pub fn enable_required(opts: &mut Opts) {
+    let (kind, name) = match opts.selected_target() {
+        Some((kind, Some(name))) => (kind, name),
+        _ => return,
+    };
+    let (enabled, all) = opts.features();
+    if all {
+        return;
+    }
+
+    // Anything going wrong here will be reported by cargo in a moment anyway.
+    let manifest_path = match opts.manifest_path {
+        Some(ref manifest_path) => manifest_path.clone(),
+        None => return,
+    };
+    let metadata = match Metadata::load(opts) {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+    let required = metadata
+        .package(&manifest_path)
+        .and_then(|package| metadata::target(package, &kind, &name))
+        .and_then(|target| target["required-features"].as_array());
+    let missing: Vec<&str> = match required {
+        Some(required) => required
+            .iter()
+            .filter_map(|feature| feature.as_str())
+            .filter(|feature| !enabled.iter().any(|enabled| enabled == feature))
+            .collect(),
+        None => return,
+    };
+    if missing.is_empty() {
+        return;
+    }
+
+    let list: Vec<String> = missing.iter().map(|f| format!("`{}`", f)).collect();
+    let _ = writeln!(
+        &mut io::stderr(),
+        "note: enabling {} required by {} `{}`",
+        list.join(", "),
+        kind,
+        name,
+    );
+    opts.cargo.push("--features".into());
+    opts.cargo.push(missing.join(",").into());
+}
+
+// Cargo only finds out that a feature given on the command line does not
+// exist once it comes to build the package, and then names none of those that
+// do. Check the features up front against those the package declares, along
+// with the implicit ones of its optional dependencies, and against its
+// dependencies for `dep/feature`.
+pub fn check(opts: &Opts) -> io::Result<()> {
+    let (enabled, _) = opts.features();
+    if enabled.is_empty() {
+        return Ok(());
+    }
+    let manifest_path = match opts.manifest_path {
+        Some(ref manifest_path) => manifest_path,
+        None => return Ok(()),
+    };
+    // Cargo will say what is wrong with a manifest it cannot read.
+    let metadata = match Metadata::load(opts) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+    let package = match metadata.package(manifest_path) {
+        Some(package) => package,
+        None => return Ok(()),
+    };
+    let declared: Vec<&str> = package["features"]
+        .as_object()
+        .map(|features| features.keys().map(String::as_str).collect())
+        .unwrap_or_default();
+    let dependencies: Vec<&str> = package["dependencies"]
+        .as_array()
+        .map(|dependencies| {
+            dependencies
+                .iter()
+                .filter_map(|dep| dep["rename"].as_str().or_else(|| dep["name"].as_str()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let name = package["name"].as_str().unwrap_or("?");
+    for feature in &enabled {
+        let msg = match feature.split_once('/') {
+            // `demo/serde` is the package's own feature, as with `-p demo`.
+            Some((dep, _)) if dep != name && !dependencies.contains(&dep) => format!(
+                "package `{}` has no dependency `{}` for feature `{}`",
+                name, dep, feature,
+            ),
+            None if !declared.contains(&feature.as_str()) => {
+                let known: Vec<String> = declared.iter().map(|f| format!("`{}`", f)).collect();
+                if known.is_empty() {
+                    format!(
+                        "package `{}` has no feature `{}`, nor any other",
+                        name, feature
+                    )
+                } else {
+                    format!(
+                        "package `{}` has no feature `{}`; its features are {}",
+                        name,
+                        feature,
+                        known.join(", "),
+                    )
+                }
+            }
+            _ => continue,
+        };
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    Ok(())
+}
+
+// --feature-report: expand with no features and then with each feature on its
+// own, and print as Markdown what public items each one adds.
+pub fn report(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let metadata = Metadata::load(opts)?;
+    let package = opts
+        .manifest_path
+        .as_ref()
+        .and_then(|manifest_path| metadata.package(manifest_path))
+        .ok_or_else(|| io::Error::other("cannot find the package to report on"))?;
+    let features: Vec<&String> = match package["features"].as_object() {
+        Some(features) => features.keys().collect(),
+        None => Vec::new(),
+    };
+
+    let public = |feature: Option<&str>| -> io::Result<Option<BTreeSet<String>>> {
+        let mut opts = opts.clone();
+        opts.cargo.push("--no-default-features".into());
+        if let Some(feature) = feature {
+            let _ = writeln!(
+                &mut io::stderr(),
+                "note: expanding with feature `{}`",
+                feature
+            );
+            opts.cargo.push("--features".into());
+            opts.cargo.push(feature.into());
+        }
+        let code = match capture::expand(&opts, config)? {
+            Some(code) => code,
+            None => return Ok(None),
+        };
+        Ok(syn::parse_file(&code)
+            .ok()
+            .map(|file| syntax::public_items(&file.items, "")))
+    };
+
+    let base = public(None)?.ok_or_else(|| {
+        io::Error::other("cannot report on features, the crate failed to expand without any")
+    })?;
+
+    let mut out = format!(
+        "# Features of `{}`\n\nPublic items and trait impls each feature adds on its own, \
+         compared with `--no-default-features`.\n",
+        package["name"].as_str().unwrap_or("?"),
+    );
+    if features.is_empty() {
+        out.push_str("\nThe package has no features.\n");
+    }
+    for feature in features {
+        out.push_str(&format!("\n## `{}`\n\n", feature));
+        match public(Some(feature))? {
+            None => out.push_str("Fails to build on its own.\n"),
+            Some(items) => {
+                let added: Vec<_> = items.difference(&base).collect();
+                if added.is_empty() {
+                    out.push_str("Adds no public items.\n");
+                }
+                for item in added {
+                    out.push_str(&format!("- `{}`\n", item));
+                }
+            }
+        }
+    }
+
+    let stdout = io::stdout();
+    stdout.lock().write_all(out.as_bytes())?;
+    Ok(0)
+}
@@ -0,0 +1,104 @@
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::{ExprUnsafe, ImplItemFn, ItemFn, ItemImpl, ItemTrait, TraitItemFn};
+
+use opts::Opts;
+
+pub enum Level {
+    Notice,
+    Warning,
+}
+
+// Something worth pointing out about an expansion, with the line of the
+// formatted expansion it is on, if it is about one place.
+pub struct Finding {
+    pub level: Level,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+// Check formatted expanded code against the size budget from --size-budget and
+// for unsafe code that macros put there.
+pub fn collect(opts: &Opts, code: &str) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    let lines = code.lines().count();
+    findings.push(match opts.size_budget {
+        Some(budget) if lines > budget => Finding {
+            level: Level::Warning,
+            line: None,
+            message: format!(
+                "expansion is {} lines, over the budget of {}",
+                lines, budget
+            ),
+        },
+        _ => Finding {
+            level: Level::Notice,
+            line: None,
+            message: format!("expansion is {} lines", lines),
+        },
+    });
+
+    // Code that does not parse has nothing more to say; rewrite will already
+    // have warned about it.
+    if let Ok(file) = syn::parse_file(code) {
+        let mut visitor = Unsafe(Vec::new());
+        visitor.visit_file(&file);
+        findings.extend(visitor.0);
+    }
+    findings
+}
+
+struct Unsafe(Vec<Finding>);
+
+impl Unsafe {
+    fn push<T: Spanned>(&mut self, node: &T, what: String) {
+        self.0.push(Finding {
+            level: Level::Warning,
+            line: Some(node.span().start().line),
+            message: format!("{} in expansion", what),
+        });
+    }
+}
+
+impl<'ast> Visit<'ast> for Unsafe {
+    fn visit_expr_unsafe(&mut self, expr: &'ast ExprUnsafe) {
+        self.push(&expr.unsafe_token, "unsafe block".to_owned());
+        visit::visit_expr_unsafe(self, expr);
+    }
+
+    fn visit_item_fn(&mut self, item: &'ast ItemFn) {
+        if item.sig.unsafety.is_some() {
+            self.push(&item.sig, format!("unsafe fn `{}`", item.sig.ident));
+        }
+        visit::visit_item_fn(self, item);
+    }
+
+    fn visit_impl_item_fn(&mut self, item: &'ast ImplItemFn) {
+        if item.sig.unsafety.is_some() {
+            self.push(&item.sig, format!("unsafe fn `{}`", item.sig.ident));
+        }
+        visit::visit_impl_item_fn(self, item);
+    }
+
+    fn visit_trait_item_fn(&mut self, item: &'ast TraitItemFn) {
+        if item.sig.unsafety.is_some() {
+            self.push(&item.sig, format!("unsafe fn `{}`", item.sig.ident));
+        }
+        visit::visit_trait_item_fn(self, item);
+    }
+
+    fn visit_item_impl(&mut self, item: &'ast ItemImpl) {
+        if let Some(ref unsafety) = item.unsafety {
+            self.push(unsafety, "unsafe impl".to_owned());
+        }
+        visit::visit_item_impl(self, item);
+    }
+
+    fn visit_item_trait(&mut self, item: &'ast ItemTrait) {
+        if let Some(ref unsafety) = item.unsafety {
+            self.push(unsafety, format!("unsafe trait `{}`", item.ident));
+        }
+        visit::visit_item_trait(self, item);
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use quote::ToTokens;
+use syn;
+
+use capture;
+use config::Config;
+use metadata::Metadata;
+use minimize::{self, Location};
+use opts::Opts;
+use syntax;
+use tmp::TmpDir;
+use watch;
+
+// --emit-fixture FILE:LINE: turn the macro invocation at a line of the source
+// into a test case in the layout macrotest uses, `tests/expand/NAME.rs` with
+// the item and the `use` statements it needs, and next to it
+// `NAME.expanded.rs` with what it expands to, to start a macro crate's test
+// suite off from the ways it is really used.
+pub fn emit(opts: &Opts, config: &Config, location: &Location) -> io::Result<i32> {
+    let package_dir = watch::package_dir(opts);
+    let snippet = minimize::extract(location, Some(&package_dir))?;
+
+    let code = match capture::expand(opts, config)? {
+        Some(code) => code,
+        None => return Ok(1),
+    };
+    let file = syn::parse_file(&code).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "cannot find the expansion, expanded code failed to parse: {}",
+                err
+            ),
+        )
+    })?;
+    let items = syntax::items_for(&file.items, &snippet.name);
+    let outdir = TmpDir::create(opts.keep_tmp)?;
+    let tokens = outdir.path().join("expanded.rs");
+    let tokens_text: Vec<String> = items
+        .iter()
+        .map(|item| item.to_token_stream().to_string())
+        .collect();
+    fs::write(&tokens, tokens_text.join("\n"))?;
+    let expanded = capture::format(opts, config, &tokens)?;
+
+    // The test is its own crate, so paths into this one need its name.
+    let crate_name = Metadata::load(opts).ok().and_then(|metadata| {
+        let manifest_path = opts.manifest_path.as_ref()?;
+        let package = metadata.package(manifest_path)?;
+        Some(package["name"].as_str()?.replace('-', "_"))
+    });
+    let mut input = String::new();
+    if let Some(ref invocation) = snippet.invocation {
+        input.push_str(&format!(
+            "// {} at {}:{}\n\n",
+            invocation,
+            location.file.display(),
+            location.line,
+        ));
+    }
+    for item in &snippet.uses {
+        let item = match crate_name {
+            Some(ref name) => item.replace("use crate::", &format!("use {}::", name)),
+            None => item.clone(),
+        };
+        input.push_str(&item);
+        input.push('\n');
+    }
+    if !snippet.uses.is_empty() {
+        input.push('\n');
+    }
+    input.push_str(&snippet.source);
+    input.push('\n');
+    if snippet.name != "main" {
+        input.push_str("\nfn main() {}\n");
+    }
+
+    let dir = package_dir.join("tests").join("expand");
+    fs::create_dir_all(&dir)?;
+    let stem = snippet.name.to_lowercase();
+    let input_path = dir.join(format!("{}.rs", stem));
+    let expanded_path = dir.join(format!("{}.expanded.rs", stem));
+    for path in [&input_path, &expanded_path] {
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} already exists; remove it to write the fixture again",
+                    path.display()
+                ),
+            ));
+        }
+    }
+    fs::write(&input_path, input)?;
+    fs::write(&expanded_path, expanded)?;
+    let _ = writeln!(
+        &mut io::stderr(),
+        "wrote {} and {}",
+        relative(&input_path, &package_dir),
+        relative(&expanded_path, &package_dir),
+    );
+    Ok(0)
+}
+
+fn relative<'a>(path: &'a Path, dir: &Path) -> std::path::Display<'a> {
+    path.strip_prefix(dir).unwrap_or(path).display()
+}
@@ -0,0 +1,412 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+use syn;
+use syn::punctuated::Punctuated;
+use syn::visit::{self, Visit};
+use syn::{Attribute, Block, File, ImplItem, Item, UseTree};
+
+use opts::Opts;
+use provenance;
+use suggest;
+use syntax;
+
+// --expand-from CRATE and --keep-folded CRATE: show expanded only what
+// macros from the crates named with --expand-from generated, if any, and not
+// what those from the crates named with --keep-folded did. Other macros are
+// shown folded, their invocations as they are written in the source. Which
+// crate a macro is from is worked out from its path and the crate's `use`
+// declarations; a macro named without either is taken to be the standard
+// library's, or the crate's own if it defines a macro_rules of that name.
+// Only whole items and function bodies are folded back: a body that invokes
+// macros both to fold and to expand is left expanded, and so are the items of
+// a module that invokes both kinds of macro in place of items.
+pub fn apply(opts: &Opts, file: &mut File) -> io::Result<()> {
+    let (name, source) = provenance::load_target(opts)?;
+    let origins = Origins::new(&source, &name);
+    let normalize = |crates: &[String]| -> Vec<String> {
+        crates.iter().map(|name| origins.normalize(name)).collect()
+    };
+    let expand_from = normalize(&opts.expand_from);
+    let keep_folded = normalize(&opts.keep_folded);
+
+    let mut used = Used {
+        origins: &origins,
+        crates: BTreeSet::new(),
+    };
+    used.visit_file(&source);
+    for name in expand_from.iter().chain(&keep_folded) {
+        if !used.crates.contains(name) {
+            let suggestions = suggest::similar(name, used.crates.iter().map(String::as_str));
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "found no macros from `{}` in the crate{}",
+                    name,
+                    suggest::did_you_mean(&suggestions),
+                ),
+            ));
+        }
+    }
+
+    let named = |crates: &[String], origin: &str| crates.iter().any(|name| name == origin);
+    let folder = Folder {
+        origins: &origins,
+        fold: &|origin| {
+            (!expand_from.is_empty() && !named(&expand_from, origin)) || named(&keep_folded, origin)
+        },
+    };
+    folder.items(&source.items, &mut file.items);
+    Ok(())
+}
+
+// The crate that each macro in the source comes from.
+struct Origins {
+    // Names brought in by `use`, with the crate they are from.
+    imports: BTreeMap<String, String>,
+    // The names of the macro_rules the crate defines.
+    local_macros: BTreeSet<String>,
+    local: String,
+}
+
+impl Origins {
+    fn new(source: &File, local: &str) -> Self {
+        let mut origins = Origins {
+            imports: BTreeMap::new(),
+            local_macros: BTreeSet::new(),
+            local: local.to_owned(),
+        };
+        origins.collect(&source.items);
+        origins
+    }
+
+    fn collect(&mut self, items: &[Item]) {
+        for item in items {
+            match *item {
+                Item::Use(ref item) => self.import(&item.tree, None),
+                Item::Macro(ref item) if item.mac.path.is_ident("macro_rules") => {
+                    self.local_macros
+                        .extend(item.ident.as_ref().map(ToString::to_string));
+                }
+                Item::Mod(ref m) => {
+                    if let Some((_, ref items)) = m.content {
+                        self.collect(items);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn import(&mut self, tree: &UseTree, root: Option<&str>) {
+        match *tree {
+            UseTree::Path(ref path) => {
+                let first = path.ident.to_string();
+                self.import(&path.tree, Some(root.unwrap_or(&first)));
+            }
+            UseTree::Name(ref name) => {
+                if let Some(root) = root {
+                    let root = self.normalize(root);
+                    self.imports.insert(name.ident.to_string(), root);
+                }
+            }
+            UseTree::Rename(ref rename) => {
+                let ident = rename.ident.to_string();
+                let root = self.normalize(root.unwrap_or(&ident));
+                self.imports.insert(rename.rename.to_string(), root);
+            }
+            UseTree::Group(ref group) => {
+                for tree in &group.items {
+                    self.import(tree, root);
+                }
+            }
+            UseTree::Glob(_) => {}
+        }
+    }
+
+    // `core` and `alloc` count as `std`, and paths within the crate as the
+    // crate's own, under its name with `-` as `_`.
+    fn normalize(&self, name: &str) -> String {
+        match name {
+            "crate" | "self" | "super" => self.local.clone(),
+            "core" | "alloc" | "std" => "std".to_owned(),
+            _ => name.replace('-', "_"),
+        }
+    }
+
+    fn of(&self, path: &syn::Path) -> String {
+        let first = match path.segments.first() {
+            Some(segment) => segment.ident.to_string(),
+            None => return "std".to_owned(),
+        };
+        if path.segments.len() > 1 {
+            self.normalize(&first)
+        } else if let Some(origin) = self.imports.get(&first) {
+            origin.clone()
+        } else if self.local_macros.contains(&first) {
+            self.local.clone()
+        } else {
+            "std".to_owned()
+        }
+    }
+
+    // The crate of an attribute macro, or None for an attribute that is not
+    // one, like `#[inline]`, or a tool's like `#[rustfmt::skip]`.
+    fn of_attribute(&self, attr: &Attribute) -> Option<String> {
+        let path = attr.path();
+        let tool = path.segments.len() > 1
+            && path.segments.first().is_some_and(|segment| {
+                ["clippy", "diagnostic", "rustdoc", "rustfmt"]
+                    .contains(&&*segment.ident.to_string())
+            });
+        if path.is_ident("derive") || tool {
+            return None;
+        }
+        Some(self.of(path)).filter(|origin| origin != "std")
+    }
+}
+
+// The paths in the `#[derive(...)]` attributes.
+fn derive_paths(attrs: &[Attribute]) -> Vec<syn::Path> {
+    let mut paths = Vec::new();
+    for attr in attrs {
+        if attr.path().is_ident("derive") {
+            let parsed =
+                attr.parse_args_with(Punctuated::<syn::Path, syn::Token![,]>::parse_terminated);
+            paths.extend(parsed.into_iter().flatten());
+        }
+    }
+    paths
+}
+
+// The crates whose macros some code invokes, derives with or puts as an
+// attribute.
+struct Used<'a> {
+    origins: &'a Origins,
+    crates: BTreeSet<String>,
+}
+
+impl<'a, 'ast> Visit<'ast> for Used<'a> {
+    fn visit_item_macro(&mut self, item: &'ast syn::ItemMacro) {
+        // Not a macro_rules definition, which has a name.
+        if item.ident.is_none() {
+            self.visit_macro(&item.mac);
+        }
+        for attr in &item.attrs {
+            self.visit_attribute(attr);
+        }
+    }
+
+    fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+        self.crates.insert(self.origins.of(&mac.path));
+        visit::visit_macro(self, mac);
+    }
+
+    fn visit_attribute(&mut self, attr: &'ast Attribute) {
+        if let Some(origin) = self.origins.of_attribute(attr) {
+            self.crates.insert(origin);
+        }
+        for path in derive_paths(std::slice::from_ref(attr)) {
+            self.crates.insert(self.origins.of(&path));
+        }
+    }
+}
+
+struct Folder<'a> {
+    origins: &'a Origins,
+    // Whether to fold the macros of a crate.
+    fold: &'a dyn Fn(&str) -> bool,
+}
+
+impl<'a> Folder<'a> {
+    // Fold the items at one level of the module tree back into the source's
+    // where their macros are to be folded.
+    fn items(&self, source: &[Item], expanded: &mut Vec<Item>) {
+        for item in expanded.iter_mut() {
+            if let Item::Mod(ref mut m) = *item {
+                if let Some(inner) = provenance::submodule(source, m) {
+                    if let Some((_, ref mut items)) = m.content {
+                        self.items(inner, items);
+                    }
+                }
+            }
+        }
+        let in_source: BTreeSet<String> = source.iter().map(syntax::item_label).collect();
+
+        self.derives(source, &in_source, expanded);
+        self.attributes(source, expanded);
+        self.invocations(source, &in_source, expanded);
+        for item in expanded.iter_mut() {
+            self.bodies(source, item);
+        }
+    }
+
+    // Take out the impls that folded derives generated and put the derives
+    // back on their types.
+    fn derives(&self, source: &[Item], in_source: &BTreeSet<String>, expanded: &mut Vec<Item>) {
+        for ty in source {
+            if matches!(*ty, Item::Impl(_)) {
+                continue;
+            }
+            let (folded, kept): (Vec<syn::Path>, Vec<syn::Path>) =
+                derive_paths(syntax::item_attrs(ty))
+                    .into_iter()
+                    .partition(|path| (self.fold)(&self.origins.of(path)));
+            if folded.is_empty() {
+                continue;
+            }
+            let names = |paths: &[syn::Path]| -> Vec<String> {
+                paths.iter().map(provenance::last_segment).collect()
+            };
+            let (folded_names, kept_names) = (names(&folded), names(&kept));
+            expanded.retain(|item| {
+                let from = |names: &[String]| {
+                    names
+                        .iter()
+                        .any(|name| provenance::from_derive(source, in_source, item, name))
+                };
+                !from(&folded_names) || from(&kept_names)
+            });
+
+            let label = syntax::item_label(ty);
+            let item = expanded
+                .iter_mut()
+                .find(|item| syntax::item_label(item) == label);
+            if let Some(attrs) = item.and_then(syntax::item_attrs_mut) {
+                attrs.insert(0, syn::parse_quote!(#[derive(#(#folded),*)]));
+            }
+        }
+    }
+
+    // Put back the items of the source that folded attribute macros are on.
+    fn attributes(&self, source: &[Item], expanded: &mut [Item]) {
+        for original in source {
+            let folds = syntax::item_attrs(original).iter().any(|attr| {
+                self.origins
+                    .of_attribute(attr)
+                    .is_some_and(|origin| (self.fold)(&origin))
+            });
+            if !folds {
+                continue;
+            }
+            let label = syntax::item_label(original);
+            if let Some(item) = expanded
+                .iter_mut()
+                .find(|item| syntax::item_label(item) == label)
+            {
+                *item = original.clone();
+            }
+        }
+    }
+
+    // Put back the macros invoked in place of items, if they are all to be
+    // folded, in place of the items the expansion has that the source does
+    // not.
+    fn invocations(&self, source: &[Item], in_source: &BTreeSet<String>, expanded: &mut Vec<Item>) {
+        let mut any = false;
+        for path in source.iter().filter_map(invoked) {
+            if !(self.fold)(&self.origins.of(path)) {
+                return;
+            }
+            any = true;
+        }
+        if !any {
+            return;
+        }
+
+        expanded.retain(|item| {
+            in_source.contains(&syntax::item_label(item))
+                || provenance::is_derived(item)
+                || provenance::is_injected(item)
+        });
+
+        // Each invocation goes after the item it follows in the source, of
+        // those the expansion still has, or after the injected prelude.
+        let labels: BTreeSet<String> = expanded.iter().map(syntax::item_label).collect();
+        let mut after: BTreeMap<Option<String>, Vec<Item>> = BTreeMap::new();
+        let mut anchor = None;
+        for item in source {
+            if invoked(item).is_some() {
+                after.entry(anchor.clone()).or_default().push(item.clone());
+                continue;
+            }
+            let label = syntax::item_label(item);
+            if labels.contains(&label) {
+                anchor = Some(label);
+            }
+        }
+        let injected = expanded
+            .iter()
+            .take_while(|item| provenance::is_injected(item))
+            .count();
+        let mut folded: Vec<Item> = expanded.drain(..injected).collect();
+        folded.extend(after.remove(&None).unwrap_or_default());
+        for item in expanded.drain(..) {
+            let label = syntax::item_label(&item);
+            folded.push(item);
+            if let Some(invocations) = after.remove(&Some(label)) {
+                folded.extend(invocations);
+            }
+        }
+        *expanded = folded;
+    }
+
+    // Put back the body from the source of a function, or of a method in an
+    // impl that the source has, if every macro invoked in it is to be folded.
+    fn bodies(&self, source: &[Item], item: &mut Item) {
+        let label = syntax::item_label(item);
+        let original = match source.iter().find(|item| syntax::item_label(item) == label) {
+            Some(original) => original,
+            None => return,
+        };
+        match (item, original) {
+            (&mut Item::Fn(ref mut item), Item::Fn(original))
+                if self.folds_body(&original.attrs, &original.block) =>
+            {
+                item.block = original.block.clone();
+            }
+            (&mut Item::Impl(ref mut item), Item::Impl(original)) => {
+                for method in &mut item.items {
+                    let method = match *method {
+                        ImplItem::Fn(ref mut method) => method,
+                        _ => continue,
+                    };
+                    let original = original.items.iter().find_map(|original| match *original {
+                        ImplItem::Fn(ref original) if original.sig.ident == method.sig.ident => {
+                            Some(original)
+                        }
+                        _ => None,
+                    });
+                    if let Some(original) = original {
+                        if self.folds_body(&original.attrs, &original.block) {
+                            method.block = original.block.clone();
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Whether a body invokes macros and all of them are to be folded, and no
+    // attribute macro left expanded rewrote it.
+    fn folds_body(&self, attrs: &[Attribute], block: &Block) -> bool {
+        let rewritten = attrs
+            .iter()
+            .any(|attr| self.origins.of_attribute(attr).is_some());
+        let mut used = Used {
+            origins: self.origins,
+            crates: BTreeSet::new(),
+        };
+        used.visit_block(block);
+        !rewritten && !used.crates.is_empty() && used.crates.iter().all(|name| (self.fold)(name))
+    }
+}
+
+// The path of the macro invoked in place of an item, if this is one.
+fn invoked(item: &Item) -> Option<&syn::Path> {
+    match *item {
+        Item::Macro(ref item) if item.ident.is_none() => Some(&item.mac.path),
+        _ => None,
+    }
+}
@@ -0,0 +1,48 @@
+use std::io::{self, Write};
+
+use capture;
+use config::Config;
+use findings::{self, Level};
+use opts::Opts;
+
+// --format github: instead of the code, print the findings about it as GitHub
+// Actions workflow commands, which show up as annotations on the run.
+pub fn print(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let code = match capture::expand(opts, config)? {
+        Some(code) => code,
+        None => return Ok(1),
+    };
+
+    let target = opts.target_label();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for finding in findings::collect(opts, &code) {
+        let command = match finding.level {
+            Level::Notice => "notice",
+            Level::Warning => "warning",
+        };
+        let message = match finding.line {
+            Some(line) => format!("{} (line {} of the expanded code)", finding.message, line),
+            None => finding.message,
+        };
+        writeln!(
+            out,
+            "::{} title={}::{}",
+            command,
+            escape_property(&format!("cargo expand {}", target)),
+            escape_data(&message),
+        )?;
+    }
+    Ok(0)
+}
+
+// https://github.com/actions/toolkit/blob/main/packages/core/src/command.ts
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
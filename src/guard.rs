@@ -0,0 +1,67 @@
+use std::io;
+
+use config::Config;
+use metadata;
+use opts::Opts;
+use tty;
+
+const DEFAULT_MAX_TARGETS: u64 = 20;
+const DEFAULT_MAX_DEPENDENCIES: u64 = 500;
+
+// Before a run that expands more than one target, like one per member of a
+// workspace, ask to go ahead if it would expand more targets than `targets`
+// under `[confirm]` in the config, or build more dependency crates than
+// `dependencies`, so that an hour-long build is not started by accident.
+// Either can be set to 0 to never ask, and --yes agrees up front.
+pub fn confirm_targets(opts: &Opts, config: &Config, targets: usize) -> io::Result<()> {
+    if opts.yes || targets <= 1 {
+        return Ok(());
+    }
+
+    let max_targets = limit(config, "targets", DEFAULT_MAX_TARGETS)?;
+    let question = if max_targets != 0 && targets as u64 > max_targets {
+        Some(format!("this would expand {} targets.", targets))
+    } else {
+        let max_dependencies = limit(config, "dependencies", DEFAULT_MAX_DEPENDENCIES)?;
+        match metadata::dependency_count(opts) {
+            Some(count) if max_dependencies != 0 && count as u64 > max_dependencies => {
+                Some(format!(
+                    "this would expand {} targets, building up to {} dependency crates.",
+                    targets, count,
+                ))
+            }
+            _ => None,
+        }
+    };
+
+    match question {
+        Some(question) => {
+            if tty::confirm(&format!("{} Go ahead?", question))? {
+                Ok(())
+            } else {
+                Err(io::Error::other("not expanding; stopping"))
+            }
+        }
+        None => Ok(()),
+    }
+}
+
+fn limit(config: &Config, key: &str, default: u64) -> io::Result<u64> {
+    let value = match config
+        .section("confirm")
+        .and_then(|confirm| confirm.get(key))
+    {
+        Some(value) => value,
+        None => return Ok(default),
+    };
+    match value.as_integer() {
+        Some(n) if n >= 0 => Ok(n as u64),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "invalid {} `{}` under [confirm]: expected a number",
+                key, value,
+            ),
+        )),
+    }
+}
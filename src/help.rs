@@ -0,0 +1,49 @@
+use std::io::{self, Write};
+use std::process::Command;
+
+use cargo_binary;
+
+// --help: what cargo expand makes of the options it reads itself, followed by
+// the help of `cargo rustc`, which builds the expansion and whose other
+// options are passed on to it.
+pub fn print() -> io::Result<i32> {
+    {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(HELP.as_bytes())?;
+        stdout.flush()?;
+    }
+    let status = Command::new(cargo_binary())
+        .args(["rustc", "--help"])
+        .status()?;
+    Ok(status.code().unwrap_or(1))
+}
+
+const HELP: &str = "\
+Show the result of macro expansion and #[derive] expansion
+
+Usage: cargo expand [OPTIONS] [ITEM]
+
+Arguments:
+  [ITEM]  Path of the item to show, like `http::Handlers`, rather than the whole crate
+
+Options:
+      --item <ITEM>          The same as [ITEM], where it could be taken for an option's value
+
+Feature Selection:
+  -F, --features <FEATURES>  Space or comma separated list of features to activate, each
+                             one the package has, or `dep/feature` for a dependency
+      --all-features         Activate all available features
+      --no-default-features  Do not activate the `default` feature
+
+Compilation Options:
+  -r, --release              Expand under the `release` profile, without debug assertions
+      --profile <NAME>       Expand under the named profile, which may be a custom one
+      --target <TRIPLE>      Expand for another platform, like `wasm32-unknown-unknown`
+      --toolchain <NAME>     Build with this rustup toolchain, like `nightly-2024-05-01`
+
+Features are checked before anything is built. The options of cargo expand
+itself are described at https://github.com/dtolnay/cargo-expand, and the
+options of `cargo rustc`, which builds the expansion, are taken as they are:
+
+";
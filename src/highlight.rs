@@ -0,0 +1,639 @@
+// Coloring of formatted Rust code for the terminal, done here rather than by
+// an external tool so that colors do not depend on what else is installed. It
+// goes a line at a time, carrying comments and string literals that span lines
+// over to the next.
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use syn::spanned::Spanned;
+use syn::{self, Item};
+
+use provenance;
+use syntax;
+
+// The SGR parameters to color each kind of token with.
+pub struct Theme {
+    pub keyword: &'static str,
+    pub ty: &'static str,
+    pub string: &'static str,
+    pub number: &'static str,
+    pub comment: &'static str,
+    pub attribute: &'static str,
+    pub macro_: &'static str,
+    pub lifetime: &'static str,
+}
+
+// The themes to pick from with --theme, the first being the default. `light`
+// is for terminals with a light background, and `mono` sticks to bold, italic
+// and faint for terminals with colors of their own.
+pub const THEMES: &[(&str, Theme)] = &[
+    (
+        "dark",
+        Theme {
+            keyword: "1;34",
+            ty: "36",
+            string: "32",
+            number: "33",
+            comment: "90",
+            attribute: "35",
+            macro_: "1;36",
+            lifetime: "33",
+        },
+    ),
+    (
+        "light",
+        Theme {
+            keyword: "1;34",
+            ty: "34",
+            string: "32",
+            number: "31",
+            comment: "90",
+            attribute: "35",
+            macro_: "1;35",
+            lifetime: "31",
+        },
+    ),
+    (
+        "mono",
+        Theme {
+            keyword: "1",
+            ty: "1",
+            string: "3",
+            number: "3",
+            comment: "2",
+            attribute: "2",
+            macro_: "1",
+            lifetime: "3",
+        },
+    ),
+];
+
+// The theme called `name`, or an error listing them all.
+pub fn theme(name: &str) -> Result<&'static Theme, String> {
+    THEMES
+        .iter()
+        .find(|&&(theme, _)| theme == name)
+        .map(|(_, theme)| theme)
+        .ok_or_else(|| {
+            let names: Vec<&str> = THEMES.iter().map(|&(name, _)| name).collect();
+            format!("expected one of {}", names.join(", "))
+        })
+}
+
+// What the themes look like, for --themes, with the one in use marked.
+pub fn print_themes(current: &Theme) -> io::Result<i32> {
+    let preview = [
+        "#[derive(Debug)]",
+        "pub struct Point<'a> {",
+        "    name: &'a str,",
+        "    x: f64,",
+        "}",
+        "// Formats the point.",
+        "fn show(p: &Point) -> String {",
+        "    format!(\"{} at {}\", p.name, 1.5 * p.x)",
+        "}",
+    ];
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let printed = THEMES
+        .iter()
+        .enumerate()
+        .try_for_each(|(i, &(name, ref theme))| {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            let marker = if std::ptr::eq(theme, current) {
+                " (in use)"
+            } else {
+                ""
+            };
+            writeln!(out, "{}{}", name, marker)?;
+            let mut highlighter = Highlighter::new(theme);
+            preview
+                .iter()
+                .try_for_each(|line| writeln!(out, "    {}", highlighter.line(line)))
+        });
+    match printed {
+        Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(0),
+        Err(err) => Err(err),
+        Ok(()) => Ok(0),
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type", "union",
+    "unsafe", "use", "where", "while", "yield",
+];
+
+const PRIMITIVES: &[&str] = &[
+    "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "str", "u8", "u16",
+    "u32", "u64", "u128", "usize",
+];
+
+// What a line starts in the middle of.
+#[derive(Clone, Copy)]
+enum State {
+    Code,
+    // A block comment, nested this deep.
+    Comment(usize),
+    Str,
+    // A raw string closed by `"` and this many `#`.
+    RawStr(usize),
+}
+
+pub struct Highlighter<'a> {
+    theme: &'a Theme,
+    state: State,
+    // The next identifier is the path of an attribute.
+    in_attribute: bool,
+    // Mark up lines for an HTML page rather than a terminal.
+    html: bool,
+}
+
+impl<'a> Highlighter<'a> {
+    pub fn new(theme: &'a Theme) -> Self {
+        Highlighter {
+            theme,
+            state: State::Code,
+            in_attribute: false,
+            html: false,
+        }
+    }
+
+    pub fn html(theme: &'a Theme) -> Self {
+        Highlighter {
+            html: true,
+            ..Highlighter::new(theme)
+        }
+    }
+
+    // The line, without its line ending, with color escapes or HTML spans
+    // added.
+    pub fn line(&mut self, line: &str) -> String {
+        let chars: Vec<char> = line.chars().collect();
+        let mut out = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let start = i;
+            match self.state {
+                State::Comment(depth) => {
+                    i = self.block_comment(&chars, i, depth);
+                    self.paint(&mut out, self.theme.comment, &chars[start..i]);
+                    continue;
+                }
+                State::Str => {
+                    i = self.string(&chars, i);
+                    self.paint(&mut out, self.theme.string, &chars[start..i]);
+                    continue;
+                }
+                State::RawStr(hashes) => {
+                    i = self.raw_string(&chars, i, hashes);
+                    self.paint(&mut out, self.theme.string, &chars[start..i]);
+                    continue;
+                }
+                State::Code => {}
+            }
+
+            let ch = chars[i];
+            let next = chars.get(i + 1).copied();
+            if ch == '/' && next == Some('/') {
+                self.paint(&mut out, self.theme.comment, &chars[i..]);
+                break;
+            } else if ch == '/' && next == Some('*') {
+                i = self.block_comment(&chars, i + 2, 0);
+                self.paint(&mut out, self.theme.comment, &chars[start..i]);
+            } else if let Some(len) = string_prefix(&chars[i..]) {
+                i += len;
+                let hashes = chars[i..].iter().take_while(|&&ch| ch == '#').count();
+                if chars[start..i].contains(&'r') {
+                    i = self.raw_string(&chars, i + hashes + 1, hashes);
+                } else {
+                    i = self.string(&chars, i + 1);
+                }
+                self.paint(&mut out, self.theme.string, &chars[start..i]);
+            } else if ch == '\'' {
+                i = char_or_lifetime(&chars, i);
+                let color = if chars[i - 1] == '\'' && i - start > 2 {
+                    self.theme.string
+                } else {
+                    self.theme.lifetime
+                };
+                self.paint(&mut out, color, &chars[start..i]);
+            } else if ch == '#' && (next == Some('[') || next == Some('!')) {
+                self.in_attribute = true;
+                i += if next == Some('!') { 3 } else { 2 };
+                i = i.min(chars.len());
+                self.paint(&mut out, self.theme.attribute, &chars[start..i]);
+            } else if ch.is_ascii_digit() {
+                i += 1;
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric()
+                        || chars[i] == '_'
+                        || (chars[i] == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit)))
+                {
+                    i += 1;
+                }
+                self.paint(&mut out, self.theme.number, &chars[start..i]);
+            } else if is_ident_start(ch) {
+                while i < chars.len() && is_ident_continue(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let is_macro = chars.get(i) == Some(&'!') && chars.get(i + 1) != Some(&'=');
+                if self.in_attribute {
+                    self.in_attribute = false;
+                    self.paint(&mut out, self.theme.attribute, &chars[start..i]);
+                } else if is_macro {
+                    i += 1;
+                    self.paint(&mut out, self.theme.macro_, &chars[start..i]);
+                } else if KEYWORDS.contains(&word.as_str()) {
+                    self.paint(&mut out, self.theme.keyword, &chars[start..i]);
+                } else if PRIMITIVES.contains(&word.as_str())
+                    || word.starts_with(|ch: char| ch.is_uppercase())
+                {
+                    self.paint(&mut out, self.theme.ty, &chars[start..i]);
+                } else {
+                    out.push_str(&word);
+                }
+            } else {
+                self.push(&mut out, &chars[i..i + 1]);
+                i += 1;
+            }
+        }
+        out
+    }
+
+    // Skip to the end of a block comment, or of the line if it goes on.
+    fn block_comment(&mut self, chars: &[char], mut i: usize, mut depth: usize) -> usize {
+        while i < chars.len() {
+            if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                i += 2;
+                if depth == 0 {
+                    self.state = State::Code;
+                    return i;
+                }
+                depth -= 1;
+            } else if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                i += 2;
+                depth += 1;
+            } else {
+                i += 1;
+            }
+        }
+        self.state = State::Comment(depth);
+        i
+    }
+
+    // Skip past the closing quote of a string, or to the end of the line.
+    fn string(&mut self, chars: &[char], mut i: usize) -> usize {
+        while i < chars.len() {
+            match chars[i] {
+                '\\' => i += 2,
+                '"' => {
+                    self.state = State::Code;
+                    return i + 1;
+                }
+                _ => i += 1,
+            }
+        }
+        self.state = State::Str;
+        chars.len()
+    }
+
+    fn raw_string(&mut self, chars: &[char], mut i: usize, hashes: usize) -> usize {
+        while i < chars.len() {
+            let closes = chars[i] == '"'
+                && chars[i + 1..].iter().take_while(|&&ch| ch == '#').count() >= hashes;
+            if closes {
+                self.state = State::Code;
+                return i + 1 + hashes;
+            }
+            i += 1;
+        }
+        self.state = State::RawStr(hashes);
+        chars.len()
+    }
+
+    fn paint(&self, out: &mut String, color: &str, chars: &[char]) {
+        if chars.is_empty() {
+            return;
+        }
+        if self.html {
+            out.push_str(&format!("<span style=\"{}\">", css(color)));
+            self.push(out, chars);
+            out.push_str("</span>");
+        } else {
+            out.push_str("\x1b[");
+            out.push_str(color);
+            out.push('m');
+            out.extend(chars);
+            out.push_str("\x1b[0m");
+        }
+    }
+
+    fn push(&self, out: &mut String, chars: &[char]) {
+        for &ch in chars {
+            match ch {
+                '&' if self.html => out.push_str("&amp;"),
+                '<' if self.html => out.push_str("&lt;"),
+                '>' if self.html => out.push_str("&gt;"),
+                _ => out.push(ch),
+            }
+        }
+    }
+}
+
+// The length of the prefix of a string literal up to its opening quote or the
+// `#`s of a raw one: `"`, `b"`, `c"`, `r"`, `br#"` and the like.
+fn string_prefix(chars: &[char]) -> Option<usize> {
+    let mut len = 0;
+    if matches!(chars.first(), Some(&'b') | Some(&'c')) {
+        len += 1;
+    }
+    let raw = chars.get(len) == Some(&'r');
+    if raw {
+        len += 1;
+    }
+    let hashes = if raw {
+        chars[len..].iter().take_while(|&&ch| ch == '#').count()
+    } else {
+        0
+    };
+    if chars.get(len + hashes) != Some(&'"') {
+        return None;
+    }
+    // The quote itself is left to the caller unless the prefix is empty.
+    Some(len)
+}
+
+// Skip a char literal like `'a'` or `'\n'`, or a lifetime like `'a`.
+fn char_or_lifetime(chars: &[char], i: usize) -> usize {
+    if chars.get(i + 1) == Some(&'\\') {
+        let mut j = i + 2;
+        while j < chars.len() && chars[j] != '\'' {
+            j += 1;
+        }
+        return (j + 1).min(chars.len());
+    }
+    if chars.get(i + 2) == Some(&'\'') {
+        return i + 3;
+    }
+    let mut j = i + 1;
+    while j < chars.len() && is_ident_continue(chars[j]) {
+        j += 1;
+    }
+    j
+}
+
+fn is_ident_start(ch: char) -> bool {
+    ch == '_' || ch.is_alphabetic()
+}
+
+fn is_ident_continue(ch: char) -> bool {
+    ch == '_' || ch.is_alphanumeric()
+}
+
+// Color a unified diff the way `git diff` does, along with the `-- ` lines that
+// --watch announces each change with.
+pub fn diff(text: &str) -> String {
+    let mut out = String::new();
+    for line in text.lines() {
+        let color =
+            if line.starts_with("-- ") || line.starts_with("--- ") || line.starts_with("+++ ") {
+                "1"
+            } else if line.starts_with("@@") {
+                "36"
+            } else if line.starts_with('+') {
+                "32"
+            } else if line.starts_with('-') {
+                "31"
+            } else {
+                out.push_str(line);
+                out.push('\n');
+                continue;
+            };
+        out.push_str(&format!("\x1b[{}m{}\x1b[0m\n", color, line));
+    }
+    out
+}
+
+// A page showing the code colored with the theme, for writing the expansion
+// to a `.html` file or for --format html. Each item of the code, down through
+// its modules, gets an anchor to link to, named for its path or its label,
+// and a link in the index above the code.
+pub fn html(code: &str, theme: &Theme, title: &str) -> String {
+    let mut anchors = Vec::new();
+    if let Ok(file) = syn::parse_file(code) {
+        item_anchors(&file.items, "", 0, &mut anchors);
+    }
+    let mut used = BTreeSet::new();
+    for anchor in &mut anchors {
+        let mut id = anchor.id.clone();
+        let mut n = 1;
+        while !used.insert(id.clone()) {
+            n += 1;
+            id = format!("{}-{}", anchor.id, n);
+        }
+        anchor.id = id;
+    }
+
+    let mut highlighter = Highlighter::html(theme);
+    let escape = |text: &str| {
+        let chars: Vec<char> = text.chars().collect();
+        let mut escaped = String::new();
+        highlighter.push(&mut escaped, &chars);
+        escaped
+    };
+    let mut index = String::new();
+    for anchor in &anchors {
+        index.push_str(&format!(
+            "<li style=\"margin-left: {}em\"><a href=\"#{}\">{}</a></li>\n",
+            anchor.depth * 2,
+            anchor.id,
+            escape(&anchor.label),
+        ));
+    }
+    let title = escape(title);
+    let index = if index.is_empty() {
+        String::new()
+    } else {
+        format!("<nav>\n<ul style=\"list-style: none\">\n{}</ul>\n</nav>\n", index)
+    };
+
+    let mut lines = String::new();
+    let mut anchors = anchors.iter().peekable();
+    for (i, line) in code.lines().enumerate() {
+        while let Some(anchor) = anchors.next_if(|anchor| anchor.line <= i + 1) {
+            lines.push_str(&format!("<span id=\"{}\"></span>", anchor.id));
+        }
+        lines.push_str(&highlighter.line(line));
+        lines.push('\n');
+    }
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{}</title>\n</head>\n<body>\n{}<pre><code>{}</code></pre>\n</body>\n</html>\n",
+        title, index, lines,
+    )
+}
+
+struct Anchor {
+    line: usize,
+    id: String,
+    label: String,
+    // How many modules deep the item is.
+    depth: usize,
+}
+
+fn item_anchors(items: &[Item], prefix: &str, depth: usize, anchors: &mut Vec<Anchor>) {
+    for item in items {
+        if provenance::is_injected(item) {
+            continue;
+        }
+        let label = syntax::item_label(item);
+        let name = match *item {
+            Item::Use(_) | Item::Impl(_) | Item::ForeignMod(_) => None,
+            Item::Const(ref item) if item.ident == "_" => None,
+            _ => syntax::item_ident(item).map(ToString::to_string),
+        };
+        let name = name.unwrap_or_else(|| {
+            label
+                .chars()
+                .map(|ch| if ch.is_alphanumeric() || ch == '_' { ch } else { '-' })
+                .collect::<String>()
+                .split('-')
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join("-")
+        });
+        let path = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}::{}", prefix, name)
+        };
+        anchors.push(Anchor {
+            line: item.span().start().line,
+            id: path.clone(),
+            label,
+            depth,
+        });
+        if let Item::Mod(ref m) = *item {
+            if let Some((_, ref content)) = m.content {
+                item_anchors(content, &path, depth + 1, anchors);
+            }
+        }
+    }
+}
+
+// The style for SGR parameters like `1;34`, in the colors terminals default
+// to.
+fn css(color: &str) -> String {
+    const COLORS: &[&str] = &[
+        "#000000", "#cd3131", "#0dbc79", "#e5e510", "#2472c8", "#bc3fbc", "#11a8cd", "#e5e5e5",
+    ];
+    let mut style = Vec::new();
+    for param in color.split(';') {
+        let param: usize = match param.parse() {
+            Ok(param) => param,
+            Err(_) => continue,
+        };
+        match param {
+            1 => style.push("font-weight: bold".to_owned()),
+            2 => style.push("opacity: 0.6".to_owned()),
+            3 => style.push("font-style: italic".to_owned()),
+            30..=37 => style.push(format!("color: {}", COLORS[param - 30])),
+            // Bright black, which comments are in, reads as gray.
+            90 => style.push("color: #767676".to_owned()),
+            91..=97 => style.push(format!("color: {}", COLORS[param - 90])),
+            _ => {}
+        }
+    }
+    style.join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A theme whose colors name the kind of token, so that `S{"a"}` reads as
+    // a string `"a"`.
+    const MARKED: Theme = Theme {
+        keyword: "K",
+        ty: "T",
+        string: "S",
+        number: "N",
+        comment: "C",
+        attribute: "A",
+        macro_: "M",
+        lifetime: "L",
+    };
+
+    // The lines highlighted one after the other, with colors marked.
+    fn marked(lines: &[&str]) -> Vec<String> {
+        let mut highlighter = Highlighter::new(&MARKED);
+        lines
+            .iter()
+            .map(|line| {
+                let mut line = highlighter.line(line);
+                for kind in ["K", "T", "S", "N", "C", "A", "M", "L"] {
+                    line = line.replace(&format!("\x1b[{}m", kind), &format!("{}{{", kind));
+                }
+                line.replace("\x1b[0m", "}")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            marked(&[r#"let s = "a\"b";"#]),
+            [r#"K{let} s = S{"a\"b"};"#]
+        );
+        assert_eq!(marked(&[r#"f(b"x", c"y")"#]), [r#"f(S{b"x"}, S{c"y"})"#]);
+        assert_eq!(marked(&[r#"x("a\\", 1)"#]), [r#"x(S{"a\\"}, N{1})"#]);
+        assert_eq!(
+            marked(&[r#"let s = "one"#, "two", r#"three"; 1"#]),
+            [r#"K{let} s = S{"one}"#, "S{two}", r#"S{three"}; N{1}"#],
+        );
+    }
+
+    #[test]
+    fn raw_strings() {
+        assert_eq!(marked(&[r#"r"a\" + 1"#]), [r#"S{r"a\"} + N{1}"#]);
+        assert_eq!(marked(&[r##"r#"a"b"# + 1"##]), [r##"S{r#"a"b"#} + N{1}"##]);
+        assert_eq!(marked(&[r##"br#"x"#"##]), [r##"S{br#"x"#}"##]);
+        assert_eq!(
+            marked(&[r###"r##"a"#"###, r###"b"## x"###]),
+            [r###"S{r##"a"#}"###, r###"S{b"##} x"###],
+        );
+        // Not a raw string, but a raw identifier.
+        assert_eq!(marked(&["r#type"]), ["r#K{type}"]);
+    }
+
+    #[test]
+    fn lifetimes_and_chars() {
+        assert_eq!(
+            marked(&["fn f<'a>(x: &'a str) -> &'static str"]),
+            ["K{fn} f<L{'a}>(x: &L{'a} T{str}) -> &L{'static} T{str}"],
+        );
+        assert_eq!(
+            marked(&[r"let c = ['a', '\n', '\u{1F600}', '''];"]),
+            [r"K{let} c = [S{'a'}, S{'\n'}, S{'\u{1F600}'}, S{'''}];"],
+        );
+        assert_eq!(marked(&["'outer: loop {}"]), ["L{'outer}: K{loop} {}"]);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            marked(&["x /* a /* b */", "c */ y // z"]),
+            ["x C{/* a /* b */}", "C{c */} y C{// z}"],
+        );
+        assert_eq!(
+            marked(&[r#"// "not a string"#, "x"]),
+            [r#"C{// "not a string}"#, "x"]
+        );
+    }
+}
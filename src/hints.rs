@@ -0,0 +1,72 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use opts::Opts;
+use tty;
+
+// What the macros of well-known frameworks leave in an expansion, the macro to
+// show on its own with --only-macro, and what that shows.
+const FRAMEWORKS: &[(&str, &str, &str)] = &[
+    (
+        "_serde::Serialize for ",
+        "Serialize",
+        "the impls of #[derive(Serialize)]",
+    ),
+    (
+        "_serde::Deserialize<'de> for ",
+        "Deserialize",
+        "the impls of #[derive(Deserialize)]",
+    ),
+    ("impl clap::Parser for ", "clap::Parser", "the clap parser"),
+    (
+        "tokio::runtime::Builder::new_",
+        "tokio::main",
+        "the runtime #[tokio::main] starts",
+    ),
+    (
+        "'async_trait",
+        "async_trait",
+        "what #[async_trait] made of the trait impls",
+    ),
+];
+
+// After a whole crate was expanded at a terminal, suggest the filters that
+// would pick out the code of the frameworks found in it, which tends to make
+// up most of the expansion.
+pub fn print(opts: &Opts, outfile: &Path) {
+    let filtered = opts.item.is_some()
+        || opts.only_macro.is_some()
+        || opts.derives
+        || !opts.expand_from.is_empty()
+        || !opts.keep_folded.is_empty();
+    let quiet = opts.cargo.iter().any(|arg| arg == "--quiet" || arg == "-q");
+    if filtered || quiet || !tty::stderr_isatty() {
+        return;
+    }
+    let code = match fs::read_to_string(outfile) {
+        Ok(code) => code,
+        Err(_) => return,
+    };
+    let found: Vec<_> = FRAMEWORKS
+        .iter()
+        .filter(|(pattern, _, _)| code.contains(pattern))
+        .collect();
+    if found.is_empty() {
+        return;
+    }
+
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+    let _ = writeln!(stderr, "note: to show only part of the expansion:");
+    let mut suggest = |command: String, what: &str| {
+        let _ = writeln!(stderr, "  {:<40} # {}", command, what);
+    };
+    for (_, name, what) in found {
+        suggest(format!("cargo expand --only-macro {}", name), what);
+    }
+    suggest(
+        "cargo expand --derives".to_owned(),
+        "the impls of every derive",
+    );
+}
@@ -0,0 +1,179 @@
+#[cfg(windows)]
+use std::ffi::c_void;
+use std::fs;
+#[cfg(windows)]
+use std::mem;
+use std::path::PathBuf;
+use std::process::{self, Child};
+#[cfg(not(unix))]
+use std::process::{Command, Stdio};
+#[cfg(windows)]
+use std::ptr;
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+
+#[cfg(unix)]
+use libc;
+#[cfg(windows)]
+use windows_sys::Win32::Foundation::CloseHandle;
+#[cfg(windows)]
+use windows_sys::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+    SetInformationJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+    JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+};
+#[cfg(windows)]
+use windows_sys::Win32::System::Threading::GetCurrentProcess;
+
+// Everything that needs tearing down if we are interrupted: the children
+// making up the pipeline, with the thread that started each, and the temporary
+// directories they write to.
+static CHILDREN: Mutex<Vec<(u32, ThreadId)>> = Mutex::new(Vec::new());
+// Threads of the library whose expansion was cancelled.
+static CANCELLED: Mutex<Vec<ThreadId>> = Mutex::new(Vec::new());
+static TMPDIRS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+// Files modified in place, with what to put back: their original content, or
+// None if they did not exist before.
+static RESTORE: Mutex<Vec<(PathBuf, Option<Vec<u8>>)>> = Mutex::new(Vec::new());
+
+// On SIGINT or SIGTERM, stop the whole pipeline rather than leave orphans
+// writing partial output, and exit with the conventional 128 + SIGINT.
+pub fn install() {
+    #[cfg(windows)]
+    join_job();
+    let _ = ctrlc::set_handler(|| {
+        for &(pid, _) in CHILDREN.lock().unwrap().iter() {
+            terminate(pid);
+        }
+        for dir in TMPDIRS.lock().unwrap().iter() {
+            let _ = fs::remove_dir_all(dir);
+        }
+        for (path, original) in RESTORE.lock().unwrap().iter() {
+            let _ = match *original {
+                Some(ref content) => fs::write(path, content),
+                None => fs::remove_file(path),
+            };
+        }
+        process::exit(130);
+    });
+}
+
+pub fn track_child(child: &Child) {
+    let thread = thread::current().id();
+    // A child started after its expansion was cancelled goes right away.
+    if CANCELLED.lock().unwrap().contains(&thread) {
+        kill(child.id());
+    }
+    CHILDREN.lock().unwrap().push((child.id(), thread));
+}
+
+// Call once the child has been waited on, so that its pid, which may be reused
+// from then on, is no longer signaled.
+pub fn untrack_child(child: &Child) {
+    CHILDREN.lock().unwrap().retain(|&(pid, _)| pid != child.id());
+}
+
+// Stop what the given thread is running for an expansion of the library,
+// along with anything it starts from now on.
+pub fn cancel(thread: ThreadId) {
+    CANCELLED.lock().unwrap().push(thread);
+    for &(pid, _) in CHILDREN
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|&&(_, started_by)| started_by == thread)
+    {
+        kill(pid);
+    }
+}
+
+// Whether the current thread's expansion was cancelled, forgetting it if so
+// since the thread is done with it.
+pub fn take_cancelled() -> bool {
+    let thread = thread::current().id();
+    let mut cancelled = CANCELLED.lock().unwrap();
+    let was = cancelled.contains(&thread);
+    cancelled.retain(|&cancelled| cancelled != thread);
+    was
+}
+
+pub fn track_dir(dir: PathBuf) {
+    TMPDIRS.lock().unwrap().push(dir);
+}
+
+pub fn untrack_dir(dir: &PathBuf) {
+    TMPDIRS.lock().unwrap().retain(|tracked| tracked != dir);
+}
+
+#[cfg(unix)]
+pub fn track_restore(path: PathBuf, original: Option<Vec<u8>>) {
+    RESTORE.lock().unwrap().push((path, original));
+}
+
+#[cfg(unix)]
+pub fn untrack_restore(path: &PathBuf) {
+    RESTORE
+        .lock()
+        .unwrap()
+        .retain(|(tracked, _)| tracked != path);
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32) {
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGTERM);
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(_pid: u32) {
+    // Console children receive the same Ctrl-C event as we do, and the job
+    // ends any that are left once we exit.
+}
+
+// Windows has no process groups to signal, and a child killed outright leaves
+// its own children running. So the process joins a job object made to end
+// everything in it when its last handle is closed, which happens however the
+// process exits, even through the Task Manager. Cargo, rustc, rustfmt and
+// whatever they start are in the job from the start, as children inherit it.
+// Only the command does this: a host of the library owns its process.
+#[cfg(windows)]
+fn join_job() {
+    unsafe {
+        let job = CreateJobObjectW(ptr::null(), ptr::null());
+        if job.is_null() {
+            return;
+        }
+        let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = mem::zeroed();
+        info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        let joined = SetInformationJobObject(
+            job,
+            JobObjectExtendedLimitInformation,
+            &info as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION as *const c_void,
+            mem::size_of_val(&info) as u32,
+        ) != 0
+            && AssignProcessToJobObject(job, GetCurrentProcess()) != 0;
+        // Otherwise, as in a job that allows no other, children are left to
+        // the Ctrl-C event. The handle of a job joined stays open until exit.
+        if !joined {
+            CloseHandle(job);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn kill(pid: u32) {
+    terminate(pid);
+}
+
+// Nothing else is sent a Ctrl-C event, so the child is ended outright, along
+// with the compilers it started.
+#[cfg(not(unix))]
+fn kill(pid: u32) {
+    let _ = Command::new("taskkill")
+        .args(["/T", "/F", "/PID"])
+        .arg(pid.to_string())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
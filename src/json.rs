@@ -0,0 +1,122 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde_json::{self, Value};
+use syn::spanned::Spanned;
+use syn::{self, Item};
+
+use capture;
+use config::Config;
+use opts::Opts;
+use provenance::SourceModule;
+use schema;
+use syntax;
+
+// --format json: instead of the code, print a document listing the items of
+// the expanded crate, each with its kind, path, formatted code and the source
+// file of the module it is in, and the items of a module nested under it, so
+// tools can build on the expansion without parsing it themselves.
+pub fn print(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let code = match capture::expand(opts, config)? {
+        Some(code) => code,
+        None => return Ok(1),
+    };
+    let file = syn::parse_file(&code).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "cannot list the items, expanded code failed to parse: {}",
+                err
+            ),
+        )
+    })?;
+
+    let (name, module) = SourceModule::root(opts)?;
+    let package_dir = opts
+        .manifest_path
+        .as_ref()
+        .and_then(|p| Path::new(p).parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let document = json!({
+        "schema": schema::VERSION,
+        "crate": name,
+        "target": opts.target_label(),
+        "profile": opts.profile().unwrap_or_else(|| "dev".to_owned()),
+        "items": list(&code, &entries(&file.items, ""), &module, &package_dir),
+    });
+    let json = serde_json::to_string_pretty(&document).map_err(io::Error::other)?;
+    let stdout = io::stdout();
+    match writeln!(stdout.lock(), "{}", json) {
+        Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(0),
+        Err(err) => Err(err),
+        Ok(()) => Ok(0),
+    }
+}
+
+fn list(code: &str, entries: &[Entry], module: &SourceModule, package_dir: &Path) -> Vec<Value> {
+    let file = module
+        .file
+        .strip_prefix(package_dir)
+        .unwrap_or(&module.file)
+        .display()
+        .to_string();
+    let mut values = Vec::new();
+    for entry in entries {
+        let item = entry.item;
+        let mut value = json!({
+            "kind": syntax::item_kind(item),
+            "path": entry.path,
+            "label": syntax::item_label(item),
+            "file": file,
+            "line": item.span().start().line,
+        });
+        match *item {
+            Item::Mod(ref m) if m.content.is_some() => {
+                let inner = module.submodule(&m.ident.to_string());
+                value["items"] = Value::Array(list(code, &entry.items, &inner, package_dir));
+            }
+            _ => value["code"] = Value::String(syntax::lines(code, item.span())),
+        }
+        values.push(value);
+    }
+    values
+}
+
+// An item of the expanded code with its path from the crate root, or None
+// for impls, `use` items and the like, and the entries of the items inside
+// it if it is a module.
+pub struct Entry<'a> {
+    pub item: &'a Item,
+    pub path: Option<String>,
+    pub items: Vec<Entry<'a>>,
+}
+
+// The items of the expanded code as --format json and the library list them,
+// `prefix` being the path of the module they are in.
+pub fn entries<'a>(items: &'a [Item], prefix: &str) -> Vec<Entry<'a>> {
+    items
+        .iter()
+        .map(|item| {
+            let path = match *item {
+                Item::Use(_) | Item::Impl(_) | Item::ForeignMod(_) => None,
+                Item::Const(ref item) if item.ident == "_" => None,
+                _ => syntax::item_ident(item).map(|ident| {
+                    if prefix.is_empty() {
+                        ident.to_string()
+                    } else {
+                        format!("{}::{}", prefix, ident)
+                    }
+                }),
+            };
+            let items = match *item {
+                Item::Mod(ref m) => match m.content {
+                    Some((_, ref content)) => entries(content, path.as_deref().unwrap_or_default()),
+                    None => Vec::new(),
+                },
+                _ => Vec::new(),
+            };
+            Entry { item, path, items }
+        })
+        .collect()
+}
@@ -0,0 +1,1098 @@
+use std::env;
+#[cfg(unix)]
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, ExitStatus, Stdio};
+use std::time::Instant;
+
+extern crate analyzeme;
+extern crate ctrlc;
+extern crate proc_macro2;
+extern crate quote;
+extern crate regex;
+#[macro_use]
+extern crate serde_json;
+extern crate syn;
+use tty::stderr_isatty;
+
+extern crate toml;
+
+#[cfg(unix)]
+extern crate libc;
+#[cfg(windows)]
+extern crate windows_sys;
+
+mod api;
+mod assertions;
+mod bisect;
+mod bug_report;
+mod cache;
+mod capture;
+mod check;
+mod complete;
+mod config;
+mod daemon;
+mod dedupe;
+mod diff;
+mod doc_example;
+mod doc_hidden;
+mod doctor;
+mod dropped;
+mod dry_run;
+mod export;
+mod features;
+mod findings;
+mod fixture;
+mod fold;
+mod github;
+mod guard;
+mod help;
+mod highlight;
+mod hints;
+mod interrupt;
+mod json;
+mod locale;
+mod locations;
+mod markdown;
+mod metadata;
+mod minimize;
+mod nice;
+mod opts;
+mod output;
+mod plan;
+mod platform;
+mod pretty;
+mod profile;
+mod project;
+mod provenance;
+mod published;
+mod redact;
+mod reuse;
+mod rewrite;
+mod sandbox;
+mod sccache;
+mod scrub;
+mod search;
+mod schema;
+mod select;
+mod sha256;
+mod stats;
+mod snapshot;
+mod source_diff;
+mod suggest;
+mod suppressed;
+mod syntax;
+mod term;
+mod tmp;
+mod trust;
+mod tty;
+mod version;
+mod watch;
+mod whence;
+mod with_source;
+
+pub use api::{
+    expand, expand_with_progress, spawn, spawn_with_progress, Diagnostic, Expansion, Handle, Item,
+    Options, Progress, Stats, Target,
+};
+use config::Config;
+use highlight::Highlighter;
+use metadata::Metadata;
+use sha256::Sha256;
+use opts::{Format, Formatter, Indent, Opts, Print};
+use tmp::TmpDir;
+
+// Everything of `cargo expand` but the `main` of its binary, which calls this.
+#[doc(hidden)]
+pub fn main() {
+    interrupt::install();
+    let result = cargo_expand_or_run_nightly();
+    process::exit(match result {
+        Ok(code) => code,
+        Err(err) => {
+            let _ = writeln!(&mut io::stderr(), "{}", err);
+            1
+        }
+    });
+}
+
+fn cargo_expand_or_run_nightly() -> io::Result<i32> {
+    const NO_RUN_NIGHTLY: &str = "CARGO_EXPAND_NO_RUN_NIGHTLY";
+
+    // Checking for a nightly toolchain is part of the doctor's job, so it runs
+    // on whichever toolchain it was started with.
+    if env::args_os().nth(2).is_some_and(|arg| arg == "doctor") {
+        return doctor::run();
+    }
+
+    // Nor does managing the cache need nightly.
+    if env::args_os().nth(2).is_some_and(|arg| arg == "cache") {
+        return cache::command(env::args_os().skip(3));
+    }
+
+    // A toolchain picked with --toolchain is the one the build runs on,
+    // whatever this one is.
+    let toolchain_given = env::args_os().any(|arg| {
+        arg == "--toolchain" || arg.to_string_lossy().starts_with("--toolchain=")
+    });
+    if toolchain_given {
+        return cargo_expand();
+    }
+
+    let maybe_nightly = !definitely_not_nightly();
+    if maybe_nightly || env::var_os(NO_RUN_NIGHTLY).is_some() {
+        return cargo_expand();
+    }
+
+    let mut nightly = Command::new("cargo");
+    nightly.arg("+nightly");
+    nightly.arg("expand");
+    nightly.args(env::args_os().skip(2));
+
+    // Hopefully prevent infinite re-run loop.
+    nightly.env(NO_RUN_NIGHTLY, "");
+
+    let status = wait(nightly.spawn()?)?;
+
+    Ok(match status.code() {
+        Some(code) => code,
+        None => if status.success() { 0 } else { 1 },
+    })
+}
+
+fn definitely_not_nightly() -> bool {
+    let mut cmd = Command::new(cargo_binary());
+    cmd.arg("--version");
+
+    let output = match cmd.output() {
+        Ok(output) => output,
+        Err(_) => return false,
+    };
+
+    let version = match String::from_utf8(output.stdout) {
+        Ok(version) => version,
+        Err(_) => return false,
+    };
+
+    version.starts_with("cargo 1") && !version.contains("nightly")
+}
+
+fn cargo_binary() -> OsString {
+    env::var_os("CARGO").unwrap_or_else(|| "cargo".to_owned().into())
+}
+
+// The packages to expand in turn, if there is more than one: those picked with
+// -p or --workspace, or else the default members of a virtual workspace. A
+// single package is instead expanded as if it had been picked with
+// --manifest-path.
+fn pick_packages(opts: &mut Opts) -> io::Result<Option<Vec<(String, OsString)>>> {
+    let packages = match metadata::selected_packages(opts)? {
+        Some(packages) => {
+            opts.cargo = opts::without_packages(&opts.cargo);
+            packages
+        }
+        None => match metadata::default_members(opts) {
+            Some(members) => members,
+            None => return Ok(None),
+        },
+    };
+    if let [(_, ref manifest_path)] = packages[..] {
+        opts.manifest_path = Some(manifest_path.clone());
+        return Ok(None);
+    }
+    Ok(Some(packages))
+}
+
+// Expand each of several packages in turn, as if it had been picked with
+// `--manifest-path`, with a line naming the package ahead of its code.
+fn expand_members(
+    opts: &Opts,
+    config: &Config,
+    args: &[OsString],
+    members: &[(String, OsString)],
+) -> io::Result<i32> {
+    guard::confirm_targets(opts, config, members.len())?;
+    let args = opts::without_packages(args);
+    let exe = env::current_exe()?;
+    let mut failed = 0;
+    for (i, (name, manifest_path)) in members.iter().enumerate() {
+        let _ = writeln!(
+            &mut io::stderr(),
+            "note: expanding package `{}`, {} of {}",
+            name,
+            i + 1,
+            members.len(),
+        );
+        separate(opts, i, name)?;
+        // After `cargo-expand expand` and ahead of any `--`.
+        let mut cmd = Command::new(&exe);
+        cmd.args(&args[1..2]);
+        cmd.arg("--manifest-path").arg(manifest_path);
+        cmd.args(&args[2..]);
+        let status = wait(cmd.spawn()?)?;
+        if !status.success() {
+            failed = status.code().unwrap_or(1);
+        }
+    }
+    Ok(failed)
+}
+
+// Ahead of the `i`th of several expansions printed one after the other, a
+// line naming it. --delimit already marks where each starts and ends.
+fn separate(opts: &Opts, i: usize, label: &str) -> io::Result<()> {
+    if opts.delimit.is_some() {
+        return Ok(());
+    }
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    if i > 0 {
+        writeln!(stdout)?;
+    }
+    writeln!(stdout, "// ---------- {} ----------", label)
+}
+
+// --tests, --benches, --examples or --bins: expand each target of the kind in
+// the package in turn, as if it had been picked by name, with a line naming
+// the target ahead of its code. --all-targets does the same for the lib and
+// every bin, test, example and bench.
+fn expand_every(
+    opts: &Opts,
+    config: &Config,
+    args: &[OsString],
+    kind: &str,
+) -> io::Result<i32> {
+    let metadata = Metadata::load(opts)?;
+    let package = opts
+        .manifest_path
+        .as_ref()
+        .and_then(|manifest_path| metadata.package(manifest_path))
+        .ok_or_else(|| io::Error::other("failed to find the package in cargo metadata"))?;
+    // The option that picks the target, for each of its kinds there is one
+    // for.
+    let picked_as = |target_kind: &str| match target_kind {
+        "bin" => Some("bin"),
+        "test" => Some("test"),
+        "example" => Some("example"),
+        "bench" => Some("bench"),
+        "lib" | "rlib" | "dylib" | "cdylib" | "staticlib" | "proc-macro" => Some("lib"),
+        _ => None,
+    };
+    let targets: Vec<(&str, &str)> = package["targets"]
+        .as_array()
+        .map(|targets| {
+            targets
+                .iter()
+                .filter_map(|target| {
+                    let picked = target["kind"]
+                        .as_array()?
+                        .iter()
+                        .filter_map(|k| k.as_str().and_then(picked_as))
+                        .next()?;
+                    if kind != "all" && picked != kind {
+                        return None;
+                    }
+                    Some((picked, target["name"].as_str()?))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if targets.is_empty() {
+        let msg = format!(
+            "package `{}` has no {} targets",
+            package["name"].as_str().unwrap_or("?"),
+            kind,
+        );
+        return Err(io::Error::other(msg));
+    }
+    guard::confirm_targets(opts, config, targets.len())?;
+
+    let every = format!("--{}", opts::plural(kind));
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    let exe = env::current_exe()?;
+    let mut failed = 0;
+    for (i, &(kind, name)) in targets.iter().enumerate() {
+        let _ = writeln!(
+            &mut io::stderr(),
+            "note: expanding {} `{}`, {} of {}",
+            kind,
+            name,
+            i + 1,
+            targets.len(),
+        );
+        let mut cmd = Command::new(&exe);
+        cmd.args(args[1..end].iter().filter(|arg| **arg != *every));
+        if kind == "lib" {
+            separate(opts, i, kind)?;
+            cmd.arg("--lib");
+        } else {
+            separate(opts, i, &format!("{}:{}", kind, name))?;
+            cmd.arg(format!("--{}", kind)).arg(name);
+        }
+        cmd.args(&args[end..]);
+        let status = wait(cmd.spawn()?)?;
+        if !status.success() {
+            failed = status.code().unwrap_or(1);
+        }
+    }
+    Ok(failed)
+}
+
+// The cargo to build the expansion with: the one running us, or rustup's proxy
+// pointed at another toolchain.
+fn cargo_command(opts: &Opts) -> Command {
+    let program = match opts.toolchain {
+        Some(_) => OsString::from("cargo"),
+        None => cargo_binary(),
+    };
+    let mut cmd = if opts.sandbox {
+        sandbox::command(opts, &program)
+    } else {
+        Command::new(program)
+    };
+    if let Some(ref toolchain) = opts.toolchain {
+        cmd.arg(format!("+{}", toolchain));
+    }
+    locale::apply(&mut cmd);
+    if let Some(ref sccache) = opts.sccache {
+        sccache.apply(&mut cmd);
+    }
+    if let Some(ref target_dir) = opts.target_dir {
+        cmd.env("CARGO_TARGET_DIR", target_dir);
+    }
+    if opts.nice {
+        nice::apply(&mut cmd);
+    }
+    cmd
+}
+
+// The modes that do something other than print the expansion of the target,
+// or print it in some other form, if one was asked for.
+fn run_mode(opts: &Opts, config: &Config) -> io::Result<Option<i32>> {
+    let code = if opts.themes {
+        highlight::print_themes(opts.theme)
+    } else if let Some(ref prefix) = opts.complete_items {
+        complete::run(opts, config, prefix)
+    } else if let Some(ref range) = opts.bisect_dep {
+        bisect::dep(opts, config, range)
+    } else if let Some(ref range) = opts.bisect_nightly {
+        bisect::nightly(opts, config, range)
+    } else if let Some(ref location) = opts.emit_fixture {
+        fixture::emit(opts, config, location)
+    } else if let Some(ref ty) = opts.emit_doc_example {
+        doc_example::print(opts, config, ty)
+    } else if opts.plan {
+        plan::print(opts)
+    } else if opts.bug_report {
+        bug_report::print(opts, config)
+    } else if let Some(Format::Github) = opts.format {
+        github::print(opts, config)
+    } else if let Some(Format::Html) = opts.format {
+        output::html(opts, config)
+    } else if let Some(Format::Json) = opts.format {
+        json::print(opts, config)
+    } else if let Some(Format::Md) = opts.format {
+        markdown::print(opts, config)
+    } else if let Some(ref whence) = opts.whence {
+        whence::print(opts, config, whence)
+    } else if let Some(ref dir) = opts.snapshots {
+        snapshot::check(opts, config, dir)
+    } else if opts.check {
+        check::run(opts, config)
+    } else if let Some(top) = opts.stats {
+        stats::print(opts, config, top)
+    } else if opts.dedupe_report {
+        dedupe::report(opts, config)
+    } else if opts.watch {
+        watch::run(opts, config)
+    } else if opts.profile_macros {
+        profile::macros(opts, config)
+    } else if opts.self_profile {
+        profile::summary(opts, config)
+    } else if opts.feature_report {
+        features::report(opts, config)
+    } else if opts.diff {
+        source_diff::print(opts, config)
+    } else if let Some(ref version) = opts.against_published {
+        published::diff(opts, config, version)
+    } else if opts.grep.is_some() {
+        search::run(opts, config)
+    } else if let Some(ref dir) = opts.output_dir {
+        export::run(opts, config, dir)
+    } else {
+        return Ok(None);
+    };
+    code.map(Some)
+}
+
+// Windows has no `cat` or `iconv` to build a pipeline out of, so the
+// expansion is formatted and printed from here instead.
+#[cfg(windows)]
+fn cargo_expand() -> io::Result<i32> {
+    let config = Config::load()?;
+    let args = opts::read_arg_files(env::args_os().collect())?;
+    if args.iter().any(|arg| arg == "--help") {
+        return help::print();
+    }
+    if args.get(2).is_some_and(|arg| arg == "daemon") {
+        return daemon::command(&args);
+    }
+    let mut opts = Opts::parse(args.clone(), &config)?;
+    if opts.connect {
+        return daemon::connect(&opts, &args);
+    }
+    if let Some(encoding) = output_encoding(&opts) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "--encoding {} needs iconv, which Windows does not have; \
+                 --output-encoding utf8-bom and utf16le work with -o",
+                encoding,
+            ),
+        ));
+    }
+    version::check_toolchain(opts.toolchain.as_deref())?;
+    if let Some(members) = pick_packages(&mut opts)? {
+        return expand_members(&opts, &config, &args, &members);
+    }
+    if let Some(kind) = opts.every {
+        return expand_every(&opts, &config, &args, kind);
+    }
+    features::check(&opts)?;
+    platform::check(&opts)?;
+    features::enable_required(&mut opts);
+    cache::configure(&mut opts, &config)?;
+    sandbox::prepare(&mut opts)?;
+    suppressed::start(&opts)?;
+    if let Some(code) = run_mode(&opts, &config)? {
+        return Ok(code);
+    }
+
+    let mut outdir = TmpDir::create(opts.keep_tmp)?;
+    let outfile = outdir.path().join("expanded");
+
+    // Build cargo command
+    let mut cmd = cargo_command(&opts);
+    cmd.args(wrap_args(&opts, Some(&outfile)));
+    if opts.dry_run {
+        dry_run::print(&cmd, &[])?;
+        outdir.keep();
+        return Ok(0);
+    }
+
+    let code = reuse::build(&opts, &config, &mut cmd, &outfile)?;
+    if code != 0 {
+        return Ok(code);
+    }
+    rewrite::apply(&opts, &outfile)?;
+    let checksum = checksum(&opts, &outfile)?;
+    let code = capture::format(&opts, &config, &outfile)?;
+    if let Some(ref path) = opts.output {
+        return output::write(&opts, &code, path, checksum.as_deref());
+    }
+    if let Some(Print::Path) = opts.print {
+        return output::print_path(&opts, &config, &code);
+    }
+    if opts.copy {
+        return output::copy(&opts, &code, checksum.as_deref());
+    }
+
+    let code = delimit(&opts, || {
+        let stdout = io::stdout();
+        match write_code(&opts, code.as_bytes(), &mut stdout.lock()) {
+            Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(0),
+            Err(err) => return Err(err),
+            Ok(()) => {}
+        }
+        print_checksum(&opts, checksum.as_deref());
+        Ok(0)
+    })?;
+    hints::print(&opts, &outfile);
+    Ok(code)
+}
+
+#[cfg(unix)]
+fn cargo_expand() -> io::Result<i32> {
+    let args = opts::read_arg_files(env::args_os().collect())?;
+    if args.iter().any(|arg| arg == "--help") {
+        return help::print();
+    }
+    if args.last().unwrap() == "--filter-rustfmt" {
+        let log = suppressed::Log::open(suppressed::path_in_args(&args).as_deref());
+        filter_err("rustfmt", ignore_rustfmt_err, log);
+    }
+    if args.get(2).is_some_and(|arg| arg == "daemon") {
+        return daemon::command(&args);
+    }
+    let config = Config::load()?;
+    let mut opts = Opts::parse(args.clone(), &config)?;
+    if opts.connect {
+        return daemon::connect(&opts, &args);
+    }
+    version::check_toolchain(opts.toolchain.as_deref())?;
+    if let Some(members) = pick_packages(&mut opts)? {
+        return expand_members(&opts, &config, &args, &members);
+    }
+    if let Some(kind) = opts.every {
+        return expand_every(&opts, &config, &args, kind);
+    }
+    features::check(&opts)?;
+    platform::check(&opts)?;
+    features::enable_required(&mut opts);
+    cache::configure(&mut opts, &config)?;
+    sandbox::prepare(&mut opts)?;
+    suppressed::start(&opts)?;
+
+    macro_rules! shell {
+        ($($arg:expr)*) => {
+            &[$(OsStr::new(&$arg)),*]
+        };
+    }
+
+    if let Some(code) = run_mode(&opts, &config)? {
+        return Ok(code);
+    }
+
+    let which_rustfmt = which_rustfmt(&opts, &config)?;
+
+    let mut outdir = TmpDir::create(opts.keep_tmp)?;
+    let outfile = outdir.path().join("expanded");
+
+    // Build cargo command
+    let mut cmd = cargo_command(&opts);
+    cmd.args(wrap_args(&opts, Some(&outfile)));
+
+    if opts.dry_run {
+        let mut pipeline = Vec::new();
+        let mut cat = Command::new("cat");
+        cat.arg(&outfile);
+        pipeline.push(cat);
+        if let Some(ref fmt) = which_rustfmt {
+            let mut cmd = Command::new(fmt);
+            cmd.args(rustfmt_args(&opts));
+            pipeline.push(cmd);
+        }
+        if let Some(encoding) = output_encoding(&opts) {
+            let mut cmd = Command::new("iconv");
+            cmd.args(["-f", "UTF-8", "-t", encoding]);
+            pipeline.push(cmd);
+        }
+        dry_run::print(&cmd, &pipeline)?;
+        // Leave the directory behind so the printed commands can be run.
+        outdir.keep();
+        return Ok(0);
+    }
+
+    // Pipe to a tmp file to separate out any println output from build scripts
+    let code = reuse::build(&opts, &config, &mut cmd, &outfile)?;
+    if code != 0 {
+        return Ok(code);
+    }
+
+    rewrite::apply(&opts, &outfile)?;
+    let checksum = checksum(&opts, &outfile)?;
+    if opts.output.is_some() || opts.print.is_some() || opts.copy {
+        let code = capture::format(&opts, &config, &outfile)?;
+        if let Some(ref path) = opts.output {
+            return output::write(&opts, &code, path, checksum.as_deref());
+        }
+        if let Some(Print::Path) = opts.print {
+            return output::print_path(&opts, &config, &code);
+        }
+        return output::copy(&opts, &code, checksum.as_deref());
+    }
+    if which_rustfmt.is_none() && opts.formatter != Some(Formatter::None) {
+        pretty::format_file(&opts, &outfile)?;
+    }
+
+    cmd = Command::new("cat");
+    cmd.arg(&outfile);
+
+    // Pipe to rustfmt
+    let _wait = match which_rustfmt {
+        Some(ref fmt) => {
+            let args: Vec<_> = env::args_os().collect();
+            let mut filter_rustfmt = Vec::new();
+            filter_rustfmt.extend(args.iter().map(OsString::as_os_str));
+            filter_rustfmt.push(OsStr::new("--filter-rustfmt"));
+
+            let rustfmt_args = rustfmt_args(&opts);
+            let mut rustfmt = vec![fmt.as_os_str()];
+            rustfmt.extend(rustfmt_args.iter().map(OsString::as_os_str));
+
+            Some((
+                cmd.pipe_to(&rustfmt, None)?,
+                cmd.pipe_to(shell!("cat"), Some(&filter_rustfmt))?,
+            ))
+        }
+        None => None,
+    };
+
+    // Color the code on its way out, before it goes to iconv if the output
+    // encoding is not UTF-8.
+    let code = if opts.output_colored() {
+        delimit(&opts, || print_code(&opts, cmd))?
+    } else {
+        let _wait = match output_encoding(&opts) {
+            Some(encoding) => Some(cmd.pipe_to(shell!("iconv" "-f" "UTF-8" "-t" encoding), None)?),
+            None => None,
+        };
+        delimit(&opts, || run(cmd))?
+    };
+    if code == 0 {
+        print_checksum(&opts, checksum.as_deref());
+        hints::print(&opts, &outfile);
+    }
+    Ok(code)
+}
+
+#[cfg(unix)]
+fn print_code(opts: &Opts, mut cmd: Command) -> io::Result<i32> {
+    cmd.stdout(Stdio::piped());
+    let mut child = cmd.spawn()?;
+    interrupt::track_child(&child);
+    let mut iconv = match output_encoding(opts) {
+        Some(encoding) => {
+            let mut iconv = Command::new("iconv");
+            iconv.args(["-f", "UTF-8", "-t", encoding]);
+            iconv.stdin(Stdio::piped());
+            Some(iconv.spawn()?)
+        }
+        None => None,
+    };
+
+    let written = {
+        let stdout = io::stdout();
+        let mut out: Box<dyn Write> = match iconv {
+            Some(ref mut iconv) => Box::new(iconv.stdin.take().unwrap()),
+            None => Box::new(stdout.lock()),
+        };
+        let code = BufReader::new(child.stdout.take().unwrap());
+        write_code(opts, code, &mut out)
+    };
+
+    let status = child.wait();
+    interrupt::untrack_child(&child);
+    if let Some(iconv) = iconv {
+        wait(iconv)?;
+    }
+    match written {
+        // Whatever was reading the output stopped, like `head` does.
+        Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(0),
+        Err(err) => return Err(err),
+        Ok(()) => {}
+    }
+    let status = status?;
+    Ok(status.code().unwrap_or(1))
+}
+
+// Write formatted code out a line at a time, colored if it should be.
+fn write_code<R: BufRead>(opts: &Opts, code: R, out: &mut dyn Write) -> io::Result<()> {
+    let mut highlighter = if opts.output_colored() {
+        Some(Highlighter::new(opts.theme))
+    } else {
+        None
+    };
+    code.lines().try_for_each(|line| {
+        let line = line?;
+        match highlighter {
+            Some(ref mut highlighter) => writeln!(out, "{}", highlighter.line(&line)),
+            None => writeln!(out, "{}", line),
+        }
+    })
+}
+
+// --checksum: the SHA-256 of the expansion once edited but before it is
+// formatted, so that neither the width of the terminal nor --max-width and
+// --indent change it. The sum is of its lines in UTF-8 with `\n` endings and
+// no trailing whitespace, so that it is the same on every platform too.
+fn checksum(opts: &Opts, outfile: &Path) -> io::Result<Option<String>> {
+    if !opts.checksum {
+        return Ok(None);
+    }
+    Ok(Some(sum_lines(&fs::read_to_string(outfile)?)))
+}
+
+fn sum_lines(code: &str) -> String {
+    let mut checksum = Sha256::new();
+    for line in code.lines() {
+        checksum.update(line.trim_end().as_bytes());
+        checksum.update(b"\n");
+    }
+    checksum.finish()
+}
+
+fn print_checksum(opts: &Opts, checksum: Option<&str>) {
+    if let Some(checksum) = checksum {
+        let _ = writeln!(
+            &mut io::stderr(),
+            "sha256: {} (target={})",
+            checksum,
+            opts.target_label(),
+        );
+    }
+}
+
+// With --delimit, bracket the output of one expansion in markers naming the
+// target it came from.
+fn delimit<F>(opts: &Opts, f: F) -> io::Result<i32>
+where
+    F: FnOnce() -> io::Result<i32>,
+{
+    let delimiter = match opts.delimit {
+        Some(ref delimiter) => delimiter,
+        None => return f(),
+    };
+
+    let target = opts.target_label();
+    let stdout = io::stdout();
+    writeln!(stdout.lock(), "{} begin target={}", delimiter, target)?;
+    let code = f()?;
+    writeln!(stdout.lock(), "{} end target={}", delimiter, target)?;
+    Ok(code)
+}
+
+fn rustfmt_args(opts: &Opts) -> Vec<OsString> {
+    let mut config = Vec::new();
+    if let Some(width) = term::max_width(opts.max_width) {
+        config.push(format!("max_width={}", width));
+    }
+    match opts.indent {
+        Some(Indent::Tabs) => config.push("hard_tabs=true".to_owned()),
+        Some(Indent::Spaces(n)) => config.push(format!("tab_spaces={}", n)),
+        None => {}
+    }
+
+    if config.is_empty() {
+        Vec::new()
+    } else {
+        vec!["--config".into(), config.join(",").into()]
+    }
+}
+
+// The --encoding to print in, unless it is UTF-8, which everything in the
+// pipeline writes anyway.
+fn output_encoding(opts: &Opts) -> Option<&str> {
+    opts.encoding.as_deref().filter(|encoding| {
+        let encoding = encoding.to_ascii_lowercase().replace(['-', '_'], "");
+        encoding != "utf8"
+    })
+}
+
+#[cfg(unix)]
+fn run(mut cmd: Command) -> io::Result<i32> {
+    wait(cmd.spawn()?).map(|status| status.code().unwrap_or(1))
+}
+
+fn wait(mut child: Child) -> io::Result<ExitStatus> {
+    interrupt::track_child(&child);
+    let status = child.wait();
+    interrupt::untrack_child(&child);
+    status
+}
+
+// Run cargo with its stderr filtered. If the build broke in a way that points
+// at stale incremental or fingerprint state, try once more without
+// incremental compilation before giving up.
+fn run_cargo(opts: &Opts, cmd: &mut Command) -> io::Result<i32> {
+    run_cargo_with(opts, cmd, &mut |_| {})
+}
+
+// The same, also handing each line of cargo's stderr to `on_line`, hidden or
+// not.
+fn run_cargo_with(
+    opts: &Opts,
+    cmd: &mut Command,
+    on_line: &mut dyn FnMut(&str),
+) -> io::Result<i32> {
+    // Piped or redirected stderr gets a one-line summary in place of cargo's
+    // progress lines, unless those were asked for with --verbose.
+    let hide_progress = !stderr_isatty() && !opts.is_verbose();
+
+    let mut log = suppressed::Log::open(opts.suppressed_log.as_deref());
+
+    let (code, stale) = run_filtered(cmd, hide_progress, &mut log, on_line)?;
+    if code == 0 || !stale {
+        return Ok(code);
+    }
+
+    let _ = writeln!(
+        &mut io::stderr(),
+        "note: expansion failed on stale incremental artifacts; retrying with CARGO_INCREMENTAL=0"
+    );
+    cmd.env("CARGO_INCREMENTAL", "0");
+    let (code, stale) = run_filtered(cmd, hide_progress, &mut log, on_line)?;
+    if code != 0 && stale {
+        let _ = writeln!(
+            &mut io::stderr(),
+            "note: run `cargo clean` to remove the stale artifacts and try again"
+        );
+    }
+    Ok(code)
+}
+
+fn run_filtered(
+    cmd: &mut Command,
+    hide_progress: bool,
+    log: &mut suppressed::Log,
+    on_line: &mut dyn FnMut(&str),
+) -> io::Result<(i32, bool)> {
+    cmd.stderr(Stdio::piped());
+    let start = Instant::now();
+    let mut child = cmd.spawn()?;
+    interrupt::track_child(&child);
+
+    let mut stale = false;
+    let mut compiled = 0;
+    let mut hidden = false;
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = Vec::new();
+    while stderr.read_until(b'\n', &mut line)? != 0 {
+        let text = String::from_utf8_lossy(&line);
+        on_line(&text);
+        stale |= is_stale_artifact_err(&text);
+        if hide_progress && is_progress(&text) {
+            hidden = true;
+            if text.trim_start().starts_with("Compiling ") {
+                compiled += 1;
+            }
+            log.record("cargo", "progress", &text);
+        } else if let Some(rule) = cargo_err_rule(&text) {
+            log.record("cargo", rule, &text);
+        } else {
+            let _ = io::stderr().write_all(&line);
+        }
+        line.clear();
+    }
+
+    let status = child.wait();
+    interrupt::untrack_child(&child);
+    let status = status?;
+    if hidden {
+        let elapsed = start.elapsed();
+        let _ = writeln!(
+            &mut io::stderr(),
+            "built {} crate{} in {}.{:01}s",
+            compiled,
+            if compiled == 1 { "" } else { "s" },
+            elapsed.as_secs(),
+            elapsed.subsec_millis() / 100,
+        );
+    }
+    Ok((status.code().unwrap_or(1), stale))
+}
+
+// Cargo's status lines, like `   Compiling serde v1.0.0`.
+fn is_progress(line: &str) -> bool {
+    let statuses = [
+        "Compiling",
+        "Checking",
+        "Fresh",
+        "Finished",
+        "Blocking",
+        "Downloading",
+        "Downloaded",
+        "Updating",
+        "Locking",
+        "Adding",
+        "Updated",
+    ];
+    let status = line.trim_start().split(' ').next().unwrap_or("");
+    line.starts_with(' ') && statuses.contains(&status)
+}
+
+#[cfg(unix)]
+struct Wait(Vec<Child>);
+
+#[cfg(unix)]
+impl Drop for Wait {
+    fn drop(&mut self) {
+        for child in self.0.drain(..) {
+            if let Err(err) = wait(child) {
+                let _ = writeln!(&mut io::stderr(), "{}", err);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+trait PipeTo {
+    fn pipe_to(&mut self, out: &[&OsStr], err: Option<&[&OsStr]>) -> io::Result<Wait>;
+}
+
+#[cfg(unix)]
+impl PipeTo for Command {
+    fn pipe_to(&mut self, out: &[&OsStr], err: Option<&[&OsStr]>) -> io::Result<Wait> {
+        self.stdout(Stdio::piped());
+        if err.is_some() {
+            self.stderr(Stdio::piped());
+        }
+
+        let mut child = self.spawn()?;
+        interrupt::track_child(&child);
+
+        *self = Command::new(out[0]);
+        self.args(&out[1..]);
+        self.stdin(Stdio::from(child.stdout.take().unwrap()));
+
+        match err {
+            None => Ok(Wait(vec![child])),
+            Some(err) => {
+                let mut errcmd = Command::new(err[0]);
+                errcmd.args(&err[1..]);
+                errcmd.stdin(Stdio::from(child.stderr.take().unwrap()));
+                errcmd.stdout(Stdio::null());
+                errcmd.stderr(Stdio::inherit());
+                let spawn = errcmd.spawn()?;
+                interrupt::track_child(&spawn);
+                Ok(Wait(vec![spawn, child]))
+            }
+        }
+    }
+}
+
+// Based on https://github.com/rsolomo/cargo-check
+fn wrap_args(opts: &Opts, outfile: Option<&PathBuf>) -> Vec<OsString> {
+    let mut args = vec!["rustc".into()];
+    if let Some(ref manifest_path) = opts.manifest_path {
+        args.push("--manifest-path".into());
+        args.push(manifest_path.clone());
+    }
+    args.extend(opts.cargo.iter().cloned());
+
+    // Cargo's stderr is a pipe to us, so it is told what to do rather than left
+    // to decide.
+    let setting = if opts.diagnostics_colored() {
+        "always"
+    } else {
+        "never"
+    };
+    args.push(format!("--color={}", setting).into());
+
+    args.push("--".into());
+    if let Some(path) = outfile {
+        args.push("-o".into());
+        args.push(path.into());
+    }
+    if opts.rustc_flags.is_empty() {
+        // Pick the right flags for this toolchain; `--rustc-flag` overrides.
+        args.extend(version::expansion_flags(opts.toolchain.as_deref()));
+    } else {
+        args.extend(opts.rustc_flags.iter().cloned());
+    }
+    args.extend(opts.rustc.iter().cloned());
+    args
+}
+
+fn which(config: &Config, cmd: &[&str]) -> io::Result<Option<OsString>> {
+    if let Some(which) = env::var_os(cmd[0].to_uppercase()) {
+        return Ok(if which.is_empty() { None } else { Some(which) });
+    }
+
+    // A tool pinned in the config must work; quietly going without it would
+    // hide a typo in the path.
+    if let Some(pinned) = config.tool(cmd[0]) {
+        return match probe(pinned, &cmd[1..]) {
+            Ok(true) => Ok(Some(pinned.into())),
+            Ok(false) => Err(io::Error::other(format!(
+                "{} pinned in config at `{}` exited unsuccessfully",
+                cmd[0], pinned,
+            ))),
+            Err(err) => Err(io::Error::new(
+                err.kind(),
+                format!("failed to run {} pinned in config at `{}`: {}", cmd[0], pinned, err),
+            )),
+        };
+    }
+
+    match probe(cmd[0], &cmd[1..]) {
+        Ok(true) => Ok(Some(cmd[0].into())),
+        _ => Ok(None),
+    }
+}
+
+// The rustfmt to format the expansion with, or None for the built-in formatter
+// or none at all as --formatter says.
+fn which_rustfmt(opts: &Opts, config: &Config) -> io::Result<Option<OsString>> {
+    match opts.formatter {
+        None => which(config, &["rustfmt"]),
+        Some(Formatter::Rustfmt) => match which(config, &["rustfmt"])? {
+            Some(fmt) => Ok(Some(fmt)),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "--formatter rustfmt given but rustfmt is not available",
+            )),
+        },
+        Some(Formatter::Builtin) | Some(Formatter::None) => Ok(None),
+    }
+}
+
+fn probe(program: &str, args: &[&str]) -> io::Result<bool> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(child.wait()?.success())
+}
+
+#[cfg(unix)]
+fn filter_err(source: &str, ignore: fn(&str) -> bool, mut log: suppressed::Log) -> ! {
+    let mut line = String::new();
+    while let Ok(n) = io::stdin().read_line(&mut line) {
+        if n == 0 {
+            break;
+        }
+        if ignore(&line) {
+            log.record(source, "all", &line);
+        } else {
+            let _ = write!(&mut io::stderr(), "{}", line);
+        }
+        line.clear();
+    }
+    process::exit(0);
+}
+
+#[cfg(unix)]
+fn ignore_rustfmt_err(_line: &str) -> bool {
+    true
+}
+
+fn ignore_cargo_err(line: &str) -> bool {
+    cargo_err_rule(line).is_some()
+}
+
+// Which rule, if any, keeps a line of cargo's stderr off the terminal.
+fn cargo_err_rule(line: &str) -> Option<&'static str> {
+    if line.trim().is_empty() {
+        return Some("blank");
+    }
+
+    let blacklist = [
+        "ignoring specified output filename because multiple outputs were \
+         requested",
+        "ignoring specified output filename for 'link' output because multiple \
+         outputs were requested",
+        // Older rustc ended this one with a period.
+        "ignoring --out-dir flag due to -o flag",
+        "due to multiple output types requested, the explicitly specified \
+         output file name will be adapted for each output type",
+    ];
+    blacklist.iter().find(|s| line.contains(*s)).copied()
+}
+
+fn is_stale_artifact_err(line: &str) -> bool {
+    let signatures = [
+        "encountered incremental compilation error",
+        "found unstable fingerprints",
+        "failed to load dep graph",
+        "could not create incremental compilation",
+        "for incremental compilation session directory",
+        "error: failed to read fingerprint",
+        "the incremental compilation cache",
+    ];
+    signatures.iter().any(|s| line.contains(s))
+}
@@ -0,0 +1,26 @@
+use std::env;
+use std::process::Command;
+
+// Run the build with its messages in the C locale, so that the lines of its
+// stderr picked out by their English wording, like the warnings about output
+// filenames that are kept off the terminal, read the same in any language. A
+// build script or linker that translates its messages would otherwise let
+// them through. Only messages are affected: whatever LC_ALL named for
+// everything else, like the character encoding, still holds.
+pub fn apply(cmd: &mut Command) {
+    if let Some(all) = env::var_os("LC_ALL").filter(|all| !all.is_empty()) {
+        cmd.env_remove("LC_ALL");
+        for category in [
+            "LC_CTYPE",
+            "LC_NUMERIC",
+            "LC_TIME",
+            "LC_COLLATE",
+            "LC_MONETARY",
+        ] {
+            cmd.env(category, &all);
+        }
+    }
+    cmd.env("LC_MESSAGES", "C");
+    // GNU gettext looks at LANGUAGE ahead of LC_MESSAGES.
+    cmd.env_remove("LANGUAGE");
+}
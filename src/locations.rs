@@ -0,0 +1,155 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::path::Path;
+
+use proc_macro2::Span;
+use quote::ToTokens;
+use syn::spanned::Spanned;
+use syn::{AttrStyle, File, Item};
+
+use opts::Opts;
+use provenance::{self, SourceModule};
+use syntax;
+
+// --source-locations: a comment like `// src/lib.rs:142` before each item of
+// the expansion, saying where in the source to find it. The expansion has no
+// spans of its own, so it is lined up with the source module by module, the
+// way --whence does it: an item goes to the one written with its label, a
+// derived impl to the derive on its type, and anything else only the expansion
+// has to the first item macro invoked after the item before it. Items nothing
+// can be found for, like what the compiler injects, come without one.
+pub fn render(opts: &Opts, expanded: &File) -> io::Result<String> {
+    let (_, mut module) = SourceModule::root(opts)?;
+    // A selected item is shown on its own, but is still looked for in its
+    // module.
+    if let Some(ref path) = opts.item {
+        let path = path.strip_prefix("crate::").unwrap_or(path);
+        let segments: Vec<&str> = path.split("::").collect();
+        for segment in &segments[..segments.len() - 1] {
+            let declared = module.items.as_ref().is_some_and(|items| {
+                items.iter().any(|item| match *item {
+                    Item::Mod(ref m) => m.ident == segment,
+                    _ => false,
+                })
+            });
+            if !declared {
+                break;
+            }
+            module = module.submodule(segment);
+        }
+    }
+
+    let package_dir = opts
+        .manifest_path
+        .as_ref()
+        .and_then(|p| Path::new(p).parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let mut out = String::new();
+    for attr in &expanded.attrs {
+        out.push_str(&attr.to_token_stream().to_string());
+        out.push('\n');
+    }
+    items(&expanded.items, &module, &package_dir, &mut out);
+    Ok(out)
+}
+
+fn items(expanded: &[Item], module: &SourceModule, package_dir: &Path, out: &mut String) {
+    let file = module
+        .file
+        .strip_prefix(package_dir)
+        .unwrap_or(&module.file)
+        .display()
+        .to_string();
+    let source = module.items.as_deref().unwrap_or_default();
+    let in_source: BTreeSet<String> = source.iter().map(syntax::item_label).collect();
+    // Each source item goes with the first of the items it could be, like the
+    // first of several impls for a type.
+    let mut unused: Vec<&Item> = source.iter().collect();
+    let mut after = 0;
+
+    for item in expanded {
+        let label = syntax::item_label(item);
+        let span = if provenance::is_injected(item) {
+            None
+        } else if let Some(at) = unused
+            .iter()
+            .position(|source| syntax::item_label(source) == label)
+        {
+            let written = unused.remove(at);
+            if let Some(index) = source.iter().position(|item| std::ptr::eq(item, written)) {
+                after = index + 1;
+            }
+            Some(written.span())
+        } else if provenance::is_derived(item) {
+            derive(source, &in_source, item)
+        } else {
+            invocation(source, after)
+        };
+        if let Some(span) = span {
+            out.push_str(&format!("// {}:{}\n", file, span.start().line));
+        }
+
+        match *item {
+            Item::Mod(ref m) if m.content.is_some() => {
+                let inner = module.submodule(&m.ident.to_string());
+                for attr in m
+                    .attrs
+                    .iter()
+                    .filter(|attr| matches!(attr.style, AttrStyle::Outer))
+                {
+                    out.push_str(&attr.to_token_stream().to_string());
+                    out.push('\n');
+                }
+                out.push_str(&m.vis.to_token_stream().to_string());
+                if m.unsafety.is_some() {
+                    out.push_str(" unsafe");
+                }
+                out.push_str(&format!(" mod {} {{\n", m.ident));
+                for attr in m
+                    .attrs
+                    .iter()
+                    .filter(|attr| !matches!(attr.style, AttrStyle::Outer))
+                {
+                    out.push_str(&attr.to_token_stream().to_string());
+                    out.push('\n');
+                }
+                let content = &m.content.as_ref().unwrap().1;
+                items(content, &inner, package_dir, out);
+                out.push_str("}\n");
+            }
+            _ => {
+                out.push_str(&item.to_token_stream().to_string());
+                out.push('\n');
+            }
+        }
+    }
+}
+
+// The derive on a type of the source that wrote this impl.
+fn derive(source: &[Item], in_source: &BTreeSet<String>, item: &Item) -> Option<Span> {
+    source
+        .iter()
+        .filter(|ty| !matches!(**ty, Item::Impl(_)))
+        .flat_map(syntax::item_attrs)
+        .filter(|attr| attr.path().is_ident("derive"))
+        .find(|attr| {
+            provenance::derives(std::slice::from_ref(*attr))
+                .iter()
+                .any(|derive| provenance::from_derive(source, in_source, item, derive))
+        })
+        .map(Spanned::span)
+}
+
+// The first item macro invoked in the source from `start` on, or failing
+// that before it.
+fn invocation(source: &[Item], start: usize) -> Option<Span> {
+    let start = start.min(source.len());
+    source[start..]
+        .iter()
+        .chain(&source[..start])
+        .find_map(|item| match *item {
+            Item::Macro(ref item) if item.ident.is_none() => Some(item.mac.span()),
+            _ => None,
+        })
+}
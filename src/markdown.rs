@@ -0,0 +1,99 @@
+use std::fs;
+use std::io::{self, Write};
+
+use syn::spanned::Spanned;
+use syn::{self, Item};
+
+use capture;
+use config::Config;
+use opts::Opts;
+use provenance;
+use syntax;
+
+// --format md: the expansion as Markdown to paste into an issue or pull
+// request, each item in a ```rust block under a heading with its label, and
+// the items of a module under a heading one level down. Code that fails to
+// parse goes in a single block. Written to the -o file if there is one,
+// otherwise printed.
+pub fn print(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let code = match capture::expand(opts, config)? {
+        Some(code) => code,
+        None => return Ok(1),
+    };
+
+    let title = match provenance::target_root(opts) {
+        Ok((name, _)) => format!("{} {}", name, opts.target_label()),
+        Err(_) => opts.target_label(),
+    };
+    let mut document = format!("# cargo expand {}\n", title);
+    match syn::parse_file(&code) {
+        Ok(file) => sections(&mut document, &code, &file.items, 2),
+        Err(_) => block(&mut document, &code),
+    }
+
+    if let Some(ref path) = opts.output {
+        fs::write(path, document).map_err(|err| {
+            let msg = format!("failed to write {}: {}", path.display(), err);
+            io::Error::new(err.kind(), msg)
+        })?;
+        let _ = writeln!(
+            &mut io::stderr(),
+            "wrote the expansion to {}",
+            path.display(),
+        );
+        return Ok(0);
+    }
+    let stdout = io::stdout();
+    match stdout.lock().write_all(document.as_bytes()) {
+        Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(0),
+        result => result.map(|()| 0),
+    }
+}
+
+fn sections(document: &mut String, code: &str, items: &[Item], level: usize) {
+    for item in items {
+        if provenance::is_injected(item) {
+            continue;
+        }
+        document.push_str(&format!(
+            "\n{} `{}`\n",
+            "#".repeat(level.min(6)),
+            label(item),
+        ));
+        match *item {
+            Item::Mod(ref m) if m.content.is_some() => {
+                let (_, ref content) = *m.content.as_ref().unwrap();
+                sections(document, code, content, level + 1);
+            }
+            _ => block(document, &syntax::lines(code, item.span())),
+        }
+    }
+}
+
+// A `const _` is headed by the impls in it, as derives like serde's wrap
+// them in one.
+fn label(item: &Item) -> String {
+    let impls: Vec<String> = syntax::const_block_items(item)
+        .iter()
+        .filter(|item| matches!(**item, Item::Impl(_)))
+        .map(|item| syntax::item_label(item))
+        .collect();
+    if impls.is_empty() {
+        syntax::item_label(item)
+    } else {
+        impls.join("`, `")
+    }
+}
+
+// A fence longer than any run of backticks in the code, such as in a doc
+// comment showing Markdown of its own.
+fn block(document: &mut String, code: &str) {
+    let longest = code.split(|ch| ch != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    document.push_str(&format!("\n{}rust\n{}", fence, code));
+    if !code.ends_with('\n') {
+        document.push('\n');
+    }
+    document.push_str(&fence);
+    document.push('\n');
+}
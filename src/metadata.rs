@@ -0,0 +1,310 @@
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde_json::{self, Value};
+
+use cargo_binary;
+use opts::{self, Opts};
+use project;
+
+// The output of `cargo metadata --no-deps` for the workspace being expanded.
+pub struct Metadata {
+    json: Value,
+}
+
+impl Metadata {
+    pub fn load(opts: &Opts) -> io::Result<Metadata> {
+        let mut cmd = Command::new(cargo_binary());
+        cmd.arg("metadata");
+        cmd.arg("--no-deps");
+        cmd.arg("--format-version=1");
+        if let Some(ref manifest_path) = opts.manifest_path {
+            cmd.arg("--manifest-path");
+            cmd.arg(manifest_path);
+        }
+        cmd.stderr(Stdio::null());
+
+        let output = cmd.output()?;
+        if !output.status.success() {
+            return Err(io::Error::other("failed to read cargo metadata"));
+        }
+        match serde_json::from_slice(&output.stdout) {
+            Ok(json) => Ok(Metadata { json }),
+            Err(err) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("failed to parse cargo metadata: {}", err),
+            )),
+        }
+    }
+
+    pub fn packages(&self) -> &[Value] {
+        match self.json["packages"].as_array() {
+            Some(packages) => packages,
+            None => &[],
+        }
+    }
+
+    pub fn target_directory(&self) -> Option<&str> {
+        self.json["target_directory"].as_str()
+    }
+
+    // The package whose manifest is the given one.
+    pub fn package(&self, manifest_path: &OsStr) -> Option<&Value> {
+        let manifest_path = Path::new(manifest_path);
+        self.packages().iter().find(|package| {
+            package["manifest_path"]
+                .as_str()
+                .is_some_and(|path| Path::new(path) == manifest_path)
+        })
+    }
+}
+
+// The directories of the packages a build of the workspace compiles from
+// local source, as opposed to a registry or git: its members and their path
+// dependencies.
+pub fn local_package_dirs(opts: &Opts) -> Option<Vec<PathBuf>> {
+    let json = resolved(opts)?;
+    let dirs = json["packages"]
+        .as_array()?
+        .iter()
+        .filter(|package| package["source"].is_null())
+        .filter_map(|package| package["manifest_path"].as_str())
+        .filter_map(|manifest_path| Path::new(manifest_path).parent())
+        .map(Path::to_path_buf)
+        .collect();
+    Some(dirs)
+}
+
+// How many crates that are not in the workspace a build of it could compile:
+// every package of the resolved dependency graph, as `cargo metadata` sees it,
+// less the workspace's own.
+pub fn dependency_count(opts: &Opts) -> Option<usize> {
+    let json = resolved(opts)?;
+    let packages = json["packages"].as_array()?.len();
+    let members = json["workspace_members"].as_array()?.len();
+    Some(packages.saturating_sub(members))
+}
+
+// The output of `cargo metadata` with the dependency graph resolved.
+fn resolved(opts: &Opts) -> Option<Value> {
+    let mut cmd = Command::new(cargo_binary());
+    cmd.arg("metadata");
+    cmd.arg("--format-version=1");
+    if let Some(ref manifest_path) = opts.manifest_path {
+        cmd.arg("--manifest-path");
+        cmd.arg(manifest_path);
+    }
+    for arg in &opts.cargo {
+        if arg == "--offline" || arg == "--frozen" || arg == "--locked" {
+            cmd.arg(arg);
+        }
+    }
+    cmd.stderr(Stdio::null());
+
+    let output = cmd.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+// The packages picked with `-p`/`--package`, which may be given more than
+// once, or with `--workspace` less those given to `--exclude`, as their name
+// and manifest in the order of the workspace. None if no package was picked,
+// or for --grep and --output-dir, which go through the workspace's targets
+// themselves.
+pub fn selected_packages(opts: &Opts) -> io::Result<Option<Vec<(String, OsString)>>> {
+    if opts.grep.is_some() || opts.output_dir.is_some() {
+        return Ok(None);
+    }
+    let mut specs = Vec::new();
+    let mut workspace = false;
+    let mut exclude = Vec::new();
+    let mut it = opts.cargo.iter();
+    while let Some(arg) = it.next() {
+        let arg = arg.to_string_lossy();
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, inline)) if arg.starts_with("--") => (name, Some(inline)),
+            _ => (arg.as_ref(), None),
+        };
+        let mut value = || match inline {
+            Some(inline) => Some(inline.to_owned()),
+            None => it.next().map(|value| value.to_string_lossy().into_owned()),
+        };
+        match name {
+            "--workspace" | "--all" => workspace = true,
+            "-p" | "--package" => specs.extend(value()),
+            "--exclude" => exclude.extend(value()),
+            _ => {
+                if let Some(spec) = name.strip_prefix("-p") {
+                    specs.push(spec.trim_start_matches('=').to_owned());
+                }
+            }
+        }
+    }
+    if !exclude.is_empty() && !workspace {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--exclude can only be used together with --workspace",
+        ));
+    }
+    if specs.is_empty() && !workspace {
+        return Ok(None);
+    }
+
+    let metadata = Metadata::load(opts)?;
+    let members = metadata.json["workspace_members"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let members: Vec<&Value> = metadata
+        .packages()
+        .iter()
+        .filter(|package| members.contains(&package["id"]))
+        .collect();
+    for spec in specs.iter().chain(&exclude) {
+        if !members.iter().any(|package| matches(package, spec)) {
+            let msg = format!("package `{}` is not a member of the workspace", spec);
+            if exclude.contains(spec) {
+                let _ = writeln!(&mut io::stderr(), "warning: {}", msg);
+            } else {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+        }
+    }
+
+    let packages: Vec<(String, OsString)> = members
+        .into_iter()
+        .filter(|package| workspace || specs.iter().any(|spec| matches(package, spec)))
+        .filter(|package| !exclude.iter().any(|spec| matches(package, spec)))
+        .filter_map(|package| {
+            let name = package["name"].as_str()?;
+            let manifest_path = package["manifest_path"].as_str()?;
+            Some((name.to_owned(), OsString::from(manifest_path)))
+        })
+        .collect();
+    if packages.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--exclude leaves no package of the workspace to expand",
+        ));
+    }
+    Ok(Some(packages))
+}
+
+// Whether `package` is the one named by `spec`: `name` or `name@version`.
+fn matches(package: &Value, spec: &str) -> bool {
+    let (name, version) = match spec.split_once('@') {
+        Some((name, version)) => (name, Some(version)),
+        None => (spec, None),
+    };
+    package["name"] == name && version.is_none_or(|version| package["version"] == version)
+}
+
+// The default members of a virtual workspace, as their name and manifest, if
+// that is what is being expanded and no package was picked with `-p` or
+// `--workspace`: cargo would refuse to build the manifest otherwise, having no
+// package in it. These are the members under `workspace.default-members`, or
+// all of them but the crate of `cargo xtask` if there is no such list.
+pub fn default_members(opts: &Opts) -> Option<Vec<(String, OsString)>> {
+    let picked = opts.cargo.iter().any(|arg| {
+        arg.to_str().is_some_and(|arg| {
+            arg == "--workspace"
+                || arg == "--all"
+                || arg.starts_with("--package")
+                || arg.starts_with("-p")
+        })
+    });
+    if picked {
+        return None;
+    }
+    let manifest_path = opts.manifest_path.as_ref()?;
+    let metadata = Metadata::load(opts).ok()?;
+    if metadata.package(manifest_path).is_some() {
+        return None;
+    }
+
+    let listed = project::lists_default_members(Path::new(manifest_path));
+    let members: Vec<(String, OsString)> = metadata.json["workspace_default_members"]
+        .as_array()?
+        .iter()
+        .filter_map(|id| {
+            let package = metadata
+                .packages()
+                .iter()
+                .find(|package| package["id"] == *id)?;
+            let name = package["name"].as_str()?;
+            let manifest_path = package["manifest_path"].as_str()?;
+            Some((name.to_owned(), OsString::from(manifest_path)))
+        })
+        .filter(|(_, manifest_path)| listed || !project::is_xtask(Path::new(manifest_path)))
+        .collect();
+    if members.is_empty() {
+        None
+    } else {
+        Some(members)
+    }
+}
+
+// The libs and bins of every workspace member not given to --exclude, as the
+// label to show for each and the options to expand it with: these options with
+// the target selected and the manifest pointed at its package.
+pub fn workspace_targets(metadata: &Metadata, opts: &Opts) -> Vec<(String, Opts)> {
+    let mut exclude = Vec::new();
+    let mut it = opts.cargo.iter();
+    while let Some(arg) = it.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--exclude" {
+            exclude.extend(it.next().map(|spec| spec.to_string_lossy().into_owned()));
+        } else if let Some(spec) = arg.strip_prefix("--exclude=") {
+            exclude.push(spec.to_owned());
+        }
+    }
+
+    let mut targets = Vec::new();
+    for package in metadata.packages() {
+        if exclude.iter().any(|spec| matches(package, spec)) {
+            continue;
+        }
+        let name = package["name"].as_str().unwrap_or("?");
+        let package_targets = match package["targets"].as_array() {
+            Some(package_targets) => package_targets,
+            None => continue,
+        };
+        for target in package_targets {
+            let kinds: Vec<&str> = target["kind"]
+                .as_array()
+                .map(|kinds| kinds.iter().filter_map(|kind| kind.as_str()).collect())
+                .unwrap_or_default();
+            let selection: Vec<OsString> = if kinds.contains(&"bin") {
+                vec!["--bin".into(), target["name"].as_str().unwrap_or("").into()]
+            } else if kinds
+                .iter()
+                .any(|kind| kind.ends_with("lib") || *kind == "proc-macro")
+            {
+                vec!["--lib".into()]
+            } else {
+                continue;
+            };
+
+            let mut opts = opts.clone();
+            opts.cargo = opts::without_packages(&opts.cargo);
+            opts.cargo.extend(selection);
+            opts.manifest_path = package["manifest_path"].as_str().map(OsString::from);
+            targets.push((format!("{} {}", name, opts.target_label()), opts));
+        }
+    }
+    targets
+}
+
+// The target of `package` matching a `--bin NAME`-style selection.
+pub fn target<'a>(package: &'a Value, kind: &str, name: &str) -> Option<&'a Value> {
+    package["targets"].as_array()?.iter().find(|target| {
+        target["name"] == name
+            && target["kind"]
+                .as_array()
+                .is_some_and(|kinds| kinds.iter().any(|k| k == kind))
+    })
+}
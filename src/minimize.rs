@@ -0,0 +1,216 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use proc_macro2::{TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::spanned::Spanned;
+use syn::{File, Item, UseTree};
+
+use syntax;
+
+// A `path/to/file.rs:LINE` argument.
+#[derive(Clone)]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+impl FromStr for Location {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = "expected FILE:LINE";
+        let colon = s.rfind(':').ok_or(expected)?;
+        let line = s[colon + 1..].parse().map_err(|_| expected)?;
+        Ok(Location {
+            file: PathBuf::from(&s[..colon]),
+            line,
+        })
+    }
+}
+
+// The item at a location in the source, cut out along with what it needs.
+pub struct Snippet {
+    // The macro invoked there, like `#[derive(Serialize)]` or `vec!`.
+    pub invocation: Option<String>,
+    // The name of the item, to find its expansion by.
+    pub name: String,
+    // The `use` statements of the file that the item needs.
+    pub uses: Vec<String>,
+    pub source: String,
+}
+
+// Build a self-contained bug report snippet out of the item at `location`: the
+// `use` statements it needs and its source, followed by what it expanded to.
+pub fn render(
+    location: &Location,
+    package_dir: Option<&Path>,
+    expanded: &File,
+) -> io::Result<String> {
+    let snippet = extract(location, package_dir)?;
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "// Reproducer for {} at {}:{}",
+        snippet
+            .invocation
+            .clone()
+            .unwrap_or_else(|| format!("`{}`", snippet.name)),
+        location.file.display(),
+        location.line,
+    );
+    for item in &snippet.uses {
+        out += item;
+        out += "\n";
+    }
+    out += "\n";
+    out += &snippet.source;
+    out += "\n\n// Expansion\n";
+    for item in syntax::items_for(&expanded.items, &snippet.name) {
+        out += &item.into_token_stream().to_string();
+        out += "\n";
+    }
+    Ok(out)
+}
+
+pub fn extract(location: &Location, package_dir: Option<&Path>) -> io::Result<Snippet> {
+    let path = match package_dir {
+        Some(dir) if !location.file.exists() => dir.join(&location.file),
+        _ => location.file.clone(),
+    };
+    let source = fs::read_to_string(&path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("failed to read {}: {}", path.display(), err),
+        )
+    })?;
+    let file = syn::parse_file(&source).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to parse {}: {}", path.display(), err),
+        )
+    })?;
+
+    let item = match item_at(&file.items, location.line) {
+        Some(item) => item,
+        None => {
+            return Err(io::Error::other(format!(
+                "no item at {}:{}",
+                location.file.display(),
+                location.line,
+            )))
+        }
+    };
+    let name = match syntax::item_ident(item) {
+        Some(ident) => ident.to_string(),
+        None => {
+            return Err(io::Error::other(format!(
+                "the item at {}:{} has no name to find its expansion by",
+                location.file.display(),
+                location.line,
+            )))
+        }
+    };
+
+    let mut used = BTreeSet::new();
+    collect_idents(item.to_token_stream(), &mut used);
+
+    let mut uses = Vec::new();
+    for item in &file.items {
+        if let Item::Use(ref item) = *item {
+            if imports_any(&item.tree, &used) {
+                uses.push(source_text(&source, item));
+            }
+        }
+    }
+    Ok(Snippet {
+        invocation: invocation_at(&source, item, location.line),
+        name,
+        uses,
+        source: source_text(&source, item),
+    })
+}
+
+// The innermost item, looking into inline modules, whose source covers `line`.
+fn item_at(items: &[Item], line: usize) -> Option<&Item> {
+    let item = items.iter().find(|item| {
+        let span = item.span();
+        span.start().line <= line && line <= span.end().line
+    })?;
+    if let Item::Mod(ref m) = *item {
+        if let Some((_, ref content)) = m.content {
+            if let Some(inner) = item_at(content, line) {
+                return Some(inner);
+            }
+        }
+    }
+    Some(item)
+}
+
+// Describe the macro invoked on `line`: an attribute such as a derive, or a
+// `name!` call.
+fn invocation_at(source: &str, item: &Item, line: usize) -> Option<String> {
+    for attr in syntax::item_attrs(item) {
+        let span = attr.span();
+        if span.start().line <= line && line <= span.end().line {
+            return Some(format!("`{}`", source_text(source, attr).trim()));
+        }
+    }
+
+    let tokens: Vec<TokenTree> = flatten(item.to_token_stream());
+    tokens
+        .windows(2)
+        .find_map(|pair| match (&pair[0], &pair[1]) {
+            (TokenTree::Ident(ident), TokenTree::Punct(bang))
+                if bang.as_char() == '!' && ident.span().start().line == line =>
+            {
+                Some(format!("`{}!`", ident))
+            }
+            _ => None,
+        })
+}
+
+fn flatten(tokens: TokenStream) -> Vec<TokenTree> {
+    let mut flat = Vec::new();
+    for tt in tokens {
+        match tt {
+            TokenTree::Group(group) => flat.extend(flatten(group.stream())),
+            tt => flat.push(tt),
+        }
+    }
+    flat
+}
+
+fn collect_idents(tokens: TokenStream, idents: &mut BTreeSet<String>) {
+    for tt in flatten(tokens) {
+        if let TokenTree::Ident(ident) = tt {
+            idents.insert(ident.to_string());
+        }
+    }
+}
+
+// Whether a `use` brings in any of the names used, counting globs as yes.
+fn imports_any(tree: &UseTree, used: &BTreeSet<String>) -> bool {
+    match *tree {
+        UseTree::Path(ref path) => imports_any(&path.tree, used),
+        UseTree::Name(ref name) => used.contains(&name.ident.to_string()),
+        UseTree::Rename(ref rename) => used.contains(&rename.rename.to_string()),
+        UseTree::Glob(_) => true,
+        UseTree::Group(ref group) => group.items.iter().any(|tree| imports_any(tree, used)),
+    }
+}
+
+// The original text of an item, whole lines from its first to its last.
+fn source_text<T: Spanned>(source: &str, node: &T) -> String {
+    let span = node.span();
+    let lines: Vec<&str> = source
+        .lines()
+        .skip(span.start().line - 1)
+        .take(span.end().line + 1 - span.start().line)
+        .collect();
+    lines.join("\n")
+}
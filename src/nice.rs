@@ -0,0 +1,57 @@
+use std::process::Command;
+
+#[cfg(unix)]
+use libc;
+#[cfg(windows)]
+use windows_sys::Win32::System::Threading::BELOW_NORMAL_PRIORITY_CLASS;
+
+// How far --nice lowers the CPU priority of the build, on nice's scale.
+#[cfg(unix)]
+const NICENESS: libc::c_int = 10;
+
+// --nice: run the build, and so every rustc it starts, at low priority, for
+// expansions done in the background of other work. On Linux the build also
+// only gets the disk when nothing else wants it.
+#[cfg(unix)]
+pub fn apply(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    let pre_exec = || {
+        // Lowering our own priority cannot fail, and should it anyway the
+        // build is better run at normal priority than not at all.
+        unsafe {
+            libc::setpriority(libc::PRIO_PROCESS, 0, NICENESS);
+        }
+        idle_io();
+        Ok(())
+    };
+    unsafe {
+        cmd.pre_exec(pre_exec);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn idle_io() {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+    unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        );
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn idle_io() {}
+
+#[cfg(windows)]
+pub fn apply(cmd: &mut Command) {
+    use std::os::windows::process::CommandExt;
+
+    // Processes started by one in this class inherit it.
+    cmd.creation_flags(BELOW_NORMAL_PRIORITY_CLASS);
+}
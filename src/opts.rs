@@ -0,0 +1,1260 @@
+use std::env;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use bisect::{DateRange, DepRange};
+use cache;
+use config::Config;
+use highlight::{self, Theme};
+use minimize::Location;
+use project;
+use sccache::Sccache;
+use tty;
+use whence::Whence;
+use cargo_binary;
+
+// The `cargo expand` command line, split into the options cargo expand handles
+// itself and everything passed through to `cargo rustc`.
+#[derive(Clone)]
+pub struct Opts {
+    // Arguments for `cargo rustc`, before the `--`.
+    pub cargo: Vec<OsString>,
+    // Arguments for rustc, after the `--`.
+    pub rustc: Vec<OsString>,
+    // Replacements for the expansion flags picked for this toolchain.
+    pub rustc_flags: Vec<OsString>,
+    pub manifest_path: Option<OsString>,
+    // Every target of this kind to expand in turn, from --tests, --bins etc.
+    pub every: Option<&'static str>,
+    pub max_width: Option<usize>,
+    pub indent: Option<Indent>,
+    pub delimit: Option<String>,
+    pub keep_tmp: bool,
+    // Set by --show-doc-hidden or --hide-doc-hidden; hidden by default.
+    pub doc_hidden: Option<bool>,
+    pub redact: bool,
+    pub minimize_around: Option<Location>,
+    pub grep: Option<String>,
+    pub bisect_dep: Option<DepRange>,
+    pub bisect_nightly: Option<DateRange>,
+    // Rustup toolchain to build with, instead of the one running us, as given
+    // with --toolchain.
+    pub toolchain: Option<String>,
+    pub yes: bool,
+    pub dry_run: bool,
+    pub bug_report: bool,
+    pub format: Option<Format>,
+    // Most lines of expanded code to allow before it is reported.
+    pub size_budget: Option<usize>,
+    // Directory of snapshots to check the expansion against.
+    pub snapshots: Option<PathBuf>,
+    // Where to write a JUnit report of the snapshot checks.
+    pub junit: Option<PathBuf>,
+    pub update_snapshots: bool,
+    // To normalize snapshots with the built-in rules, and the patterns of
+    // --scrub=REGEX on top of them.
+    pub scrub: Option<Vec<String>>,
+    // Check that the expansion compiles rather than print it.
+    pub check: bool,
+    // Print statistics of the expansion, with this many of the macros that
+    // generated the most lines.
+    pub stats: Option<usize>,
+    // Group the generated impls that are the same but for their type.
+    pub dedupe_report: bool,
+    pub review: bool,
+    pub watch: bool,
+    pub profile_macros: bool,
+    pub self_profile: bool,
+    pub flamegraph: Option<PathBuf>,
+    pub feature_report: bool,
+    pub output_dir: Option<PathBuf>,
+    // File to write the expansion to instead of stdout.
+    pub output: Option<PathBuf>,
+    // To put the expansion on the clipboard instead of stdout.
+    pub copy: bool,
+    pub print: Option<Print>,
+    // Character encoding to print the expansion in.
+    pub encoding: Option<String>,
+    pub output_encoding: OutputEncoding,
+    // Where to record the lines of tool output that were hidden.
+    pub suppressed_log: Option<PathBuf>,
+    pub plan: bool,
+    pub sccache: Option<Sccache>,
+    // Target directory for the expansion build, if not the workspace's.
+    pub target_dir: Option<PathBuf>,
+    pub nice: bool,
+    // Show the expansion kept from an earlier run if the crate is unchanged,
+    // rather than building it again.
+    pub reuse: bool,
+    // Have the workspace's daemon expand the crate.
+    pub connect: bool,
+    // Trust the workspace to run its build scripts and proc macros.
+    pub trust: bool,
+    // Build the expansion with no network and little writable.
+    pub sandbox: bool,
+    pub complete_items: Option<String>,
+    // Path of the item to show rather than the whole crate.
+    pub item: Option<String>,
+    // Show the item as written in the source above the expansion of it.
+    pub with_source: bool,
+    // Mark each item with where it is in the source.
+    pub source_locations: bool,
+    // The line of the expansion to tell the macro invocation of.
+    pub whence: Option<Whence>,
+    pub emit_fixture: Option<Location>,
+    pub only_macro: Option<String>,
+    // Show only the impls that derives generated.
+    pub derives: bool,
+    // Show trait assertions in full rather than folded into comments.
+    pub show_assertions: bool,
+    pub diff: bool,
+    // The published version of the package to diff the expansion against.
+    pub against_published: Option<String>,
+    pub emit_doc_example: Option<String>,
+    // Set by --color or cargo's CARGO_TERM_COLOR, for both streams.
+    pub color: Option<Color>,
+    // Whether to color cargo's diagnostics on stderr.
+    pub color_diagnostics: Option<Color>,
+    // Whether to color the expanded code on stdout.
+    pub color_output: Option<Color>,
+    pub checksum: bool,
+    // Colors to highlight the expansion with.
+    pub theme: &'static Theme,
+    pub themes: bool,
+    // Set by --formatter, else rustfmt if it is available and the built-in
+    // formatter if not.
+    pub formatter: Option<Formatter>,
+    // The crates whose macros to show expanded, folding the rest.
+    pub expand_from: Vec<String>,
+    // The crates whose macros to show folded.
+    pub keep_folded: Vec<String>,
+    pub show_dropped: bool,
+}
+
+#[derive(Clone, Copy)]
+pub enum Indent {
+    Spaces(usize),
+    Tabs,
+}
+
+// What to print in place of the expanded code, from --format.
+#[derive(Clone, Copy)]
+pub enum Format {
+    Github,
+    Html,
+    Json,
+    Md,
+}
+
+// What to print in place of the expanded code once it is written out, from
+// --print.
+#[derive(Clone, Copy)]
+pub enum Print {
+    Path,
+}
+
+// When to color a stream, from --color, --color-diagnostics or
+// --color-output.
+#[derive(Clone, Copy)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    // Auto colors if the stream can show colors.
+    fn enabled(self, can_show: bool) -> bool {
+        match self {
+            Color::Auto => can_show,
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+}
+
+// How to format the expanded code, from --formatter.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Formatter {
+    Rustfmt,
+    Builtin,
+    None,
+}
+
+// How to encode the files that expansions are written to.
+#[derive(Clone, Copy)]
+pub enum OutputEncoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+}
+
+impl OutputEncoding {
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            OutputEncoding::Utf8 => text.as_bytes().to_vec(),
+            OutputEncoding::Utf8Bom => {
+                let mut bytes = b"\xEF\xBB\xBF".to_vec();
+                bytes.extend_from_slice(text.as_bytes());
+                bytes
+            }
+            OutputEncoding::Utf16Le => {
+                let mut bytes = vec![0xFF, 0xFE];
+                for unit in text.encode_utf16() {
+                    bytes.extend_from_slice(&unit.to_le_bytes());
+                }
+                bytes
+            }
+        }
+    }
+}
+
+impl Opts {
+    pub fn parse<I>(it: I, config: &Config) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = OsString>,
+    {
+        let mut opts = Opts {
+            cargo: Vec::new(),
+            rustc: Vec::new(),
+            rustc_flags: Vec::new(),
+            manifest_path: None,
+            every: None,
+            max_width: None,
+            indent: None,
+            delimit: None,
+            keep_tmp: false,
+            doc_hidden: None,
+            redact: false,
+            minimize_around: None,
+            grep: None,
+            bisect_dep: None,
+            bisect_nightly: None,
+            toolchain: None,
+            yes: false,
+            dry_run: false,
+            bug_report: false,
+            format: None,
+            size_budget: None,
+            snapshots: None,
+            junit: None,
+            update_snapshots: false,
+            scrub: None,
+            check: false,
+            stats: None,
+            dedupe_report: false,
+            review: false,
+            watch: false,
+            profile_macros: false,
+            self_profile: false,
+            flamegraph: None,
+            feature_report: false,
+            output_dir: None,
+            output: None,
+            copy: false,
+            print: None,
+            encoding: None,
+            output_encoding: OutputEncoding::Utf8,
+            suppressed_log: None,
+            plan: false,
+            sccache: Sccache::configured(config),
+            target_dir: None,
+            nice: false,
+            reuse: cache::reuse(config),
+            connect: false,
+            trust: false,
+            sandbox: false,
+            complete_items: None,
+            item: None,
+            with_source: false,
+            source_locations: false,
+            whence: None,
+            emit_fixture: None,
+            only_macro: None,
+            derives: false,
+            show_assertions: false,
+            diff: false,
+            against_published: None,
+            emit_doc_example: None,
+            color: env::var("CARGO_TERM_COLOR")
+                .ok()
+                .and_then(|color| color.parse().ok()),
+            color_diagnostics: None,
+            color_output: None,
+            checksum: false,
+            theme: configured_theme(config)?,
+            themes: false,
+            formatter: None,
+            expand_from: Vec::new(),
+            keep_folded: Vec::new(),
+            show_dropped: false,
+        };
+
+        // Skip `cargo-expand expand`.
+        let args = expand_pipelines(it.into_iter().skip(2).collect(), config)?;
+        let mut ugly = false;
+        let mut it = args.into_iter();
+        while let Some(arg) = it.next() {
+            if arg == *"--" {
+                opts.rustc.extend(it);
+                break;
+            }
+
+            let (name, inline) = split_flag(&arg);
+            match name.as_str() {
+                "--rustc-flag" => opts.rustc_flags.extend(inline.or_else(|| it.next())),
+                "--manifest-path" => opts.manifest_path = inline.or_else(|| it.next()),
+                "--toolchain" => {
+                    let value = inline.or_else(|| it.next());
+                    let toolchain: String = parse_value(&name, value)?;
+                    opts.toolchain = Some(toolchain.trim_start_matches('+').to_owned());
+                }
+                "--tests" => opts.every = Some("test"),
+                "--benches" => opts.every = Some("bench"),
+                "--examples" => opts.every = Some("example"),
+                "--bins" => opts.every = Some("bin"),
+                "--all-targets" => opts.every = Some("all"),
+                "--max-width" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.max_width = Some(parse_value(&name, value)?);
+                }
+                "--indent" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.indent = Some(parse_value(&name, value)?);
+                }
+                "--delimit" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.delimit = Some(parse_value(&name, value)?);
+                }
+                "--keep-tmp" => opts.keep_tmp = true,
+                "--show-doc-hidden" => opts.doc_hidden = Some(true),
+                "--hide-doc-hidden" => opts.doc_hidden = Some(false),
+                "--redact" => opts.redact = true,
+                "--minimize-around" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.minimize_around = Some(parse_value(&name, value)?);
+                }
+                "--grep" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.grep = Some(parse_value(&name, value)?);
+                }
+                "--bisect-dep" => {
+                    let dep: String = parse_value(&name, inline.or_else(|| it.next()))?;
+                    let range: String = parse_value(&name, it.next())?;
+                    opts.bisect_dep = Some(DepRange::new(dep, &range).map_err(usage)?);
+                }
+                "--bisect-nightly" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.bisect_nightly = Some(parse_value(&name, value)?);
+                }
+                "--yes" | "-y" => opts.yes = true,
+                "--dry-run" => opts.dry_run = true,
+                "--bug-report" => opts.bug_report = true,
+                "--format" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.format = Some(parse_value(&name, value)?);
+                }
+                "--size-budget" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.size_budget = Some(parse_value(&name, value)?);
+                }
+                "--check-snapshots" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.snapshots = Some(parse_value(&name, value)?);
+                }
+                "--update" => opts.update_snapshots = true,
+                "--scrub" => {
+                    let patterns = opts.scrub.get_or_insert_with(Vec::new);
+                    if let Some(value) = inline {
+                        patterns.push(parse_value(&name, Some(value))?);
+                    }
+                }
+                "--check" => opts.check = true,
+                "--dedupe-report" => opts.dedupe_report = true,
+                "--stats" => {
+                    opts.stats = Some(match inline {
+                        Some(value) => parse_value(&name, Some(value))?,
+                        None => 10,
+                    });
+                }
+                "--review" => opts.review = true,
+                "--watch" => opts.watch = true,
+                "--profile-macros" => opts.profile_macros = true,
+                "--self-profile" => opts.self_profile = true,
+                "--feature-report" => opts.feature_report = true,
+                "--plan" => opts.plan = true,
+                "--sccache" => {
+                    if opts.sccache.is_none() {
+                        opts.sccache = Some(Sccache::default());
+                    }
+                }
+                "--no-sccache" => opts.sccache = None,
+                "--nice" => opts.nice = true,
+                "--reuse" => opts.reuse = true,
+                "--no-cache" => opts.reuse = false,
+                "--connect" => opts.connect = true,
+                "--trust" => opts.trust = true,
+                "--sandbox" => opts.sandbox = true,
+                "--derives" => opts.derives = true,
+                "--show-assertions" => opts.show_assertions = true,
+                "--show-dropped" => opts.show_dropped = true,
+                "--with-source" => opts.with_source = true,
+                "--source-locations" => opts.source_locations = true,
+                "--whence" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.whence = Some(parse_value(&name, value)?);
+                }
+                "--diff" => opts.diff = true,
+                "--against-published" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.against_published = Some(parse_value(&name, value)?);
+                }
+                "--checksum" => opts.checksum = true,
+                "--theme" => {
+                    let name: String = parse_value(&name, inline.or_else(|| it.next()))?;
+                    opts.theme = highlight::theme(&name).map_err(|err| {
+                        usage(format!("invalid value `{}` for --theme: {}", name, err))
+                    })?;
+                }
+                "--themes" => opts.themes = true,
+                "--ugly" => ugly = true,
+                "--expand-from" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.expand_from.push(parse_value(&name, value)?);
+                }
+                "--keep-folded" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.keep_folded.push(parse_value(&name, value)?);
+                }
+                "--formatter" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.formatter = Some(parse_value(&name, value)?);
+                }
+                "--color" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.color = Some(parse_value(&name, value)?);
+                }
+                "--color-diagnostics" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.color_diagnostics = Some(parse_value(&name, value)?);
+                }
+                "--color-output" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.color_output = Some(parse_value(&name, value)?);
+                }
+                "--only-macro" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.only_macro = Some(parse_value(&name, value)?);
+                }
+                "--emit-doc-example" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.emit_doc_example = Some(parse_value(&name, value)?);
+                }
+                "--emit-fixture" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.emit_fixture = Some(parse_value(&name, value)?);
+                }
+                "--complete-items" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.complete_items = Some(parse_value(&name, value)?);
+                }
+                "--encoding" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.encoding = Some(parse_value(&name, value)?);
+                }
+                "--output-encoding" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.output_encoding = parse_value(&name, value)?;
+                }
+                "--output" | "-o" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.output = Some(parse_value(&name, value)?);
+                }
+                "--copy" => opts.copy = true,
+                "--print" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.print = Some(parse_value(&name, value)?);
+                }
+                "--output-dir" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.output_dir = Some(parse_value(&name, value)?);
+                }
+                "--suppressed-log" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.suppressed_log = Some(parse_value(&name, value)?);
+                }
+                "--flamegraph" => {
+                    let value = inline.or_else(|| it.next());
+                    opts.flamegraph = Some(parse_value(&name, value)?);
+                    opts.self_profile = true;
+                }
+                "--report" => {
+                    let kind: String = parse_value(&name, inline.or_else(|| it.next()))?;
+                    if kind != "junit" {
+                        return Err(usage(format!(
+                            "invalid value `{}` for {}: expected `junit`",
+                            kind, name,
+                        )));
+                    }
+                    opts.junit = Some(parse_value(&name, it.next())?);
+                }
+                "--item" => {
+                    let value = inline.or_else(|| it.next());
+                    let item: String = parse_value(&name, value)?;
+                    set_item(&mut opts, item)?;
+                }
+                _ if is_item_path(&arg, opts.cargo.last()) => {
+                    set_item(&mut opts, arg.to_string_lossy().into_owned())?;
+                }
+                _ => opts.cargo.push(arg),
+            }
+        }
+
+        // --ugly: the code exactly as the compiler printed it, without
+        // formatting, coloring or hiding #[doc(hidden)] items, for tools that
+        // do their own.
+        if ugly {
+            opts.formatter = Some(Formatter::None);
+            opts.color_output = Some(Color::Never);
+            opts.doc_hidden = opts.doc_hidden.or(Some(true));
+        }
+
+        opts.check_exclusive()?;
+
+        if opts.with_source && opts.item.is_none() {
+            return Err(usage(
+                "--with-source needs the path of an item to show, as in \
+                 `cargo expand --with-source http::Handlers`"
+                    .to_owned(),
+            ));
+        }
+
+        if let Some(kind) = opts.every {
+            if let Some((picked, _)) = opts.selected_target() {
+                return Err(usage(format!(
+                    "--{} cannot be combined with --{}, which picks a single target",
+                    plural(kind),
+                    picked,
+                )));
+            }
+            if opts.output.is_some() {
+                return Err(usage(format!(
+                    "--{} expands several targets, which --output cannot write to one file; \
+                     redirect stdout instead",
+                    plural(kind),
+                )));
+            }
+        }
+
+        if opts.cargo.last().is_some_and(|arg| arg == "--test") {
+            // Expand the `test.rs` test by default.
+            opts.cargo.push("test".into());
+        }
+
+        if opts.cargo.last().is_some_and(|arg| arg == "--example") {
+            // Expand the `example.rs` example by default.
+            opts.cargo.push("example".into());
+        }
+
+        if opts.cargo.last().is_some_and(|arg| arg == "--bench") {
+            // Expand the `bench` bench by default.
+            opts.cargo.push("bench".into());
+        }
+
+        let bench = opts.selected_target().is_some_and(|(kind, _)| kind == "bench");
+        if bench && opts.profile().is_none() {
+            // Build benchmarks with the profile `cargo bench` uses, so that
+            // what is under cfg(debug_assertions) is left out as it is there.
+            opts.cargo.push("--profile=bench".into());
+        }
+
+        if opts.manifest_path.is_none() {
+            opts.manifest_path = project::locate_manifest();
+        }
+
+        if opts.sccache.as_ref().is_some_and(|sccache| !sccache.check()) {
+            opts.sccache = None;
+        }
+
+        Ok(opts)
+    }
+
+    // Each run does one thing and writes it to one place, so the options that
+    // pick what it does, or where the expansion goes, cannot be given more
+    // than one at a time, except that --format html and md pages can be
+    // written to the -o file and --grep tells the bisections what to look for.
+    fn check_exclusive(&self) -> io::Result<()> {
+        let format = self.format.map(|format| match format {
+            Format::Github => "--format github",
+            Format::Html => "--format html",
+            Format::Json => "--format json",
+            Format::Md => "--format md",
+        });
+        let bisect = self.bisect_dep.is_some() || self.bisect_nightly.is_some();
+        let modes = [
+            ("--themes", self.themes),
+            ("--complete-items", self.complete_items.is_some()),
+            ("--bisect-dep", self.bisect_dep.is_some()),
+            ("--bisect-nightly", self.bisect_nightly.is_some()),
+            ("--emit-fixture", self.emit_fixture.is_some()),
+            ("--emit-doc-example", self.emit_doc_example.is_some()),
+            ("--plan", self.plan),
+            ("--bug-report", self.bug_report),
+            (format.unwrap_or("--format"), format.is_some()),
+            ("--whence", self.whence.is_some()),
+            ("--check-snapshots", self.snapshots.is_some()),
+            ("--check", self.check),
+            ("--stats", self.stats.is_some()),
+            ("--dedupe-report", self.dedupe_report),
+            ("--watch", self.watch),
+            ("--profile-macros", self.profile_macros),
+            (
+                if self.flamegraph.is_some() {
+                    "--flamegraph"
+                } else {
+                    "--self-profile"
+                },
+                self.self_profile,
+            ),
+            ("--feature-report", self.feature_report),
+            ("--diff", self.diff),
+            ("--against-published", self.against_published.is_some()),
+            ("--grep", self.grep.is_some() && !bisect),
+            ("--output-dir", self.output_dir.is_some()),
+        ];
+        let sinks = [
+            ("--output", self.output.is_some()),
+            ("--print", self.print.is_some()),
+            ("--copy", self.copy),
+        ];
+        let given = |options: &[(&'static str, bool)]| -> Vec<&'static str> {
+            options
+                .iter()
+                .filter(|&&(_, given)| given)
+                .map(|&(name, _)| name)
+                .collect()
+        };
+        let modes = given(&modes);
+        let sinks = given(&sinks);
+        let page = matches!(self.format, Some(Format::Html) | Some(Format::Md));
+        let conflict = if modes.len() > 1 {
+            Some((modes[0], modes[1]))
+        } else if sinks.len() > 1 {
+            Some((sinks[0], sinks[1]))
+        } else if let (Some(&mode), Some(&sink)) = (modes.first(), sinks.first()) {
+            if page && sink == "--output" {
+                None
+            } else {
+                Some((mode, sink))
+            }
+        } else {
+            None
+        };
+        match conflict {
+            Some((first, second)) => Err(usage(format!(
+                "{} cannot be combined with {}",
+                first, second,
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // The target picked with `--lib`, `--bin NAME` etc., as its kind and, for
+    // anything but the lib, its name.
+    pub fn selected_target(&self) -> Option<(String, Option<String>)> {
+        let kinds = ["--lib", "--bin", "--test", "--example", "--bench"];
+        for (i, arg) in self.cargo.iter().enumerate() {
+            let (name, inline) = split_flag(arg);
+            if !kinds.contains(&name.as_str()) {
+                continue;
+            }
+            let kind = name[2..].to_owned();
+            if kind == "lib" {
+                return Some((kind, None));
+            }
+            let value = inline.or_else(|| self.cargo.get(i + 1).cloned());
+            return Some((
+                kind,
+                value.map(|value| value.to_string_lossy().into_owned()),
+            ));
+        }
+        None
+    }
+
+    // Name of the selected target as `kind` or `kind:name`, for labeling
+    // output.
+    pub fn target_label(&self) -> String {
+        match self.selected_target() {
+            Some((kind, Some(name))) => format!("{}:{}", kind, name),
+            Some((kind, None)) => kind,
+            None => "default".to_owned(),
+        }
+    }
+
+    // The profile picked with `--release` or `--profile NAME`, or None for
+    // cargo's default of `dev`.
+    pub fn profile(&self) -> Option<String> {
+        let mut profile = None;
+        let mut it = self.cargo.iter();
+        while let Some(arg) = it.next() {
+            let (name, inline) = split_flag(arg);
+            match name.as_str() {
+                "--release" | "-r" => profile = Some("release".to_owned()),
+                "--profile" => {
+                    profile = inline
+                        .or_else(|| it.next().cloned())
+                        .map(|value| value.to_string_lossy().into_owned());
+                }
+                _ => {}
+            }
+        }
+        profile
+    }
+
+    // The platform to expand for, from `--target` or cargo's
+    // CARGO_BUILD_TARGET, or None for the host.
+    pub fn platform(&self) -> Option<String> {
+        let mut it = self.cargo.iter();
+        while let Some(arg) = it.next() {
+            let (name, inline) = split_flag(arg);
+            if name == "--target" {
+                return inline
+                    .or_else(|| it.next().cloned())
+                    .map(|value| value.to_string_lossy().into_owned());
+            }
+        }
+        env::var("CARGO_BUILD_TARGET").ok()
+    }
+
+    // Features turned on with `--features`/`-F`, and whether `--all-features`
+    // was given.
+    pub fn features(&self) -> (Vec<String>, bool) {
+        let mut features = Vec::new();
+        let mut all = false;
+        let mut it = self.cargo.iter();
+        while let Some(arg) = it.next() {
+            let (name, inline) = split_flag(arg);
+            let value = match name.as_str() {
+                "--all-features" => {
+                    all = true;
+                    continue;
+                }
+                "--features" | "-F" => inline.or_else(|| it.next().cloned()),
+                // -Fserde, as cargo takes it too.
+                _ if name.starts_with("-F") => Some(OsString::from(&name[2..])),
+                _ => continue,
+            };
+            if let Some(value) = value {
+                let value = value.to_string_lossy();
+                features.extend(
+                    value
+                        .split([',', ' '])
+                        .filter(|feature| !feature.is_empty())
+                        .map(str::to_owned),
+                );
+            }
+        }
+        (features, all)
+    }
+
+    pub fn is_verbose(&self) -> bool {
+        self.cargo.iter().any(|arg| {
+            arg == "--verbose"
+                || arg.to_str().is_some_and(|arg| {
+                    arg.starts_with("-v") && arg[1..].chars().all(|ch| ch == 'v')
+                })
+        })
+    }
+
+    // Whether to have cargo color its diagnostics.
+    pub fn diagnostics_colored(&self) -> bool {
+        self.color_diagnostics
+            .or(self.color)
+            .unwrap_or(Color::Auto)
+            .enabled(tty::stderr_colors())
+    }
+
+    // Whether to color the expanded code.
+    pub fn output_colored(&self) -> bool {
+        self.color_output
+            .or(self.color)
+            .unwrap_or(Color::Auto)
+            .enabled(tty::stdout_colors())
+    }
+}
+
+impl FromStr for Indent {
+    type Err = String;
+
+    // `tabs`, `spaces` or `spaces:N`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = "expected `tabs`, `spaces` or `spaces:N`";
+        match s {
+            "tabs" => Ok(Indent::Tabs),
+            "spaces" => Ok(Indent::Spaces(4)),
+            _ => match s.strip_prefix("spaces:") {
+                Some(n) => match n.parse() {
+                    Ok(0) | Err(_) => Err(expected.to_owned()),
+                    Ok(n) => Ok(Indent::Spaces(n)),
+                },
+                None => Err(expected.to_owned()),
+            },
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "github" => Ok(Format::Github),
+            "html" => Ok(Format::Html),
+            "json" => Ok(Format::Json),
+            "md" => Ok(Format::Md),
+            _ => Err("expected `github`, `html`, `json` or `md`".to_owned()),
+        }
+    }
+}
+
+impl FromStr for Print {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(Print::Path),
+            _ => Err("expected `path`".to_owned()),
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => Err("expected `auto`, `always` or `never`".to_owned()),
+        }
+    }
+}
+
+impl FromStr for Formatter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rustfmt" => Ok(Formatter::Rustfmt),
+            "builtin" => Ok(Formatter::Builtin),
+            "none" => Ok(Formatter::None),
+            _ => Err("expected `rustfmt`, `builtin` or `none`".to_owned()),
+        }
+    }
+}
+
+impl FromStr for OutputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf8" => Ok(OutputEncoding::Utf8),
+            "utf8-bom" => Ok(OutputEncoding::Utf8Bom),
+            "utf16le" => Ok(OutputEncoding::Utf16Le),
+            _ => Err("expected `utf8`, `utf8-bom` or `utf16le`".to_owned()),
+        }
+    }
+}
+
+// The `theme` under `[highlight]` in the config, or else the default.
+fn configured_theme(config: &Config) -> io::Result<&'static Theme> {
+    let name = config
+        .section("highlight")
+        .and_then(|highlight| highlight.get("theme"))
+        .and_then(|theme| theme.as_str());
+    match name {
+        Some(name) => highlight::theme(name).map_err(|err| {
+            usage(format!("invalid theme `{}` under [highlight]: {}", name, err))
+        }),
+        None => Ok(&highlight::THEMES[0].1),
+    }
+}
+
+// `tests` for `test`, as the option taking every target of a kind is named,
+// and `all-targets` for all of them.
+pub fn plural(kind: &str) -> String {
+    match kind {
+        "all" => "all-targets".to_owned(),
+        "bench" => "benches".to_owned(),
+        _ => format!("{}s", kind),
+    }
+}
+
+fn set_item(opts: &mut Opts, item: String) -> io::Result<()> {
+    if let Some(ref given) = opts.item {
+        return Err(usage(format!(
+            "only one item path can be given, found `{}` and `{}`",
+            given, item,
+        )));
+    }
+    opts.item = Some(item);
+    Ok(())
+}
+
+// Whether an argument is a positional item path, rather than an option or the
+// value of the cargo option before it. Which cargo options take a value is
+// read from the help of `cargo rustc`, so an option it does not list is
+// assumed to take one, leaving the argument for cargo to make sense of.
+fn is_item_path(arg: &OsString, previous: Option<&OsString>) -> bool {
+    if !arg
+        .to_str()
+        .is_some_and(|arg| !arg.is_empty() && !arg.starts_with('-'))
+    {
+        return false;
+    }
+    let previous = match previous.and_then(|previous| previous.to_str()) {
+        Some(previous) => previous,
+        // Not valid UTF-8, or nothing before it.
+        None => return previous.is_none(),
+    };
+    let complete = !previous.starts_with('-')
+        || previous.contains('=')
+        // A short option with its value attached, like `-j4`.
+        || (!previous.starts_with("--") && previous.len() > 2);
+    complete || !cargo_takes_value(previous)
+}
+
+fn cargo_takes_value(option: &str) -> bool {
+    let output = Command::new(cargo_binary())
+        .args(["rustc", "--help"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+    let help = match output {
+        Ok(ref output) if output.status.success() => String::from_utf8_lossy(&output.stdout),
+        _ => return true,
+    };
+    // Lines like `  -p, --package [<SPEC>]  Package to build`.
+    for line in help.lines() {
+        let mut names = Vec::new();
+        let mut next = None;
+        for word in line.split_whitespace() {
+            if word.starts_with('-') {
+                names.push(word.trim_end_matches(',').trim_end_matches("..."));
+            } else {
+                next = Some(word);
+                break;
+            }
+        }
+        if names.contains(&option) {
+            return next.is_some_and(|next| next.starts_with('<') || next.starts_with("[<"));
+        }
+    }
+    true
+}
+
+// The arguments without those picking packages, -p, --workspace and
+// --exclude, for expanding one of the packages they picked.
+pub fn without_packages(args: &[OsString]) -> Vec<OsString> {
+    let mut kept = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        if arg == "--" {
+            kept.push(arg.clone());
+            kept.extend(it.cloned());
+            break;
+        }
+        let (name, inline) = split_flag(arg);
+        match name.as_str() {
+            "--workspace" | "--all" => {}
+            "-p" | "--package" | "--exclude" => {
+                if inline.is_none() {
+                    it.next();
+                }
+            }
+            _ if name.starts_with("-p") => {}
+            _ => kept.push(arg.clone()),
+        }
+    }
+    kept
+}
+
+// Replace each `@FILE` or `--args-file FILE` in a raw command line, before any
+// `--`, with the arguments in the file, one per line. Blank lines and lines
+// starting with `#` are skipped, and arguments read from a file are not looked
+// into for further files.
+pub fn read_arg_files(args: Vec<OsString>) -> io::Result<Vec<OsString>> {
+    let mut expanded = Vec::new();
+    let mut it = args.into_iter();
+    // `cargo-expand expand`.
+    expanded.extend(it.by_ref().take(2));
+    while let Some(arg) = it.next() {
+        if arg == *"--" {
+            expanded.push(arg);
+            expanded.extend(it);
+            break;
+        }
+        let (name, inline) = split_flag(&arg);
+        let path = if name == "--args-file" {
+            parse_value::<PathBuf>(&name, inline.or_else(|| it.next()))?
+        } else {
+            match arg.to_str().and_then(|arg| arg.strip_prefix('@')) {
+                Some(path) if !path.is_empty() => PathBuf::from(path),
+                _ => {
+                    expanded.push(arg);
+                    continue;
+                }
+            }
+        };
+        let content = fs::read_to_string(&path).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("failed to read arguments from {}: {}", path.display(), err),
+            )
+        })?;
+        expanded.extend(
+            content
+                .lines()
+                .map(|line| line.trim_end_matches('\r'))
+                .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+                .map(OsString::from),
+        );
+    }
+    Ok(expanded)
+}
+
+// Replace each `--pipeline NAME` with the options it stands for in the config,
+// after the config's `[defaults]` and then the project's own options from its
+// manifest, so that what is given on the command line comes later and wins.
+fn expand_pipelines(args: Vec<OsString>, config: &Config) -> io::Result<Vec<OsString>> {
+    let mut expanded = Vec::new();
+    let mut it = args.into_iter();
+    while let Some(arg) = it.next() {
+        if arg == *"--" {
+            expanded.push(arg);
+            expanded.extend(it);
+            break;
+        }
+        let (name, inline) = split_flag(&arg);
+        if name == "--pipeline" {
+            let pipeline: String = parse_value(&name, inline.or_else(|| it.next()))?;
+            expanded.extend(config.pipeline(&pipeline)?);
+        } else {
+            expanded.push(arg);
+        }
+    }
+
+    let mut manifest_path = None;
+    let mut target_given = false;
+    let mut it = expanded.iter();
+    while let Some(arg) = it.next() {
+        if arg == "--" {
+            break;
+        }
+        let (name, inline) = split_flag(arg);
+        match name.as_str() {
+            "--manifest-path" => manifest_path = inline.or_else(|| it.next().cloned()),
+            "--lib" | "--bin" | "--test" | "--example" | "--bench" | "--bins" | "--tests"
+            | "--examples" | "--benches" | "--all-targets" => target_given = true,
+            _ => {}
+        }
+    }
+    let mut args = config.defaults()?;
+    args.extend(project::configured_options(
+        manifest_path.as_ref(),
+        target_given,
+    )?);
+    args.extend(expanded);
+    Ok(args)
+}
+
+// Split `--flag=value` into its name and value. Anything else is returned
+// whole as the name.
+fn split_flag(arg: &OsString) -> (String, Option<OsString>) {
+    let arg = match arg.to_str() {
+        Some(arg) => arg,
+        None => return (String::new(), None),
+    };
+    match arg.find('=') {
+        Some(eq) if arg.starts_with("--") => (arg[..eq].to_owned(), Some(arg[eq + 1..].into())),
+        _ => (arg.to_owned(), None),
+    }
+}
+
+fn parse_value<T>(name: &str, value: Option<OsString>) -> io::Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = match value {
+        Some(value) => value,
+        None => return Err(usage(format!("{} requires a value", name))),
+    };
+    let string = value.to_string_lossy();
+    string
+        .parse()
+        .map_err(|err| usage(format!("invalid value `{}` for {}: {}", string, name, err)))
+}
+
+fn usage(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parse `cargo expand` with these arguments, for a manifest that does not
+    // exist so that nothing is read from it.
+    fn parse(args: &[&str], config: &str) -> io::Result<Opts> {
+        let args = [
+            "cargo-expand",
+            "expand",
+            "--manifest-path",
+            "/nonexistent/Cargo.toml",
+        ]
+        .iter()
+        .chain(args)
+        .map(OsString::from);
+        Opts::parse(args, &Config::from_toml(config))
+    }
+
+    fn error(args: &[&str]) -> String {
+        match parse(args, "") {
+            Ok(_) => panic!("{:?} parsed", args),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    fn cargo(opts: &Opts) -> Vec<&str> {
+        opts.cargo.iter().map(|arg| arg.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn cargo_and_rustc_args() {
+        let opts = parse(&["--lib", "-j4", "--", "-Zunstable-options"], "").unwrap();
+        assert_eq!(cargo(&opts), ["--lib", "-j4"]);
+        assert_eq!(opts.rustc, ["-Zunstable-options"]);
+        assert_eq!(opts.target_label(), "lib");
+    }
+
+    #[test]
+    fn values() {
+        let opts = parse(
+            &["--max-width=80", "--indent", "spaces:2", "-o", "out.rs"],
+            "",
+        )
+        .unwrap();
+        assert_eq!(opts.max_width, Some(80));
+        assert!(matches!(opts.indent, Some(Indent::Spaces(2))));
+        assert_eq!(opts.output, Some(PathBuf::from("out.rs")));
+        assert!(error(&["--max-width", "wide"]).contains("invalid value `wide` for --max-width"));
+        assert_eq!(error(&["--grep"]), "--grep requires a value");
+    }
+
+    #[test]
+    fn item_paths() {
+        let opts = parse(&["-j4", "inner::f"], "").unwrap();
+        assert_eq!(opts.item.as_deref(), Some("inner::f"));
+        assert_eq!(cargo(&opts), ["-j4"]);
+        let opts = parse(&["--features=serde", "--item", "S"], "").unwrap();
+        assert_eq!(opts.item.as_deref(), Some("S"));
+        assert!(error(&["--item", "a", "--item", "b"]).contains("only one item path"));
+    }
+
+    #[test]
+    fn default_targets() {
+        let opts = parse(&["--test"], "").unwrap();
+        assert_eq!(cargo(&opts), ["--test", "test"]);
+        let opts = parse(&["--bench", "b"], "").unwrap();
+        assert_eq!(cargo(&opts), ["--bench", "b", "--profile=bench"]);
+        let opts = parse(&["--bench", "b", "--release"], "").unwrap();
+        assert_eq!(opts.profile().as_deref(), Some("release"));
+    }
+
+    #[test]
+    fn every_target() {
+        let opts = parse(&["--tests"], "").unwrap();
+        assert_eq!(opts.every, Some("test"));
+        assert!(error(&["--tests", "--lib"]).contains("cannot be combined with --lib"));
+        assert!(error(&["--bins", "-o", "out.rs"]).contains("--output cannot write to one file"));
+        let opts = parse(&["--all-targets"], "").unwrap();
+        assert_eq!(opts.every, Some("all"));
+        assert!(
+            error(&["--all-targets", "--bin", "b"]).contains("--all-targets cannot be combined")
+        );
+    }
+
+    #[test]
+    fn ugly() {
+        let opts = parse(&["--ugly"], "").unwrap();
+        assert!(opts.formatter == Some(Formatter::None));
+        assert!(matches!(opts.color_output, Some(Color::Never)));
+        assert_eq!(opts.doc_hidden, Some(true));
+        let opts = parse(&["--ugly", "--hide-doc-hidden"], "").unwrap();
+        assert_eq!(opts.doc_hidden, Some(false));
+    }
+
+    #[test]
+    fn exclusive() {
+        assert_eq!(
+            error(&["-o", "out.rs", "--print", "path"]),
+            "--output cannot be combined with --print",
+        );
+        assert_eq!(
+            error(&["-o", "out.rs", "--copy"]),
+            "--output cannot be combined with --copy"
+        );
+        assert_eq!(
+            error(&["--stats", "--diff"]),
+            "--stats cannot be combined with --diff"
+        );
+        assert_eq!(
+            error(&["--watch", "--format", "json"]),
+            "--format json cannot be combined with --watch",
+        );
+        assert_eq!(
+            error(&["--flamegraph", "f.svg", "--profile-macros"]),
+            "--profile-macros cannot be combined with --flamegraph",
+        );
+        assert_eq!(
+            error(&["--stats", "--copy"]),
+            "--stats cannot be combined with --copy"
+        );
+        assert!(parse(&["--format", "html", "-o", "page.html"], "").is_ok());
+        assert!(parse(&["--format", "md", "-o", "page.md"], "").is_ok());
+        assert!(parse(
+            &["--bisect-dep", "serde", "1.0.1..1.0.2", "--grep", "x"],
+            ""
+        )
+        .is_ok());
+        assert!(parse(&["--self-profile", "--flamegraph", "f.svg"], "").is_ok());
+    }
+
+    #[test]
+    fn with_source_needs_item() {
+        assert!(error(&["--with-source"]).contains("needs the path of an item"));
+        assert!(parse(&["--with-source", "--item", "f"], "").is_ok());
+    }
+
+    #[test]
+    fn config() {
+        let config =
+            "[defaults]\nmax-width = 60\n\n[pipeline.ci]\nredact = true\nscrub = [\"a\", \"b\"]\n";
+        let opts = parse(&["--pipeline", "ci"], config).unwrap();
+        assert_eq!(opts.max_width, Some(60));
+        assert!(opts.redact);
+        assert_eq!(opts.scrub, Some(vec!["a".to_owned(), "b".to_owned()]));
+        assert!(error(&["--pipeline", "ci"]).contains("no pipeline named `ci`"));
+
+        assert!(parse(&[], "").unwrap().reuse);
+        assert!(!parse(&["--no-cache"], "").unwrap().reuse);
+        assert!(!parse(&[], "[cache]\nreuse = false\n").unwrap().reuse);
+    }
+}
@@ -0,0 +1,152 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use cache;
+use capture;
+use config::Config;
+use highlight;
+use opts::{Format, Opts};
+use print_checksum;
+use provenance;
+use tmp;
+
+// -o/--output FILE: write the formatted expansion to FILE rather than stdout,
+// uncolored and in the --output-encoding, or as a highlighted page if FILE
+// ends in `.html` or --format html is given. Only the code goes in the file;
+// what cargo prints stays on stderr.
+pub fn write(opts: &Opts, code: &str, path: &Path, checksum: Option<&str>) -> io::Result<i32> {
+    let html = matches!(opts.format, Some(Format::Html))
+        || path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("html"));
+    let contents = if html {
+        let title = format!("cargo expand {}", opts.target_label());
+        highlight::html(code, opts.theme, &title).into_bytes()
+    } else {
+        opts.output_encoding.encode(code)
+    };
+    fs::write(path, contents).map_err(|err| {
+        let msg = format!("failed to write {}: {}", path.display(), err);
+        io::Error::new(err.kind(), msg)
+    })?;
+
+    let _ = writeln!(
+        &mut io::stderr(),
+        "wrote the expansion to {}",
+        path.display(),
+    );
+    print_checksum(opts, checksum);
+    Ok(0)
+}
+
+// --format html: the expansion as a standalone page, highlighted in the
+// --theme, with an anchor for each item and an index of them at the top, to
+// attach to a bug report or link to from a review. Written to the -o file if
+// there is one, otherwise printed.
+pub fn html(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let code = match capture::expand(opts, config)? {
+        Some(code) => code,
+        None => return Ok(1),
+    };
+    if let Some(ref path) = opts.output {
+        return write(opts, &code, path, None);
+    }
+    let title = format!("cargo expand {}", opts.target_label());
+    let stdout = io::stdout();
+    stdout
+        .lock()
+        .write_all(highlight::html(&code, opts.theme, &title).as_bytes())?;
+    Ok(0)
+}
+
+// --copy: put the formatted expansion on the clipboard rather than print it,
+// uncolored as it goes in a file, using whichever of the platform's clipboard
+// tools is installed.
+pub fn copy(opts: &Opts, code: &str, checksum: Option<&str>) -> io::Result<i32> {
+    let tools: &[&[&str]] = if cfg!(target_os = "macos") {
+        &[&["pbcopy"]]
+    } else if cfg!(windows) {
+        &[&["clip"]]
+    } else {
+        &[
+            &["wl-copy"],
+            &["xclip", "-selection", "clipboard"],
+            &["xsel", "--clipboard", "--input"],
+        ]
+    };
+    let mut failed = Vec::new();
+    for tool in tools {
+        let mut cmd = Command::new(tool[0]);
+        cmd.args(&tool[1..]);
+        cmd.stdin(Stdio::piped()).stdout(Stdio::null());
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        let written = child.stdin.take().unwrap().write_all(code.as_bytes());
+        let status = child.wait()?;
+        if written.is_err() || !status.success() {
+            // wl-copy without a Wayland session or xclip without an X one.
+            failed.push(tool[0]);
+            continue;
+        }
+        let _ = writeln!(
+            &mut io::stderr(),
+            "copied the expansion to the clipboard with {}",
+            tool[0],
+        );
+        print_checksum(opts, checksum);
+        return Ok(0);
+    }
+    let names: Vec<&str> = tools.iter().map(|tool| tool[0]).collect();
+    let names = match names.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+        _ => names.concat(),
+    };
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        if failed.is_empty() {
+            format!("--copy needs a clipboard tool: install {}", names)
+        } else {
+            format!("failed to copy the expansion with {}", failed.join(" or "))
+        },
+    ))
+}
+
+// --print path: write the expansion to a file in the cache, named for the
+// crate and target, and print only its path, for editors and scripts that
+// want a file to open rather than a stream. The file is overwritten by the
+// next expansion of the same target.
+pub fn print_path(opts: &Opts, config: &Config, code: &str) -> io::Result<i32> {
+    let dir = cache::workspace_dir(opts, config)
+        .unwrap_or_else(tmp::cache_dir)
+        .join("expanded");
+    fs::create_dir_all(&dir)?;
+    let name = match provenance::target_root(opts) {
+        Ok((name, _)) => format!("{} {}", name, opts.target_label()),
+        Err(_) => opts.target_label(),
+    };
+    let path = dir.join(format!("{}.rs", file_stem(&name)));
+    fs::write(&path, opts.output_encoding.encode(code))?;
+
+    let stdout = io::stdout();
+    writeln!(stdout.lock(), "{}", path.display())?;
+    Ok(0)
+}
+
+// `feat lib` or `demo bin:demo` as something safe to name a file.
+pub fn file_stem(label: &str) -> String {
+    label
+        .chars()
+        .map(|ch| {
+            if ch.is_alphanumeric() || ch == '-' || ch == '_' {
+                ch
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use serde_json::{self, Value};
+
+use metadata::Metadata;
+use opts::Opts;
+use {cargo_command, wrap_args};
+
+// --plan: ask cargo for the unit graph of the expansion build and summarize
+// what it would compile, without compiling anything. Whether a unit is fresh
+// is only known to cargo once it builds, so packages with nothing at all in
+// the target directory are counted as an estimate of the work left.
+pub fn print(opts: &Opts) -> io::Result<i32> {
+    let mut args = wrap_args(opts, None);
+    let separator = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    args.splice(
+        separator..separator,
+        vec!["--unit-graph".into(), "-Zunstable-options".into()],
+    );
+
+    let mut cmd = cargo_command(opts);
+    cmd.args(args);
+    cmd.stderr(Stdio::inherit());
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Ok(output.status.code().unwrap_or(1));
+    }
+    let graph: Value = serde_json::from_slice(&output.stdout).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to parse cargo's unit graph: {}", err),
+        )
+    })?;
+    let units = match graph["units"].as_array() {
+        Some(units) => units,
+        None => return Err(io::Error::other("cargo's unit graph has no units")),
+    };
+    // The packages being expanded, whose own build scripts are not counted
+    // as dependencies.
+    let roots: BTreeSet<String> = graph["roots"]
+        .as_array()
+        .map(|roots| {
+            roots
+                .iter()
+                .filter_map(|root| units.get(root.as_u64()? as usize))
+                .map(|unit| package_name(unit["pkg_id"].as_str().unwrap_or("")))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let target_dir = opts.target_dir.clone().or_else(|| {
+        Metadata::load(opts)
+            .ok()
+            .and_then(|metadata| metadata.target_directory().map(PathBuf::from))
+    });
+
+    let mut kinds: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut packages = BTreeSet::new();
+    let mut unbuilt = BTreeSet::new();
+    for unit in units {
+        *kinds.entry(kind(unit)).or_insert(0) += 1;
+        let package = package_name(unit["pkg_id"].as_str().unwrap_or(""));
+        if roots.contains(&package) {
+            continue;
+        }
+        if let Some(ref target_dir) = target_dir {
+            if !built_before(&fingerprint_dir(target_dir, unit), &package) {
+                unbuilt.insert(package.clone());
+            }
+        }
+        packages.insert(package);
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(
+        out,
+        "expanding {} takes {} unit{} of compilation from {} dependenc{}",
+        roots.iter().cloned().collect::<Vec<_>>().join(", "),
+        units.len(),
+        if units.len() == 1 { "" } else { "s" },
+        packages.len(),
+        if packages.len() == 1 { "y" } else { "ies" },
+    )?;
+    for (kind, count) in &kinds {
+        writeln!(out, "{:>6}  {}", count, kind)?;
+    }
+    match target_dir {
+        _ if packages.is_empty() => {}
+        Some(ref target_dir) if unbuilt.is_empty() => writeln!(
+            out,
+            "every dependency has been built in {} before",
+            target_dir.display(),
+        )?,
+        Some(ref target_dir) => {
+            let list: Vec<&str> = unbuilt.iter().map(String::as_str).collect();
+            writeln!(
+                out,
+                "{} of them have never been built in {}: {}",
+                unbuilt.len(),
+                target_dir.display(),
+                list.join(", "),
+            )?;
+        }
+        None => {}
+    }
+    Ok(0)
+}
+
+// `serde` from `registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0`,
+// from `path+file:///path/to/serde#1.0.0` where the name is the directory's, or
+// from the older `serde 1.0.0 (registry+...)`.
+fn package_name(pkg_id: &str) -> String {
+    if pkg_id.ends_with(')') {
+        return pkg_id.split(' ').next().unwrap_or("").to_owned();
+    }
+    let (url, fragment) = match pkg_id.rfind('#') {
+        Some(hash) => (&pkg_id[..hash], &pkg_id[hash + 1..]),
+        None => (pkg_id, ""),
+    };
+    match fragment.find('@') {
+        Some(at) => fragment[..at].to_owned(),
+        None => url.rsplit('/').next().unwrap_or(url).to_owned(),
+    }
+}
+
+fn kind(unit: &Value) -> &'static str {
+    let kinds: Vec<&str> = unit["target"]["kind"]
+        .as_array()
+        .map(|kinds| kinds.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    if unit["mode"] == "run-custom-build" {
+        "build script runs"
+    } else if kinds.contains(&"custom-build") {
+        "build scripts"
+    } else if kinds.contains(&"proc-macro") {
+        "proc macros"
+    } else if kinds.contains(&"bin") {
+        "binaries"
+    } else {
+        "libraries"
+    }
+}
+
+// Where cargo keeps the fingerprints of units like this one: under the
+// profile's directory, inside a directory for the target platform if the
+// unit is cross-compiled.
+fn fingerprint_dir(target_dir: &Path, unit: &Value) -> PathBuf {
+    let mut dir = target_dir.to_path_buf();
+    if let Some(platform) = unit["platform"].as_str() {
+        dir.push(platform);
+    }
+    dir.push(match unit["profile"]["name"].as_str() {
+        Some("dev") | Some("test") | None => "debug",
+        Some("bench") => "release",
+        Some(profile) => profile,
+    });
+    dir.push(".fingerprint");
+    dir
+}
+
+// Whether any unit of the package has a fingerprint, in a directory named
+// for the package and a hash.
+fn built_before(fingerprints: &Path, package: &str) -> bool {
+    let entries = match fs::read_dir(fingerprints) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.filter_map(Result::ok).any(|entry| {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        name.strip_prefix(package)
+            .and_then(|rest| rest.strip_prefix('-'))
+            .is_some_and(|hash| hash.chars().all(|ch| ch.is_ascii_hexdigit()))
+    })
+}
@@ -0,0 +1,80 @@
+use std::env;
+use std::io;
+use std::path::Path;
+
+use opts::Opts;
+use version;
+
+// --target TRIPLE: expand for another platform, so that its `cfg` branches are
+// the ones taken. The dependencies are compiled for it only as rlibs, which
+// need no linker, and the crate itself only as far as expansion; build scripts
+// and proc macros are still built and run for the host. What it does need is
+// the platform's standard library. The compiler's error when that is missing
+// does not say how to get it, so look for it up front.
+pub fn check(opts: &Opts) -> io::Result<()> {
+    let triple = match opts.platform() {
+        Some(triple) => triple,
+        None => return Ok(()),
+    };
+    // A target spec file, or a standard library built from source.
+    let build_std = opts
+        .cargo
+        .iter()
+        .any(|arg| arg.to_string_lossy().contains("build-std"));
+    if triple.ends_with(".json") || build_std {
+        return Ok(());
+    }
+
+    let print = |what: &str| -> Option<String> {
+        let output = version::rustc_command(opts.toolchain.as_deref())
+            .args(["--print", what])
+            .output()
+            .ok()?;
+        if output.status.success() {
+            String::from_utf8(output.stdout).ok()
+        } else {
+            None
+        }
+    };
+    // Anything going wrong here is for the compiler to report.
+    let sysroot = match print("sysroot") {
+        Some(sysroot) => sysroot,
+        None => return Ok(()),
+    };
+    let std_dir = Path::new(sysroot.trim())
+        .join("lib")
+        .join("rustlib")
+        .join(&triple)
+        .join("lib");
+    if std_dir.is_dir() {
+        return Ok(());
+    }
+
+    let known = print("target-list").is_none_or(|list| list.lines().any(|t| t == triple));
+    if !known {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "unknown target `{}`; `rustc --print target-list` lists the built-in ones",
+                triple,
+            ),
+        ));
+    }
+    // The toolchain the build runs on, which is not the default one if we were
+    // run on nightly for it.
+    let toolchain = opts
+        .toolchain
+        .clone()
+        .or_else(|| env::var("RUSTUP_TOOLCHAIN").ok());
+    let mut add = format!("rustup target add {}", triple);
+    if let Some(toolchain) = toolchain {
+        add.push_str(&format!(" --toolchain {}", toolchain));
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "the standard library for `{}` is not installed; install it with `{}`",
+            triple, add,
+        ),
+    ))
+}
@@ -0,0 +1,488 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use proc_macro2::{Delimiter, Spacing, TokenStream, TokenTree};
+
+use opts::{Indent, Opts};
+
+// A formatter of last resort for when rustfmt is not available: it lays out
+// the tokens of the expansion with a line per statement, item and field and
+// braces indented the usual way, but does not wrap long lines. Code that does
+// not tokenize is left as it is.
+pub fn format_file(opts: &Opts, path: &Path) -> io::Result<()> {
+    let code = fs::read_to_string(path)?;
+    match format(&code, &indent(opts)) {
+        Some(formatted) => fs::write(path, formatted),
+        None => Ok(()),
+    }
+}
+
+// What to indent with for each level, from --indent.
+pub fn indent(opts: &Opts) -> String {
+    match opts.indent {
+        Some(Indent::Tabs) => "\t".to_owned(),
+        Some(Indent::Spaces(n)) => " ".repeat(n),
+        None => "    ".to_owned(),
+    }
+}
+
+// Lines of `//` comments in between items, like the blocks of --with-source,
+// are kept as they are and the code around them formatted on its own.
+pub fn format(code: &str, indent: &str) -> Option<String> {
+    let is_comment = |line: &str| {
+        let line = line.trim_start();
+        line.starts_with("//") && !line.starts_with("///") && !line.starts_with("//!")
+    };
+    if !code.lines().any(is_comment) {
+        return format_tokens(code, indent);
+    }
+    let mut out = String::new();
+    let mut chunk = String::new();
+    for line in code.lines() {
+        if is_comment(line) {
+            if !chunk.trim().is_empty() {
+                out.push_str(&format_tokens(&chunk, indent)?);
+                chunk.clear();
+            }
+            out.push_str(line.trim_start());
+            out.push('\n');
+        } else {
+            chunk.push_str(line);
+            chunk.push('\n');
+        }
+    }
+    if !chunk.trim().is_empty() {
+        out.push_str(&format_tokens(&chunk, indent)?);
+    }
+    Some(out)
+}
+
+fn format_tokens(code: &str, indent: &str) -> Option<String> {
+    let tokens = TokenStream::from_str(code).ok()?;
+    let mut printer = Printer {
+        out: String::new(),
+        unit: indent,
+        indent: 0,
+        line_start: true,
+        prev: Prev::Open,
+        closure_params: false,
+        angle: 0,
+    };
+    printer.stream(tokens, true);
+    let mut out = printer.out;
+    let trimmed = out.trim_end().len();
+    out.truncate(trimmed);
+    out.push('\n');
+    Some(out)
+}
+
+// Keywords that are followed by a space even before `(` or `::`.
+const SPACED: &[&str] = &[
+    "as", "async", "break", "const", "dyn", "else", "enum", "extern", "fn", "for", "if", "impl",
+    "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "static", "struct",
+    "trait", "type", "union", "unsafe", "use", "where", "while", "yield",
+];
+
+// Keywords that declare the name after them, which may have generics.
+const DECLARES: &[&str] = &["enum", "fn", "struct", "trait", "type", "union"];
+
+// Operators of more than one character, longest first.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "...", "..=", "::", "->", "=>", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "^=", "&=", "|=", "<<", ">>", "..",
+];
+
+// What was printed last, to decide on the space before the next token.
+#[derive(Clone, Copy, PartialEq)]
+enum Prev {
+    // The start of a line, or an opening delimiter.
+    Open,
+    // An identifier, literal or closing delimiter.
+    Word,
+    Keyword,
+    // Something the next token sticks to, like `::`, `.` or unary `&`.
+    Tight,
+    // The `!` of a macro invocation, which only a macro_rules name is spaced
+    // from.
+    Bang,
+    // An operator with a space on either side, like `=` or `=>`.
+    Punct,
+}
+
+struct Printer<'a> {
+    out: String,
+    // What to indent with for each level.
+    unit: &'a str,
+    indent: usize,
+    line_start: bool,
+    prev: Prev,
+    // In between the `|`s of a closure's parameters.
+    closure_params: bool,
+    // How many `<` of generics are open.
+    angle: usize,
+}
+
+impl<'a> Printer<'a> {
+    // Print a token stream. In a block, the contents of braces or the whole
+    // file, commas and semicolons end lines.
+    fn stream(&mut self, tokens: TokenStream, block: bool) {
+        let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+        let mut i = 0;
+        while i < tokens.len() {
+            match tokens[i] {
+                TokenTree::Punct(ref punct) if punct.as_char() == '#' && is_attr(&tokens[i..]) => {
+                    self.space();
+                    self.write("#");
+                    i += 1;
+                    if let TokenTree::Punct(_) = tokens[i] {
+                        self.write("!");
+                        i += 1;
+                    }
+                    if let TokenTree::Group(ref group) = tokens[i] {
+                        self.write("[");
+                        self.prev = Prev::Open;
+                        self.stream(group.stream(), false);
+                        self.write("]");
+                    }
+                    if block {
+                        self.newline();
+                    } else {
+                        self.prev = Prev::Word;
+                    }
+                }
+                TokenTree::Punct(ref punct) if matches!(punct.as_char(), ',' | ';') => {
+                    // A stray `;`, like after an item, joins the line before.
+                    if self.line_start && self.out.ends_with('\n') {
+                        self.out.pop();
+                        self.line_start = false;
+                    }
+                    self.write(&punct.to_string());
+                    if punct.as_char() == ';' {
+                        self.angle = 0;
+                    }
+                    if block && self.angle == 0 && !self.closure_params {
+                        self.newline();
+                    } else {
+                        self.prev = Prev::Punct;
+                    }
+                }
+                TokenTree::Punct(_) => {
+                    // Joint punctuation is split into operators, being
+                    // joined in `->` but also in `<&` of `Vec<&str>`.
+                    let mut run = String::new();
+                    while let TokenTree::Punct(ref punct) = tokens[i] {
+                        run.push(punct.as_char());
+                        let joined = punct.spacing() == Spacing::Joint
+                            && matches!(tokens.get(i + 1), Some(TokenTree::Punct(next))
+                                if !matches!(next.as_char(), ',' | ';' | '#' | '\''));
+                        if !joined {
+                            break;
+                        }
+                        i += 1;
+                    }
+                    let mut rest = run.as_str();
+                    while !rest.is_empty() {
+                        let len = OPERATORS
+                            .iter()
+                            .find(|op| rest.starts_with(*op))
+                            .map_or(1, |op| op.len());
+                        self.op(&rest[..len]);
+                        rest = &rest[len..];
+                    }
+                }
+                TokenTree::Group(ref group) => match group.delimiter() {
+                    // The braces of `use a::{b, c}` stay on one line.
+                    Delimiter::Brace if self.prev == Prev::Tight => {
+                        self.write("{");
+                        self.prev = Prev::Open;
+                        self.stream(group.stream(), false);
+                        self.write("}");
+                        self.prev = Prev::Word;
+                    }
+                    Delimiter::Brace => {
+                        if self.prev != Prev::Open {
+                            self.space_always();
+                        }
+                        self.write("{");
+                        self.angle = 0;
+                        if !group.stream().is_empty() {
+                            self.indent += 1;
+                            self.newline();
+                            self.stream(group.stream(), true);
+                            self.indent -= 1;
+                            self.newline();
+                        }
+                        self.write("}");
+                        self.prev = Prev::Word;
+                        let continues = match tokens.get(i + 1) {
+                            Some(TokenTree::Punct(p)) => {
+                                matches!(p.as_char(), ';' | ',' | '.' | '?')
+                            }
+                            Some(TokenTree::Ident(ident)) => ident == "else",
+                            _ => false,
+                        };
+                        if block && !continues {
+                            self.newline();
+                        }
+                    }
+                    Delimiter::Parenthesis | Delimiter::Bracket => {
+                        let (open, close) = if group.delimiter() == Delimiter::Parenthesis {
+                            ("(", ")")
+                        } else {
+                            ("[", "]")
+                        };
+                        // Calls and indexing stick to what they follow.
+                        if self.prev != Prev::Word {
+                            self.space();
+                        }
+                        self.write(open);
+                        self.prev = Prev::Open;
+                        self.stream(group.stream(), false);
+                        self.write(close);
+                        self.prev = Prev::Word;
+                    }
+                    Delimiter::None => self.stream(group.stream(), block),
+                },
+                ref tt => {
+                    let text = tt.to_string();
+                    if self.prev == Prev::Bang {
+                        self.space_always();
+                    }
+                    self.space();
+                    self.write(&text);
+                    self.prev = if SPACED.contains(&text.as_str()) {
+                        Prev::Keyword
+                    } else {
+                        Prev::Word
+                    };
+                }
+            }
+            i += 1;
+        }
+    }
+
+    fn op(&mut self, op: &str) {
+        // Where `&`, `*`, `-` and `!` are prefix operators and `|` opens a
+        // closure.
+        let prefix = matches!(
+            self.prev,
+            Prev::Open | Prev::Punct | Prev::Keyword | Prev::Tight
+        );
+        match op {
+            "::" => {
+                if matches!(self.prev, Prev::Punct | Prev::Keyword) {
+                    self.space_always();
+                }
+                self.write(op);
+                self.prev = Prev::Tight;
+            }
+            "." | ".." | "..=" | "..." => {
+                self.write(op);
+                self.prev = Prev::Tight;
+            }
+            "?" => {
+                self.write(op);
+                self.prev = Prev::Word;
+            }
+            ":" => {
+                self.write(op);
+                // The fragment specifier of a macro_rules metavariable, as
+                // in `$name:ident`.
+                let start = self.out[..self.out.len() - 1].rfind(|ch: char| !is_ident(ch));
+                let metavariable = start.is_some_and(|at| self.out[at..].starts_with('$'));
+                self.prev = if metavariable {
+                    Prev::Tight
+                } else {
+                    Prev::Punct
+                };
+            }
+            "'" | "$" | "#" => {
+                self.space();
+                self.write(op);
+                self.prev = Prev::Tight;
+            }
+            // A macro invocation.
+            "!" if self.prev == Prev::Word => {
+                self.write(op);
+                self.prev = Prev::Bang;
+            }
+            "|" if prefix => {
+                self.space();
+                self.write(op);
+                self.closure_params = true;
+                self.prev = Prev::Tight;
+            }
+            "|" if self.closure_params => {
+                self.write(op);
+                self.closure_params = false;
+                self.prev = Prev::Punct;
+            }
+            "||" if prefix => {
+                self.space();
+                self.write(op);
+                self.prev = Prev::Punct;
+            }
+            _ if prefix && op.chars().all(|ch| matches!(ch, '&' | '*' | '-' | '!')) => {
+                self.space();
+                self.write(op);
+                self.prev = Prev::Tight;
+            }
+            "<" if self.opens_generics() => {
+                if self.prev == Prev::Punct {
+                    self.space_always();
+                }
+                self.write(op);
+                self.angle += 1;
+                self.prev = Prev::Tight;
+            }
+            ">" | ">>" if self.angle > 0 => {
+                self.write(op);
+                self.angle = self.angle.saturating_sub(op.len());
+                self.prev = Prev::Word;
+            }
+            _ => {
+                if !matches!(self.prev, Prev::Open | Prev::Tight | Prev::Bang) {
+                    self.space_always();
+                }
+                self.write(op);
+                self.prev = Prev::Punct;
+            }
+        }
+    }
+
+    // Whether a `<` here starts generics or a qualified path rather than
+    // comparing: anywhere but after a word, or after a capitalized type name
+    // or the name in a declaration like `fn name<T>`.
+    fn opens_generics(&self) -> bool {
+        if self.prev != Prev::Word {
+            return true;
+        }
+        if !self.out.ends_with(is_ident) {
+            return false;
+        }
+        let start = self.out.rfind(|ch| !is_ident(ch)).map_or(0, |at| at + 1);
+        if self.out[start..].starts_with(char::is_uppercase) {
+            return true;
+        }
+        let before = self.out[..start].trim_end();
+        let keyword = before.rsplit(|ch| !is_ident(ch)).next().unwrap_or("");
+        DECLARES.contains(&keyword)
+    }
+
+    // A space before the next token if what came before does not stick to it.
+    fn space(&mut self) {
+        if matches!(self.prev, Prev::Word | Prev::Keyword | Prev::Punct) {
+            self.space_always();
+        }
+    }
+
+    fn space_always(&mut self) {
+        if !self.line_start {
+            self.out.push(' ');
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.line_start {
+            for _ in 0..self.indent {
+                self.out.push_str(self.unit);
+            }
+            self.line_start = false;
+        }
+        self.out.push_str(text);
+    }
+
+    fn newline(&mut self) {
+        if !self.line_start {
+            self.out.push('\n');
+            self.line_start = true;
+        }
+        self.prev = Prev::Open;
+    }
+}
+
+// `#[...]` or `#![...]`.
+fn is_attr(tokens: &[TokenTree]) -> bool {
+    let group = match tokens.get(1) {
+        Some(TokenTree::Punct(p)) if p.as_char() == '!' => tokens.get(2),
+        other => other,
+    };
+    matches!(group, Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket)
+}
+
+fn is_ident(ch: char) -> bool {
+    ch == '_' || ch.is_alphanumeric()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formatted(code: &str) -> String {
+        format(code, "    ").unwrap()
+    }
+
+    #[test]
+    fn statements_and_blocks() {
+        assert_eq!(
+            formatted("fn f ( x : u32 ) -> u32 { let y = x + 1 ; y * 2 }"),
+            "fn f(x: u32) -> u32 {\n    let y = x + 1;\n    y * 2\n}\n",
+        );
+        assert_eq!(
+            formatted("fn x ( ) { match v { Some ( a ) => a , None => 0 , } }"),
+            "fn x() {\n    match v {\n        Some(a) => a,\n        None => 0,\n    }\n}\n",
+        );
+        assert_eq!(formatted("mod m { }"), "mod m {}\n");
+    }
+
+    #[test]
+    fn items() {
+        assert_eq!(
+            formatted("#[derive(Debug)] pub enum E { A , B ( u8 ) , }"),
+            "#[derive(Debug)]\npub enum E {\n    A,\n    B(u8),\n}\n",
+        );
+        assert_eq!(
+            formatted("struct S < T > { a : T , b : Vec < u8 > , }"),
+            "struct S<T> {\n    a: T,\n    b: Vec<u8>,\n}\n",
+        );
+        assert_eq!(
+            formatted("impl < T : Clone > Tr for S < T > where T : Copy { fn g ( & self ) { } }"),
+            "impl<T: Clone> Tr for S<T> where T: Copy {\n    fn g(&self) {}\n}\n",
+        );
+    }
+
+    #[test]
+    fn operators_and_macros() {
+        assert_eq!(
+            formatted("fn h ( ) { let c = | a , b | a + b ; if ! x { - 1 } else { & y } }"),
+            "fn h() {\n    let c = |a, b| a + b;\n    if !x {\n        -1\n    } else {\n        &y\n    }\n}\n",
+        );
+        assert_eq!(
+            formatted("fn g ( ) { println ! ( \"{}\" , self . a ) ; }"),
+            "fn g() {\n    println!(\"{}\", self.a);\n}\n",
+        );
+        assert_eq!(
+            formatted("macro_rules ! m { ( ) => { } }"),
+            "macro_rules! m {\n    () => {}\n}\n",
+        );
+    }
+
+    #[test]
+    fn comments_and_indent() {
+        assert_eq!(
+            formatted("mod m { }\n// a comment\nfn k ( ) { }"),
+            "mod m {}\n// a comment\nfn k() {}\n",
+        );
+        assert_eq!(
+            format("fn k ( ) { a ( ) ; }", "\t").unwrap(),
+            "fn k() {\n\ta();\n}\n",
+        );
+    }
+
+    #[test]
+    fn untokenized() {
+        assert_eq!(format("fn (", "    "), None);
+    }
+}
@@ -0,0 +1,377 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::time::{Duration, SystemTime};
+
+use analyzeme::ProfilingData;
+
+use config::Config;
+use opts::Opts;
+use tmp::TmpDir;
+use trust;
+use {cargo_command, run_cargo, wrap_args};
+
+// Build the expansion with rustc's self-profiler on, recording the given
+// `-Zself-profile-events`, and load what it wrote. None if the build failed.
+pub fn record(opts: &Opts, config: &Config, events: &str) -> io::Result<Option<ProfilingData>> {
+    let outdir = TmpDir::create(opts.keep_tmp)?;
+    let profile_dir = outdir.path().join("profile");
+    fs::create_dir(&profile_dir)?;
+
+    let mut opts = opts.clone();
+    let mut flag = OsString::from("-Zself-profile=");
+    flag.push(&profile_dir);
+    opts.rustc.push(flag);
+    opts.rustc
+        .push(format!("-Zself-profile-events={}", events).into());
+
+    let mut cmd = cargo_command(&opts);
+    cmd.args(wrap_args(&opts, Some(&outdir.path().join("expanded"))));
+    trust::check(&opts, config)?;
+    if run_cargo(&opts, &mut cmd)? != 0 {
+        return Ok(None);
+    }
+
+    let path = fs::read_dir(&profile_dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "mm_profdata"))
+        .ok_or_else(|| io::Error::other("rustc did not write a self-profile"))?;
+    ProfilingData::new(&path)
+        .map(Some)
+        .map_err(|err| io::Error::other(format!("failed to read {}: {}", path.display(), err)))
+}
+
+// --profile-macros: how long each proc macro invocation took to expand,
+// slowest first, as rustc's self-profiler measured it.
+pub fn macros(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let data = match record(opts, config, "default,args")? {
+        Some(data) => data,
+        None => return Ok(1),
+    };
+
+    let mut invocations = Vec::new();
+    let mut expansion = Duration::default();
+    for event in data.iter_full() {
+        let duration = event.duration().unwrap_or_default();
+        match &*event.label {
+            "expand_proc_macro" => {
+                let arg = |i: usize| event.additional_data.get(i).map_or("", |arg| &**arg);
+                // The location is given as a span, `file:line:col: line:col`.
+                let location = arg(1).split(": ").next().unwrap_or("").to_owned();
+                invocations.push((duration, arg(0).to_owned(), location));
+            }
+            "macro_expand_crate" => expansion += duration,
+            _ => {}
+        }
+    }
+    invocations.sort_by_key(|invocation| Reverse(invocation.0));
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if invocations.is_empty() {
+        writeln!(out, "no proc macro invocations")?;
+        return Ok(0);
+    }
+    let width = invocations
+        .iter()
+        .map(|invocation| invocation.1.len())
+        .max()
+        .unwrap_or(0)
+        .max("macro".len());
+    writeln!(
+        out,
+        "{:>10}  {:width$}  location",
+        "time",
+        "macro",
+        width = width
+    )?;
+    let mut total = Duration::default();
+    for (duration, name, location) in &invocations {
+        total += *duration;
+        writeln!(
+            out,
+            "{:>10}  {:width$}  {}",
+            millis(*duration),
+            name,
+            location,
+            width = width,
+        )?;
+    }
+    writeln!(
+        out,
+        "\n{} proc macro invocation{} took {} of {} spent expanding macros",
+        invocations.len(),
+        if invocations.len() == 1 { "" } else { "s" },
+        millis(total),
+        millis(expansion),
+    )?;
+    Ok(0)
+}
+
+pub fn millis(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+// Events the self-profiler records while expanding macros, as opposed to
+// parsing, type checking and the rest of the build.
+fn is_expansion(label: &str) -> bool {
+    label.contains("expand") || label.contains("macro")
+}
+
+// --self-profile: profile the build and list the time spent in the events
+// that have to do with macro expansion, with --flamegraph also writing all of
+// it out as an SVG flame graph.
+pub fn summary(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let data = match record(opts, config, "default,args")? {
+        Some(data) => data,
+        None => return Ok(1),
+    };
+
+    let mut totals: BTreeMap<String, (Duration, usize)> = BTreeMap::new();
+    let mut spans = Vec::new();
+    for event in data.iter_full() {
+        let timestamp = match event.payload.timestamp() {
+            Some(timestamp) if !timestamp.is_instant() => timestamp,
+            _ => continue,
+        };
+        let duration = event.duration().unwrap_or_default();
+        if is_expansion(&event.label) {
+            let total = totals.entry(event.label.to_string()).or_default();
+            total.0 += duration;
+            total.1 += 1;
+        }
+        if opts.flamegraph.is_some() {
+            let name = match event.additional_data.first() {
+                Some(arg) if event.label == "expand_proc_macro" => {
+                    format!("{} {}", event.label, arg)
+                }
+                _ => event.label.to_string(),
+            };
+            spans.push(Span {
+                thread: event.thread_id,
+                start: timestamp.start(),
+                end: timestamp.end(),
+                name,
+            });
+        }
+    }
+    let build = data
+        .iter_full()
+        .filter_map(|event| event.payload.timestamp())
+        .fold(
+            None,
+            |range: Option<(SystemTime, SystemTime)>, timestamp| {
+                Some(match range {
+                    Some((start, end)) => (start.min(timestamp.start()), end.max(timestamp.end())),
+                    None => (timestamp.start(), timestamp.end()),
+                })
+            },
+        )
+        .and_then(|(start, end)| end.duration_since(start).ok())
+        .unwrap_or_default();
+
+    let mut rows: Vec<_> = totals.into_iter().collect();
+    rows.sort_by_key(|row| Reverse((row.1).0));
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "{:>10}  {:>5}  event", "time", "count")?;
+    for (label, (duration, count)) in &rows {
+        writeln!(out, "{:>10}  {:>5}  {}", millis(*duration), count, label,)?;
+    }
+    let expansion = rows
+        .iter()
+        .find(|row| row.0 == "macro_expand_crate")
+        .map(|row| (row.1).0)
+        .unwrap_or_default();
+    writeln!(
+        out,
+        "\nexpanding macros took {} of the {} rustc spent on the crate",
+        millis(expansion),
+        millis(build),
+    )?;
+
+    if let Some(ref path) = opts.flamegraph {
+        fs::write(path, flamegraph(spans))?;
+        writeln!(out, "wrote flame graph to {}", path.display())?;
+    }
+    Ok(0)
+}
+
+struct Span {
+    thread: u32,
+    start: SystemTime,
+    end: SystemTime,
+    name: String,
+}
+
+// A node of the flame graph: an event, with the events that happened within
+// it as children.
+#[derive(Default)]
+struct Frame {
+    name: String,
+    nanos: u64,
+    children: Vec<Frame>,
+}
+
+impl Frame {
+    fn child(&mut self, name: &str) -> &mut Frame {
+        let i = match self.children.iter().position(|child| child.name == name) {
+            Some(i) => i,
+            None => {
+                self.children.push(Frame {
+                    name: name.to_owned(),
+                    ..Frame::default()
+                });
+                self.children.len() - 1
+            }
+        };
+        &mut self.children[i]
+    }
+}
+
+const SVG_WIDTH: f64 = 1200.0;
+const FRAME_HEIGHT: f64 = 16.0;
+
+// An SVG flame graph of the spans, with the time spent expanding macros drawn
+// in blue and everything else in the usual warm colors. Hovering a frame shows
+// its full name and time.
+fn flamegraph(mut spans: Vec<Span>) -> String {
+    // Spans on the same thread nest, so in order of starting time, with the
+    // longer of two that start together first, each one is inside the last
+    // one on the stack that has not ended yet.
+    spans.sort_by(|a, b| {
+        (a.thread, a.start)
+            .cmp(&(b.thread, b.start))
+            .then(b.end.cmp(&a.end))
+    });
+    let mut root = Frame {
+        name: "all".to_owned(),
+        ..Frame::default()
+    };
+    let mut stack: Vec<&Span> = Vec::new();
+    for span in &spans {
+        while let Some(top) = stack.last() {
+            if top.thread == span.thread && top.end > span.start {
+                break;
+            }
+            stack.pop();
+        }
+        stack.push(span);
+
+        let nanos = span
+            .end
+            .duration_since(span.start)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        if stack.len() == 1 {
+            root.nanos += nanos;
+        }
+        let mut frame = &mut root;
+        for span in &stack {
+            frame = frame.child(&span.name);
+        }
+        frame.nanos += nanos;
+    }
+
+    let depth = depth(&root);
+    let height = (depth as f64 + 1.0) * FRAME_HEIGHT;
+    let mut svg = format!(
+        "<?xml version=\"1.0\" standalone=\"no\"?>\n\
+         <svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+         font-family=\"monospace\" font-size=\"11\">\n",
+        SVG_WIDTH, height,
+    );
+    let scale = if root.nanos == 0 {
+        0.0
+    } else {
+        SVG_WIDTH / root.nanos as f64
+    };
+    draw(&mut svg, &root, 0.0, 0, scale, height, root.nanos);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn depth(frame: &Frame) -> usize {
+    frame
+        .children
+        .iter()
+        .map(|child| 1 + depth(child))
+        .max()
+        .unwrap_or(0)
+}
+
+fn draw(
+    svg: &mut String,
+    frame: &Frame,
+    x: f64,
+    level: usize,
+    scale: f64,
+    height: f64,
+    total: u64,
+) {
+    let width = frame.nanos as f64 * scale;
+    if width < 0.1 {
+        return;
+    }
+    let y = height - (level as f64 + 1.0) * FRAME_HEIGHT;
+    let hash = frame.name.bytes().fold(0u32, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(byte as u32)
+    });
+    let fill = if is_expansion(&frame.name) {
+        format!("rgb({},{},255)", 80 + hash % 60, 140 + hash % 80)
+    } else {
+        format!("rgb(230,{},{})", 80 + hash % 120, hash % 60)
+    };
+    let title = format!(
+        "{} ({}, {:.2}%)",
+        frame.name,
+        millis(Duration::from_nanos(frame.nanos)),
+        frame.nanos as f64 * 100.0 / total.max(1) as f64,
+    );
+    svg.push_str(&format!(
+        "<g><title>{}</title><rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" \
+         fill=\"{}\"/>",
+        escape(&title),
+        x,
+        y,
+        width,
+        FRAME_HEIGHT - 1.0,
+        fill,
+    ));
+    // About 7 pixels to a character at this font size.
+    let fits = ((width - 6.0) / 7.0).max(0.0) as usize;
+    if fits >= 3 {
+        let mut text: String = frame.name.chars().take(fits).collect();
+        if text.len() < frame.name.len() {
+            text.truncate(text.char_indices().nth(fits - 2).map_or(0, |(i, _)| i));
+            text.push_str("..");
+        }
+        svg.push_str(&format!(
+            "<text x=\"{:.2}\" y=\"{:.2}\">{}</text>",
+            x + 3.0,
+            y + FRAME_HEIGHT - 4.0,
+            escape(&text),
+        ));
+    }
+    svg.push_str("</g>\n");
+
+    let mut child_x = x;
+    for child in &frame.children {
+        // Clocks are not perfectly consistent, so never let children spill
+        // out of their parent.
+        let nanos = child.nanos.min(frame.nanos);
+        draw(svg, child, child_x, level + 1, scale, height, total);
+        child_x += nanos as f64 * scale;
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,193 @@
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use toml::{Table, Value};
+
+use cargo_binary;
+use config;
+
+// Ask cargo which manifest it would pick for the current directory and pin it,
+// so the expansion does not depend on guessing the package from the working
+// directory. In the crate of a workspace's `cargo xtask` automation, which is
+// never what is meant, the workspace root is picked instead, so that its
+// package or its default members are expanded. Returns None outside of any
+// package, leaving cargo to report that on its own.
+pub fn locate_manifest() -> Option<OsString> {
+    let manifest = locate(&[])?;
+    if is_xtask(&manifest) {
+        if let Some(root) = locate(&[OsStr::new("--workspace")]) {
+            return Some(root.into_os_string());
+        }
+    }
+    Some(manifest.into_os_string())
+}
+
+// Whether the package at `manifest` is the one that the `xtask` alias in
+// cargo's config runs, picked with `-p`, `--package` or `--manifest-path`, or
+// is called `xtask` if there is no such alias.
+pub fn is_xtask(manifest: &Path) -> bool {
+    let cwd = match env::current_dir() {
+        Ok(cwd) => cwd,
+        Err(_) => return false,
+    };
+    let name = read_manifest(manifest).and_then(|manifest| {
+        manifest
+            .get("package")?
+            .get("name")?
+            .as_str()
+            .map(str::to_owned)
+    });
+    let name = match name {
+        Some(name) => name,
+        None => return false,
+    };
+    let words: Vec<String> = match xtask_alias(&cwd) {
+        Some(Value::String(alias)) => alias.split_whitespace().map(str::to_owned).collect(),
+        Some(Value::Array(alias)) => alias
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_owned)
+            .collect(),
+        _ => return name == "xtask",
+    };
+    let mut words = words.iter().map(String::as_str);
+    while let Some(word) = words.next() {
+        let (flag, value) = match word.split_once('=') {
+            Some((flag, value)) => (flag, Some(value)),
+            None => (word, None),
+        };
+        if flag != "-p" && flag != "--package" && flag != "--manifest-path" {
+            continue;
+        }
+        let value = match value.or_else(|| words.next()) {
+            Some(value) => value,
+            None => return false,
+        };
+        if flag == "--manifest-path" {
+            let path = cwd.join(value).canonicalize().ok();
+            return path.is_some() && path == manifest.canonicalize().ok();
+        }
+        return value == name;
+    }
+    name == "xtask"
+}
+
+// Whether the workspace manifest lists `default-members`.
+pub fn lists_default_members(manifest: &Path) -> bool {
+    read_manifest(manifest)
+        .and_then(|manifest| manifest.get("workspace")?.get("default-members").cloned())
+        .is_some()
+}
+
+// `alias.xtask` from the nearest of cargo's config files above `dir`, or else
+// the one in cargo's home, which is where cargo looks for it when run there.
+fn xtask_alias(dir: &Path) -> Option<Value> {
+    let cargo_home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cargo")));
+    let dirs = dir
+        .ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .chain(cargo_home);
+    for dir in dirs {
+        for name in ["config.toml", "config"] {
+            let alias = read_manifest(&dir.join(name))
+                .and_then(|config| config.get("alias")?.get("xtask").cloned());
+            if alias.is_some() {
+                return alias;
+            }
+        }
+    }
+    None
+}
+
+// The root manifest of the workspace the given package belongs to.
+pub fn locate_workspace(manifest_path: Option<&OsString>) -> Option<PathBuf> {
+    let mut args = vec![OsStr::new("--workspace")];
+    if let Some(manifest_path) = manifest_path {
+        args.push(OsStr::new("--manifest-path"));
+        args.push(manifest_path);
+    }
+    locate(&args)
+}
+
+fn locate(args: &[&OsStr]) -> Option<PathBuf> {
+    let output = Command::new(cargo_binary())
+        .arg("locate-project")
+        .arg("--message-format=plain")
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let manifest = PathBuf::from(stdout.trim_end());
+    if manifest.as_os_str().is_empty() {
+        None
+    } else {
+        Some(manifest)
+    }
+}
+
+// The options a project sets for everyone expanding it, under
+// `[workspace.metadata.expand]` in the workspace's manifest and then
+// `[package.metadata.expand]` in the package's, in the form of a pipeline in
+// the user config. The package's come later and so win. If the command line
+// already picks a target, the project's default one is left out.
+pub fn configured_options(
+    manifest_path: Option<&OsString>,
+    target_given: bool,
+) -> io::Result<Vec<OsString>> {
+    let manifest_path = match manifest_path {
+        Some(manifest_path) => PathBuf::from(manifest_path),
+        None => match locate(&[]) {
+            Some(manifest_path) => manifest_path,
+            None => return Ok(Vec::new()),
+        },
+    };
+    let package = match read_manifest(&manifest_path) {
+        Some(package) => package,
+        None => return Ok(Vec::new()),
+    };
+    let root = if package.contains_key("workspace") {
+        None
+    } else {
+        let manifest_path = manifest_path.into_os_string();
+        locate_workspace(Some(&manifest_path)).and_then(|path| read_manifest(&path))
+    };
+    let workspace = root.as_ref().unwrap_or(&package);
+
+    let mut options = Vec::new();
+    for (manifest, section) in [(workspace, "workspace"), (&package, "package")] {
+        let table = manifest
+            .get(section)
+            .and_then(|section| section.get("metadata"))
+            .and_then(|metadata| metadata.get("expand"))
+            .and_then(Value::as_table);
+        let mut table = match table {
+            Some(table) => table.clone(),
+            None => continue,
+        };
+        if target_given {
+            for kind in ["lib", "bin", "test", "example", "bench"] {
+                table.remove(kind);
+            }
+        }
+        let what = format!("[{}.metadata.expand]", section);
+        options.extend(config::options(&table, &what)?);
+    }
+    Ok(options)
+}
+
+// A manifest, or a config file of cargo's, if it can be read. One that cannot
+// is left to cargo to report.
+fn read_manifest(path: &Path) -> Option<Table> {
+    fs::read_to_string(path).ok()?.parse().ok()
+}
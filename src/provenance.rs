@@ -0,0 +1,593 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use syn;
+use syn::punctuated::Punctuated;
+use syn::visit::{self, Visit};
+use syn::{Attribute, Block, File, ImplItem, Item, ItemMod, Macro};
+
+use metadata::{self, Metadata};
+use opts::Opts;
+use suggest;
+use syntax;
+
+// --only-macro NAME: cut the expansion down to the code that one macro
+// generated, going by the last segment of its path, so `thiserror::Error`
+// and `Error` are the same. The expansion does not say where its code came
+// from, so this is worked out by lining it up with the crate's source, module
+// by module: the impls a derive added for the types it is on, the items an
+// attribute macro is on, and for a macro invoked in place of items, whatever
+// items the expansion has there that the source does not. For a macro invoked
+// inside function bodies, it is the statements of the bodies it is in.
+pub fn retain(opts: &Opts, file: &mut File, name: &str) -> io::Result<()> {
+    let name = name.trim_end_matches('!');
+    let name = name.rsplit("::").next().unwrap_or(name);
+    let source = load_crate(opts)?;
+
+    let generated = generated(&source.items, &file.items, name);
+    if generated.is_empty() {
+        let mut seen = BTreeSet::new();
+        invoked(&source.items, &mut seen);
+        let suggestions = suggest::similar(name, seen.iter().map(String::as_str));
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "found no code generated by `{}`{}",
+                name,
+                suggest::did_you_mean(&suggestions),
+            ),
+        ));
+    }
+    file.shebang = None;
+    file.attrs.clear();
+    file.items = generated;
+    Ok(())
+}
+
+// --derives: cut the expansion down to the impls that derives generated, in
+// the modules they are in, for every type that has some.
+pub fn retain_derived(opts: &Opts, file: &mut File) -> io::Result<()> {
+    let source = load_crate(opts)?;
+    let derived = all_derived(&source.items, &file.items);
+    if derived.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "found no code generated by derives",
+        ));
+    }
+    file.shebang = None;
+    file.attrs.clear();
+    file.items = derived;
+    Ok(())
+}
+
+// The impls that derives on the type called `ty` generated, wherever it is in
+// the module tree, each with the name of the derive it is put down to.
+pub fn derived_for(opts: &Opts, file: &File, ty: &str) -> io::Result<Vec<(String, Item)>> {
+    let source = load_crate(opts)?;
+    let mut found = Vec::new();
+    derived_at(&source.items, &file.items, ty, &mut found);
+    Ok(found)
+}
+
+fn derived_at(source: &[Item], expanded: &[Item], ty: &str, found: &mut Vec<(String, Item)>) {
+    let in_source: BTreeSet<String> = source.iter().map(syntax::item_label).collect();
+    let derives: Vec<String> = source
+        .iter()
+        .filter(|item| {
+            !matches!(**item, Item::Impl(_)) && syntax::item_ident(item).is_some_and(|id| id == ty)
+        })
+        .flat_map(|item| derives(syntax::item_attrs(item)))
+        .collect();
+
+    for item in expanded {
+        if let Item::Mod(ref m) = *item {
+            if let (Some(inner), Some((_, ref items))) = (submodule(source, m), &m.content) {
+                derived_at(inner, items, ty, found);
+            }
+            continue;
+        }
+        if syntax::items_for(std::slice::from_ref(item), ty).is_empty() {
+            continue;
+        }
+        let derive = derives
+            .iter()
+            .find(|derive| from_derive(source, &in_source, item, derive));
+        if let Some(derive) = derive {
+            found.push((derive.clone(), item.clone()));
+        }
+    }
+}
+
+// The items at one level of the module tree that macro `name` generated,
+// given the items at the same level in the source.
+pub fn generated(source: &[Item], expanded: &[Item], name: &str) -> Vec<Item> {
+    walk(source, expanded, &|source, in_source, item| {
+        let invoked_here = source.iter().any(|item| match *item {
+            Item::Macro(ref item) => last_segment(&item.mac.path) == name,
+            _ => false,
+        });
+        let whole = from_derive(source, in_source, item, name)
+            || from_attribute(source, item, name)
+            || (invoked_here
+                && !in_source.contains(&syntax::item_label(item))
+                && !is_derived(item)
+                && !is_injected(item));
+        if whole {
+            Some(item.clone())
+        } else {
+            in_bodies(source, item, name)
+        }
+    })
+}
+
+// The items at one level of the module tree that any derive generated.
+fn all_derived(source: &[Item], expanded: &[Item]) -> Vec<Item> {
+    walk(source, expanded, &|source, in_source, item| {
+        let derived = source
+            .iter()
+            .filter(|source| !matches!(**source, Item::Impl(_)))
+            .flat_map(|source| derives(syntax::item_attrs(source)))
+            .any(|derive| from_derive(source, in_source, item, &derive));
+        if derived {
+            Some(item.clone())
+        } else {
+            None
+        }
+    })
+}
+
+// What to keep of an item of the expansion, given the items at the same level
+// in the source and their labels.
+type Keep<'a> = dyn Fn(&[Item], &BTreeSet<String>, &Item) -> Option<Item> + 'a;
+
+// What `keep` picks of the items of the expansion, with the modules they are
+// in.
+fn walk(source: &[Item], expanded: &[Item], keep: &Keep) -> Vec<Item> {
+    let mut found = Vec::new();
+    let in_source: BTreeSet<String> = source.iter().map(syntax::item_label).collect();
+
+    for item in expanded {
+        if let Item::Mod(ref m) = *item {
+            if let Some(inner) = submodule(source, m) {
+                let items = match m.content {
+                    Some((_, ref items)) => items,
+                    None => continue,
+                };
+                let inner = walk(inner, items, keep);
+                if !inner.is_empty() {
+                    let mut m = m.clone();
+                    if let Some((_, ref mut items)) = m.content {
+                        *items = inner;
+                    }
+                    found.push(Item::Mod(m));
+                }
+                continue;
+            }
+        }
+        found.extend(keep(source, &in_source, item));
+    }
+    found
+}
+
+// A function, or an impl cut down to its methods, with the bodies cut down to
+// the statements that invoke macro `name` in the source. Statements are lined
+// up by position, so where a body of the expansion has a different number of
+// them, all of it is kept if the macro is invoked anywhere in the source's.
+fn in_bodies(source: &[Item], item: &Item, name: &str) -> Option<Item> {
+    let label = syntax::item_label(item);
+    let mut written = source
+        .iter()
+        .filter(|source| syntax::item_label(source) == label);
+    match *item {
+        Item::Fn(ref f) => written.find_map(|source| match *source {
+            Item::Fn(ref source) => {
+                let block = in_body(&f.block, &source.block, name)?;
+                let mut f = f.clone();
+                *f.block = block;
+                Some(Item::Fn(f))
+            }
+            _ => None,
+        }),
+        Item::Impl(ref imp) => written.find_map(|source| {
+            let source = match *source {
+                Item::Impl(ref source) => source,
+                _ => return None,
+            };
+            let methods: Vec<ImplItem> = imp
+                .items
+                .iter()
+                .filter_map(|item| {
+                    let f = match *item {
+                        ImplItem::Fn(ref f) => f,
+                        _ => return None,
+                    };
+                    source.items.iter().find_map(|source| match *source {
+                        ImplItem::Fn(ref source) if source.sig.ident == f.sig.ident => {
+                            let block = in_body(&f.block, &source.block, name)?;
+                            let mut f = f.clone();
+                            f.block = block;
+                            Some(ImplItem::Fn(f))
+                        }
+                        _ => None,
+                    })
+                })
+                .collect();
+            if methods.is_empty() {
+                return None;
+            }
+            let mut imp = imp.clone();
+            imp.items = methods;
+            Some(Item::Impl(imp))
+        }),
+        _ => None,
+    }
+}
+
+fn in_body(expanded: &Block, source: &Block, name: &str) -> Option<Block> {
+    if expanded.stmts.len() != source.stmts.len() {
+        return if invokes(source, name) {
+            Some(expanded.clone())
+        } else {
+            None
+        };
+    }
+    let stmts: Vec<_> = expanded
+        .stmts
+        .iter()
+        .zip(&source.stmts)
+        .filter(|&(_, source)| {
+            let mut macros = Invoked(BTreeSet::new());
+            macros.visit_stmt(source);
+            macros.0.contains(name)
+        })
+        .map(|(stmt, _)| stmt.clone())
+        .collect();
+    if stmts.is_empty() {
+        return None;
+    }
+    let mut block = expanded.clone();
+    block.stmts = stmts;
+    Some(block)
+}
+
+fn invokes(block: &Block, name: &str) -> bool {
+    let mut macros = Invoked(BTreeSet::new());
+    macros.visit_block(block);
+    macros.0.contains(name)
+}
+
+// The last segments of the paths of the macros invoked in some code.
+struct Invoked(BTreeSet<String>);
+
+impl<'ast> Visit<'ast> for Invoked {
+    fn visit_macro(&mut self, mac: &'ast Macro) {
+        self.0.insert(last_segment(&mac.path));
+        visit::visit_macro(self, mac);
+    }
+}
+
+// The contents in the source of the module `m` in the expansion.
+pub fn submodule<'a>(source: &'a [Item], m: &ItemMod) -> Option<&'a [Item]> {
+    source.iter().find_map(|item| match *item {
+        Item::Mod(ref source) if source.ident == m.ident => {
+            source.content.as_ref().map(|content| &*content.1)
+        }
+        _ => None,
+    })
+}
+
+// The derives built into the compiler, which only ever implement the trait of
+// the same name, or for `PartialEq`, also `StructuralPartialEq`.
+pub const BUILTIN_DERIVES: &[&str] = &[
+    "Clone",
+    "Copy",
+    "Debug",
+    "Default",
+    "Eq",
+    "Hash",
+    "Ord",
+    "PartialEq",
+    "PartialOrd",
+];
+
+// Whether an item of the expansion is an impl that derive `name` wrote for a
+// type it is on in the source, or a const block holding such an impl. An impl
+// is put down to the derive of the same name as its trait. Impls of other
+// traits, like the `Display` that thiserror's `Error` writes, are put down to
+// every derive on the type that is not built in.
+pub fn from_derive(source: &[Item], in_source: &BTreeSet<String>, item: &Item, name: &str) -> bool {
+    let consts = syntax::const_block_items(item);
+    let impls = if consts.is_empty() {
+        vec![item]
+    } else {
+        consts
+    };
+    impls.into_iter().any(|item| {
+        let imp = match *item {
+            Item::Impl(ref imp) if imp.trait_.is_some() => imp,
+            _ => return false,
+        };
+        let ty = match syntax::impl_self_ident(imp) {
+            Some(ty) => ty,
+            None => return false,
+        };
+        if in_source.contains(&syntax::item_label(item)) {
+            return false;
+        }
+        let trait_name = imp
+            .trait_
+            .as_ref()
+            .map_or(String::new(), |trait_| last_segment(&trait_.1));
+        let implements = |derive: &str| {
+            if BUILTIN_DERIVES.contains(&derive) {
+                trait_name == derive || trait_name == format!("Structural{}", derive)
+            } else {
+                trait_name == derive
+            }
+        };
+        source.iter().any(|source| {
+            if syntax::item_ident(source) != Some(ty) || matches!(*source, Item::Impl(_)) {
+                return false;
+            }
+            let derives = derives(syntax::item_attrs(source));
+            derives.iter().any(|derive| derive == name)
+                && (implements(name)
+                    || (!BUILTIN_DERIVES.contains(&name)
+                        && !derives.iter().any(|derive| implements(derive))))
+        })
+    })
+}
+
+// Whether an item of the expansion is what an attribute macro `name` turned
+// an item of the source into.
+fn from_attribute(source: &[Item], item: &Item, name: &str) -> bool {
+    let ident = match syntax::item_ident(item) {
+        Some(ident) if !matches!(*item, Item::Impl(_)) => ident,
+        _ => return false,
+    };
+    source.iter().any(|source| {
+        syntax::item_ident(source) == Some(ident)
+            && !matches!(*source, Item::Impl(_))
+            && syntax::item_attrs(source)
+                .iter()
+                .any(|attr| !attr.path().is_ident("derive") && last_segment(attr.path()) == name)
+    })
+}
+
+// The prelude import and `extern crate std` that the compiler adds to every
+// crate.
+pub fn is_injected(item: &Item) -> bool {
+    match *item {
+        Item::Use(ref item) => item
+            .attrs
+            .iter()
+            .any(|attr| attr.path().is_ident("prelude_import")),
+        Item::ExternCrate(ref item) => item.ident == "std" || item.ident == "core",
+        _ => false,
+    }
+}
+
+pub fn is_derived(item: &Item) -> bool {
+    let derived = |item: &Item| {
+        syntax::item_attrs(item)
+            .iter()
+            .any(|attr| attr.path().is_ident("automatically_derived"))
+    };
+    derived(item) || syntax::const_block_items(item).into_iter().any(derived)
+}
+
+// The last segments of the macros in `#[derive(...)]` attributes.
+pub fn derives(attrs: &[Attribute]) -> Vec<String> {
+    let mut derives = Vec::new();
+    for attr in attrs {
+        if !attr.path().is_ident("derive") {
+            continue;
+        }
+        let paths = attr.parse_args_with(Punctuated::<syn::Path, syn::Token![,]>::parse_terminated);
+        if let Ok(paths) = paths {
+            derives.extend(paths.iter().map(last_segment));
+        }
+    }
+    derives
+}
+
+// The names of the derive, attribute and function-like macros used in the
+// source, to suggest when the one asked for is not.
+fn invoked(items: &[Item], seen: &mut BTreeSet<String>) {
+    for item in items {
+        let attrs = syntax::item_attrs(item);
+        seen.extend(derives(attrs));
+        seen.extend(
+            attrs
+                .iter()
+                .filter(|attr| !attr.path().is_ident("derive"))
+                .map(|attr| last_segment(attr.path())),
+        );
+        match *item {
+            Item::Mod(ref m) => {
+                if let Some((_, ref content)) = m.content {
+                    invoked(content, seen);
+                }
+            }
+            _ => {
+                let mut macros = Invoked(BTreeSet::new());
+                macros.visit_item(item);
+                seen.extend(macros.0);
+            }
+        }
+    }
+}
+
+pub fn last_segment(path: &syn::Path) -> String {
+    path.segments
+        .last()
+        .map_or(String::new(), |segment| segment.ident.to_string())
+}
+
+// The source of the target being expanded, with each `mod m;` read in from
+// its file so the module tree is whole.
+pub fn load_crate(opts: &Opts) -> io::Result<File> {
+    load_target(opts).map(|(_, file)| file)
+}
+
+// load_crate, along with the name of the crate.
+pub fn load_target(opts: &Opts) -> io::Result<(String, File)> {
+    let (name, root) = target_root(opts)?;
+    let dir = root.parent().map(Path::to_path_buf).unwrap_or_default();
+    load(&root, &dir).map(|file| (name, file))
+}
+
+// The name of the target being expanded and the path of its root source file.
+pub fn target_root(opts: &Opts) -> io::Result<(String, PathBuf)> {
+    let metadata = Metadata::load(opts)?;
+    let package = opts
+        .manifest_path
+        .as_ref()
+        .and_then(|manifest_path| metadata.package(manifest_path))
+        .ok_or_else(|| io::Error::other("cannot find the package being expanded"))?;
+    let target = match opts.selected_target() {
+        Some((kind, Some(name))) => metadata::target(package, &kind, &name),
+        _ => package["targets"].as_array().and_then(|targets| {
+            let has_kind = |target: &&serde_json::Value, wanted: &dyn Fn(&str) -> bool| {
+                target["kind"]
+                    .as_array()
+                    .is_some_and(|kinds| kinds.iter().filter_map(|k| k.as_str()).any(wanted))
+            };
+            targets
+                .iter()
+                .find(|target| {
+                    has_kind(target, &|kind| {
+                        kind.ends_with("lib") || kind == "proc-macro"
+                    })
+                })
+                .or_else(|| {
+                    targets
+                        .iter()
+                        .find(|target| has_kind(target, &|kind| kind == "bin"))
+                })
+        }),
+    };
+    let name = target
+        .and_then(|target| target["name"].as_str())
+        .unwrap_or_default()
+        .replace('-', "_");
+    let root = target
+        .and_then(|target| target["src_path"].as_str())
+        .map(PathBuf::from)
+        .ok_or_else(|| io::Error::other("cannot find the source of the target being expanded"))?;
+    Ok((name, root))
+}
+
+// Parse the file at `path`, whose `mod m;` declarations are in `dir`.
+fn load(path: &Path, dir: &Path) -> io::Result<File> {
+    let content = fs::read_to_string(path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("failed to read {}: {}", path.display(), err),
+        )
+    })?;
+    let mut file = syn::parse_file(&content).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to parse {}: {}", path.display(), err),
+        )
+    })?;
+    inline_mods(&mut file.items, dir);
+    Ok(file)
+}
+
+fn inline_mods(items: &mut [Item], dir: &Path) {
+    for item in items {
+        let m = match *item {
+            Item::Mod(ref mut m) => m,
+            _ => continue,
+        };
+        let name = m.ident.to_string();
+        match m.content {
+            Some((_, ref mut content)) => inline_mods(content, &dir.join(&name)),
+            None => {
+                let (path, child_dir) = module_file(dir, &name);
+                // A module that cannot be read just contributes nothing.
+                if let Ok(loaded) = load(&path, &child_dir) {
+                    m.content = Some((Default::default(), loaded.items));
+                    m.semi = None;
+                }
+            }
+        }
+    }
+}
+
+// Where `mod name;` in a file whose modules are in `dir` is read from, and the
+// directory of its own modules.
+pub fn module_file(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+    let file = dir.join(format!("{}.rs", name));
+    if file.exists() {
+        (file, dir.join(name))
+    } else {
+        (dir.join(name).join("mod.rs"), dir.join(name))
+    }
+}
+
+// A module of the source and the file it is in, with its items as read from
+// there, so that their spans are lines of that file.
+pub struct SourceModule {
+    pub file: PathBuf,
+    // Where the files of its own modules are.
+    dir: PathBuf,
+    // None for a module that only the expansion has, made by a macro.
+    pub items: Option<Vec<Item>>,
+}
+
+impl SourceModule {
+    // The root module of the target being expanded, and the name of the
+    // crate.
+    pub fn root(opts: &Opts) -> io::Result<(String, SourceModule)> {
+        let (name, file) = target_root(opts)?;
+        let module = SourceModule {
+            dir: file.parent().map(Path::to_path_buf).unwrap_or_default(),
+            items: parse(&file),
+            file,
+        };
+        Ok((name, module))
+    }
+
+    // The module `name` inside this one, in a file of its own if the source
+    // declares it as `mod name;`. A module that only the expansion has is put
+    // in the file of the macro that made it.
+    pub fn submodule(&self, name: &str) -> SourceModule {
+        let declared = self.items.as_ref().and_then(|items| {
+            items.iter().find_map(|item| match *item {
+                Item::Mod(ref m) if m.ident == name => Some(m),
+                _ => None,
+            })
+        });
+        match declared.map(|m| &m.content) {
+            Some(Some((_, ref content))) => SourceModule {
+                file: self.file.clone(),
+                dir: self.dir.join(name),
+                items: Some(content.clone()),
+            },
+            Some(None) => {
+                let (file, dir) = module_file(&self.dir, name);
+                SourceModule {
+                    items: parse(&file),
+                    file,
+                    dir,
+                }
+            }
+            None => SourceModule {
+                file: self.file.clone(),
+                dir: self.dir.join(name),
+                items: None,
+            },
+        }
+    }
+}
+
+fn parse(path: &Path) -> Option<Vec<Item>> {
+    let content = fs::read_to_string(path).ok()?;
+    syn::parse_file(&content).ok().map(|file| file.items)
+}
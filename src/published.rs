@@ -0,0 +1,143 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde_json::{self, Value};
+
+use capture;
+use cargo_binary;
+use config::Config;
+use diff;
+use highlight;
+use metadata::Metadata;
+use opts::Opts;
+use project;
+use tmp::TmpDir;
+
+// --against-published VERSION: diff the expansion of the published release of
+// the package against that of the working copy, to review what a new release
+// changes in the code its macros generate. The release is downloaded by
+// resolving a throwaway crate that depends on exactly that version, and
+// expanded from a copy of its source with the same target, features and
+// lockfile as the working copy.
+pub fn diff(opts: &Opts, config: &Config, version: &str) -> io::Result<i32> {
+    let metadata = Metadata::load(opts)?;
+    let name = opts
+        .manifest_path
+        .as_ref()
+        .and_then(|manifest_path| metadata.package(manifest_path))
+        .and_then(|package| package["name"].as_str())
+        .ok_or_else(|| io::Error::other("cannot find the package to compare with its release"))?
+        .to_owned();
+
+    let outdir = TmpDir::create(opts.keep_tmp)?;
+    let source = download(opts, &outdir, &name, version)?;
+    let copy = outdir.path().join(format!("{}-{}", name, version));
+    copy_dir(&source, &copy)?;
+    if let Some(root) = project::locate_workspace(opts.manifest_path.as_ref()) {
+        if let Some(lockfile) = root.parent().map(|root| root.join("Cargo.lock")) {
+            let _ = fs::copy(lockfile, copy.join("Cargo.lock"));
+        }
+    }
+
+    let mut published_opts = opts.clone();
+    published_opts.manifest_path = Some(copy.join("Cargo.toml").into_os_string());
+    // The dependencies the two have in common are built once.
+    if published_opts.target_dir.is_none() {
+        published_opts.target_dir = metadata.target_directory().map(Into::into);
+    }
+    let published = match capture::expand(&published_opts, config)? {
+        Some(code) => code,
+        None => return Ok(1),
+    };
+    let local = match capture::expand(opts, config)? {
+        Some(code) => code,
+        None => return Ok(1),
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let label = format!("{} {}", name, version);
+    match diff::unified(&label, "working copy", &published, &local, 3) {
+        Some(ref diff) if opts.output_colored() => {
+            out.write_all(highlight::diff(diff).as_bytes())?
+        }
+        Some(diff) => out.write_all(diff.as_bytes())?,
+        None => writeln!(out, "-- the expansion is the same as that of {}", label)?,
+    }
+    Ok(0)
+}
+
+// The directory cargo unpacked the release into, downloading it if it has
+// not yet.
+fn download(opts: &Opts, outdir: &TmpDir, name: &str, version: &str) -> io::Result<PathBuf> {
+    let dir = outdir.path().join("download");
+    fs::create_dir_all(dir.join("src"))?;
+    fs::write(dir.join("src").join("lib.rs"), "")?;
+    fs::write(
+        dir.join("Cargo.toml"),
+        format!(
+            "[package]\n\
+             name = \"cargo-expand-published\"\n\
+             version = \"0.0.0\"\n\
+             edition = \"2021\"\n\
+             publish = false\n\
+             \n\
+             [workspace]\n\
+             \n\
+             [dependencies]\n\
+             {} = \"={}\"\n",
+            name, version,
+        ),
+    )?;
+
+    let mut cmd = Command::new(cargo_binary());
+    cmd.arg("metadata");
+    cmd.arg("--format-version=1");
+    cmd.arg("--manifest-path").arg(dir.join("Cargo.toml"));
+    for arg in &opts.cargo {
+        if arg == "--offline" || arg == "--frozen" {
+            cmd.arg(arg);
+        }
+    }
+    let output = cmd.output()?;
+    if !output.status.success() {
+        let _ = io::stderr().write_all(&output.stderr);
+        return Err(io::Error::other(format!(
+            "failed to download {} {} from the registry",
+            name, version,
+        )));
+    }
+    let json: Value = serde_json::from_slice(&output.stdout).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to parse cargo metadata: {}", err),
+        )
+    })?;
+    json["packages"]
+        .as_array()
+        .map_or(&[][..], Vec::as_slice)
+        .iter()
+        .find(|package| package["name"] == name && package["version"] == version)
+        .and_then(|package| package["manifest_path"].as_str())
+        .and_then(|manifest_path| Path::new(manifest_path).parent())
+        .map(Path::to_path_buf)
+        .ok_or_else(|| io::Error::other(format!("cargo did not download {} {}", name, version)))
+}
+
+// Cargo would write the lockfile and build into the registry's copy, which is
+// meant to stay as it was published.
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let path = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &path)?;
+        } else {
+            fs::copy(entry.path(), path)?;
+        }
+    }
+    Ok(())
+}
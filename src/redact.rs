@@ -0,0 +1,79 @@
+use proc_macro2::{Group, Literal, TokenStream, TokenTree};
+use syn::visit_mut::{self, VisitMut};
+use syn::{File, LitByteStr, LitStr, Macro};
+
+// Blank out the contents of string literals and doc comments, keeping their
+// length in bytes and layout, so code can be shared without what it says.
+// Letters become `x`, one for each byte of a letter like `ü`; digits,
+// punctuation and whitespace stay so that format strings and the shape of the
+// text survive. In byte strings only ASCII letters are letters, and any other
+// byte stays as it is.
+pub fn apply(file: &mut File) {
+    Redact.visit_file_mut(file);
+}
+
+struct Redact;
+
+impl VisitMut for Redact {
+    fn visit_lit_str_mut(&mut self, lit: &mut LitStr) {
+        *lit = LitStr::new(&redact(&lit.value()), lit.span());
+    }
+
+    fn visit_lit_byte_str_mut(&mut self, lit: &mut LitByteStr) {
+        *lit = LitByteStr::new(&redact_bytes(&lit.value()), lit.span());
+    }
+
+    // Macro arguments are left as tokens, so strings in e.g. a `format_args!`
+    // need handling separately.
+    fn visit_macro_mut(&mut self, mac: &mut Macro) {
+        mac.tokens = redact_tokens(mac.tokens.clone());
+        visit_mut::visit_macro_mut(self, mac);
+    }
+}
+
+fn redact_tokens(tokens: TokenStream) -> TokenStream {
+    tokens
+        .into_iter()
+        .map(|tt| match tt {
+            TokenTree::Group(group) => {
+                let mut redacted = Group::new(group.delimiter(), redact_tokens(group.stream()));
+                redacted.set_span(group.span());
+                TokenTree::Group(redacted)
+            }
+            TokenTree::Literal(lit) => TokenTree::Literal(redact_literal(lit)),
+            tt => tt,
+        })
+        .collect()
+}
+
+fn redact_literal(lit: Literal) -> Literal {
+    let repr = lit.to_string();
+    let mut redacted = if let Ok(s) = syn::parse_str::<LitStr>(&repr) {
+        Literal::string(&redact(&s.value()))
+    } else if let Ok(s) = syn::parse_str::<LitByteStr>(&repr) {
+        Literal::byte_string(&redact_bytes(&s.value()))
+    } else {
+        return lit;
+    };
+    redacted.set_span(lit.span());
+    redacted
+}
+
+fn redact(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if ch.is_alphabetic() {
+            redacted.extend((0..ch.len_utf8()).map(|_| 'x'));
+        } else {
+            redacted.push(ch);
+        }
+    }
+    redacted
+}
+
+fn redact_bytes(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .map(|&b| if b.is_ascii_alphabetic() { b'x' } else { b })
+        .collect()
+}
@@ -0,0 +1,375 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::SystemTime;
+
+use serde_json::{self, Map, Value};
+
+use cache;
+use config::Config;
+use metadata::{self, Metadata};
+use opts::Opts;
+use project;
+use run_cargo;
+use sha256::Sha256;
+use trust;
+use version;
+use wrap_args;
+
+// How many expansions to keep for a workspace. Those used least recently go
+// first.
+const KEEP: usize = 20;
+
+// Environment that changes how rustc expands the crate.
+const ENV: &[&str] = &[
+    "RUSTFLAGS",
+    "CARGO_ENCODED_RUSTFLAGS",
+    "CARGO_BUILD_RUSTFLAGS",
+    "CARGO_BUILD_TARGET",
+    "RUSTC",
+    "RUSTC_BOOTSTRAP",
+];
+
+// Build the expansion into `outfile` with `cmd`, unless an unchanged crate
+// was expanded the same way before, in which case that expansion is put there
+// instead; --no-cache or `reuse = false` under `[cache]` always builds.
+// Expansions are kept in the workspace's cache directory, keyed by a hash of
+// everything that goes into one that can be known without building: the files
+// of the workspace and of its path dependencies, Cargo.lock among them, the
+// options, the rustc release and the environment that rustc is run with. Each
+// is kept along with what rustc's dep-info says the expansion read besides,
+// like files pulled in with `include_str!` and variables read with `env!` or
+// by proc macros that tell the compiler so, and the variables the build
+// scripts asked to be rerun for. It is reused only while those are unchanged
+// too, which makes it as fresh as cargo's own idea of when to rebuild.
+pub fn build(opts: &Opts, config: &Config, cmd: &mut Command, outfile: &Path) -> io::Result<i32> {
+    let stored = if opts.reuse {
+        stored(opts, config)
+    } else {
+        None
+    };
+    let stored = match stored {
+        Some(stored) => stored,
+        None => {
+            trust::check(opts, config)?;
+            return run_cargo(opts, cmd);
+        }
+    };
+
+    if unchanged(&stored) && fs::copy(&stored.expansion, outfile).is_ok() {
+        // The modification time is when it was last used.
+        if let Ok(file) = fs::OpenOptions::new()
+            .append(true)
+            .open(&stored.expansion)
+        {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        let _ = writeln!(
+            &mut io::stderr(),
+            "note: the crate is unchanged since it was last expanded, showing that expansion; \
+             pass --no-cache to build it again",
+        );
+        return Ok(0);
+    }
+
+    trust::check(opts, config)?;
+    // The arguments end with those for rustc.
+    let dep_info = outfile.with_extension("d");
+    let mut emit = OsString::from("--emit=dep-info=");
+    emit.push(&dep_info);
+    cmd.arg(emit);
+    let code = run_cargo(opts, cmd)?;
+    if code == 0 {
+        if let Some(inputs) = inputs(opts, &dep_info) {
+            keep(&stored, outfile, &inputs);
+        }
+    }
+    Ok(code)
+}
+
+// Where an expansion is kept, and what it read that is not in its key.
+struct Stored {
+    expansion: PathBuf,
+    inputs: PathBuf,
+}
+
+// Store the expansion in `outfile`, the inputs first so that an expansion is
+// never found without them. Each file is copied in whole before it takes its
+// name, so that a run at the same time never reads half of it.
+fn keep(stored: &Stored, outfile: &Path, inputs: &Value) {
+    let partial = |path: &Path| path.with_extension(format!("{}.partial", process::id()));
+    let (expansion, inputs_file) = (partial(&stored.expansion), partial(&stored.inputs));
+    let dirs = [stored.expansion.parent(), stored.inputs.parent()];
+    let kept = dirs
+        .iter()
+        .flatten()
+        .all(|dir| fs::create_dir_all(dir).is_ok())
+        && fs::write(&inputs_file, inputs.to_string()).is_ok()
+        && fs::rename(&inputs_file, &stored.inputs).is_ok()
+        && fs::copy(outfile, &expansion).is_ok()
+        && fs::rename(&expansion, &stored.expansion).is_ok();
+    let _ = fs::remove_file(&inputs_file);
+    let _ = fs::remove_file(&expansion);
+    if kept {
+        if let Some(dir) = stored.expansion.parent() {
+            trim(dir, stored.inputs.parent());
+        }
+    }
+}
+
+// How many expansions are kept for the workspace in `dir`, for `cargo expand
+// cache stats`.
+pub fn count(dir: &Path) -> usize {
+    fs::read_dir(dir.join("expansions")).map_or(0, |entries| entries.count())
+}
+
+// Where the expansion is kept, if it can be. Not being able to work out the key
+// takes a warning, as the crate is then built again.
+fn stored(opts: &Opts, config: &Config) -> Option<Stored> {
+    let dir = cache::workspace_dir(opts, config)?;
+    match key(opts) {
+        Ok(key) => Some(Stored {
+            expansion: dir.join("expansions").join(&key),
+            inputs: dir.join("inputs").join(&key),
+        }),
+        Err(err) => {
+            let _ = writeln!(
+                &mut io::stderr(),
+                "warning: building the crate rather than reusing an earlier expansion: {}",
+                err,
+            );
+            None
+        }
+    }
+}
+
+// Whether what the kept expansion read besides what is in its key is as it
+// was.
+fn unchanged(stored: &Stored) -> bool {
+    let inputs = match fs::read_to_string(&stored.inputs)
+        .ok()
+        .and_then(|inputs| serde_json::from_str::<Value>(&inputs).ok())
+    {
+        Some(inputs) => inputs,
+        None => return false,
+    };
+    let files = inputs["files"].as_object();
+    let vars = inputs["env"].as_object();
+    match (files, vars) {
+        (Some(files), Some(vars)) => {
+            files
+                .iter()
+                .all(|(path, hash)| file_hash(Path::new(path)) == *hash)
+                && vars.iter().all(|(var, value)| env_value(var) == *value)
+        }
+        _ => false,
+    }
+}
+
+// What the expansion just built read besides what is in its key: the files and
+// variables in the dep-info rustc wrote, and the variables that build scripts
+// asked to be rerun for, as cargo recorded them in the target directory.
+fn inputs(opts: &Opts, dep_info: &Path) -> Option<Value> {
+    let content = fs::read_to_string(dep_info).ok()?;
+    let root = project::locate_workspace(opts.manifest_path.as_ref())?;
+    let root = root.parent()?;
+    let mut files = Map::new();
+    let mut vars = Map::new();
+    for line in content.lines() {
+        if let Some(var) = line.strip_prefix("# env-dep:") {
+            let var = var.split('=').next().unwrap_or(var);
+            vars.insert(var.to_owned(), env_value(var));
+        } else if let Some(path) = line.strip_suffix(':') {
+            // Rustc lists each file again on its own, as a target with no
+            // dependencies, with spaces escaped.
+            if path.is_empty() || path.contains(": ") {
+                continue;
+            }
+            let path = root.join(path.replace("\\ ", " "));
+            let hash = file_hash(&path);
+            files.insert(path.display().to_string(), hash);
+        }
+    }
+    for dir in build_dirs(opts) {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let output = match fs::read_to_string(entry.path().join("output")) {
+                Ok(output) => output,
+                Err(_) => continue,
+            };
+            for line in output.lines() {
+                let directive = line
+                    .strip_prefix("cargo::")
+                    .or_else(|| line.strip_prefix("cargo:"));
+                if let Some(var) = directive.and_then(|d| d.strip_prefix("rerun-if-env-changed=")) {
+                    vars.insert(var.to_owned(), env_value(var));
+                }
+            }
+        }
+    }
+    Some(json!({ "files": files, "env": vars }))
+}
+
+// The directories where cargo keeps what build scripts printed, for the host
+// and for the platform being expanded for.
+fn build_dirs(opts: &Opts) -> Vec<PathBuf> {
+    let target_dir = opts.target_dir.clone().or_else(|| {
+        Metadata::load(opts)
+            .ok()
+            .and_then(|metadata| metadata.target_directory().map(PathBuf::from))
+    });
+    let target_dir = match target_dir {
+        Some(target_dir) => target_dir,
+        None => return Vec::new(),
+    };
+    let profile = match opts.profile().as_deref() {
+        None | Some("dev") | Some("test") => "debug".to_owned(),
+        Some("bench") => "release".to_owned(),
+        Some(profile) => profile.to_owned(),
+    };
+    let mut dirs = vec![target_dir.join(&profile).join("build")];
+    if let Some(platform) = opts.platform() {
+        dirs.push(target_dir.join(platform).join(&profile).join("build"));
+    }
+    dirs
+}
+
+fn file_hash(path: &Path) -> Value {
+    match fs::read(path) {
+        Ok(content) => {
+            let mut hash = Sha256::new();
+            hash.update(&content);
+            Value::String(hash.finish())
+        }
+        Err(_) => Value::Null,
+    }
+}
+
+fn env_value(var: &str) -> Value {
+    match env::var_os(var) {
+        Some(value) => Value::String(value.to_string_lossy().into_owned()),
+        None => Value::Null,
+    }
+}
+
+fn key(opts: &Opts) -> io::Result<String> {
+    let mut hash = Sha256::new();
+    hash.update(env!("CARGO_PKG_VERSION").as_bytes());
+    // Not whether cargo colors its messages, which the expansion does not
+    // depend on.
+    let args = wrap_args(opts, None);
+    for arg in args
+        .iter()
+        .filter(|arg| !arg.to_string_lossy().starts_with("--color="))
+    {
+        hash.update(b"\0");
+        hash.update(arg.to_string_lossy().as_bytes());
+    }
+    for var in ENV {
+        hash.update(format!("\0{}=", var).as_bytes());
+        if let Some(value) = env::var_os(var) {
+            hash.update(value.to_string_lossy().as_bytes());
+        }
+    }
+    let rustc = version::rustc_command(opts.toolchain.as_deref())
+        .arg("-vV")
+        .output()?;
+    if !rustc.status.success() {
+        return Err(io::Error::other("`rustc -vV` failed"));
+    }
+    hash.update(&rustc.stdout);
+
+    // Cargo's own config, wherever it is, is read for the build too.
+    let cargo_home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cargo")));
+    if let Some(cargo_home) = cargo_home {
+        for name in ["config", "config.toml"] {
+            if let Ok(content) = fs::read(cargo_home.join(name)) {
+                hash.update(name.as_bytes());
+                hash.update(&content);
+            }
+        }
+    }
+
+    let root = project::locate_workspace(opts.manifest_path.as_ref())
+        .ok_or_else(|| io::Error::other("cannot find the workspace"))?;
+    let mut dirs = metadata::local_package_dirs(opts)
+        .ok_or_else(|| io::Error::other("cannot find the path dependencies"))?;
+    dirs.extend(root.parent().map(Path::to_path_buf));
+    dirs.sort();
+    dirs.dedup();
+    // A member inside the workspace root is walked as part of it.
+    let mut walked: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        if walked.iter().any(|walked| dir.starts_with(walked)) {
+            continue;
+        }
+        let mut files = Vec::new();
+        walk(&dir, opts.target_dir.as_deref(), &mut files);
+        files.sort();
+        for file in files {
+            let content = fs::read(&file).map_err(|err| {
+                let msg = format!("failed to read {}: {}", file.display(), err);
+                io::Error::new(err.kind(), msg)
+            })?;
+            hash.update(format!("\0{}\0{}\0", file.display(), content.len()).as_bytes());
+            hash.update(&content);
+        }
+        walked.push(dir);
+    }
+    Ok(hash.finish())
+}
+
+// The files under `dir`, leaving out hidden ones other than `.cargo` and
+// cargo's target directories, which it marks with a CACHEDIR.TAG.
+fn walk(dir: &Path, target_dir: Option<&Path>, files: &mut Vec<PathBuf>) {
+    if dir.join("CACHEDIR.TAG").exists() || Some(dir) == target_dir {
+        return;
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') && name != ".cargo" {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => walk(&entry.path(), target_dir, files),
+            Ok(file_type) if file_type.is_file() => files.push(entry.path()),
+            _ => {}
+        }
+    }
+}
+
+// Remove all but the `KEEP` expansions used most recently, with their inputs.
+fn trim(dir: &Path, inputs: Option<&Path>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut stored: Vec<(SystemTime, PathBuf)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let modified = entry.metadata().and_then(|metadata| metadata.modified());
+            Some((modified.ok()?, entry.path()))
+        })
+        .collect();
+    stored.sort();
+    let excess = stored.len().saturating_sub(KEEP);
+    for (_, path) in stored.into_iter().take(excess) {
+        if let (Some(inputs), Some(key)) = (inputs, path.file_name()) {
+            let _ = fs::remove_file(inputs.join(key));
+        }
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,197 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use quote::ToTokens;
+use syn;
+
+use assertions;
+use doc_hidden;
+use dropped;
+use fold;
+use locations;
+use minimize;
+use opts::Opts;
+use provenance;
+use redact;
+use select;
+use syntax;
+use with_source;
+
+// Edits made to the expanded code in between rustc writing it out and rustfmt
+// formatting it. The file is left untouched unless something changed.
+pub fn apply(opts: &Opts, path: &Path) -> io::Result<()> {
+    let content = fs::read_to_string(path)?;
+    let mut syntax = match syn::parse_file(&content) {
+        Ok(syntax) => syntax,
+        Err(err) => {
+            if let Some(ref item) = opts.item {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "cannot find `{}`, expanded code failed to parse: {}",
+                        item, err
+                    ),
+                ));
+            }
+            if let Some(ref name) = opts.only_macro {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "cannot find what `{}` generated, expanded code failed to parse: {}",
+                        name, err,
+                    ),
+                ));
+            }
+            if !opts.expand_from.is_empty() || !opts.keep_folded.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "cannot fold macros back, expanded code failed to parse: {}",
+                        err
+                    ),
+                ));
+            }
+            if opts.derives {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "cannot find what derives generated, expanded code failed to parse: {}",
+                        err,
+                    ),
+                ));
+            }
+            // Never show what was asked to be redacted.
+            if opts.redact {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("cannot redact expanded code, it failed to parse: {}", err),
+                ));
+            }
+            if opts.source_locations {
+                let _ = writeln!(
+                    &mut io::stderr(),
+                    "warning: cannot mark where items are in the source, expanded code failed to parse: {}",
+                    err,
+                );
+            }
+            if opts.show_dropped {
+                let _ = writeln!(
+                    &mut io::stderr(),
+                    "warning: cannot list items dropped by #[cfg], expanded code failed to parse: {}",
+                    err,
+                );
+            }
+            // Pretty-printed expansions are not always valid Rust. Only
+            // complain if an edit was explicitly asked for.
+            if opts.doc_hidden == Some(false) {
+                let _ = writeln!(
+                    &mut io::stderr(),
+                    "warning: showing expanded code unedited, it failed to parse: {}",
+                    err,
+                );
+            }
+            return Ok(());
+        }
+    };
+
+    if opts.show_dropped {
+        dropped::report(opts, &syntax)?;
+    }
+
+    // Hidden items and assertions are taken out of the text as rustc wrote
+    // it, so that the rest keeps its comments and layout unless another edit
+    // needs the code printed again from tokens.
+    let mut edits = Vec::new();
+
+    if !opts.doc_hidden.unwrap_or(false) {
+        let hidden = doc_hidden::find(&syntax);
+        let removed = hidden.len();
+        if removed > 0 && opts.doc_hidden.is_none() {
+            let _ = writeln!(
+                &mut io::stderr(),
+                "note: hid {} #[doc(hidden)] item{}; pass --show-doc-hidden to include {}",
+                removed,
+                if removed == 1 { "" } else { "s" },
+                if removed == 1 { "it" } else { "them" },
+            );
+        }
+        edits.extend(hidden.into_iter().map(|range| (range, String::new())));
+    }
+
+    let mut summaries = Vec::new();
+    if !opts.show_assertions && opts.minimize_around.is_none() {
+        let (folded, folded_summaries) = assertions::fold(&syntax);
+        summaries = folded_summaries;
+        if !summaries.is_empty() {
+            let _ = writeln!(
+                &mut io::stderr(),
+                "note: folded {} trait assertion{} into comments; pass --show-assertions to show {}",
+                summaries.len(),
+                if summaries.len() == 1 { "" } else { "s" },
+                if summaries.len() == 1 { "it" } else { "them" },
+            );
+        }
+        edits.extend(folded);
+    }
+
+    let reprint = !opts.expand_from.is_empty()
+        || !opts.keep_folded.is_empty()
+        || opts.only_macro.is_some()
+        || opts.derives
+        || opts.item.is_some()
+        || opts.redact
+        || opts.minimize_around.is_some()
+        || opts.source_locations;
+    if !edits.is_empty() {
+        let code = syntax::splice(&content, &syntax, edits);
+        if !reprint {
+            return fs::write(path, assertions::render(&code, &summaries));
+        }
+        syntax = syn::parse_file(&code).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expanded code failed to parse once edited: {}", err),
+            )
+        })?;
+    }
+
+    if !opts.expand_from.is_empty() || !opts.keep_folded.is_empty() {
+        fold::apply(opts, &mut syntax)?;
+    }
+
+    if let Some(ref name) = opts.only_macro {
+        provenance::retain(opts, &mut syntax, name)?;
+    }
+
+    if opts.derives {
+        provenance::retain_derived(opts, &mut syntax)?;
+    }
+
+    if let Some(ref item) = opts.item {
+        select::apply(&mut syntax, item)?;
+    }
+
+    if opts.redact {
+        redact::apply(&mut syntax);
+    }
+
+    if let Some(ref location) = opts.minimize_around {
+        let package_dir = opts
+            .manifest_path
+            .as_ref()
+            .and_then(|p| Path::new(p).parent());
+        let reproducer = minimize::render(location, package_dir, &syntax)?;
+        return fs::write(path, reproducer);
+    }
+
+    if !reprint {
+        return Ok(());
+    }
+    let code = match opts.item {
+        Some(ref item) if opts.with_source => with_source::render(opts, &syntax, item)?,
+        _ if opts.source_locations => locations::render(opts, &syntax)?,
+        _ => syntax.into_token_stream().to_string(),
+    };
+    fs::write(path, assertions::render(&code, &summaries))
+}
@@ -0,0 +1,199 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use cargo_binary;
+use metadata::{self, Metadata};
+use opts::Opts;
+use project;
+use tmp;
+
+// --sandbox: build the expansion with no network and nothing writable but the
+// target directory and this run's temporary directories, so that the build
+// scripts and proc macros of a crate being audited cannot reach out or leave
+// anything behind. On Linux that is bubblewrap, which also hides the home
+// directory apart from cargo's and rustup's; on macOS it is sandbox-exec.
+// Dependencies are fetched first, outside of it, since fetching runs none of
+// their code.
+pub fn prepare(opts: &mut Opts) -> io::Result<()> {
+    // --dry-run only shows the command.
+    if !opts.sandbox || opts.dry_run {
+        return Ok(());
+    }
+    let program = if cfg!(target_os = "linux") {
+        "bwrap"
+    } else if cfg!(target_os = "macos") {
+        "sandbox-exec"
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--sandbox is only supported on Linux and macOS",
+        ));
+    };
+    if !found(program) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "--sandbox needs `{}`, which was not found{}",
+                program,
+                if program == "bwrap" {
+                    "; install bubblewrap from your distribution's packages"
+                } else {
+                    ""
+                },
+            ),
+        ));
+    }
+    // The target directory has to exist to be made writable.
+    if opts.target_dir.is_none() {
+        opts.target_dir = Metadata::load(opts)?.target_directory().map(PathBuf::from);
+    }
+    if let Some(ref target_dir) = opts.target_dir {
+        fs::create_dir_all(target_dir)?;
+    }
+    fetch(opts)?;
+    let _ = writeln!(
+        &mut io::stderr(),
+        "note: building in a sandbox, with no network and nothing writable outside of {}",
+        opts.target_dir
+            .as_deref()
+            .unwrap_or_else(|| Path::new("the target directory"))
+            .display(),
+    );
+    Ok(())
+}
+
+// The command to run cargo `program` with inside the sandbox, to which its
+// arguments are then added.
+pub fn command(opts: &Opts, program: &OsStr) -> Command {
+    // Only this run's own directories: the rest of the cache has the
+    // expansions of other workspaces and the sockets of their daemons.
+    let mut writable = tmp::live();
+    writable.extend(opts.target_dir.clone());
+    for dir in &writable {
+        let _ = fs::create_dir_all(dir);
+    }
+
+    let mut cmd = if cfg!(target_os = "macos") {
+        let mut cmd = Command::new("sandbox-exec");
+        cmd.arg("-p").arg(profile(&writable));
+        cmd
+    } else {
+        let mut cmd = Command::new("bwrap");
+        cmd.args(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc"]);
+        cmd.args(["--tmpfs", "/tmp"]);
+        if let Some(home) = env::var_os("HOME") {
+            cmd.arg("--tmpfs").arg(home);
+        }
+        for dir in readable(opts) {
+            cmd.arg("--ro-bind").arg(&dir).arg(&dir);
+        }
+        for dir in &writable {
+            cmd.arg("--bind").arg(dir).arg(dir);
+        }
+        cmd.args(["--unshare-all", "--die-with-parent", "--new-session"]);
+        cmd.arg("--chdir")
+            .arg(env::current_dir().unwrap_or_else(|_| "/".into()));
+        cmd.arg("--");
+        cmd
+    };
+    cmd.env("CARGO_NET_OFFLINE", "true");
+    cmd.arg(program);
+    cmd
+}
+
+fn found(program: &str) -> bool {
+    let arg = if program == "bwrap" {
+        "--version"
+    } else {
+        "-h"
+    };
+    Command::new(program)
+        .arg(arg)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok()
+}
+
+// Download what the build needs, unless cargo was told not to.
+fn fetch(opts: &Opts) -> io::Result<()> {
+    let offline = opts
+        .cargo
+        .iter()
+        .any(|arg| arg == "--offline" || arg == "--frozen")
+        || env::var_os("CARGO_NET_OFFLINE").is_some_and(|offline| offline == "true");
+    if offline {
+        return Ok(());
+    }
+    let mut cmd = match opts.toolchain {
+        Some(ref toolchain) => {
+            let mut cmd = Command::new("cargo");
+            cmd.arg(format!("+{}", toolchain));
+            cmd
+        }
+        None => Command::new(cargo_binary()),
+    };
+    cmd.arg("fetch");
+    if let Some(ref manifest_path) = opts.manifest_path {
+        cmd.arg("--manifest-path").arg(manifest_path);
+    }
+    if opts.cargo.iter().any(|arg| arg == "--locked") {
+        cmd.arg("--locked");
+    }
+    if let Some(triple) = opts.platform() {
+        cmd.arg("--target").arg(triple);
+    }
+    let status = cmd.status()?;
+    if !status.success() {
+        return Err(io::Error::other(
+            "could not fetch the dependencies to build in the sandbox",
+        ));
+    }
+    Ok(())
+}
+
+// What in the home directory the build reads: the crate itself, its path
+// dependencies, and where cargo and rustup keep their things.
+fn readable(opts: &Opts) -> Vec<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let mut dirs = metadata::local_package_dirs(opts).unwrap_or_default();
+    if let Some(root) = project::locate_workspace(opts.manifest_path.as_ref()) {
+        dirs.extend(root.parent().map(Path::to_path_buf));
+    }
+    for (var, default) in [("CARGO_HOME", ".cargo"), ("RUSTUP_HOME", ".rustup")] {
+        dirs.extend(
+            env::var_os(var)
+                .map(PathBuf::from)
+                .or_else(|| home.as_ref().map(|home| home.join(default))),
+        );
+    }
+    dirs.retain(|dir| dir.exists());
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+// A sandbox-exec profile denying the network and writes outside of
+// `writable` and the temporary directories.
+fn profile(writable: &[PathBuf]) -> String {
+    let mut profile = String::from(
+        "(version 1)\n(allow default)\n(deny network*)\n(deny file-write*)\n\
+         (allow file-write* (subpath \"/private/tmp\") (subpath \"/private/var/folders\") \
+         (literal \"/dev/null\") (literal \"/dev/tty\")",
+    );
+    for dir in writable {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+        let dir = dir
+            .to_string_lossy()
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+        profile.push_str(&format!(" (subpath \"{}\")", dir));
+    }
+    profile.push_str(")\n");
+    profile
+}
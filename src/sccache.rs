@@ -0,0 +1,89 @@
+use std::env;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use config::Config;
+
+// Running the expansion build under sccache, so that the dependencies compiled
+// for one expansion are reused by the next, including in other checkouts and
+// worktrees of the same crate.
+#[derive(Clone)]
+pub struct Sccache {
+    program: OsString,
+    // Where sccache keeps its cache, and how big to let it grow, for a server
+    // started by this run.
+    dir: Option<PathBuf>,
+    cache_size: Option<String>,
+}
+
+impl Sccache {
+    // The sccache to use according to `[sccache]` in the config: none unless
+    // `enable = true` or a `path`.
+    pub fn configured(config: &Config) -> Option<Sccache> {
+        let table = config.section("sccache")?;
+        let path = table.get("path").and_then(|path| path.as_str());
+        let enable = table.get("enable").and_then(|enable| enable.as_bool());
+        if enable == Some(false) || (enable.is_none() && path.is_none()) {
+            return None;
+        }
+        Some(Sccache {
+            program: path.unwrap_or("sccache").into(),
+            dir: table
+                .get("dir")
+                .and_then(|dir| dir.as_str())
+                .map(PathBuf::from),
+            cache_size: table
+                .get("cache-size")
+                .and_then(|size| size.as_str())
+                .map(str::to_owned),
+        })
+    }
+
+    // Make sure sccache can be run, so a missing one costs a warning rather
+    // than a build that fails on every crate.
+    pub fn check(&self) -> bool {
+        let ok = Command::new(&self.program)
+            .arg("--version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if !ok {
+            let _ = writeln!(
+                &mut io::stderr(),
+                "warning: cannot run `{}`; building without sccache",
+                self.program.to_string_lossy(),
+            );
+        }
+        ok
+    }
+
+    // Have cargo run rustc through sccache. A RUSTC_WRAPPER already set in
+    // the environment is left alone.
+    pub fn apply(&self, cmd: &mut Command) {
+        if env::var_os("RUSTC_WRAPPER").is_some_and(|wrapper| !wrapper.is_empty()) {
+            return;
+        }
+        cmd.env("RUSTC_WRAPPER", &self.program);
+        if let Some(ref dir) = self.dir {
+            cmd.env("SCCACHE_DIR", dir);
+        }
+        if let Some(ref cache_size) = self.cache_size {
+            cmd.env("SCCACHE_CACHE_SIZE", cache_size);
+        }
+    }
+}
+
+// --sccache with nothing in the config.
+impl Default for Sccache {
+    fn default() -> Sccache {
+        Sccache {
+            program: "sccache".into(),
+            dir: None,
+            cache_size: None,
+        }
+    }
+}
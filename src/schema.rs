@@ -0,0 +1,4 @@
+// Version of the JSON that cargo expand writes, given as `"schema"` in every
+// document or record. Within a version, fields are only ever added; removing or
+// renaming a field or changing what one means takes a new version.
+pub const VERSION: u32 = 1;
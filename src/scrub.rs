@@ -0,0 +1,223 @@
+use std::env;
+use std::io;
+
+use regex::{Captures, Regex};
+
+use opts::Opts;
+
+// What user patterns that match are replaced with.
+const SCRUBBED: &str = "[SCRUBBED]";
+
+// One way of normalizing the expansion before it is compared with snapshots.
+pub struct Rule {
+    pattern: Regex,
+    kind: Kind,
+}
+
+enum Kind {
+    // Replace the numbers in the match, as this finds them, with `[LINE]`.
+    Line(Regex),
+    // Replace runs of 16 or more hex digits, mixing digits and letters, with
+    // `[HASH]`.
+    Hash,
+    // Replace the whole match with this.
+    Whole(&'static str),
+    // A --scrub=REGEX of the user's: replace its groups with `[SCRUBBED]`, or
+    // if it has none, the whole match.
+    User,
+}
+
+// --scrub: the rules to normalize what macros embed in their output that
+// changes from one build or checkout to the next, so that snapshots do not
+// churn over it: line and column numbers, hashes, and the paths of temporary
+// and build directories, along with those of any --scrub=REGEX. Empty without
+// --scrub.
+pub fn rules(opts: &Opts) -> io::Result<Vec<Rule>> {
+    let patterns = match opts.scrub {
+        Some(ref patterns) => patterns,
+        None => return Ok(Vec::new()),
+    };
+    let mut rules = Vec::new();
+    let mut builtin = |pattern: &str, kind: Kind| {
+        rules.push(Rule {
+            pattern: Regex::new(pattern).unwrap(),
+            kind,
+        });
+    };
+    // Paths first, so their hashes go with them. The OUT_DIR of a build script
+    // comes before temporary paths, since the target directory may well be in
+    // a temporary one.
+    builtin(
+        r#"[^"\s]*[/\\]build[/\\][A-Za-z0-9_-]+-[0-9a-f]{16}[/\\]out"#,
+        Kind::Whole("[OUT_DIR]"),
+    );
+    let tmp = env::temp_dir();
+    let tmp = tmp.to_string_lossy();
+    let tmp = tmp.trim_end_matches(['/', '\\']);
+    // As written in a string literal, too, where backslashes are doubled.
+    let tmp_patterns = [
+        regex::escape(tmp),
+        regex::escape(&tmp.replace('\\', "\\\\")),
+        "/tmp".to_owned(),
+        "(?:/private)?/var/folders".to_owned(),
+    ];
+    builtin(
+        &format!(r#"(?:{})(?:[/\\][^"\s/\\]+)+"#, tmp_patterns.join("|")),
+        Kind::Whole("[TMP]"),
+    );
+
+    // `line: 12u32` and `column: Some(5u32)`, as the likes of tracing record
+    // them, and the `src/lib.rs:12:5` of locations in strings.
+    builtin(
+        r"\b(?:line|column|col|lineno)\s*[:=]\s*(?:(?:::)?(?:core|std)::option::Option::)?(?:Some\s*\(\s*)?\d+(?:u32|usize)?",
+        Kind::Line(Regex::new(r"\d+(?:u32|usize)?").unwrap()),
+    );
+    builtin(
+        r"\.rs:\d+(?::\d+)?",
+        Kind::Line(Regex::new(r"\d+").unwrap()),
+    );
+    builtin(r"[0-9a-fA-F]{16,}", Kind::Hash);
+    for pattern in patterns {
+        let pattern = Regex::new(pattern).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid --scrub pattern: {}", err),
+            )
+        })?;
+        rules.push(Rule {
+            pattern,
+            kind: Kind::User,
+        });
+    }
+    Ok(rules)
+}
+
+// The code with every rule applied, in order.
+pub fn apply(rules: &[Rule], code: &str) -> String {
+    let mut code = code.to_owned();
+    for rule in rules {
+        let scrubbed = rule.pattern.replace_all(&code, |caps: &Captures| {
+            let matched = &caps[0];
+            match rule.kind {
+                Kind::Line(ref numbers) => numbers.replace_all(matched, "[LINE]").into_owned(),
+                Kind::Hash => {
+                    let digits = matched.bytes().any(|b| b.is_ascii_digit());
+                    let letters = matched.bytes().any(|b| b.is_ascii_alphabetic());
+                    if digits && letters {
+                        "[HASH]".to_owned()
+                    } else {
+                        matched.to_owned()
+                    }
+                }
+                Kind::Whole(placeholder) => placeholder.to_owned(),
+                Kind::User => {
+                    let start = caps.get(0).unwrap().start();
+                    let groups: Vec<_> = caps.iter().skip(1).flatten().collect();
+                    if groups.is_empty() {
+                        return SCRUBBED.to_owned();
+                    }
+                    let mut replaced = String::new();
+                    let mut at = 0;
+                    for group in groups {
+                        let (from, to) = (group.start() - start, group.end() - start);
+                        if from < at {
+                            continue;
+                        }
+                        replaced.push_str(&matched[at..from]);
+                        replaced.push_str(SCRUBBED);
+                        at = to;
+                    }
+                    replaced.push_str(&matched[at..]);
+                    replaced
+                }
+            }
+        });
+        code = scrubbed.into_owned();
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+
+    use super::*;
+    use config::Config;
+
+    fn opts(args: &[&str]) -> Opts {
+        let args = [
+            "cargo-expand",
+            "expand",
+            "--manifest-path",
+            "/nonexistent/Cargo.toml",
+        ]
+        .iter()
+        .chain(args)
+        .map(OsString::from);
+        Opts::parse(args, &Config::from_toml("")).unwrap()
+    }
+
+    fn scrubbed(args: &[&str], code: &str) -> String {
+        apply(&rules(&opts(args)).unwrap(), code)
+    }
+
+    #[test]
+    fn off_without_scrub() {
+        assert_eq!(scrubbed(&[], "line: 12u32"), "line: 12u32");
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(
+            scrubbed(&["--scrub"], "Location { line: 12u32, column: Some(5u32) }"),
+            "Location { line: [LINE], column: Some([LINE]) }",
+        );
+        assert_eq!(
+            scrubbed(&["--scrub"], r#"panic!("at src/lib.rs:12:5")"#),
+            r#"panic!("at src/lib.rs:[LINE]:[LINE]")"#,
+        );
+        assert_eq!(scrubbed(&["--scrub"], "let line = x;"), "let line = x;");
+    }
+
+    #[test]
+    fn hashes() {
+        assert_eq!(
+            scrubbed(
+                &["--scrub"],
+                "__impl_4f1a9c02e85b7d36 0123456789012345 abcdefabcdefabcdef"
+            ),
+            "__impl_[HASH] 0123456789012345 abcdefabcdefabcdef",
+        );
+        assert_eq!(scrubbed(&["--scrub"], "x1f2e3d"), "x1f2e3d");
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(
+            scrubbed(
+                &["--scrub"],
+                r#"include!("/home/u/crate/target/debug/build/foo-0123456789abcdef/out/gen.rs")"#,
+            ),
+            r#"include!("[OUT_DIR]/gen.rs")"#,
+        );
+        assert_eq!(
+            scrubbed(&["--scrub"], r#"include_str!("/tmp/.tmpA1b2/input.txt")"#),
+            r#"include_str!("[TMP]")"#,
+        );
+    }
+
+    #[test]
+    fn user_patterns() {
+        assert_eq!(
+            scrubbed(
+                &["--scrub=built at \\d+", "--scrub=took (\\d+)ms"],
+                "built at 1700000000, took 35ms",
+            ),
+            "[SCRUBBED], took [SCRUBBED]ms",
+        );
+        match rules(&opts(&["--scrub=("])) {
+            Ok(_) => panic!("invalid pattern accepted"),
+            Err(err) => assert!(err.to_string().starts_with("invalid --scrub pattern")),
+        }
+    }
+}
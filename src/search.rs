@@ -0,0 +1,118 @@
+use std::io::{self, Write};
+
+use regex::Regex;
+use syn;
+use syn::spanned::Spanned;
+use syn::{ImplItem, Item};
+
+use bisect::grep_pattern;
+use capture;
+use config::Config;
+use guard;
+use metadata::{self, Metadata};
+use opts::Opts;
+use syntax;
+
+// --grep without a bisection: print the lines of the expansion that match,
+// each with the innermost item it is in. With --workspace, every lib and bin
+// of every workspace member is expanded and searched.
+pub fn run(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let pattern = grep_pattern(opts)?;
+    let workspace = opts.cargo.iter().any(|arg| arg == "--workspace");
+    if !workspace {
+        return search(opts, config, &pattern, None).map(|found| if found { 0 } else { 1 });
+    }
+
+    let metadata = Metadata::load(opts)?;
+    let targets = metadata::workspace_targets(&metadata, opts);
+    guard::confirm_targets(opts, config, targets.len())?;
+    let mut failed = false;
+    let mut found = false;
+    for (label, opts) in targets {
+        match search(&opts, config, &pattern, Some(&label)) {
+            Ok(true) => found = true,
+            Ok(false) => {}
+            Err(err) => {
+                let _ = writeln!(&mut io::stderr(), "warning: skipping {}: {}", label, err);
+                failed = true;
+            }
+        }
+    }
+    Ok(if found && !failed { 0 } else { 1 })
+}
+
+// Search one expansion, prefixing matches with `label` if given. Whether
+// anything matched.
+fn search(opts: &Opts, config: &Config, pattern: &Regex, label: Option<&str>) -> io::Result<bool> {
+    let code = match capture::expand(opts, config)? {
+        Some(code) => code,
+        None => return Err(io::Error::other("build failed")),
+    };
+    let file = syn::parse_file(&code).ok();
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut found = false;
+    for (i, line) in code.lines().enumerate() {
+        if !pattern.is_match(line) {
+            continue;
+        }
+        found = true;
+        let path = match file {
+            Some(ref file) => item_path(&file.items, i + 1),
+            None => Vec::new(),
+        };
+        let mut location = String::new();
+        if let Some(label) = label {
+            location.push_str(label);
+            location.push_str(": ");
+        }
+        if !path.is_empty() {
+            location.push_str(&path.join(" > "));
+            location.push_str(": ");
+        }
+        writeln!(out, "{}{}:{}", location, i + 1, line)?;
+    }
+    Ok(found)
+}
+
+// Labels of the items the line is nested in, outermost first. An impl that a
+// derive generated is marked as such.
+fn item_path(items: &[Item], line: usize) -> Vec<String> {
+    let contains = |node: &dyn Spanned| {
+        let span = node.span();
+        span.start().line <= line && line <= span.end().line
+    };
+    let item = match items.iter().find(|item| contains(*item)) {
+        Some(item) => item,
+        None => return Vec::new(),
+    };
+
+    let mut label = syntax::item_label(item);
+    let derived = syntax::item_attrs(item)
+        .iter()
+        .any(|attr| attr.path().is_ident("automatically_derived"));
+    if derived {
+        label.push_str(" (derived)");
+    }
+    let mut path = vec![label];
+
+    let consts = syntax::const_block_items(item);
+    if !consts.is_empty() {
+        let items: Vec<Item> = consts.into_iter().cloned().collect();
+        path.extend(item_path(&items, line));
+    } else if let Item::Mod(ref m) = *item {
+        if let Some((_, ref content)) = m.content {
+            path.extend(item_path(content, line));
+        }
+    } else if let Item::Impl(ref imp) = *item {
+        for impl_item in &imp.items {
+            if let ImplItem::Fn(ref f) = *impl_item {
+                if contains(f) {
+                    path.push(format!("fn {}", f.sig.ident));
+                }
+            }
+        }
+    }
+    path
+}
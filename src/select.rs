@@ -0,0 +1,99 @@
+use std::io;
+
+use syn::{File, ImplItem, Item, TraitItem};
+
+use suggest;
+use syntax;
+
+// `cargo expand path::to::item`: cut the expansion down to the item at that
+// path, along with the impls for it if it is a type, or to a single method as
+// `Type::method` or `Trait::method`, kept inside its impl or trait.
+pub fn apply(file: &mut File, path: &str) -> io::Result<()> {
+    let path = path.strip_prefix("crate::").unwrap_or(path);
+    let segments: Vec<&str> = path.split("::").collect();
+    let selected = select(&file.items, &segments);
+    if selected.is_empty() {
+        let paths = syntax::item_paths(&file.items, "");
+        let suggestions = suggest::similar(path, paths.iter().map(String::as_str));
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "no item `{}` in the expansion{}",
+                path,
+                suggest::did_you_mean(&suggestions),
+            ),
+        ));
+    }
+    file.shebang = None;
+    file.attrs.clear();
+    file.items = selected;
+    Ok(())
+}
+
+// The items at the path given as segments, with the impls for a type.
+pub fn select(items: &[Item], segments: &[&str]) -> Vec<Item> {
+    let (name, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    let named = |item: &Item| match *item {
+        Item::Use(_) => false,
+        Item::Const(ref item) if item.ident == "_" => false,
+        _ => syntax::item_ident(item).is_some_and(|ident| ident == name),
+    };
+
+    let mut selected = Vec::new();
+    for item in items {
+        let consts = syntax::const_block_items(item);
+        if rest.is_empty() {
+            if named(item) || consts.into_iter().any(&named) {
+                selected.push(item.clone());
+            }
+            continue;
+        }
+
+        if let Item::Mod(ref m) = *item {
+            if m.ident == name {
+                if let Some((_, ref content)) = m.content {
+                    selected.extend(select(content, rest));
+                }
+            }
+        }
+        if rest.len() == 1 {
+            let candidates = Some(item).into_iter().chain(consts);
+            selected.extend(candidates.filter_map(|item| member(item, name, rest[0])));
+        }
+    }
+    selected
+}
+
+// The impl for `ty` or trait `ty` with only its `name` method left in, if it has one.
+fn member(item: &Item, ty: &str, name: &str) -> Option<Item> {
+    match *item {
+        Item::Impl(ref imp) if syntax::impl_self_ident(imp).is_some_and(|ident| ident == ty) => {
+            let mut imp = imp.clone();
+            imp.items.retain(|item| match *item {
+                ImplItem::Fn(ref f) => f.sig.ident == name,
+                _ => false,
+            });
+            if imp.items.is_empty() {
+                None
+            } else {
+                Some(Item::Impl(imp))
+            }
+        }
+        Item::Trait(ref t) if t.ident == ty => {
+            let mut t = t.clone();
+            t.items.retain(|item| match *item {
+                TraitItem::Fn(ref f) => f.sig.ident == name,
+                _ => false,
+            });
+            if t.items.is_empty() {
+                None
+            } else {
+                Some(Item::Trait(t))
+            }
+        }
+        _ => None,
+    }
+}
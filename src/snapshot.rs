@@ -0,0 +1,310 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use quote::ToTokens;
+use syn;
+
+use capture;
+use config::Config;
+use diff;
+use opts::Opts;
+use scrub;
+use suggest;
+use syntax;
+use term;
+use tmp::TmpDir;
+use tty::stdin_isatty;
+
+// rustfmt's own default, used for snapshots in place of the terminal width so
+// that they do not depend on who ran the check.
+const SNAPSHOT_WIDTH: usize = 100;
+
+// One `NAME.rs` file under the snapshot directory, holding the expected
+// expansion of the item called NAME, or for `crate.rs` of the whole target.
+struct Snapshot {
+    name: String,
+    path: PathBuf,
+    failure: Option<String>,
+    diff: Option<String>,
+    // The new expansion, kept when it differs so that it can be accepted.
+    actual: Option<String>,
+    updated: bool,
+}
+
+// --check-snapshots DIR: compare each snapshot in DIR against the expansion
+// and show how the ones that no longer match differ, or with --update, rewrite
+// them, or with --review, ask about each one. Exits with 1 if any fail.
+pub fn check(opts: &Opts, config: &Config, dir: &Path) -> io::Result<i32> {
+    let mut opts = opts.clone();
+    opts.max_width = Some(opts.max_width.unwrap_or(SNAPSHOT_WIDTH));
+    let opts = &opts;
+
+    let rules = scrub::rules(opts)?;
+    let mut snapshots = list(dir)?;
+    if snapshots.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no snapshots in {}", dir.display()),
+        ));
+    }
+
+    let code = match capture::expand(opts, config)? {
+        Some(code) => code,
+        None => return Ok(1),
+    };
+    let file = syn::parse_file(&code).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "cannot check snapshots, expanded code failed to parse: {}",
+                err
+            ),
+        )
+    })?;
+
+    // Pulling the items out of the syntax tree has to happen here, but
+    // formatting and comparing them is independent per snapshot.
+    let outdir = TmpDir::create(opts.keep_tmp)?;
+    let mut work = Vec::new();
+    for snapshot in &mut snapshots {
+        if snapshot.name == "crate" {
+            work.push((snapshot, None));
+            continue;
+        }
+        let items = syntax::items_for(&file.items, &snapshot.name);
+        if items.is_empty() {
+            let names = syntax::item_names(&file.items);
+            let suggestions = suggest::similar(&snapshot.name, names.iter().map(String::as_str));
+            snapshot.failure = Some(format!(
+                "no item named `{}`{}",
+                snapshot.name,
+                suggest::did_you_mean(&suggestions),
+            ));
+            continue;
+        }
+        let path = outdir.path().join(format!("{}.rs", snapshot.name));
+        let tokens: Vec<String> = items
+            .iter()
+            .map(|item| item.to_token_stream().to_string())
+            .collect();
+        fs::write(&path, tokens.join("\n"))?;
+        work.push((snapshot, Some(path)));
+    }
+
+    let threads = thread::available_parallelism().map_or(1, usize::from);
+    let chunk = work.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        let handles: Vec<_> = work
+            .chunks_mut(chunk)
+            .map(|chunk| {
+                let code = &code;
+                let rules = &rules;
+                scope.spawn(move || -> io::Result<()> {
+                    for &mut (ref mut snapshot, ref path) in chunk {
+                        let actual = match *path {
+                            Some(ref path) => capture::format(opts, config, path)?,
+                            None => code.clone(),
+                        };
+                        compare(opts, snapshot, &scrub::apply(rules, &actual))?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().unwrap())
+    })?;
+
+    if opts.review {
+        review(&mut snapshots)?;
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut failed = 0;
+    for snapshot in &snapshots {
+        if snapshot.updated {
+            writeln!(out, "snapshot {} ... updated", snapshot.name)?;
+            continue;
+        }
+        match snapshot.failure {
+            None => writeln!(out, "snapshot {} ... ok", snapshot.name)?,
+            Some(ref failure) => {
+                failed += 1;
+                writeln!(out, "snapshot {} ... FAILED: {}", snapshot.name, failure)?;
+                if let Some(ref diff) = snapshot.diff {
+                    write!(out, "{}", diff)?;
+                }
+            }
+        }
+    }
+    writeln!(
+        out,
+        "\nsnapshots: {} passed, {} failed",
+        snapshots.len() - failed,
+        failed,
+    )?;
+
+    if let Some(ref path) = opts.junit {
+        fs::write(path, junit(&opts.target_label(), &snapshots))?;
+    }
+
+    Ok(if failed == 0 { 0 } else { 1 })
+}
+
+// Check one snapshot against what it expanded to, or with --update, accept
+// the new expansion in its place.
+fn compare(opts: &Opts, snapshot: &mut Snapshot, actual: &str) -> io::Result<()> {
+    let expected = fs::read_to_string(&snapshot.path)?;
+    if expected == actual {
+        return Ok(());
+    }
+    if opts.update_snapshots {
+        fs::write(&snapshot.path, actual)?;
+        snapshot.updated = true;
+        return Ok(());
+    }
+    let path = snapshot.path.display().to_string();
+    snapshot.diff = diff::unified(&path, "expanded", &expected, actual, 3);
+    snapshot.failure = Some(match snapshot.diff {
+        Some(_) => format!("differs from {}", path),
+        None => format!("differs from {} in the trailing newline", path),
+    });
+    snapshot.actual = Some(actual.to_owned());
+    Ok(())
+}
+
+// --review: show each snapshot that differs and ask whether to accept the new
+// expansion into it, reject it, or skip it for now. Quitting skips the rest.
+fn review(snapshots: &mut [Snapshot]) -> io::Result<()> {
+    if !stdin_isatty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--review needs a terminal to ask on",
+        ));
+    }
+    let pending: Vec<_> = snapshots
+        .iter_mut()
+        .filter(|snapshot| snapshot.actual.is_some())
+        .collect();
+    let total = pending.len();
+    let mut quit = false;
+    for (i, snapshot) in pending.into_iter().enumerate() {
+        if quit {
+            snapshot.failure = Some("skipped".to_owned());
+            snapshot.diff = None;
+            continue;
+        }
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        writeln!(out, "snapshot {} ({} of {})", snapshot.name, i + 1, total)?;
+        match snapshot.diff.take() {
+            Some(diff) => write!(out, "{}", diff)?,
+            None => writeln!(out, "{}", snapshot.failure.as_ref().unwrap())?,
+        }
+        write!(out, "a: accept, r: reject, s: skip, q: quit > ")?;
+        out.flush()?;
+        let decision = loop {
+            match term::read_key()? {
+                'a' => break "accepted",
+                'r' => break "rejected",
+                's' | '\n' => break "skipped",
+                'q' | '\x03' => break "quit",
+                _ => {}
+            }
+        };
+        writeln!(out, "{}\n", decision)?;
+        match decision {
+            "accepted" => {
+                fs::write(&snapshot.path, snapshot.actual.as_ref().unwrap())?;
+                snapshot.updated = true;
+            }
+            "quit" => {
+                snapshot.failure = Some("skipped".to_owned());
+                quit = true;
+            }
+            _ => snapshot.failure = Some(decision.to_owned()),
+        }
+    }
+    Ok(())
+}
+
+fn list(dir: &Path) -> io::Result<Vec<Snapshot>> {
+    let entries = fs::read_dir(dir).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("failed to read {}: {}", dir.display(), err),
+        )
+    })?;
+    let mut snapshots = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "rs") {
+            continue;
+        }
+        if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+            snapshots.push(Snapshot {
+                name: name.to_owned(),
+                path: path.clone(),
+                failure: None,
+                diff: None,
+                actual: None,
+                updated: false,
+            });
+        }
+    }
+    snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(snapshots)
+}
+
+// A JUnit XML report with a test case per snapshot, which is what most CI
+// dashboards know how to show.
+fn junit(target: &str, snapshots: &[Snapshot]) -> String {
+    let failures = snapshots.iter().filter(|s| s.failure.is_some()).count();
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites tests=\"{}\" failures=\"{}\">\n",
+        snapshots.len(),
+        failures,
+    ));
+    xml.push_str(&format!(
+        "  <testsuite name=\"cargo expand {}\" tests=\"{}\" failures=\"{}\">\n",
+        escape(target),
+        snapshots.len(),
+        failures,
+    ));
+    for snapshot in snapshots {
+        let name = escape(&snapshot.name);
+        match snapshot.failure {
+            None => xml.push_str(&format!(
+                "    <testcase name=\"{}\" classname=\"snapshots\"/>\n",
+                name,
+            )),
+            Some(ref failure) => {
+                xml.push_str(&format!(
+                    "    <testcase name=\"{}\" classname=\"snapshots\">\n",
+                    name,
+                ));
+                xml.push_str(&format!(
+                    "      <failure message=\"{}\"/>\n",
+                    escape(failure),
+                ));
+                xml.push_str("    </testcase>\n");
+            }
+        }
+    }
+    xml.push_str("  </testsuite>\n");
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
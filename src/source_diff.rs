@@ -0,0 +1,66 @@
+use std::fs;
+use std::io::{self, Write};
+
+use quote::ToTokens;
+use syn::{self, File};
+
+use capture;
+use config::Config;
+use diff;
+use highlight;
+use opts::Opts;
+use provenance;
+use select;
+use tmp::TmpDir;
+
+// --diff: print a unified diff from the crate's source to its expansion, so
+// that what macros added stands out from the code they left alone. Both sides
+// are printed from their syntax tree and formatted the same way, which loses
+// the source's comments and layout but keeps the diff down to real changes.
+pub fn print(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let expanded = match capture::expand(opts, config)? {
+        Some(code) => code,
+        None => return Ok(1),
+    };
+    let expanded = syn::parse_file(&expanded).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("cannot diff, expanded code failed to parse: {}", err),
+        )
+    })?;
+
+    let mut source = provenance::load_crate(opts)?;
+    if let Some(ref item) = opts.item {
+        // An item that only the expansion has leaves nothing on this side.
+        if select::apply(&mut source, item).is_err() {
+            source.items.clear();
+        }
+    }
+
+    let outdir = TmpDir::create(opts.keep_tmp)?;
+    let source = normalize(opts, config, &outdir, "source.rs", &source)?;
+    let expanded = normalize(opts, config, &outdir, "expanded.rs", &expanded)?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match diff::unified("source", "expanded", &source, &expanded, 3) {
+        Some(ref diff) if opts.output_colored() => {
+            out.write_all(highlight::diff(diff).as_bytes())?
+        }
+        Some(diff) => out.write_all(diff.as_bytes())?,
+        None => writeln!(out, "-- the expansion is the same as the source")?,
+    }
+    Ok(0)
+}
+
+fn normalize(
+    opts: &Opts,
+    config: &Config,
+    outdir: &TmpDir,
+    name: &str,
+    file: &File,
+) -> io::Result<String> {
+    let path = outdir.path().join(name);
+    fs::write(&path, file.to_token_stream().to_string())?;
+    capture::format(opts, config, &path)
+}
@@ -0,0 +1,258 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::{Attribute, ImplItemFn, Item, Macro, TraitItemFn};
+
+use cache::human_size;
+use capture;
+use config::Config;
+use opts::Opts;
+use provenance::{self, SourceModule};
+use syntax;
+
+// Attributes of the language and of tools, which are not macros.
+const BUILTIN_ATTRS: &[&str] = &[
+    "allow",
+    "automatically_derived",
+    "bench",
+    "cfg",
+    "cfg_attr",
+    "cold",
+    "coverage",
+    "deny",
+    "deprecated",
+    "derive",
+    "doc",
+    "expect",
+    "export_name",
+    "forbid",
+    "global_allocator",
+    "ignore",
+    "inline",
+    "link",
+    "link_name",
+    "link_section",
+    "macro_export",
+    "macro_use",
+    "must_use",
+    "naked",
+    "no_mangle",
+    "non_exhaustive",
+    "panic_handler",
+    "path",
+    "proc_macro",
+    "proc_macro_attribute",
+    "proc_macro_derive",
+    "repr",
+    "should_panic",
+    "target_feature",
+    "test",
+    "track_caller",
+    "unsafe",
+    "used",
+    "warn",
+];
+const TOOLS: &[&str] = &["clippy", "diagnostic", "rustfmt"];
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Derive,
+    Attribute,
+    FunctionLike,
+}
+
+// --stats: how big the crate is before and after expansion, how many macros
+// it invokes, and which of them generated the most lines. Lines are put down
+// to macros the way --only-macro does it.
+pub fn print(opts: &Opts, config: &Config, top: usize) -> io::Result<i32> {
+    let expanded = match capture::unedited(opts, config)? {
+        Some(expanded) => expanded,
+        None => return Ok(1),
+    };
+
+    let (_, root) = SourceModule::root(opts)?;
+    let mut files = BTreeSet::new();
+    source_files(&root, &mut files);
+    let mut source_lines = 0;
+    let mut source_bytes = 0;
+    for file in &files {
+        if let Ok(content) = fs::read_to_string(file) {
+            source_lines += content.lines().count();
+            source_bytes += content.len();
+        }
+    }
+    let expanded_lines = expanded.lines().count();
+
+    let source = provenance::load_crate(opts)?;
+    let mut invocations = Invocations::default();
+    invocations.visit_file(&source);
+
+    let mut generated = Vec::new();
+    let parsed = syn::parse_file(&expanded).ok();
+    if let Some(ref file) = parsed {
+        for (&(kind, ref name), &count) in &invocations.counts {
+            let lines = lines(&provenance::generated(&source.items, &file.items, name));
+            if lines > 0 {
+                generated.push((lines, label(kind, name), count));
+            }
+        }
+    }
+    generated.sort_by_key(|&(lines, ref label, _)| (Reverse(lines), label.clone()));
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "{:11}{:>8}{:>12}", "", "lines", "bytes")?;
+    writeln!(
+        out,
+        "{:11}{:>8}{:>12}   in {} file{}",
+        "source",
+        source_lines,
+        human_size(source_bytes as u64),
+        files.len(),
+        if files.len() == 1 { "" } else { "s" },
+    )?;
+    writeln!(
+        out,
+        "{:11}{:>8}{:>12}   {:.1}x the lines",
+        "expansion",
+        expanded_lines,
+        human_size(expanded.len() as u64),
+        expanded_lines as f64 / source_lines.max(1) as f64,
+    )?;
+
+    let count = |wanted: Kind| -> usize {
+        invocations
+            .counts
+            .iter()
+            .filter(|entry| (entry.0).0 == wanted)
+            .map(|entry| *entry.1)
+            .sum()
+    };
+    let plural = |n: usize, what: &str| format!("{} {}{}", n, what, if n == 1 { "" } else { "s" });
+    let (derives, attributes, function_like) = (
+        count(Kind::Derive),
+        count(Kind::Attribute),
+        count(Kind::FunctionLike),
+    );
+    writeln!(
+        out,
+        "\n{}: {}, {}, {}",
+        plural(derives + attributes + function_like, "macro invocation"),
+        plural(derives, "derive"),
+        plural(attributes, "attribute macro"),
+        plural(function_like, "function-like macro"),
+    )?;
+
+    if parsed.is_none() {
+        writeln!(
+            out,
+            "\nthe expanded code failed to parse, so its lines are not put down to macros"
+        )?;
+    } else if !generated.is_empty() {
+        writeln!(out, "\nlines generated by macro:")?;
+        for (lines, label, count) in generated.iter().take(top) {
+            writeln!(out, "{:>8}  {} x{}", lines, label, count)?;
+        }
+    }
+    Ok(0)
+}
+
+// The files the module tree of the source is read from.
+fn source_files(module: &SourceModule, files: &mut BTreeSet<PathBuf>) {
+    files.insert(module.file.clone());
+    let items = match module.items {
+        Some(ref items) => items,
+        None => return,
+    };
+    for item in items {
+        if let Item::Mod(ref m) = *item {
+            source_files(&module.submodule(&m.ident.to_string()), files);
+        }
+    }
+}
+
+// The lines of the expansion that items take up. A module kept for the items
+// in it counts for those only.
+fn lines(items: &[Item]) -> usize {
+    items
+        .iter()
+        .map(|item| match *item {
+            Item::Mod(ref m) => m.content.as_ref().map_or(0, |content| lines(&content.1)),
+            _ => {
+                let span = item.span();
+                span.end().line + 1 - span.start().line
+            }
+        })
+        .sum()
+}
+
+fn label(kind: Kind, name: &str) -> String {
+    match kind {
+        Kind::Derive => format!("#[derive({})]", name),
+        Kind::Attribute => format!("#[{}]", name),
+        Kind::FunctionLike => format!("{}!", name),
+    }
+}
+
+// The macros invoked in the source, by kind and the last segment of their
+// path, with how many times each is.
+#[derive(Default)]
+struct Invocations {
+    counts: BTreeMap<(Kind, String), usize>,
+}
+
+impl Invocations {
+    fn attrs(&mut self, attrs: &[Attribute]) {
+        for derive in provenance::derives(attrs) {
+            *self.counts.entry((Kind::Derive, derive)).or_default() += 1;
+        }
+        for attr in attrs {
+            let path = attr.path();
+            let first = path
+                .segments
+                .first()
+                .map_or(String::new(), |segment| segment.ident.to_string());
+            let builtin = if path.segments.len() == 1 {
+                BUILTIN_ATTRS.contains(&&*first)
+            } else {
+                TOOLS.contains(&&*first)
+            };
+            if !builtin {
+                let name = provenance::last_segment(path);
+                *self.counts.entry((Kind::Attribute, name)).or_default() += 1;
+            }
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for Invocations {
+    // Attributes on fields and the like are the helpers of derives, not
+    // macros of their own.
+    fn visit_item(&mut self, item: &'ast Item) {
+        self.attrs(syntax::item_attrs(item));
+        visit::visit_item(self, item);
+    }
+
+    fn visit_impl_item_fn(&mut self, f: &'ast ImplItemFn) {
+        self.attrs(&f.attrs);
+        visit::visit_impl_item_fn(self, f);
+    }
+
+    fn visit_trait_item_fn(&mut self, f: &'ast TraitItemFn) {
+        self.attrs(&f.attrs);
+        visit::visit_trait_item_fn(self, f);
+    }
+
+    fn visit_macro(&mut self, mac: &'ast Macro) {
+        let name = provenance::last_segment(&mac.path);
+        if name != "macro_rules" {
+            *self.counts.entry((Kind::FunctionLike, name)).or_default() += 1;
+        }
+        visit::visit_macro(self, mac);
+    }
+}
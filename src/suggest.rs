@@ -0,0 +1,66 @@
+// Telling someone who asked for an item that does not exist what they might
+// have meant instead.
+
+// Up to this many suggestions are offered.
+const MAX_SUGGESTIONS: usize = 3;
+
+// The candidates that look most like `wanted`, best first: those within a few
+// typos of it, and paths whose last segments are what was asked for, as when
+// `handle` finds `http::Handlers::handle`. Case is ignored.
+pub fn similar<'a, I>(wanted: &str, candidates: I) -> Vec<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let wanted = wanted.to_lowercase();
+    let wanted_tail = wanted.rsplit("::").next().unwrap_or(&wanted).to_owned();
+    let mut scored = Vec::new();
+    for candidate in candidates {
+        let lower = candidate.to_lowercase();
+        let tail = lower.rsplit("::").next().unwrap_or(&lower);
+        let score = if lower.ends_with(&format!("::{}", wanted)) {
+            0
+        } else {
+            let whole = distance(&wanted, &lower);
+            let last = distance(&wanted_tail, tail);
+            let closest = whole.min(last + 1);
+            if closest > (wanted_tail.chars().count() / 3).max(1) {
+                continue;
+            }
+            closest
+        };
+        scored.push((score, candidate.len(), candidate));
+    }
+    scored.sort();
+    scored.dedup_by(|a, b| a.2 == b.2);
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, _, candidate)| candidate)
+        .collect()
+}
+
+// `; did you mean `a`?` to add to an error message, or nothing.
+pub fn did_you_mean(suggestions: &[&str]) -> String {
+    let quoted: Vec<String> = suggestions.iter().map(|s| format!("`{}`", s)).collect();
+    match quoted.len() {
+        0 => String::new(),
+        1 => format!("; did you mean {}?", quoted[0]),
+        _ => format!("; did you mean one of {}?", quoted.join(", ")),
+    }
+}
+
+// Levenshtein distance, in characters.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
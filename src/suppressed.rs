@@ -0,0 +1,72 @@
+#[cfg(unix)]
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use opts::Opts;
+use schema;
+
+// --suppressed-log PATH: every line of cargo's or rustfmt's stderr that was
+// kept off the terminal, appended to PATH as one JSON object per line naming
+// the tool, the rule that hid the line, and the line itself.
+pub struct Log {
+    file: Option<File>,
+}
+
+impl Log {
+    pub fn open(path: Option<&Path>) -> Log {
+        let file =
+            path.and_then(|path| OpenOptions::new().create(true).append(true).open(path).ok());
+        Log { file }
+    }
+
+    pub fn record(&mut self, source: &str, rule: &str, line: &str) {
+        if let Some(ref mut file) = self.file {
+            let record = json!({
+                "schema": schema::VERSION,
+                "source": source,
+                "rule": rule,
+                "line": line.trim_end_matches(['\r', '\n']),
+            });
+            let _ = writeln!(file, "{}", record);
+        }
+    }
+}
+
+// Start the log afresh for this run.
+pub fn start(opts: &Opts) -> io::Result<()> {
+    match opts.suppressed_log {
+        Some(ref path) => File::create(path).map(drop).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("failed to create {}: {}", path.display(), err),
+            )
+        }),
+        None => Ok(()),
+    }
+}
+
+// The --suppressed-log in a raw command line, for the process that filters
+// rustfmt's stderr, which gets our arguments but does not parse them.
+#[cfg(unix)]
+pub fn path_in_args(args: &[OsString]) -> Option<PathBuf> {
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--suppressed-log" {
+            return it.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg
+            .to_str()
+            .and_then(|arg| arg.strip_prefix("--suppressed-log="))
+        {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
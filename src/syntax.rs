@@ -0,0 +1,365 @@
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use proc_macro2::{Ident, Span};
+use quote::ToTokens;
+use syn::{Attribute, Expr, File, ImplItem, Item, ItemImpl, Stmt, TraitItem, Type, Visibility};
+
+pub fn item_attrs(item: &Item) -> &[Attribute] {
+    match *item {
+        Item::Const(ref item) => &item.attrs,
+        Item::Enum(ref item) => &item.attrs,
+        Item::ExternCrate(ref item) => &item.attrs,
+        Item::Fn(ref item) => &item.attrs,
+        Item::ForeignMod(ref item) => &item.attrs,
+        Item::Impl(ref item) => &item.attrs,
+        Item::Macro(ref item) => &item.attrs,
+        Item::Mod(ref item) => &item.attrs,
+        Item::Static(ref item) => &item.attrs,
+        Item::Struct(ref item) => &item.attrs,
+        Item::Trait(ref item) => &item.attrs,
+        Item::TraitAlias(ref item) => &item.attrs,
+        Item::Type(ref item) => &item.attrs,
+        Item::Union(ref item) => &item.attrs,
+        Item::Use(ref item) => &item.attrs,
+        _ => &[],
+    }
+}
+
+pub fn item_attrs_mut(item: &mut Item) -> Option<&mut Vec<Attribute>> {
+    match *item {
+        Item::Const(ref mut item) => Some(&mut item.attrs),
+        Item::Enum(ref mut item) => Some(&mut item.attrs),
+        Item::ExternCrate(ref mut item) => Some(&mut item.attrs),
+        Item::Fn(ref mut item) => Some(&mut item.attrs),
+        Item::ForeignMod(ref mut item) => Some(&mut item.attrs),
+        Item::Impl(ref mut item) => Some(&mut item.attrs),
+        Item::Macro(ref mut item) => Some(&mut item.attrs),
+        Item::Mod(ref mut item) => Some(&mut item.attrs),
+        Item::Static(ref mut item) => Some(&mut item.attrs),
+        Item::Struct(ref mut item) => Some(&mut item.attrs),
+        Item::Trait(ref mut item) => Some(&mut item.attrs),
+        Item::TraitAlias(ref mut item) => Some(&mut item.attrs),
+        Item::Type(ref mut item) => Some(&mut item.attrs),
+        Item::Union(ref mut item) => Some(&mut item.attrs),
+        Item::Use(ref mut item) => Some(&mut item.attrs),
+        _ => None,
+    }
+}
+
+// The name an item declares, or for an impl, the name of the type it is for.
+pub fn item_ident(item: &Item) -> Option<&Ident> {
+    match *item {
+        Item::Const(ref item) => Some(&item.ident),
+        Item::Enum(ref item) => Some(&item.ident),
+        Item::ExternCrate(ref item) => Some(&item.ident),
+        Item::Fn(ref item) => Some(&item.sig.ident),
+        Item::Impl(ref item) => impl_self_ident(item),
+        Item::Macro(ref item) => item.ident.as_ref(),
+        Item::Mod(ref item) => Some(&item.ident),
+        Item::Static(ref item) => Some(&item.ident),
+        Item::Struct(ref item) => Some(&item.ident),
+        Item::Trait(ref item) => Some(&item.ident),
+        Item::TraitAlias(ref item) => Some(&item.ident),
+        Item::Type(ref item) => Some(&item.ident),
+        Item::Union(ref item) => Some(&item.ident),
+        _ => None,
+    }
+}
+
+pub fn impl_self_ident(item: &ItemImpl) -> Option<&Ident> {
+    match *item.self_ty {
+        Type::Path(ref ty) => ty.path.segments.last().map(|segment| &segment.ident),
+        _ => None,
+    }
+}
+
+// Items in an anonymous `const _: () = { ... };` block, which is where derives
+// commonly put the impls they generate.
+pub fn const_block_items(item: &Item) -> Vec<&Item> {
+    let block = match *item {
+        Item::Const(ref item) if item.ident == "_" => match *item.expr {
+            Expr::Block(ref expr) => &expr.block,
+            _ => return Vec::new(),
+        },
+        _ => return Vec::new(),
+    };
+    block
+        .stmts
+        .iter()
+        .filter_map(|stmt| match *stmt {
+            Stmt::Item(ref item) => Some(item),
+            _ => None,
+        })
+        .collect()
+}
+
+// Everything in the expanded crate that is, or was generated for, the item
+// called `name`: the item itself and impls for it, anywhere in the module
+// tree, including impls tucked into anonymous const blocks.
+pub fn items_for(items: &[Item], name: &str) -> Vec<Item> {
+    let mut found = Vec::new();
+    for item in items {
+        let is_impl_for = |item: &Item| match *item {
+            Item::Impl(ref item) => impl_self_ident(item).is_some_and(|ident| ident == name),
+            _ => false,
+        };
+        if item_ident(item).is_some_and(|ident| ident == name)
+            || const_block_items(item).into_iter().any(is_impl_for)
+        {
+            found.push(item.clone());
+        } else if let Item::Mod(ref m) = *item {
+            if let Some((_, ref content)) = m.content {
+                found.extend(items_for(content, name));
+            }
+        }
+    }
+    found
+}
+
+// The names items_for can find: those of the items anywhere in the module tree.
+pub fn item_names(items: &[Item]) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for item in items {
+        match *item {
+            Item::Impl(_) | Item::Use(_) => {}
+            Item::Const(ref item) if item.ident == "_" => {}
+            _ => names.extend(item_ident(item).map(Ident::to_string)),
+        }
+        if let Item::Mod(ref m) = *item {
+            if let Some((_, ref content)) = m.content {
+                names.extend(item_names(content));
+            }
+        }
+    }
+    names
+}
+
+// A short description of an item that tells it apart from its siblings, like
+// `struct Foo` or `impl Debug for Foo`.
+pub fn item_label(item: &Item) -> String {
+    let kind = item_kind(item);
+    match *item {
+        Item::Impl(ref item) => {
+            let self_ty = compact(&item.self_ty);
+            match item.trait_ {
+                Some((_, ref path, _)) => format!("impl {} for {}", compact(path), self_ty),
+                None => format!("impl {}", self_ty),
+            }
+        }
+        Item::Use(ref item) => format!("use {}", compact(&item.tree)),
+        _ => match item_ident(item) {
+            Some(ident) => format!("{} {}", kind, ident),
+            None => kind.to_owned(),
+        },
+    }
+}
+
+// The keyword an item is declared with, like `struct` or `extern crate`.
+pub fn item_kind(item: &Item) -> &'static str {
+    match *item {
+        Item::Const(_) => "const",
+        Item::Enum(_) => "enum",
+        Item::ExternCrate(_) => "extern crate",
+        Item::Fn(_) => "fn",
+        Item::ForeignMod(_) => "extern",
+        Item::Macro(_) => "macro",
+        Item::Mod(_) => "mod",
+        Item::Static(_) => "static",
+        Item::Struct(_) => "struct",
+        Item::Trait(_) => "trait",
+        Item::TraitAlias(_) => "trait",
+        Item::Type(_) => "type",
+        Item::Union(_) => "union",
+        Item::Use(_) => "use",
+        Item::Impl(_) => "impl",
+        _ => "item",
+    }
+}
+
+// Tokens printed the way they would be written, rather than with a space
+// between every one of them.
+pub fn compact<T: ToTokens>(tokens: &T) -> String {
+    tokens
+        .to_token_stream()
+        .to_string()
+        .replace(" :: ", "::")
+        .replace(":: ", "::")
+        .replace(" < ", "<")
+        .replace(" <", "<")
+        .replace(" >", ">")
+        .replace(" ,", ",")
+}
+
+pub fn item_vis(item: &Item) -> Option<&Visibility> {
+    match *item {
+        Item::Const(ref item) => Some(&item.vis),
+        Item::Enum(ref item) => Some(&item.vis),
+        Item::ExternCrate(ref item) => Some(&item.vis),
+        Item::Fn(ref item) => Some(&item.vis),
+        Item::Mod(ref item) => Some(&item.vis),
+        Item::Static(ref item) => Some(&item.vis),
+        Item::Struct(ref item) => Some(&item.vis),
+        Item::Trait(ref item) => Some(&item.vis),
+        Item::TraitAlias(ref item) => Some(&item.vis),
+        Item::Type(ref item) => Some(&item.vis),
+        Item::Union(ref item) => Some(&item.vis),
+        Item::Use(ref item) => Some(&item.vis),
+        _ => None,
+    }
+}
+
+// The paths of the items in a crate, like `http::Handlers` for a struct in
+// module `http` and `http::Handlers::handle` for a method in an impl of it.
+// Impls put in anonymous const blocks are looked into as if they were not.
+pub fn item_paths(items: &[Item], prefix: &str) -> BTreeSet<String> {
+    let join = |name: &dyn std::fmt::Display| {
+        if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}::{}", prefix, name)
+        }
+    };
+    let mut paths = BTreeSet::new();
+    for item in items {
+        let consts: Vec<Item> = const_block_items(item).into_iter().cloned().collect();
+        paths.extend(item_paths(&consts, prefix));
+        match *item {
+            Item::Use(_) | Item::ExternCrate(_) | Item::ForeignMod(_) => continue,
+            Item::Const(ref item) if item.ident == "_" => continue,
+            Item::Impl(ref imp) => {
+                if let Some(ident) = impl_self_ident(imp) {
+                    let path = join(ident);
+                    for impl_item in &imp.items {
+                        if let ImplItem::Fn(ref f) = *impl_item {
+                            paths.insert(format!("{}::{}", path, f.sig.ident));
+                        }
+                    }
+                }
+                continue;
+            }
+            Item::Trait(ref t) => {
+                let path = join(&t.ident);
+                for trait_item in &t.items {
+                    if let TraitItem::Fn(ref f) = *trait_item {
+                        paths.insert(format!("{}::{}", path, f.sig.ident));
+                    }
+                }
+            }
+            Item::Mod(ref m) => {
+                if let Some((_, ref content)) = m.content {
+                    paths.extend(item_paths(content, &join(&m.ident)));
+                }
+            }
+            _ => {}
+        }
+        if let Some(ident) = item_ident(item) {
+            paths.insert(join(ident));
+        }
+    }
+    paths
+}
+
+// What a crate exposes, as the labels of its `pub` items and of the trait
+// impls it contains, prefixed with the path of the module they are in. Only
+// public modules are looked into.
+pub fn public_items(items: &[Item], prefix: &str) -> BTreeSet<String> {
+    let mut public = BTreeSet::new();
+    for item in items {
+        let path = |item: &Item| {
+            let label = item_label(item);
+            let ident = item_ident(item).map(Ident::to_string);
+            match ident {
+                _ if prefix.is_empty() => label,
+                Some(ref ident) if !matches!(*item, Item::Impl(_)) && label.ends_with(ident) => {
+                    let kind = &label[..label.len() - ident.len()];
+                    format!("{}{}::{}", kind, prefix, ident)
+                }
+                _ => format!("{} in {}", label, prefix),
+            }
+        };
+        let impls = const_block_items(item)
+            .into_iter()
+            .chain(Some(item))
+            .filter(|item| matches!(**item, Item::Impl(ref item) if item.trait_.is_some()));
+        public.extend(impls.map(path));
+
+        let is_pub = matches!(item_vis(item), Some(&Visibility::Public(_)));
+        if !is_pub {
+            continue;
+        }
+        public.insert(path(item));
+        if let Item::Mod(ref m) = *item {
+            if let Some((_, ref content)) = m.content {
+                let prefix = if prefix.is_empty() {
+                    m.ident.to_string()
+                } else {
+                    format!("{}::{}", prefix, m.ident)
+                };
+                public.extend(public_items(content, &prefix));
+            }
+        }
+    }
+    public
+}
+
+// Whole lines from the first of the span to its last, with the indentation
+// they have in common taken off.
+pub fn lines(content: &str, span: Span) -> String {
+    let lines: Vec<&str> = content
+        .lines()
+        .skip(span.start().line - 1)
+        .take(span.end().line + 1 - span.start().line)
+        .collect();
+    let common = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let lines: Vec<&str> = lines
+        .iter()
+        .map(|line| line.get(common..).unwrap_or(""))
+        .collect();
+    lines.join("\n")
+}
+
+// The text a file was parsed from with each range of it replaced, in place of
+// the file printed again from tokens, which would lose its comments and
+// layout. A range inside one already replaced is skipped, and a line left
+// empty by taking out what was on it goes too.
+pub fn splice(content: &str, file: &File, mut edits: Vec<(Range<usize>, String)>) -> String {
+    // Spans are from what syn parsed, after any byte order mark and shebang.
+    let offset = content.len() - content.trim_start_matches('\u{feff}').len()
+        + file.shebang.as_ref().map_or(0, String::len);
+    edits.sort_by_key(|(range, _)| (range.start, Reverse(range.end)));
+
+    let mut out = String::with_capacity(content.len());
+    let mut at = 0;
+    for (range, replacement) in edits {
+        let mut start = range.start + offset;
+        let mut end = range.end + offset;
+        if start < at {
+            continue;
+        }
+        if replacement.is_empty() {
+            let line_start = content[..start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = content[end..].find('\n').map(|i| end + i + 1);
+            if let Some(line_end) = line_end {
+                if line_start >= at
+                    && content[line_start..start].trim().is_empty()
+                    && content[end..line_end].trim().is_empty()
+                {
+                    start = line_start;
+                    end = line_end;
+                }
+            }
+        }
+        out.push_str(&content[at..start]);
+        out.push_str(&replacement);
+        at = end;
+    }
+    out.push_str(&content[at..]);
+    out
+}
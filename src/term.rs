@@ -0,0 +1,207 @@
+use std::io;
+#[cfg(windows)]
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use libc;
+#[cfg(windows)]
+use windows_sys::Win32::Foundation::{WAIT_OBJECT_0, WAIT_TIMEOUT};
+#[cfg(windows)]
+use windows_sys::Win32::System::Console::{
+    GetConsoleMode, GetConsoleScreenBufferInfo, GetStdHandle, ReadConsoleInputW, SetConsoleMode,
+    CONSOLE_SCREEN_BUFFER_INFO, ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, ENABLE_PROCESSED_INPUT,
+    INPUT_RECORD, KEY_EVENT, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE,
+};
+#[cfg(windows)]
+use windows_sys::Win32::System::Threading::WaitForSingleObject;
+
+// Below this rustfmt gives up on too many lines to be worth following the
+// terminal.
+const MIN_WIDTH: usize = 40;
+
+// Width of the terminal on stdout, if stdout is one.
+#[cfg(unix)]
+pub fn width() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if ret == 0 && size.ws_col > 0 {
+        Some(size.ws_col as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(windows)]
+pub fn width() -> Option<usize> {
+    let mut info: CONSOLE_SCREEN_BUFFER_INFO = unsafe { std::mem::zeroed() };
+    let ret = unsafe { GetConsoleScreenBufferInfo(GetStdHandle(STD_OUTPUT_HANDLE), &mut info) };
+    let width = info.srWindow.Right - info.srWindow.Left + 1;
+    if ret != 0 && width > 0 {
+        Some(width as usize)
+    } else {
+        None
+    }
+}
+
+// The max_width to hand rustfmt: the one asked for, or else the terminal's.
+pub fn max_width(requested: Option<usize>) -> Option<usize> {
+    requested.or_else(|| width().map(|width| width.max(MIN_WIDTH)))
+}
+
+// Read a single key press from the terminal on stdin, without waiting for
+// enter or echoing it. Ctrl-C arrives as '\x03' rather than as a signal, so
+// the terminal is always put back the way it was.
+#[cfg(unix)]
+pub fn read_key() -> io::Result<char> {
+    let _raw = Raw::enter()?;
+    read_byte()
+}
+
+#[cfg(windows)]
+pub fn read_key() -> io::Result<char> {
+    let _raw = Raw::enter()?;
+    loop {
+        if let Some(key) = read_event()? {
+            return Ok(key);
+        }
+    }
+}
+
+// Like read_key, but give up after `millis` without a key press.
+#[cfg(unix)]
+pub fn poll_key(millis: i32) -> io::Result<Option<char>> {
+    let _raw = Raw::enter()?;
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    match unsafe { libc::poll(&mut fd, 1, millis) } {
+        0 => Ok(None),
+        n if n < 0 => Err(io::Error::last_os_error()),
+        _ => read_byte().map(Some),
+    }
+}
+
+// The console also wakes up for the mouse, focus and the like, which are read
+// past in what is left of the time.
+#[cfg(windows)]
+pub fn poll_key(millis: i32) -> io::Result<Option<char>> {
+    let _raw = Raw::enter()?;
+    let deadline = Instant::now() + Duration::from_millis(millis.max(0) as u64);
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let stdin = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+        match unsafe { WaitForSingleObject(stdin, left.as_millis() as u32) } {
+            WAIT_TIMEOUT => return Ok(None),
+            WAIT_OBJECT_0 => {}
+            _ => return Err(io::Error::last_os_error()),
+        }
+        if let Some(key) = read_event()? {
+            return Ok(Some(key));
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_byte() -> io::Result<char> {
+    let mut byte = 0u8;
+    let n = unsafe {
+        libc::read(
+            libc::STDIN_FILENO,
+            &mut byte as *mut u8 as *mut libc::c_void,
+            1,
+        )
+    };
+    if n == 1 {
+        Ok(byte as char)
+    } else if n == 0 {
+        Err(io::Error::from(io::ErrorKind::UnexpectedEof))
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// The character typed by the next event of the console on stdin, if it is a
+// key press that types one. Enter reads as '\n', as it does on a terminal.
+#[cfg(windows)]
+fn read_event() -> io::Result<Option<char>> {
+    let mut record: INPUT_RECORD = unsafe { std::mem::zeroed() };
+    let mut read = 0;
+    let stdin = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+    if unsafe { ReadConsoleInputW(stdin, &mut record, 1, &mut read) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if read == 0 || u32::from(record.EventType) != KEY_EVENT {
+        return Ok(None);
+    }
+    let key = unsafe { record.Event.KeyEvent };
+    let unit = unsafe { key.uChar.UnicodeChar };
+    if key.bKeyDown == 0 || unit == 0 {
+        return Ok(None);
+    }
+    Ok(match char::from_u32(u32::from(unit)) {
+        Some('\r') => Some('\n'),
+        key => key,
+    })
+}
+
+// The terminal on stdin with line buffering, echo and signal keys turned off,
+// until dropped.
+#[cfg(unix)]
+struct Raw {
+    saved: libc::termios,
+}
+
+#[cfg(windows)]
+struct Raw {
+    saved: u32,
+}
+
+#[cfg(unix)]
+impl Raw {
+    fn enter() -> io::Result<Raw> {
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Raw { saved })
+    }
+}
+
+#[cfg(windows)]
+impl Raw {
+    fn enter() -> io::Result<Raw> {
+        let stdin = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+        let mut saved = 0;
+        if unsafe { GetConsoleMode(stdin, &mut saved) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let raw = saved & !(ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT | ENABLE_PROCESSED_INPUT);
+        if unsafe { SetConsoleMode(stdin, raw) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Raw { saved })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Raw {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
+
+#[cfg(windows)]
+impl Drop for Raw {
+    fn drop(&mut self) {
+        unsafe { SetConsoleMode(GetStdHandle(STD_INPUT_HANDLE), self.saved) };
+    }
+}
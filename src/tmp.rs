@@ -0,0 +1,134 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+use libc;
+
+use interrupt;
+
+// Leftovers from a run that did not get to clean up after itself, most likely
+// because it was killed, are removed by the next run once they are this old
+// or their process is gone.
+const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+// The directories this run has created and not yet dropped.
+static LIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+// A directory for intermediate output, named `<pid>-<unix time>` under
+// cargo-expand's own cache directory and removed when dropped.
+pub struct TmpDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TmpDir {
+    pub fn create(keep: bool) -> io::Result<TmpDir> {
+        let parent = cache_dir().join("tmp");
+        fs::create_dir_all(&parent)?;
+        clean_stale(&parent);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let name = format!(
+            "{}-{}{:03}",
+            process::id(),
+            now.as_secs(),
+            now.subsec_millis()
+        );
+        let path = parent.join(name);
+        fs::create_dir(&path)?;
+        if !keep {
+            interrupt::track_dir(path.clone());
+        }
+        LIVE.lock().unwrap().push(path.clone());
+        Ok(TmpDir { path, keep })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Leave the directory in place, reporting where it is.
+    pub fn keep(&mut self) {
+        self.keep = true;
+        interrupt::untrack_dir(&self.path);
+    }
+}
+
+impl Drop for TmpDir {
+    fn drop(&mut self) {
+        LIVE.lock().unwrap().retain(|live| *live != self.path);
+        if self.keep {
+            let _ = writeln!(
+                &mut io::stderr(),
+                "note: kept temporary files in {}",
+                self.path.display(),
+            );
+        } else {
+            let _ = fs::remove_dir_all(&self.path);
+            interrupt::untrack_dir(&self.path);
+        }
+    }
+}
+
+// The directories of this run that are in use, as opposed to the rest of the
+// cache, which other runs and other workspaces share.
+pub fn live() -> Vec<PathBuf> {
+    LIVE.lock().unwrap().clone()
+}
+
+pub fn cache_dir() -> PathBuf {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+    };
+    base.unwrap_or_else(env::temp_dir).join("cargo-expand")
+}
+
+pub fn clean_stale(parent: &Path) {
+    let entries = match fs::read_dir(parent) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let name = entry.file_name();
+        let pid = match name.to_str().and_then(|name| name.split('-').next()) {
+            Some(pid) => pid.parse().ok(),
+            None => None,
+        };
+        let age = entry
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok());
+        let expired = age.is_some_and(|age| age > STALE_AFTER);
+        if expired || pid.is_some_and(|pid| pid != process::id() && !is_running(pid)) {
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let ret = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    ret == 0 || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    // Without a cheap liveness check, rely on age alone.
+    true
+}
@@ -0,0 +1,190 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use config::{self, Config};
+use opts::Opts;
+use project;
+use tmp;
+use tty;
+
+// The workspaces warned about being built untrusted, to warn just once each.
+static WARNED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+// Expanding a crate runs its build scripts and proc macros, and those of its
+// dependencies, which can do whatever a program can. So before a workspace is
+// built, it has to be trusted: listed under `directories` in `[trust]` in the
+// config, or below one that is, or trusted before with --trust or by saying
+// so when asked. Workspaces trusted that way are recorded in a `trusted` file
+// next to the config, one root per line. With no terminal to ask on, the build
+// is refused, unless `require = false` under `[trust]`, which makes it a
+// warning instead.
+pub fn check(opts: &Opts, config: &Config) -> io::Result<()> {
+    // Nothing is built for --dry-run.
+    if opts.dry_run {
+        return Ok(());
+    }
+    check_workspace(
+        opts.manifest_path.as_ref(),
+        opts.trust,
+        config,
+        tty::stdin_isatty(),
+    )
+}
+
+// The same for the workspace of the package at `manifest_path`, asking
+// whether to trust it only if `ask`, as the daemon cannot for its clients.
+pub fn check_workspace(
+    manifest_path: Option<&OsString>,
+    trust: bool,
+    config: &Config,
+    ask: bool,
+) -> io::Result<()> {
+    let root = match workspace_root(manifest_path) {
+        Some(root) => root,
+        // Cargo will say that there is no package here.
+        None => return Ok(()),
+    };
+    if allowed(config)?.iter().any(|dir| root.starts_with(dir)) || recorded(&root) {
+        return Ok(());
+    }
+    // A copy this run made of a workspace, which was checked before copying.
+    let copies = tmp::live();
+    if copies
+        .iter()
+        .any(|dir| root.starts_with(dir.canonicalize().unwrap_or_else(|_| dir.clone())))
+    {
+        return Ok(());
+    }
+    if trust {
+        return record(&root);
+    }
+
+    let refused = || {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "not expanding {}, which is not trusted; pass --trust to trust it, or list it \
+                 under `directories` in [trust] in the config",
+                root.display(),
+            ),
+        )
+    };
+    if ask {
+        let question = format!(
+            "warning: expanding {} runs its build scripts and proc macros, which can run \
+             any code.\nTrust it from now on?",
+            root.display(),
+        );
+        if !tty::confirm(&question)? {
+            return Err(refused());
+        }
+        return record(&root);
+    }
+    if required(config)? {
+        return Err(refused());
+    }
+    let mut warned = WARNED.lock().unwrap();
+    if !warned.contains(&root) {
+        let _ = writeln!(
+            &mut io::stderr(),
+            "warning: expanding {}, which is not trusted, runs its build scripts and proc \
+             macros, which can run any code; pass --trust to trust it",
+            root.display(),
+        );
+        warned.push(root);
+    }
+    Ok(())
+}
+
+fn workspace_root(manifest_path: Option<&OsString>) -> Option<PathBuf> {
+    let manifest = project::locate_workspace(manifest_path)?;
+    manifest.parent()?.canonicalize().ok()
+}
+
+// The directories under `[trust]`, with a leading `~` for the home directory.
+fn allowed(config: &Config) -> io::Result<Vec<PathBuf>> {
+    let value = match config
+        .section("trust")
+        .and_then(|trust| trust.get("directories"))
+    {
+        Some(value) => value,
+        None => return Ok(Vec::new()),
+    };
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "invalid directories `{}` under [trust]: expected a list of paths",
+                value,
+            ),
+        )
+    };
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let mut dirs = Vec::new();
+    for dir in value.as_array().ok_or_else(invalid)? {
+        let dir = dir.as_str().ok_or_else(invalid)?;
+        let dir = match (dir.strip_prefix('~'), home.as_ref()) {
+            (Some(rest), Some(home)) => home.join(rest.trim_start_matches(['/', '\\'])),
+            _ => PathBuf::from(dir),
+        };
+        // A directory that does not exist has nothing in it to trust.
+        dirs.extend(dir.canonicalize().ok());
+    }
+    Ok(dirs)
+}
+
+// Whether to refuse to build a workspace that is not trusted when there is no
+// asking, which is unless `require = false` under `[trust]`.
+fn required(config: &Config) -> io::Result<bool> {
+    let value = match config
+        .section("trust")
+        .and_then(|trust| trust.get("require"))
+    {
+        Some(value) => value,
+        None => return Ok(true),
+    };
+    value.as_bool().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "invalid require `{}` under [trust]: expected true or false",
+                value
+            ),
+        )
+    })
+}
+
+fn store() -> Option<PathBuf> {
+    config::path()?.parent().map(|dir| dir.join("trusted"))
+}
+
+fn recorded(root: &Path) -> bool {
+    let content = match store().and_then(|store| fs::read_to_string(store).ok()) {
+        Some(content) => content,
+        None => return false,
+    };
+    content.lines().any(|line| Path::new(line) == root)
+}
+
+fn record(root: &Path) -> io::Result<()> {
+    let store = match store() {
+        Some(store) => store,
+        None => return Ok(()),
+    };
+    if let Some(dir) = store.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&store)?;
+    writeln!(file, "{}", root.display())?;
+    let _ = writeln!(
+        &mut io::stderr(),
+        "note: trusting {} from now on, as recorded in {}",
+        root.display(),
+        store.display(),
+    );
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+
+// Whether the standard streams are terminals. This is std's check, which on
+// Windows also recognizes the pipes that MSYS and Cygwin terminals such as Git
+// Bash use in place of a console, as well as ConPTY consoles like Windows
+// Terminal.
+pub fn stdin_isatty() -> bool {
+    io::stdin().is_terminal()
+}
+
+pub fn stdout_isatty() -> bool {
+    io::stdout().is_terminal()
+}
+
+pub fn stderr_isatty() -> bool {
+    io::stderr().is_terminal()
+}
+
+// Ask a yes/no question on the terminal. Without one, the answer is no.
+pub fn confirm(question: &str) -> io::Result<bool> {
+    if !stdin_isatty() {
+        let _ = writeln!(&mut io::stderr(), "{} Pass --yes to agree.", question);
+        return Ok(false);
+    }
+    let mut stderr = io::stderr();
+    write!(stderr, "{} [y/N] ", question)?;
+    stderr.flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("y") || answer.trim().eq_ignore_ascii_case("yes"))
+}
+
+// Text without the color escapes that cargo puts in when stderr is a
+// terminal.
+pub fn strip_ansi(text: &str) -> String {
+    let mut plain = String::new();
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\x1b' {
+            for ch in chars.by_ref() {
+                if ch.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            plain.push(ch);
+        }
+    }
+    plain
+}
+
+// Whether to color what goes to a terminal. Pseudo-terminals that cannot show
+// colors, like the ones some CI systems and editors provide, say so with
+// `TERM=dumb`.
+pub fn stdout_colors() -> bool {
+    stdout_isatty() && !dumb() && console::escapes(console::STD_OUTPUT_HANDLE)
+}
+
+pub fn stderr_colors() -> bool {
+    stderr_isatty() && !dumb() && console::escapes(console::STD_ERROR_HANDLE)
+}
+
+fn dumb() -> bool {
+    env::var_os("TERM").is_some_and(|term| term == "dumb")
+}
+
+// A Windows console shows color escapes only once asked to, which consoles
+// older than Windows 10 cannot be. MSYS and Cygwin pipes are not consoles and
+// pass escapes through to a terminal that understands them.
+#[cfg(windows)]
+mod console {
+    pub use windows_sys::Win32::System::Console::{STD_ERROR_HANDLE, STD_OUTPUT_HANDLE};
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+        STD_HANDLE,
+    };
+
+    pub fn escapes(std_handle: STD_HANDLE) -> bool {
+        unsafe {
+            let console = GetStdHandle(std_handle);
+            let mut mode = 0;
+            if GetConsoleMode(console, &mut mode) == 0 {
+                return true;
+            }
+            mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+                || SetConsoleMode(console, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod console {
+    pub const STD_OUTPUT_HANDLE: u32 = 1;
+    pub const STD_ERROR_HANDLE: u32 = 2;
+
+    pub fn escapes(_std_handle: u32) -> bool {
+        true
+    }
+}
@@ -0,0 +1,72 @@
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::process::{Command, Stdio};
+
+// Flags that make rustc print the expanded crate, keyed by the first rustc
+// minor version that accepts them. Newest first; the first row whose version
+// is not newer than the toolchain in use is selected.
+const EXPANSION_FLAGS: &[(u32, &[&str])] = &[
+    // Nightly dropped `--pretty` in favor of `-Zunpretty` in 1.54.
+    (54, &["-Zunpretty=expanded"]),
+    (0, &["-Zunstable-options", "--pretty=expanded"]),
+];
+
+pub fn expansion_flags(toolchain: Option<&str>) -> Vec<OsString> {
+    let flags = match rustc_minor_version(toolchain) {
+        Some(minor) => EXPANSION_FLAGS
+            .iter()
+            .find(|row| row.0 <= minor)
+            .map(|row| row.1)
+            .unwrap(),
+        None => EXPANSION_FLAGS[0].1,
+    };
+    flags.iter().map(OsString::from).collect()
+}
+
+// The rustc that cargo will use for the given toolchain.
+pub fn rustc_command(toolchain: Option<&str>) -> Command {
+    match toolchain {
+        Some(toolchain) => {
+            let mut cmd = Command::new("rustc");
+            cmd.arg(format!("+{}", toolchain));
+            cmd
+        }
+        None => Command::new(env::var_os("RUSTC").unwrap_or_else(|| "rustc".into())),
+    }
+}
+
+// A toolchain picked with --toolchain has to be one rustup has, or every
+// command run on it fails with rustup's error.
+pub fn check_toolchain(toolchain: Option<&str>) -> io::Result<()> {
+    let toolchain = match toolchain {
+        Some(toolchain) => toolchain,
+        None => return Ok(()),
+    };
+    let works = rustc_command(Some(toolchain))
+        .arg("-vV")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if works {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "toolchain `{}` is not installed; install it with `rustup toolchain install {}`",
+            toolchain, toolchain,
+        ),
+    ))
+}
+
+fn rustc_minor_version(toolchain: Option<&str>) -> Option<u32> {
+    let output = rustc_command(toolchain).arg("-vV").output().ok()?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let release = stdout
+        .lines()
+        .find(|line| line.starts_with("release: "))?
+        .trim_start_matches("release: ");
+    release.split('.').nth(1)?.parse().ok()
+}
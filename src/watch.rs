@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use syn;
+use syn::spanned::Spanned;
+
+use capture;
+use config::Config;
+use diff;
+use highlight::{self, Highlighter};
+use opts::Opts;
+use syntax;
+use term;
+use tty::{stdin_isatty, stdout_isatty};
+
+// How often to look for edits to the package's files.
+const POLL_MILLIS: i32 = 500;
+
+// --watch: expand, then expand again whenever a file in the package changes,
+// printing only the items that changed since the previous expansion. Press `f`
+// for the whole of the latest expansion and `q` to stop. On a terminal the
+// screen is cleared for each run, and output is colored as it would be
+// without --watch.
+pub fn run(opts: &Opts, config: &Config) -> io::Result<i32> {
+    let dir = package_dir(opts);
+    let colored = opts.output_colored();
+    let show = |text: &str| -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        if !colored {
+            return out.write_all(text.as_bytes());
+        }
+        let mut highlighter = Highlighter::new(opts.theme);
+        for line in text.lines() {
+            writeln!(out, "{}", highlighter.line(line))?;
+        }
+        Ok(())
+    };
+
+    let keys = stdin_isatty();
+    let clear = stdout_isatty();
+    let mut last: Option<String> = None;
+    let mut stamp = stamp(&dir);
+    loop {
+        if clear && last.is_some() {
+            write!(io::stdout().lock(), "\x1b[2J\x1b[H")?;
+        }
+        match capture::expand(opts, config)? {
+            Some(code) => {
+                match last {
+                    None => show(&code)?,
+                    Some(ref last) => {
+                        let delta = delta(last, &code);
+                        let mut out = io::stdout().lock();
+                        if colored {
+                            out.write_all(highlight::diff(&delta).as_bytes())?;
+                        } else {
+                            out.write_all(delta.as_bytes())?;
+                        }
+                    }
+                }
+                if keys {
+                    writeln!(io::stdout().lock(), "-- watching; f: full output, q: quit")?;
+                }
+                last = Some(code);
+            }
+            None => {
+                let _ = writeln!(&mut io::stderr(), "-- build failed; watching for changes");
+            }
+        }
+
+        loop {
+            if keys {
+                match term::poll_key(POLL_MILLIS)? {
+                    Some('q') | Some('\x03') => return Ok(0),
+                    Some('f') => {
+                        if let Some(ref last) = last {
+                            show(last)?;
+                        }
+                    }
+                    _ => {}
+                }
+            } else {
+                thread::sleep(Duration::from_millis(POLL_MILLIS as u64));
+            }
+            let now = self::stamp(&dir);
+            if now != stamp {
+                stamp = now;
+                break;
+            }
+        }
+    }
+}
+
+// The directory of the package being expanded.
+pub fn package_dir(opts: &Opts) -> PathBuf {
+    match opts.manifest_path {
+        Some(ref manifest_path) => Path::new(manifest_path)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+        None => PathBuf::from("."),
+    }
+}
+
+// Modification times of the files in the package, skipping `target` and
+// hidden directories like `.git`.
+pub fn stamp(dir: &Path) -> BTreeMap<PathBuf, SystemTime> {
+    let mut files = BTreeMap::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if metadata.is_dir() {
+                if name != "target" && !name.starts_with('.') {
+                    dirs.push(path);
+                }
+            } else if let Ok(modified) = metadata.modified() {
+                files.insert(path, modified);
+            }
+        }
+    }
+    files
+}
+
+// What changed between two expansions, item by item: a diff for each changed
+// top-level item and the labels of those added or removed. Falls back to a diff
+// of the whole output for code that does not parse.
+fn delta(old: &str, new: &str) -> String {
+    let (old_items, new_items) = match (sections(old), sections(new)) {
+        (Some(old_items), Some(new_items)) => (old_items, new_items),
+        _ => {
+            return match diff::unified("previous", "expanded", old, new, 3) {
+                Some(diff) => format!("-- expansion changed\n{}", diff),
+                None => "-- no change in the expansion\n".to_owned(),
+            };
+        }
+    };
+
+    let mut out = String::new();
+    let mut changed = 0;
+    for (label, new_text) in &new_items {
+        match old_items.get(label) {
+            None => {
+                changed += 1;
+                out.push_str(&format!("-- added `{}`\n{}\n", label, new_text));
+            }
+            Some(old_text) => {
+                if let Some(diff) = diff::unified(label, label, old_text, new_text, 3) {
+                    changed += 1;
+                    out.push_str(&format!("-- changed `{}`\n", label));
+                    // Both sides are the same item, so skip the file header.
+                    out.extend(diff.lines().skip(2).map(|line| format!("{}\n", line)));
+                }
+            }
+        }
+    }
+    for label in old_items.keys() {
+        if !new_items.contains_key(label) {
+            changed += 1;
+            out.push_str(&format!("-- removed `{}`\n", label));
+        }
+    }
+    if changed == 0 {
+        out.push_str("-- no change in the expansion\n");
+    }
+    out
+}
+
+// The text of each top-level item in formatted code, keyed by its label. Later
+// items with the same label as an earlier one are numbered.
+fn sections(code: &str) -> Option<BTreeMap<String, String>> {
+    let file = syn::parse_file(code).ok()?;
+    let lines: Vec<&str> = code.lines().collect();
+    let mut sections = BTreeMap::new();
+    for item in &file.items {
+        let span = item.span();
+        let start = span.start().line.max(1) - 1;
+        let end = span.end().line.min(lines.len());
+        let text = lines[start..end.max(start)].join("\n");
+
+        let label = syntax::item_label(item);
+        let mut unique = label.clone();
+        let mut n = 1;
+        while sections.contains_key(&unique) {
+            n += 1;
+            unique = format!("{} ({})", label, n);
+        }
+        sections.insert(unique, text);
+    }
+    Some(sections)
+}
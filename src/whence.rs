@@ -0,0 +1,373 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use proc_macro2::Span;
+use quote::ToTokens;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::{self, Block, ImplItem, Item, Macro};
+
+use capture;
+use config::Config;
+use minimize::Location;
+use opts::Opts;
+use provenance::{self, SourceModule};
+use syntax;
+
+// A `--whence` argument: a line of the expansion as cargo expand prints it, or
+// `FILE:LINE` of an expansion written out earlier with -o.
+#[derive(Clone)]
+pub struct Whence {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+}
+
+impl FromStr for Whence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(line) = s.parse() {
+            return Ok(Whence { file: None, line });
+        }
+        let location = Location::from_str(s).map_err(|_| "expected LINE or FILE:LINE")?;
+        Ok(Whence {
+            file: Some(location.file),
+            line: location.line,
+        })
+    }
+}
+
+// --whence LINE: say which macro invocation in the source a line of the
+// expansion came from. The expansion is lined up with the source the way
+// --only-macro does it, module by module: impls go to the derives on their
+// type, items to the attribute macros they lost and items only the expansion
+// has to the item macro invoked where they are. In a function body, the line
+// goes to the macros in the statement of the source it is in.
+pub fn print(opts: &Opts, config: &Config, whence: &Whence) -> io::Result<i32> {
+    let code = match whence.file {
+        Some(ref file) => fs::read_to_string(file).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("failed to read {}: {}", file.display(), err),
+            )
+        })?,
+        None => match capture::expand(opts, config)? {
+            Some(code) => code,
+            None => return Ok(1),
+        },
+    };
+    let lines = code.lines().count();
+    if whence.line == 0 || whence.line > lines {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "there is no line {} in the expansion, which has {} lines",
+                whence.line, lines,
+            ),
+        ));
+    }
+    let expanded = syn::parse_file(&code).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "cannot tell where line {} came from, expanded code failed to parse: {}",
+                whence.line, err,
+            ),
+        )
+    })?;
+
+    let (_, root) = SourceModule::root(opts)?;
+    let package_dir = opts
+        .manifest_path
+        .as_ref()
+        .and_then(|p| Path::new(p).parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let finder = Finder {
+        line: whence.line,
+        package_dir: &package_dir,
+    };
+    let answer = finder.module(&expanded.items, &root);
+    let stdout = io::stdout();
+    writeln!(stdout.lock(), "{}", answer)?;
+    Ok(0)
+}
+
+struct Finder<'a> {
+    line: usize,
+    package_dir: &'a Path,
+}
+
+impl<'a> Finder<'a> {
+    fn module(&self, expanded: &[Item], module: &SourceModule) -> String {
+        let index = match expanded.iter().position(|item| self.within(item.span())) {
+            Some(index) => index,
+            None => return format!("line {} is not inside any item of the expansion", self.line),
+        };
+        let item = &expanded[index];
+        if let Item::Mod(ref m) = *item {
+            let inner = module.submodule(&m.ident.to_string());
+            if let (Some((_, ref items)), Some(_)) = (&m.content, &inner.items) {
+                return self.module(items, &inner);
+            }
+        }
+        let source = module.items.as_deref().unwrap_or_default();
+        self.item(expanded, index, source, module)
+    }
+
+    fn item(
+        &self,
+        expanded: &[Item],
+        index: usize,
+        source: &[Item],
+        module: &SourceModule,
+    ) -> String {
+        let item = &expanded[index];
+        let label = syntax::item_label(item);
+        if provenance::is_injected(item) {
+            return format!(
+                "line {} is not from a macro: the compiler adds `{}` to every crate",
+                self.line, label,
+            );
+        }
+
+        let in_source: BTreeSet<String> = source.iter().map(syntax::item_label).collect();
+        for ty in source
+            .iter()
+            .filter(|item| !matches!(**item, Item::Impl(_)))
+        {
+            for attr in syntax::item_attrs(ty) {
+                if !attr.path().is_ident("derive") {
+                    continue;
+                }
+                let derive = provenance::derives(std::slice::from_ref(attr))
+                    .into_iter()
+                    .find(|derive| provenance::from_derive(source, &in_source, item, derive));
+                if let Some(derive) = derive {
+                    return format!(
+                        "line {} comes from `#[derive({})]` on `{}`, at {}",
+                        self.line,
+                        derive,
+                        syntax::item_label(ty),
+                        self.at(module, attr.span()),
+                    );
+                }
+            }
+        }
+
+        // Of several impls for a type, the one with the method the line is in.
+        let method = match *item {
+            Item::Impl(ref imp) => imp.items.iter().find_map(|item| match *item {
+                ImplItem::Fn(ref f) if self.within(f.span()) => Some(&f.sig.ident),
+                _ => None,
+            }),
+            _ => None,
+        };
+        let has_method = |source: &Item| match (source, method) {
+            (Item::Impl(imp), Some(method)) => imp.items.iter().any(|item| match *item {
+                ImplItem::Fn(ref f) => f.sig.ident == *method,
+                _ => false,
+            }),
+            _ => false,
+        };
+        let same: Vec<&Item> = source
+            .iter()
+            .filter(|source| syntax::item_label(source) == label)
+            .collect();
+        let written = same
+            .iter()
+            .find(|source| has_method(source))
+            .or_else(|| same.first())
+            .copied();
+        match written {
+            Some(written) => self.written(item, written, module),
+            None => self.generated(expanded, index, source, module),
+        }
+    }
+
+    // An item that is in the source too.
+    fn written(&self, item: &Item, source: &Item, module: &SourceModule) -> String {
+        let label = syntax::item_label(source);
+        let consumed = syntax::item_attrs(source).iter().find(|attr| {
+            !["derive", "cfg", "cfg_attr", "doc"]
+                .iter()
+                .any(|builtin| attr.path().is_ident(builtin))
+                && !syntax::item_attrs(item)
+                    .iter()
+                    .any(|kept| path(kept.path()) == path(attr.path()))
+        });
+        if let Some(attr) = consumed {
+            return format!(
+                "line {} comes from `#[{}]` on `{}`, at {}",
+                self.line,
+                path(attr.path()),
+                label,
+                self.at(module, attr.span()),
+            );
+        }
+
+        let bodies = match (item, source) {
+            (Item::Fn(item), Item::Fn(source)) => {
+                Some((&*item.block, &*source.block, label.clone()))
+            }
+            (Item::Impl(item), Item::Impl(source)) => item.items.iter().find_map(|expanded| {
+                let expanded = match *expanded {
+                    ImplItem::Fn(ref f) if self.within(f.span()) => f,
+                    _ => return None,
+                };
+                source.items.iter().find_map(|source| match *source {
+                    ImplItem::Fn(ref f) if f.sig.ident == expanded.sig.ident => Some((
+                        &expanded.block,
+                        &f.block,
+                        format!("fn {} in {}", f.sig.ident, label),
+                    )),
+                    _ => None,
+                })
+            }),
+            _ => None,
+        };
+        if let Some((expanded, source, label)) = bodies {
+            if let Some(answer) = self.body(expanded, source, &label, module) {
+                return answer;
+            }
+        }
+        format!(
+            "line {} is not from a macro: it is part of `{}`, written at {}",
+            self.line,
+            label,
+            self.at(module, source.span()),
+        )
+    }
+
+    // A line in a function body, put down to the macros in the statement of
+    // the source it is in. Statements are lined up by position, so if the
+    // numbers differ it can only be narrowed down to the whole body.
+    fn body(
+        &self,
+        expanded: &Block,
+        source: &Block,
+        label: &str,
+        module: &SourceModule,
+    ) -> Option<String> {
+        let stmt = expanded
+            .stmts
+            .iter()
+            .position(|stmt| self.within(stmt.span()))?;
+        let mut macros = Macros(Vec::new());
+        if expanded.stmts.len() == source.stmts.len() {
+            macros.visit_stmt(&source.stmts[stmt]);
+        } else {
+            macros.visit_block(source);
+        }
+        Some(match macros.0.len() {
+            0 => return None,
+            1 => format!(
+                "line {} comes from `{}!` in `{}`, at {}",
+                self.line,
+                macros.0[0].0,
+                label,
+                self.at(module, macros.0[0].1),
+            ),
+            _ => {
+                let mut answer = format!(
+                    "line {} comes from one of these macros in `{}`:",
+                    self.line, label,
+                );
+                for (name, span) in macros.0 {
+                    answer.push_str(&format!("\n  `{}!` at {}", name, self.at(module, span)));
+                }
+                answer
+            }
+        })
+    }
+
+    // An item only the expansion has, put down to the first item macro
+    // invoked in the source after the item written there that comes before it
+    // in the expansion.
+    fn generated(
+        &self,
+        expanded: &[Item],
+        index: usize,
+        source: &[Item],
+        module: &SourceModule,
+    ) -> String {
+        let label = syntax::item_label(&expanded[index]);
+        let anchor = expanded[..index]
+            .iter()
+            .rev()
+            .filter(|item| !provenance::is_injected(item) && !provenance::is_derived(item))
+            .find_map(|item| {
+                let label = syntax::item_label(item);
+                source
+                    .iter()
+                    .position(|source| syntax::item_label(source) == label)
+            });
+        let start = anchor.map_or(0, |anchor| anchor + 1);
+        let invocation =
+            source[start..]
+                .iter()
+                .chain(&source[..start])
+                .find_map(|item| match *item {
+                    Item::Macro(ref item) if item.ident.is_none() => Some(&item.mac),
+                    _ => None,
+                });
+        match invocation {
+            Some(mac) => format!(
+                "line {} comes from `{}!`, which made `{}`, at {}",
+                self.line,
+                path(&mac.path),
+                label,
+                self.at(module, mac.span()),
+            ),
+            None => format!(
+                "line {} is in `{}`, which the source does not have, but no macro invocation \
+                 in {} could be found to have made it",
+                self.line,
+                label,
+                self.file(module),
+            ),
+        }
+    }
+
+    fn within(&self, span: Span) -> bool {
+        span.start().line <= self.line && self.line <= span.end().line
+    }
+
+    fn at(&self, module: &SourceModule, span: Span) -> String {
+        format!(
+            "{}:{}:{}",
+            self.file(module),
+            span.start().line,
+            span.start().column + 1,
+        )
+    }
+
+    fn file(&self, module: &SourceModule) -> String {
+        module
+            .file
+            .strip_prefix(self.package_dir)
+            .unwrap_or(&module.file)
+            .display()
+            .to_string()
+    }
+}
+
+// The macros invoked in some code, by the last segment of their path and
+// where they are.
+struct Macros(Vec<(String, Span)>);
+
+impl<'ast> Visit<'ast> for Macros {
+    fn visit_macro(&mut self, mac: &'ast Macro) {
+        self.0
+            .push((provenance::last_segment(&mac.path), mac.span()));
+        visit::visit_macro(self, mac);
+    }
+}
+
+// A path as written, like `tokio::main`.
+fn path(path: &syn::Path) -> String {
+    path.to_token_stream().to_string().replace(" :: ", "::")
+}
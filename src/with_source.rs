@@ -0,0 +1,160 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use quote::ToTokens;
+use syn::spanned::Spanned;
+use syn::{File, Item};
+
+use opts::Opts;
+use pretty;
+use provenance;
+use redact;
+use select;
+use syntax;
+
+// --with-source: above each item that `cargo expand path` picked out of the
+// expansion, the same item as written in the source, in a block of comments,
+// for a before and after in one view. Items that only the expansion has, like
+// derived impls, come without one.
+pub fn render(opts: &Opts, expanded: &File, path: &str) -> io::Result<String> {
+    let mut sources = find(opts, path)?;
+    let mut out = String::new();
+    for item in &expanded.items {
+        let label = syntax::item_label(item);
+        // Each goes with the first of the items it could be, like the first
+        // of several impls for a type.
+        if let Some(at) = sources.iter().position(|source| source.0 == label) {
+            let (_, block) = sources.remove(at);
+            out.push_str(&block);
+        }
+        out.push_str(&item.into_token_stream().to_string());
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+// The label and comment block of each source item at the path.
+fn find(opts: &Opts, path: &str) -> io::Result<Vec<(String, String)>> {
+    let (_, root) = provenance::target_root(opts)?;
+    let path = path.strip_prefix("crate::").unwrap_or(path);
+    let mut segments: Vec<&str> = path.split("::").collect();
+
+    // Go down through the modules on the path first, so that everything
+    // selected is in one file to take the lines of.
+    let mut file = root.clone();
+    let mut dir = root.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut content = read(&file)?;
+    let mut items = parse(&file, &content)?.items;
+    while segments.len() > 1 {
+        let m = items.iter().find_map(|item| match *item {
+            Item::Mod(ref m) if m.ident == segments[0] => Some(m.clone()),
+            _ => None,
+        });
+        let m = match m {
+            Some(m) => m,
+            None => break,
+        };
+        segments.remove(0);
+        let name = m.ident.to_string();
+        match m.content {
+            Some((_, inner)) => {
+                items = inner;
+                dir = dir.join(&name);
+            }
+            None => {
+                let (path, child_dir) = provenance::module_file(&dir, &name);
+                content = read(&path)?;
+                items = parse(&path, &content)?.items;
+                file = path;
+                dir = child_dir;
+            }
+        }
+    }
+
+    let package_dir = opts
+        .manifest_path
+        .as_ref()
+        .and_then(|p| Path::new(p).parent());
+    let shown = package_dir
+        .and_then(|package_dir| file.strip_prefix(package_dir).ok())
+        .unwrap_or(&file)
+        .display()
+        .to_string();
+
+    let mut sources = Vec::new();
+    for item in select::select(&items, &segments) {
+        let label = syntax::item_label(&item);
+        // `Type::method` comes as the impl with only that method left, of
+        // which just the method is shown.
+        let member = segments.len() == 2;
+        let line = if member {
+            member_span(&item).start().line
+        } else {
+            item.span().start().line
+        };
+        let text = if opts.redact {
+            // Never show what was asked to be redacted, even as written.
+            let mut file = File {
+                shebang: None,
+                attrs: Vec::new(),
+                items: vec![item],
+            };
+            redact::apply(&mut file);
+            let item = file.items.remove(0);
+            let tokens = if member {
+                member_tokens(&item)
+            } else {
+                item.into_token_stream().to_string()
+            };
+            pretty::format(&tokens, &pretty::indent(opts)).unwrap_or(tokens)
+        } else if member {
+            syntax::lines(&content, member_span(&item))
+        } else {
+            syntax::lines(&content, item.span())
+        };
+
+        let mut block = format!("// ---- source at {}:{} ----\n", shown, line);
+        for line in text.lines() {
+            block.push_str(format!("// {}", line).trim_end());
+            block.push('\n');
+        }
+        block.push_str("// ---- expanded ----\n");
+        sources.push((label, block));
+    }
+    Ok(sources)
+}
+
+fn member_span(item: &Item) -> proc_macro2::Span {
+    match *item {
+        Item::Impl(ref imp) => imp.items[0].span(),
+        Item::Trait(ref t) => t.items[0].span(),
+        _ => item.span(),
+    }
+}
+
+fn member_tokens(item: &Item) -> String {
+    match *item {
+        Item::Impl(ref imp) => imp.items[0].to_token_stream().to_string(),
+        Item::Trait(ref t) => t.items[0].to_token_stream().to_string(),
+        _ => item.to_token_stream().to_string(),
+    }
+}
+
+fn read(path: &Path) -> io::Result<String> {
+    fs::read_to_string(path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("failed to read {}: {}", path.display(), err),
+        )
+    })
+}
+
+fn parse(path: &Path, content: &str) -> io::Result<File> {
+    syn::parse_file(content).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to parse {}: {}", path.display(), err),
+        )
+    })
+}